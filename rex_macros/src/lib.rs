@@ -0,0 +1,688 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+#![feature(plugin_registrar, rustc_private, core, collections)]
+
+//! `rex_macros` is a compiler plugin which provides `#[derive(RexEncodable, RexDecodable)]` for
+//! structs whose fields are annotated with `#[rex(...)]` attributes:
+//!
+//! ```ignore
+//! #![feature(plugin, custom_derive)]
+//! #![plugin(rex_macros)]
+//!
+//! extern crate rex;
+//!
+//! #[derive(RexEncodable, RexDecodable)]
+//! struct Vertex {
+//!     #[rex(id = 1)]             pos:    [f32; 3],
+//!     #[rex(id = 2, opt)]        uv:     Option<[f32; 2]>,
+//!     #[rex(id = 3, rep, fixed)] bones:  Vec<u32>,
+//!     #[rex(id = 4)]             mat:    Material,
+//! }
+//! ```
+//!
+//! The attribute arguments are:
+//!
+//! * `id = N` -- the `FieldID` of the field (required).
+//! * `opt` -- the field is optional, and has type `Option<T>`.
+//! * `rep` -- the field is repeated, and has type `Vec<T>`.
+//! * `fixed` -- a `u32`/`u64` is encoded as `fixed32`/`fixed64` rather than as a varint.
+//!
+//! A fixed array type `[T; N]` becomes an array field with bounds `N`. Nested arrays are flattened,
+//! as in .rex files, so `[[f32; 3]; 4]` has bounds `12`. `Vec<u8>` is `bytes` and
+//! `String` is `string`. Any other type is taken to be a nested record, and must itself derive
+//! `RexEncodable`/`RexDecodable`.
+//!
+//! `RexEncodable` also implements `rex::encoding::RecordType`, so that the encoding of the struct
+//! can be had from `CompleteEncoding::of::<Vertex>()`.
+//!
+//! The derives can't be spelled `Encodable`/`Decodable` because those names belong to the
+//! compiler's built-in `rustc-serialize` derives.
+
+extern crate syntax;
+extern crate rustc;
+
+use syntax::ast;
+use syntax::attr;
+use syntax::codemap::Span;
+use syntax::ext::base::{Decorator, ExtCtxt};
+use syntax::parse;
+use syntax::parse::token;
+use syntax::print::pprust;
+use syntax::ptr::P;
+
+use rustc::plugin::Registry;
+
+#[plugin_registrar]
+#[doc(hidden)]
+pub fn plugin_registrar(reg: &mut Registry) {
+    reg.register_syntax_extension(token::intern("derive_RexEncodable"),
+                                  Decorator(Box::new(expand_encodable)));
+
+    reg.register_syntax_extension(token::intern("derive_RexDecodable"),
+                                  Decorator(Box::new(expand_decodable)));
+}
+
+#[derive(Copy,Clone,PartialEq)]
+enum Quant {
+    Required,
+    Optional,
+    Repeated,
+}
+
+// The type of a single element of a field.
+enum Elem {
+    // A primitive type. The string is the name of both the `Type` and `Primitive` variants.
+    Prim(&'static str),
+
+    // A nested record, given by the name of its Rust type.
+    Record(String),
+}
+
+struct Field {
+    id:     u64,
+    name:   String,
+    quant:  Quant,
+    bounds: Option<usize>,
+    elem:   Elem,
+
+    // The length of each level of a (possibly nested) array field, outermost first. Empty for
+    // non-array fields.
+    dims: Vec<usize>,
+}
+
+struct Record {
+    name:   String,
+    fields: Vec<Field>,
+}
+
+fn expand_encodable(cx: &mut ExtCtxt, span: Span, _: &ast::MetaItem, item: &ast::Item,
+                    push: &mut FnMut(P<ast::Item>)) {
+
+    if let Some(rec) = describe(cx, span, item) {
+        emit(cx, span, encodable_impl(&rec), push);
+        emit(cx, span, record_type_impl(&rec), push);
+    }
+}
+
+fn expand_decodable(cx: &mut ExtCtxt, span: Span, _: &ast::MetaItem, item: &ast::Item,
+                    push: &mut FnMut(P<ast::Item>)) {
+
+    if let Some(rec) = describe(cx, span, item) {
+        emit(cx, span, decodable_impl(&rec), push);
+    }
+}
+
+// Parse the generated source `src` and hand the resulting item back to the compiler.
+fn emit(cx: &mut ExtCtxt, span: Span, src: String, push: &mut FnMut(P<ast::Item>)) {
+    let parsed = parse::parse_item_from_source_str("<rex_macros>".to_string(), src, cx.cfg(),
+                                                    cx.parse_sess());
+    match parsed {
+        Some(item) => push(item),
+        None       => cx.span_err(span, "rex_macros generated code which failed to parse"),
+    }
+}
+
+// Build a `Record` from a struct item, reporting any problems with the `#[rex(...)]` attributes.
+fn describe(cx: &mut ExtCtxt, span: Span, item: &ast::Item) -> Option<Record> {
+    let def = match item.node {
+        ast::ItemStruct(ref def, ref generics) => {
+            if generics.is_parameterized() {
+                cx.span_err(span, "rex records can't have type or lifetime parameters");
+                return None;
+            }
+            def
+        }
+        _ => {
+            cx.span_err(span, "rex records must be structs");
+            return None;
+        }
+    };
+
+    let mut fields = vec![];
+    let mut ok     = true;
+
+    for field in def.fields.iter() {
+        match describe_field(cx, field) {
+            Some(f) => fields.push(f),
+            None    => ok = false,
+        }
+    }
+
+    for (i, f) in fields.iter().enumerate() {
+        if fields[..i].iter().any(|g| g.id == f.id) {
+            cx.span_err(span, &format!("field id {} is used more than once", f.id)[..]);
+            ok = false;
+        }
+    }
+
+    if ok {
+        Some(Record {
+            name:   token::get_ident(item.ident).to_string(),
+            fields: fields,
+        })
+    } else {
+        None
+    }
+}
+
+fn describe_field(cx: &mut ExtCtxt, field: &ast::StructField) -> Option<Field> {
+    let span = field.span;
+
+    let name = match field.node.kind {
+        ast::NamedField(ident, _) => token::get_ident(ident).to_string(),
+        ast::UnnamedField(_) => {
+            cx.span_err(span, "rex records can't be tuple structs");
+            return None;
+        }
+    };
+
+    let mut id    = None;
+    let mut quant = Quant::Required;
+    let mut fixed = false;
+
+    for a in field.node.attrs.iter() {
+        if !a.check_name("rex") {
+            continue;
+        }
+
+        attr::mark_used(a);
+
+        let items = match a.meta_item_list() {
+            Some(items) => items,
+            None        => {
+                cx.span_err(a.span, "expected #[rex(id = N, ...)]");
+                return None;
+            }
+        };
+
+        for mi in items.iter() {
+            match &*mi.name() {
+                "opt"   => quant = Quant::Optional,
+                "rep"   => quant = Quant::Repeated,
+                "fixed" => fixed = true,
+                "id"    => id = match mi.node {
+                    ast::MetaNameValue(_, ref lit) => match lit.node {
+                        ast::LitInt(0, _) => {
+                            cx.span_err(mi.span, "field id 0 is reserved to mark the end of a record");
+                            return None;
+                        }
+                        ast::LitInt(x, _) => Some(x),
+                        _                 => {
+                            cx.span_err(lit.span, "field id must be an integer literal");
+                            return None;
+                        }
+                    },
+                    _ => {
+                        cx.span_err(mi.span, "expected #[rex(id = N)]");
+                        return None;
+                    }
+                },
+                other => {
+                    cx.span_err(mi.span, &format!("unknown rex attribute '{}'", other)[..]);
+                    return None;
+                }
+            }
+        }
+    }
+
+    let id = match id {
+        Some(id) => id,
+        None     => {
+            cx.span_err(span, &format!("field '{}' needs a #[rex(id = N)] attribute", name)[..]);
+            return None;
+        }
+    };
+
+    // Work out the element type and bounds from the Rust type, with the quantifier peeled off.
+    let full: String = pprust::ty_to_string(&*field.node.ty)
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+
+    let inner = match quant {
+        Quant::Required => Some(&full[..]),
+        Quant::Optional => unwrap_generic(&full[..], "Option"),
+        Quant::Repeated => unwrap_generic(&full[..], "Vec"),
+    };
+
+    let inner = match inner {
+        Some(inner) => inner,
+        None        => {
+            let wanted = if quant == Quant::Optional { "Option<T>" } else { "Vec<T>" };
+            cx.span_err(span, &format!("field '{}' should have type {}", name, wanted)[..]);
+            return None;
+        }
+    };
+
+    let (elem_ty, dims) = unwrap_arrays(inner);
+
+    let bounds = if dims.is_empty() {
+        None
+    } else {
+        match dims.iter().fold(Some(1usize), |p, n| p.and_then(|p| p.checked_mul(*n))) {
+            Some(0)       => {
+                cx.span_err(span, &format!("field '{}' is an empty array", name)[..]);
+                return None;
+            }
+            Some(product) => Some(product),
+            None          => {
+                cx.span_err(span, &format!("array bounds of '{}' are too large", name)[..]);
+                return None;
+            }
+        }
+    };
+
+    let elem = match (elem_ty, fixed) {
+        ("i8",  _)      => Elem::Prim("Int8"),
+        ("i16", _)      => Elem::Prim("Int16"),
+        ("i32", _)      => Elem::Prim("Int32"),
+        ("i64", _)      => Elem::Prim("Int64"),
+
+        ("u8",  _)      => Elem::Prim("UInt8"),
+        ("u16", _)      => Elem::Prim("UInt16"),
+        ("u32", false)  => Elem::Prim("UInt32"),
+        ("u64", false)  => Elem::Prim("UInt64"),
+        ("u32", true)   => Elem::Prim("Fixed32"),
+        ("u64", true)   => Elem::Prim("Fixed64"),
+
+        ("f32", _)      => Elem::Prim("Float32"),
+        ("f64", _)      => Elem::Prim("Float64"),
+
+        ("Vec<u8>", _)  => Elem::Prim("Bytes"),
+        ("String", _)   => Elem::Prim("String"),
+
+        ("bool", _)     => Elem::Prim("Bool"),
+
+        (_, true) => {
+            cx.span_err(span, "only u32 and u64 fields can be 'fixed'");
+            return None;
+        }
+
+        (other, false) => Elem::Record(other.to_string()),
+    };
+
+    Some(Field {
+        id:     id,
+        name:   name,
+        quant:  quant,
+        bounds: bounds,
+        elem:   elem,
+        dims:   dims,
+    })
+}
+
+// If `ty` is `name<T>`, return `T`.
+fn unwrap_generic<'x>(ty: &'x str, name: &str) -> Option<&'x str> {
+    if ty.starts_with(name) && ty[name.len()..].starts_with("<") && ty.ends_with(">") {
+        Some(&ty[name.len() + 1 .. ty.len() - 1])
+    } else {
+        None
+    }
+}
+
+// If `ty` is `[T;N]`, return `T` and `N`.
+fn unwrap_array(ty: &str) -> Option<(&str, usize)> {
+    if !ty.starts_with("[") || !ty.ends_with("]") {
+        return None;
+    }
+
+    let body = &ty[1 .. ty.len() - 1];
+
+    match body.rfind(';') {
+        Some(semi) => match body[semi + 1..].parse::<usize>() {
+            Ok(n)  => Some((&body[..semi], n)),
+            Err(_) => None,
+        },
+        None => None,
+    }
+}
+
+// Peel every level of array off `ty`, returning the element type and the length of each level,
+// outermost first.
+fn unwrap_arrays(ty: &str) -> (&str, Vec<usize>) {
+    let mut elem = ty;
+    let mut dims = vec![];
+
+    while let Some((inner, n)) = unwrap_array(elem) {
+        dims.push(n);
+        elem = inner;
+    }
+
+    (elem, dims)
+}
+
+// An expression giving an `Option` of a reference to element `idx` of the (possibly nested) array
+// `base` with dimensions `dims`, using `get` (or `get_mut`) at each level.
+fn index_chain(base: &str, dims: &[usize], idx: &str, get: &str) -> String {
+    let mut expr = String::new();
+
+    for (level, n) in dims.iter().enumerate() {
+        let stride = dims[level + 1..].iter().fold(1, |p, d| p * *d);
+
+        let sub = match (level, stride) {
+            (0, 1) => idx.to_string(),
+            (0, _) => format!("{} / {}", idx, stride),
+            (_, 1) => format!("{} % {}", idx, n),
+            (_, _) => format!("{} / {} % {}", idx, stride, n),
+        };
+
+        expr = if level == 0 {
+            format!("{}.{}({})", base, get, sub)
+        } else {
+            format!("{}.and_then(|v| v.{}({}))", expr, get, sub)
+        };
+    }
+
+    expr
+}
+
+// An expression giving an `Option<&T>` (or `Option<&mut T>` if `mutable`) to element `idx` of
+// field `f`.
+fn elem_ref(f: &Field, mutable: bool) -> String {
+    let (get, as_ref, amp) = if mutable {
+        ("get_mut", "as_mut", "&mut ")
+    } else {
+        ("get", "as_ref", "&")
+    };
+
+    let m = &f.name;
+
+    match (f.quant, f.bounds) {
+        (Quant::Required, None)    => format!("Some({}self.{})", amp, m),
+        (Quant::Required, Some(_)) => {
+            let base = format!("self.{}", m);
+            index_chain(&base[..], &f.dims[..], "idx", get)
+        }
+        (Quant::Optional, None)    => format!("self.{}.{}()", m, as_ref),
+        (Quant::Optional, Some(_)) => format!("self.{}.{}().and_then(|v| {})",
+                                              m, as_ref, index_chain("v", &f.dims[..], "idx", get)),
+
+        // Elements of repeated fields are created as they're decoded; see `decodable_impl`.
+        (Quant::Repeated, None) if mutable =>
+            format!("::rex::next_elem(&mut self.{}, idx, Default::default)", m),
+        (Quant::Repeated, Some(n)) if mutable => {
+            let idx = format!("(idx % {})", n);
+            format!("::rex::next_elem(&mut self.{}, idx / {}, Default::default).and_then(|v| {})",
+                    m, n, index_chain("v", &f.dims[..], &idx[..], get))
+        }
+
+        (Quant::Repeated, None)    => format!("self.{}.{}(idx)", m, get),
+        (Quant::Repeated, Some(n)) => {
+            let idx = format!("(idx % {})", n);
+            format!("self.{}.{}(idx / {}).and_then(|v| {})",
+                    m, get, n, index_chain("v", &f.dims[..], &idx[..], get))
+        }
+    }
+}
+
+// Whether the elements of `f` are stored contiguously, with a type that can go in a
+// `::rex::Slice`. If so, return the name of the `Slice` variant. Nested arrays can't be viewed as a
+// flat slice without `unsafe`, so they're left out.
+fn slice_variant(f: &Field) -> Option<&'static str> {
+    if f.dims.len() > 1 {
+        return None;
+    }
+
+    let p = match f.elem {
+        Elem::Prim(p @ "UInt8")   | Elem::Prim(p @ "UInt16")  |
+        Elem::Prim(p @ "Int8")    | Elem::Prim(p @ "Int16")   |
+        Elem::Prim(p @ "Fixed32") | Elem::Prim(p @ "Fixed64") |
+        Elem::Prim(p @ "Float32") | Elem::Prim(p @ "Float64") => p,
+
+        _ => return None,
+    };
+
+    match (f.quant, f.bounds) {
+        (Quant::Repeated, None) | (Quant::Required, Some(_)) | (Quant::Optional, Some(_)) => Some(p),
+        _                                                                                 => None,
+    }
+}
+
+fn encodable_impl(rec: &Record) -> String {
+    let mut prims   = String::new();
+    let mut records = String::new();
+    let mut counts  = String::new();
+    let mut slices  = String::new();
+
+    for f in rec.fields.iter() {
+        match f.elem {
+            Elem::Prim(p) => {
+                let val = match p {
+                    "Bytes" | "String" => "x.clone()",
+                    _                  => "*x",
+                };
+                prims.push_str(&format!(
+                    "{} => match {} {{ Some(x) => Ok(::rex::Primitive::{}({})), \
+                                       None => Err(::rex::EncError::FieldTypeMismatch) }},\n",
+                    f.id, elem_ref(f, false), p, val)[..]);
+            }
+            Elem::Record(_) => {
+                records.push_str(&format!(
+                    "{} => match {} {{ Some(x) => e.encode(x), \
+                                       None => Err(::rex::EncError::FieldTypeMismatch) }},\n",
+                    f.id, elem_ref(f, false))[..]);
+            }
+        }
+
+        match f.quant {
+            Quant::Required => {}
+            Quant::Optional => counts.push_str(&format!(
+                "{} => Ok(if self.{}.is_some() {{ 1 }} else {{ 0 }}),\n", f.id, f.name)[..]),
+            Quant::Repeated => counts.push_str(&format!(
+                "{} => Ok(self.{}.len()),\n", f.id, f.name)[..]),
+        }
+
+        if let Some(v) = slice_variant(f) {
+            slices.push_str(&match f.quant {
+                Quant::Optional => format!(
+                    "{} => self.{}.as_ref().map(|v| ::rex::Slice::{}(&v[..])),\n", f.id, f.name, v),
+                _ => format!(
+                    "{} => Some(::rex::Slice::{}(&self.{}[..])),\n", f.id, v, f.name),
+            }[..]);
+        }
+    }
+
+    format!("
+        impl ::rex::Encodable for {name} {{
+            #[allow(unused_variables)]
+            fn get_primitive(&self, id: ::rex::encoding::FieldID, idx: usize)
+                -> Result<::rex::Primitive, ::rex::EncError> {{
+                match id.0 {{
+                    {prims}
+                    _ => Err(::rex::EncError::FieldTypeMismatch),
+                }}
+            }}
+
+            #[allow(unused_variables, unused_mut)]
+            fn encode_record(&self, mut e: ::rex::Encoder, id: ::rex::encoding::FieldID, idx: usize)
+                -> Result<usize, ::rex::EncError> {{
+                match id.0 {{
+                    {records}
+                    _ => Err(::rex::EncError::FieldTypeMismatch),
+                }}
+            }}
+
+            fn count_field(&self, id: ::rex::encoding::FieldID)
+                -> Result<usize, ::rex::EncError> {{
+                match id.0 {{
+                    {counts}
+                    _ => Err(::rex::EncError::FieldTypeMismatch),
+                }}
+            }}
+
+            fn get_slice(&self, id: ::rex::encoding::FieldID) -> Option<::rex::Slice> {{
+                match id.0 {{
+                    {slices}
+                    _ => None,
+                }}
+            }}
+        }}",
+        name = rec.name, prims = prims, records = records, counts = counts, slices = slices)
+}
+
+fn decodable_impl(rec: &Record) -> String {
+    let mut prims   = String::new();
+    let mut records = String::new();
+    let mut allocs  = String::new();
+    let mut slices  = String::new();
+
+    for f in rec.fields.iter() {
+        match f.elem {
+            Elem::Prim(p) => prims.push_str(&format!(
+                "({}, ::rex::Primitive::{}(v)) => match {} {{ Some(x) => {{ *x = v; Ok(()) }}, \
+                                                  None => Err(::rex::DecError::FieldTypeMismatch) }},\n",
+                f.id, p, elem_ref(f, true))[..]),
+
+            Elem::Record(_) => records.push_str(&format!(
+                "{} => match {} {{ Some(x) => d.decode(x), \
+                                   None => Err(::rex::DecError::FieldTypeMismatch) }},\n",
+                f.id, elem_ref(f, true))[..]),
+        }
+
+        match f.quant {
+            Quant::Required => {}
+            Quant::Optional => allocs.push_str(&format!(
+                "{} => {{ self.{} = Some(Default::default()); Ok(true) }},\n", f.id, f.name)[..]),
+
+            // `count` comes off the wire, so only a bounded amount is reserved and the elements are
+            // created as they're decoded. Sliceable fields are only filled in up front if they're
+            // small, and otherwise `slice_mut` declines them so that they're decoded an element
+            // at a time.
+            Quant::Repeated if slice_variant(f).is_some() => allocs.push_str(&format!(
+                "{} => {{ self.{} = if count <= ::rex::PREALLOC_MAX {{ \
+                                        (0..count).map(|_| Default::default()).collect() \
+                                    }} else {{ \
+                                        vec![] \
+                                    }}; Ok(true) }},\n",
+                f.id, f.name)[..]),
+            Quant::Repeated => allocs.push_str(&format!(
+                "{} => {{ self.{}.clear(); \
+                          self.{}.reserve(::std::cmp::min(count, ::rex::PREALLOC_MAX)); Ok(true) }},\n",
+                f.id, f.name, f.name)[..]),
+        }
+
+        if let Some(v) = slice_variant(f) {
+            slices.push_str(&match f.quant {
+                Quant::Optional => format!(
+                    "{} => self.{}.as_mut().map(|v| ::rex::SliceMut::{}(&mut v[..])),\n", f.id, f.name, v),
+                Quant::Repeated => format!(
+                    "{} => if self.{}.is_empty() {{ None }} \
+                          else {{ Some(::rex::SliceMut::{}(&mut self.{}[..])) }},\n",
+                    f.id, f.name, v, f.name),
+                _ => format!(
+                    "{} => Some(::rex::SliceMut::{}(&mut self.{}[..])),\n", f.id, v, f.name),
+            }[..]);
+        }
+    }
+
+    format!("
+        impl ::rex::Decodable for {name} {{
+            #[allow(unused_variables)]
+            fn set_primitive(&mut self, id: ::rex::encoding::FieldID, idx: usize, prim: ::rex::Primitive)
+                -> Result<(), ::rex::DecError> {{
+                match (id.0, prim) {{
+                    {prims}
+                    _ => Err(::rex::DecError::FieldTypeMismatch),
+                }}
+            }}
+
+            #[allow(unused_variables, unused_mut)]
+            fn decode_record<'x, R>(&mut self, mut d: ::rex::Decoder<'x, R>, id: ::rex::encoding::FieldID,
+                                    idx: usize) -> Result<(), ::rex::DecError>
+                where R: ::std::io::Read + 'x {{
+                match id.0 {{
+                    {records}
+                    _ => Err(::rex::DecError::FieldTypeMismatch),
+                }}
+            }}
+
+            #[allow(unused_variables)]
+            fn alloc_field(&mut self, id: ::rex::encoding::FieldID, count: usize)
+                -> Result<bool, ::rex::DecError> {{
+                match id.0 {{
+                    {allocs}
+                    _ => Ok(false),
+                }}
+            }}
+
+            fn slice_mut(&mut self, id: ::rex::encoding::FieldID) -> Option<::rex::SliceMut> {{
+                match id.0 {{
+                    {slices}
+                    _ => None,
+                }}
+            }}
+        }}",
+        name = rec.name, prims = prims, records = records, allocs = allocs, slices = slices)
+}
+
+fn record_type_impl(rec: &Record) -> String {
+    let mut req_fields     = String::new();
+    let mut opt_rep_fields = String::new();
+
+    for f in rec.fields.iter() {
+        let typ = match f.elem {
+            Elem::Prim(p)        => format!("::rex::encoding::Type::{}", p),
+            Elem::Record(ref ty) => format!("::rex::encoding::Type::Record{{index: deps.index::<{}>()}}", ty),
+        };
+
+        let quant = match f.quant {
+            Quant::Required => "Required",
+            Quant::Optional => "Optional",
+            Quant::Repeated => "Repeated",
+        };
+
+        let fenc = format!(
+            "::rex::encoding::FieldEncoding {{
+                id:     ::rex::encoding::FieldID({}),
+                name:   {:?}.to_string(),
+                quant:  ::rex::encoding::Quantifier::{},
+                typ:    {},
+                bounds: {:?},
+                union:  None,
+                default: None,
+            }},\n",
+            f.id, f.name, quant, typ, f.bounds);
+
+        match f.quant {
+            Quant::Required => req_fields.push_str(&fenc[..]),
+            _               => opt_rep_fields.push_str(&fenc[..]),
+        }
+    }
+
+    format!("
+        impl ::rex::encoding::RecordType for {name} {{
+            fn record_name() -> &'static str {{
+                {name:?}
+            }}
+
+            fn record_path() -> &'static str {{
+                concat!(module_path!(), "::", {name:?})
+            }}
+
+            #[allow(unused_variables)]
+            fn record_encoding(deps: &mut ::rex::encoding::Depends)
+                -> ::rex::encoding::RecordEncoding {{
+                ::rex::encoding::RecordEncoding {{
+                    name:           {name:?}.to_string(),
+                    req_fields:     vec![{req_fields}],
+                    opt_rep_fields: vec![{opt_rep_fields}],
+                }}
+            }}
+        }}",
+        name = rec.name, req_fields = req_fields, opt_rep_fields = opt_rep_fields)
+}
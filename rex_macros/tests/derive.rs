@@ -0,0 +1,138 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+#![feature(plugin, custom_derive)]
+#![plugin(rex_macros)]
+
+extern crate rex;
+
+use rex::{encode_to, decode_from, DynamicRecord, Primitive, Value};
+use rex::encoding::{CompleteEncoding, Type};
+
+mod a {
+    #[derive(Clone,Debug,Default,PartialEq,RexEncodable,RexDecodable)]
+    pub struct Material {
+        #[rex(id = 1)] pub roughness: f32,
+    }
+}
+
+mod b {
+    #[derive(Clone,Debug,Default,PartialEq,RexEncodable,RexDecodable)]
+    pub struct Material {
+        #[rex(id = 1)] pub name: String,
+    }
+}
+
+#[derive(Clone,Debug,Default,PartialEq,RexEncodable,RexDecodable)]
+struct Mesh {
+    #[rex(id = 1)]             name:      String,
+    #[rex(id = 2)]             transform: [[f32; 4]; 3],
+    #[rex(id = 3, opt)]        uv:        Option<[[u16; 2]; 2]>,
+    #[rex(id = 4, rep)]        corners:   Vec<[[i8; 2]; 2]>,
+    #[rex(id = 5, rep, fixed)] bones:     Vec<u32>,
+    #[rex(id = 6)]             surface:   a::Material,
+    #[rex(id = 7, opt)]        label:     Option<b::Material>,
+}
+
+fn mesh() -> Mesh {
+    Mesh {
+        name:      "quad".to_string(),
+        transform: [[1.0, 0.0, 0.0, 0.5], [0.0, 1.0, 0.0, 1.5], [0.0, 0.0, 1.0, 2.5]],
+        uv:        Some([[0, 1], [2, 3]]),
+        corners:   vec![[[-1, -2], [3, 4]], [[5, 6], [-7, -8]]],
+        bones:     vec![7, 11],
+        surface:   a::Material { roughness: 0.75 },
+        label:     Some(b::Material { name: "stone".to_string() }),
+    }
+}
+
+#[test]
+fn derived_records_round_trip() {
+    let enc = CompleteEncoding::of::<Mesh>();
+    let m   = mesh();
+
+    let mut buf = vec![];
+    encode_to(&enc, &mut buf, &m).unwrap();
+
+    let mut out: Mesh = Default::default();
+    decode_from(&enc, &mut &buf[..], &mut out).unwrap();
+    assert_eq!(out, m);
+}
+
+#[test]
+fn nested_arrays_are_flattened() {
+    let enc = CompleteEncoding::of::<Mesh>();
+
+    let transform = enc.target.req_fields.iter().find(|f| f.name == "transform").unwrap();
+    assert_eq!(transform.bounds, Some(12));
+
+    let mut buf = vec![];
+    encode_to(&enc, &mut buf, &mesh()).unwrap();
+
+    // Elements are written outermost array first.
+    let rec = DynamicRecord::decode_from(&enc, &mut &buf[..]).unwrap();
+    let corners = &rec.field_by_name("corners").unwrap().values;
+    let flat: Vec<Value> = [-1, -2, 3, 4, 5, 6, -7, -8].iter()
+        .map(|x| Value::Primitive(Primitive::Int8(*x)))
+        .collect();
+    assert_eq!(*corners, flat);
+}
+
+#[test]
+fn same_named_records_in_different_modules() {
+    let enc = CompleteEncoding::of::<Mesh>();
+    assert_eq!(enc.depends.len(), 2);
+
+    let typ = |name: &str| enc.target.req_fields.iter()
+        .chain(enc.target.opt_rep_fields.iter())
+        .find(|f| f.name == name)
+        .unwrap()
+        .typ;
+
+    match (typ("surface"), typ("label")) {
+        (Type::Record{index: s}, Type::Record{index: l}) => assert!(s != l),
+        _                                               => panic!("expected record fields"),
+    }
+}
+
+#[test]
+fn repeated_counts_are_not_trusted() {
+    let enc = CompleteEncoding::of::<Mesh>();
+
+    let mut m = mesh();
+    m.uv      = None;
+    m.corners = vec![];
+    m.bones   = vec![];
+    m.label   = None;
+
+    let mut buf = vec![];
+    encode_to(&enc, &mut buf, &m).unwrap();
+
+    // Keep the required fields, dropping the 0 which ends the record.
+    let required = buf.len() - 1;
+
+    // Each repeated field in turn claims 2^40 elements, but the record ends soon after. Decoding
+    // fails at the end of the data, rather than allocating all of the elements first.
+    for &id in [4u8, 5].iter() {
+        let mut bad = buf[..required].to_vec();
+        bad.extend([id, 0x80, 0x80, 0x80, 0x80, 0x80, 0x40,
+                        0x80, 0x80, 0x80, 0x80, 0x80, 0x20, 1, 2, 3].iter().cloned());
+
+        let mut out: Mesh = Default::default();
+        assert!(decode_from(&enc, &mut &bad[..], &mut out).is_err());
+    }
+}
@@ -0,0 +1,469 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::error;
+use std::error::FromError;
+use std::fmt;
+use std::io;
+use std::io::{Read, ReadExt, Seek, SeekFrom, Write};
+
+use codec;
+use codec::{Codec, Identity};
+use decoder;
+use decoder::{Decodable, DecodeLimits, decode_from_with_limits};
+use encoder;
+use encoder::{Encodable, encode_to};
+use encoding::{CompleteEncoding, COMPLETE_ENC};
+use value::DynamicRecord;
+
+/// `MAGIC` is the first four bytes of every archive.
+pub const MAGIC: &'static [u8] = b"REXA";
+
+/// `FORMAT_VERSION` is the version of the archive format written by `ArchiveWriter`.
+pub const FORMAT_VERSION: u64 = 1;
+
+/// `Error` is used to report errors that occur while reading or writing an archive.
+#[derive(Debug)]
+pub enum Error {
+    /// `BadMagic` indicates that the input doesn't start with `MAGIC`, so it probably isn't an
+    /// archive at all.
+    BadMagic,
+
+    /// `UnsupportedVersion` indicates that the archive was written with a newer version of the
+    /// archive format than this library understands.
+    UnsupportedVersion(u64),
+
+    /// `UnknownCodec` indicates that the archive was compressed with a codec which isn't available.
+    UnknownCodec(u64),
+
+    /// `DuplicateName` indicates an attempt to add two entries with the same name to an archive.
+    DuplicateName(String),
+
+    /// `NotFound` indicates that the archive has no entry with the requested name.
+    NotFound(String),
+
+    /// `Corrupt` indicates that the directory or schema table of the archive is inconsistent, e.g.
+    /// an entry refers to a schema which doesn't exist.
+    Corrupt,
+
+    /// `IoError` is used to pass through `std::io` errors.
+    IoError(io::Error),
+
+    /// `EncError` is used to pass through errors from encoding entries or schemas.
+    EncError(encoder::Error),
+
+    /// `DecError` is used to pass through errors from decoding entries or schemas.
+    DecError(decoder::Error),
+}
+
+impl FromError<encoder::Error> for Error {
+    fn from_error(err: encoder::Error) -> Error {
+        Error::EncError(err)
+    }
+}
+
+impl FromError<decoder::Error> for Error {
+    fn from_error(err: decoder::Error) -> Error {
+        Error::DecError(err)
+    }
+}
+
+impl FromError<io::Error> for Error {
+    fn from_error(err: io::Error) -> Error {
+        Error::IoError(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnsupportedVersion(v)  => write!(f, "unsupported archive version {}", v),
+            Error::UnknownCodec(id)       => write!(f, "unknown codec {}", id),
+            Error::DuplicateName(ref n)   => write!(f, "duplicate entry {:?}", n),
+            Error::NotFound(ref n)        => write!(f, "no entry named {:?}", n),
+            Error::IoError(ref err)       => write!(f, "i/o error: {}", err),
+            Error::EncError(ref err)      => write!(f, "error encoding: {}", err),
+            Error::DecError(ref err)      => write!(f, "error decoding: {}", err),
+            ref err                       => write!(f, "{}", error::Error::description(err)),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::BadMagic              => "not a rex archive",
+            Error::UnsupportedVersion(_) => "unsupported archive version",
+            Error::UnknownCodec(_)       => "unknown codec",
+            Error::DuplicateName(_)      => "duplicate entry name",
+            Error::NotFound(_)           => "entry not found",
+            Error::Corrupt               => "corrupt archive directory",
+            Error::IoError(_)            => "i/o error",
+            Error::EncError(ref err)     => error::Error::description(err),
+            Error::DecError(ref err)     => error::Error::description(err),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::IoError(ref err)  => Some(err as &error::Error),
+            Error::EncError(ref err) => Some(err as &error::Error),
+            Error::DecError(ref err) => Some(err as &error::Error),
+            _                        => None,
+        }
+    }
+}
+
+/// An `Entry` describes a single record stored in an archive.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Entry {
+    /// The name that the entry is looked up by.
+    pub name: String,
+
+    /// Offset of the record from the start of the archive's data section.
+    pub offset: u64,
+
+    /// Length of the encoded (and compressed) record in bytes.
+    pub length: u64,
+
+    /// Index of the record's encoding in the archive's schema table.
+    pub schema: usize,
+}
+
+/// An `ArchiveWriter` collects named records, and then writes them out as an archive. An archive
+/// consists of
+///
+/// 1. The four bytes of `MAGIC`.
+/// 2. `FORMAT_VERSION`, as a varint.
+/// 3. The id of the codec that records are compressed with, as a varint.
+/// 4. The directory, as a varint byte-size followed by a varint count of entries. Each entry is
+///    its name (a varint length followed by utf-8), offset, length and schema index, the last three
+///    as varints. Entries are sorted by name.
+/// 5. The schema table, as a varint byte-size followed by a varint count of schemas. Each schema is
+///    a `CompleteEncoding` encoded according to `COMPLETE_ENC`.
+/// 6. The data section, which is the concatenation of every record.
+///
+/// Each record is compressed separately, so that entries can still be read individually. Records
+/// sharing a `CompleteEncoding` share a single entry in the schema table.
+pub struct ArchiveWriter {
+    entries: Vec<Entry>,
+    schemas: Vec<CompleteEncoding>,
+    data:    Vec<u8>,
+    codec:   Box<Codec>,
+}
+
+impl ArchiveWriter {
+    /// `new` creates an empty `ArchiveWriter`, which doesn't compress its records.
+    pub fn new() -> ArchiveWriter {
+        ArchiveWriter::with_codec(Box::new(Identity))
+    }
+
+    /// `with_codec` creates an empty `ArchiveWriter`, which compresses its records with `codec`.
+    pub fn with_codec(codec: Box<Codec>) -> ArchiveWriter {
+        ArchiveWriter {
+            entries: vec![],
+            schemas: vec![],
+            data:    vec![],
+            codec:   codec,
+        }
+    }
+
+    /// `add` encodes `e` according to `enc`, and adds it to the archive under `name`.
+    pub fn add<E>(&mut self, name: &str, enc: &CompleteEncoding, e: &E) -> Result<(), Error>
+        where E: Encodable {
+
+        if self.entries.iter().any(|entry| entry.name == name) {
+            return Err(Error::DuplicateName(name.to_string()));
+        }
+
+        let mut buf = vec![];
+        try!(encode_to(enc, &mut buf, e));
+
+        if self.codec.id() != codec::NONE {
+            buf = self.codec.compress(&buf[..]);
+        }
+
+        let schema = match self.schemas.iter().position(|s| s == enc) {
+            Some(idx) => idx,
+            None      => {
+                self.schemas.push(enc.clone());
+                self.schemas.len() - 1
+            }
+        };
+
+        self.entries.push(Entry {
+            name:   name.to_string(),
+            offset: self.data.len() as u64,
+            length: buf.len() as u64,
+            schema: schema,
+        });
+
+        try!(self.data.write_all(&buf[..]));
+
+        Ok(())
+    }
+
+    /// `write_to` writes the archive to `w`.
+    pub fn write_to<W>(&self, w: &mut W) -> Result<(), Error>
+        where W: io::Write {
+
+        let mut entries: Vec<&Entry> = self.entries.iter().collect();
+        entries.sort_by(|x, y| x.name.cmp(&y.name));
+
+        let mut dir = vec![];
+        try!(encoder::write_uvarint(&mut dir, entries.len() as u64));
+
+        for entry in entries.iter() {
+            try!(encoder::write_uvarint(&mut dir, entry.name.len() as u64));
+            try!(dir.write_all(entry.name.as_bytes()));
+            try!(encoder::write_uvarint(&mut dir, entry.offset));
+            try!(encoder::write_uvarint(&mut dir, entry.length));
+            try!(encoder::write_uvarint(&mut dir, entry.schema as u64));
+        }
+
+        let mut schemas = vec![];
+        try!(encoder::write_uvarint(&mut schemas, self.schemas.len() as u64));
+
+        for enc in self.schemas.iter() {
+            try!(encode_to(&COMPLETE_ENC, &mut schemas, enc));
+        }
+
+        try!(w.write_all(MAGIC));
+        try!(encoder::write_uvarint(w, FORMAT_VERSION));
+        try!(encoder::write_uvarint(w, self.codec.id()));
+        try!(encoder::write_uvarint(w, dir.len() as u64));
+        try!(w.write_all(&dir[..]));
+        try!(encoder::write_uvarint(w, schemas.len() as u64));
+        try!(w.write_all(&schemas[..]));
+        try!(w.write_all(&self.data[..]));
+
+        Ok(())
+    }
+}
+
+/// An `ArchiveReader` gives random access to the entries of an archive. The directory and schema
+/// table are read when the archive is opened; entries are only read when they're asked for.
+pub struct ArchiveReader<R> {
+    r: R,
+
+    // `data_start` is the position in `r` of the data section.
+    data_start: u64,
+
+    // `entries` is sorted by name.
+    entries: Vec<Entry>,
+    schemas: Vec<CompleteEncoding>,
+
+    codec:  Box<Codec>,
+    limits: DecodeLimits,
+}
+
+impl<R> ArchiveReader<R>
+    where R: io::Read + io::Seek {
+
+    /// `open` reads the header, directory and schema table of the archive at the current position
+    /// of `r`. Only archives compressed with the built-in codecs can be opened this way; see
+    /// `open_with_codec` for others. The default `DecodeLimits` are used; see `open_with`.
+    pub fn open(r: R) -> Result<ArchiveReader<R>, Error> {
+        ArchiveReader::open_with(r, None, Default::default())
+    }
+
+    /// `open_with_codec` is like `open`, but uses `codec` if the archive was compressed with it.
+    pub fn open_with_codec(r: R, codec: Box<Codec>) -> Result<ArchiveReader<R>, Error> {
+        ArchiveReader::open_with(r, Some(codec), Default::default())
+    }
+
+    /// `open_with` is like `open_with_codec` when `custom` is given, and like `open` otherwise,
+    /// but holds the archive to `limits` in place of the default ones. The directory, the schema
+    /// table and each entry may be at most `limits.max_bytes` long, and the schemas and entries
+    /// are decoded with `limits`.
+    pub fn open_with(mut r: R, custom: Option<Box<Codec>>, limits: DecodeLimits)
+        -> Result<ArchiveReader<R>, Error> {
+        for b in MAGIC.iter() {
+            if try!(decoder::read_u8(&mut r)) != *b {
+                return Err(Error::BadMagic);
+            }
+        }
+
+        let version = try!(decoder::read_uvarint(&mut r));
+        if version > FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        let id = try!(decoder::read_uvarint(&mut r));
+
+        let codec = match custom {
+            Some(c) if c.id() == id => c,
+            _                       => match codec::find(id) {
+                Some(c) => c,
+                None    => return Err(Error::UnknownCodec(id)),
+            },
+        };
+
+        let dir     = try!(read_section(&mut r, &limits));
+        let schemas = try!(read_section(&mut r, &limits));
+
+        let data_start = try!(r.seek(SeekFrom::Current(0)));
+
+        let mut rd      = &dir[..];
+        let mut entries = vec![];
+
+        for _ in 0..try!(decoder::read_uvarint(&mut rd)) {
+            let name_len = try!(decoder::read_uvarint(&mut rd)) as usize;
+            if name_len > rd.len() {
+                return Err(Error::Corrupt);
+            }
+
+            let name = try!(String::from_utf8(rd[..name_len].to_vec())
+                .map_err(decoder::Error::Utf8Error));
+            rd = &rd[name_len..];
+
+            entries.push(Entry {
+                name:   name,
+                offset: try!(decoder::read_uvarint(&mut rd)),
+                length: try!(decoder::read_uvarint(&mut rd)),
+                schema: try!(decoder::read_uvarint(&mut rd)) as usize,
+            });
+        }
+
+        let mut rd    = &schemas[..];
+        let mut encs  = vec![];
+
+        for _ in 0..try!(decoder::read_uvarint(&mut rd)) {
+            let mut enc: CompleteEncoding = Default::default();
+            try!(decode_from_with_limits(&COMPLETE_ENC, &mut rd, &mut enc, &limits));
+            encs.push(enc);
+        }
+
+        // Lookups rely on the directory being sorted, and every entry needs a schema.
+        for win in entries.windows(2) {
+            if win[0].name >= win[1].name {
+                return Err(Error::Corrupt);
+            }
+        }
+
+        if entries.iter().any(|entry| entry.schema >= encs.len()) {
+            return Err(Error::Corrupt);
+        }
+
+        Ok(ArchiveReader {
+            r:          r,
+            data_start: data_start,
+            entries:    entries,
+            schemas:    encs,
+            codec:      codec,
+            limits:     limits,
+        })
+    }
+
+    /// `entries` returns the archive's directory, sorted by name.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries[..]
+    }
+
+    /// `entry` returns the directory entry called `name`, if there is one.
+    pub fn entry(&self, name: &str) -> Option<&Entry> {
+        match self.entries.binary_search_by(|entry| (&entry.name[..]).cmp(name)) {
+            Ok(idx) => Some(&self.entries[idx]),
+            Err(_)  => None,
+        }
+    }
+
+    /// `encoding` returns the encoding of the entry called `name`, if there is one.
+    pub fn encoding(&self, name: &str) -> Option<&CompleteEncoding> {
+        self.entry(name).map(|entry| &self.schemas[entry.schema])
+    }
+
+    /// `read` decodes the entry called `name` into `d`. It's up to the caller to check that `d`
+    /// corresponds to the entry's encoding.
+    pub fn read<D>(&mut self, name: &str, d: &mut D) -> Result<(), Error>
+        where D: Decodable {
+
+        let (buf, schema) = try!(self.read_raw(name));
+
+        let mut rd = &buf[..];
+        try!(decode_from_with_limits(&self.schemas[schema], &mut rd, d, &self.limits));
+
+        Ok(())
+    }
+
+    /// `read_dynamic` decodes the entry called `name` as a `DynamicRecord`.
+    pub fn read_dynamic(&mut self, name: &str) -> Result<DynamicRecord, Error> {
+        let (buf, schema) = try!(self.read_raw(name));
+
+        let mut rd  = &buf[..];
+        let mut rec = DynamicRecord::new(&self.schemas[schema].target);
+        try!(decode_from_with_limits(&self.schemas[schema], &mut rd, &mut rec, &self.limits));
+
+        Ok(rec)
+    }
+
+    /// `into_inner` returns the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.r
+    }
+
+    // Read the encoded bytes of the entry called `name`, along with its schema index.
+    fn read_raw(&mut self, name: &str) -> Result<(Vec<u8>, usize), Error> {
+        let (offset, length, schema) = match self.entry(name) {
+            Some(entry) => (entry.offset, entry.length, entry.schema),
+            None        => return Err(Error::NotFound(name.to_string())),
+        };
+
+        if length > self.limits.max_bytes {
+            return Err(Error::DecError(decoder::Error::SizeLimitExceeded));
+        }
+
+        let start = match self.data_start.checked_add(offset) {
+            Some(start) => start,
+            None        => return Err(Error::Corrupt),
+        };
+
+        try!(self.r.seek(SeekFrom::Start(start)));
+
+        let mut buf = vec![];
+        try!((&mut self.r).take(length).read_to_end(&mut buf));
+
+        if (buf.len() as u64) < length {
+            return Err(Error::DecError(decoder::Error::EOF));
+        }
+
+        if self.codec.id() != codec::NONE {
+            buf = try!(self.codec.decompress(&buf[..], self.limits.max_bytes));
+        }
+
+        Ok((buf, schema))
+    }
+}
+
+// Read a section of the archive header, which is a varint byte-size followed by that many bytes.
+fn read_section<R>(r: &mut R, limits: &DecodeLimits) -> Result<Vec<u8>, Error>
+    where R: io::Read {
+
+    let len = try!(decoder::read_uvarint(r));
+    if len > limits.max_bytes {
+        return Err(Error::DecError(decoder::Error::SizeLimitExceeded));
+    }
+
+    let mut buf = vec![];
+    try!(r.take(len).read_to_end(&mut buf));
+
+    if (buf.len() as u64) < len {
+        return Err(Error::DecError(decoder::Error::EOF));
+    }
+
+    Ok(buf)
+}
@@ -0,0 +1,278 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! `rex` is a command-line tool for working with rex schemas and records. Run it without arguments
+//! for usage.
+
+extern crate rex;
+extern crate "rustc-serialize" as rustc_serialize;
+
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::process;
+
+use rustc_serialize::json::Json;
+
+use rex::{encode_to, decode_from_with_limits, DecodeLimits, Encoding, DynamicRecord};
+use rex::compiler;
+use rex::encoding::COMPLETE_ENC;
+use rex::json;
+use rex::text;
+
+const USAGE: &'static str = "\
+usage: rex [options] <command> <args>
+
+options:
+    --max-bytes <n>     Fail on records (and encodings) of more than <n> bytes.
+    --max-length <n>    Fail on bytes and string values of more than <n> bytes.
+    --max-count <n>     Fail on repeated fields with more than <n> elements.
+    --max-depth <n>     Fail on records nested more than <n> deep.
+    --unlimited         Don't limit anything. Only use this for trusted input.
+
+commands:
+    compile  <schema.rex> <record> <output>
+        Compile the encoding for <record> and write it to <output>.
+
+    dump     <schema> <record> <input>
+        Print the binary record in <input> in the text format.
+
+    convert  <schema> <record> <from> <to> <input> <output>
+        Convert a record between formats. <from> and <to> are each one of
+        'binary', 'text' or 'json'.
+
+    validate <schema> <record> <input>
+        Check that <input> is a well-formed binary record. Exits with a non-zero
+        status if it isn't.
+
+<schema> is either a .rex file or an encoding written by 'rex compile'. In the
+latter case, <record> must match the record it was compiled for. Binary input
+is decoded with the library's default limits unless the options say otherwise.
+";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str>   = args.iter().map(|s| &s[..]).collect();
+
+    let mut limits: DecodeLimits = Default::default();
+    let mut rest = &args[..];
+
+    while !rest.is_empty() && rest[0].starts_with("--") {
+        match parse_option(&mut limits, rest) {
+            Ok(used) => rest = &rest[used..],
+            Err(msg) => {
+                let _ = writeln!(&mut std::io::stderr(), "rex: {}", msg);
+                process::exit(2);
+            }
+        }
+    }
+
+    let result = match rest {
+        ["compile", schema, record, output] => compile(schema, record, output, &limits),
+
+        ["dump", schema, record, input] => dump(schema, record, input, &limits),
+
+        ["convert", schema, record, from, to, input, output] =>
+            convert(schema, record, from, to, input, output, &limits),
+
+        ["validate", schema, record, input] => validate(schema, record, input, &limits),
+
+        _ => {
+            let _ = write!(&mut std::io::stderr(), "{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(msg) = result {
+        let _ = writeln!(&mut std::io::stderr(), "rex: {}", msg);
+        process::exit(1);
+    }
+}
+
+// Apply the option at the start of `args` to `limits`, returning the number of arguments it took
+// up.
+fn parse_option(limits: &mut DecodeLimits, args: &[&str]) -> Result<usize, String> {
+    let opt = args[0];
+
+    if opt == "--unlimited" {
+        *limits = DecodeLimits::unlimited();
+        return Ok(1);
+    }
+
+    if !["--max-bytes", "--max-length", "--max-count", "--max-depth"].contains(&opt) {
+        return Err(format!("unknown option '{}'", opt));
+    }
+
+    let n: u64 = match args.get(1).and_then(|value| value.parse().ok()) {
+        Some(n) => n,
+        None    => return Err(format!("{} needs a number", opt)),
+    };
+
+    match opt {
+        "--max-bytes"  => limits.max_bytes  = n,
+        "--max-length" => limits.max_length = n,
+        "--max-count"  => limits.max_count  = n,
+        _              => limits.max_depth  = n as usize,
+    }
+
+    Ok(2)
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    let mut buf = vec![];
+
+    try!(File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut buf))
+        .map_err(|err| format!("{}: {}", path, err)));
+
+    Ok(buf)
+}
+
+fn write_file(path: &str, data: &[u8]) -> Result<(), String> {
+    File::create(path)
+        .and_then(|mut f| f.write_all(data))
+        .map_err(|err| format!("{}: {}", path, err))
+}
+
+fn read_text(path: &str) -> Result<String, String> {
+    String::from_utf8(try!(read_file(path)))
+        .map_err(|_| format!("{}: file is not valid utf-8", path))
+}
+
+// Load the encoding of `record` from `schema`, which is either a .rex file or the output of
+// `rex compile`. A compiled encoding is decoded with `limits`.
+fn load_encoding(schema: &str, record: &str, limits: &DecodeLimits) -> Result<Encoding, String> {
+    let enc = if schema.ends_with(".rex") {
+        let src  = try!(read_text(schema));
+        let encs = try!(compiler::compile_str(&src[..])
+            .map_err(|err| format!("{}:{}", schema, err)));
+
+        match compiler::find_encoding(&encs[..], record) {
+            Some(enc) => enc.clone(),
+            None      => return Err(format!("{}: no record named '{}'", schema, record)),
+        }
+    } else {
+        let buf = try!(read_file(schema));
+        let mut enc: Encoding = Default::default();
+
+        try!(decode_from_with_limits(&COMPLETE_ENC, &mut &buf[..], &mut enc, limits)
+            .map_err(|err| format!("{}: not a valid encoding: {}", schema, err)));
+
+        if enc.target.name != record {
+            return Err(format!("{}: encoding is for record '{}', not '{}'",
+                               schema, enc.target.name, record));
+        }
+
+        enc
+    };
+
+    Ok(enc)
+}
+
+// Decode a binary record of type `enc` from `buf`, which was read from `input`. The record must
+// take up the whole of `buf`, and fit within `limits`.
+fn decode_binary(enc: &Encoding, buf: &[u8], input: &str, limits: &DecodeLimits)
+    -> Result<DynamicRecord, String> {
+
+    let mut rd  = buf;
+    let mut rec = DynamicRecord::new(&enc.target);
+
+    if let Err(err) = decode_from_with_limits(enc, &mut rd, &mut rec, limits) {
+        return Err(format!("{}: {}", input, err));
+    }
+
+    if !rd.is_empty() {
+        return Err(format!("{}: {} bytes of trailing data after the record", input, rd.len()));
+    }
+
+    Ok(rec)
+}
+
+fn compile(schema: &str, record: &str, output: &str, limits: &DecodeLimits)
+    -> Result<(), String> {
+
+    let enc = try!(load_encoding(schema, record, limits));
+
+    let mut buf = vec![];
+    try!(encode_to(&COMPLETE_ENC, &mut buf, &enc)
+        .map_err(|err| format!("failed to encode the encoding: {}", err)));
+
+    write_file(output, &buf[..])
+}
+
+fn dump(schema: &str, record: &str, input: &str, limits: &DecodeLimits) -> Result<(), String> {
+    let enc = try!(load_encoding(schema, record, limits));
+    let buf = try!(read_file(input));
+
+    let rec = try!(decode_binary(&enc, &buf[..], input, limits));
+
+    print!("{}", text::print(&rec));
+    Ok(())
+}
+
+fn convert(schema: &str, record: &str, from: &str, to: &str, input: &str, output: &str,
+           limits: &DecodeLimits) -> Result<(), String> {
+
+    let enc = try!(load_encoding(schema, record, limits));
+
+    let rec = match from {
+        "binary" => {
+            let buf = try!(read_file(input));
+            try!(decode_binary(&enc, &buf[..], input, limits))
+        }
+
+        "text" => {
+            let src = try!(read_text(input));
+            try!(text::parse(&enc, &src[..]).map_err(|err| format!("{}:{}", input, err)))
+        }
+
+        "json" => {
+            let src  = try!(read_text(input));
+            let json = try!(Json::from_str(&src[..])
+                .map_err(|err| format!("{}: invalid JSON: {:?}", input, err)));
+            try!(json::from_json(&enc, &json).map_err(|err| format!("{}: {}", input, err)))
+        }
+
+        other => return Err(format!("unknown format '{}'", other)),
+    };
+
+    let out = match to {
+        "binary" => {
+            let mut buf = vec![];
+            try!(encode_to(&enc, &mut buf, &rec).map_err(|err| format!("failed to encode record: {}", err)));
+            buf
+        }
+
+        "text" => text::print(&rec).into_bytes(),
+
+        "json" => format!("{}\n", json::to_json(&rec).pretty()).into_bytes(),
+
+        other => return Err(format!("unknown format '{}'", other)),
+    };
+
+    write_file(output, &out[..])
+}
+
+fn validate(schema: &str, record: &str, input: &str, limits: &DecodeLimits)
+    -> Result<(), String> {
+
+    let enc = try!(load_encoding(schema, record, limits));
+    let buf = try!(read_file(input));
+
+    try!(decode_binary(&enc, &buf[..], input, limits));
+    Ok(())
+}
@@ -0,0 +1,421 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::str;
+
+use decoder::{Error, DecodeLimits, UNLIMITED};
+use decoder::{read_uvarint, read_varint, read_u8, read_i8, read_le_u16, read_le_i16, read_le_u32,
+              read_le_u64, read_le_f32, read_le_f64};
+use encoding::{CompleteEncoding, RecordEncoding, FieldEncoding, Type, FieldID};
+use primitive::Primitive;
+
+/// A `BorrowedPrimitive` is like a `Primitive`, except that `Bytes` and `String` values borrow
+/// from the buffer being decoded rather than owning a copy of it.
+#[derive(Copy,Clone,Debug,PartialEq)]
+#[allow(missing_docs)]
+pub enum BorrowedPrimitive<'a> {
+    UInt8(u8),
+    UInt16(u16),
+    UInt32(u32),
+    UInt64(u64),
+
+    Int8(i8),
+    Int16(i16),
+    Int32(i32),
+    Int64(i64),
+
+    Fixed32(u32),
+    Fixed64(u64),
+
+    Float32(f32),
+    Float64(f64),
+
+    Bool(bool),
+
+    Bytes(&'a [u8]),
+    String(&'a str),
+
+    Enum(i64),
+}
+
+impl<'a> BorrowedPrimitive<'a> {
+    /// `to_primitive` copies `self` into an owned `Primitive`.
+    pub fn to_primitive(&self) -> Primitive {
+        match *self {
+            BorrowedPrimitive::UInt8(x)  => Primitive::UInt8(x),
+            BorrowedPrimitive::UInt16(x) => Primitive::UInt16(x),
+            BorrowedPrimitive::UInt32(x) => Primitive::UInt32(x),
+            BorrowedPrimitive::UInt64(x) => Primitive::UInt64(x),
+
+            BorrowedPrimitive::Int8(x)  => Primitive::Int8(x),
+            BorrowedPrimitive::Int16(x) => Primitive::Int16(x),
+            BorrowedPrimitive::Int32(x) => Primitive::Int32(x),
+            BorrowedPrimitive::Int64(x) => Primitive::Int64(x),
+
+            BorrowedPrimitive::Fixed32(x) => Primitive::Fixed32(x),
+            BorrowedPrimitive::Fixed64(x) => Primitive::Fixed64(x),
+
+            BorrowedPrimitive::Float32(x) => Primitive::Float32(x),
+            BorrowedPrimitive::Float64(x) => Primitive::Float64(x),
+
+            BorrowedPrimitive::Bool(x) => Primitive::Bool(x),
+
+            BorrowedPrimitive::Bytes(x)  => Primitive::Bytes(x.to_vec()),
+            BorrowedPrimitive::String(x) => Primitive::String(x.to_string()),
+
+            BorrowedPrimitive::Enum(x) => Primitive::Enum(x),
+        }
+    }
+}
+
+/// The `BorrowedDecodable` trait is the counterpart of `Decodable` for types which hold on to data
+/// borrowed from the buffer they were decoded from. The methods have the same meaning as those of
+/// `Decodable`.
+pub trait BorrowedDecodable<'a> {
+    /// `set_primitive` sets the value of a single element of a field with primitive type.
+    fn set_primitive(&mut self, id: FieldID, idx: usize, prim: BorrowedPrimitive<'a>)
+        -> Result<(), Error>;
+
+    /// `decode_record` is a request to call `d.decode` on a record field.
+    fn decode_record<'x>(&mut self, d: SliceDecoder<'a, 'x>, id: FieldID, idx: usize)
+        -> Result<(), Error>;
+
+    /// `alloc_field` requests that the receiver allocate space for an optional or repeated field.
+    /// The boolean return value can be `false` to indicate that the receiver is uninterested in
+    /// this field.
+    fn alloc_field(&mut self, id: FieldID, count: usize) -> Result<bool, Error>;
+
+    /// `set_union_arm` reports which member of a union is present, before it's decoded.
+    fn set_union_arm(&mut self, union: &str, id: FieldID) -> Result<(), Error> {
+        let _ = (union, id);
+        Ok(())
+    }
+
+    /// `set_map_key` sets the key of entry `idx` of a map field, before its value is set.
+    fn set_map_key(&mut self, id: FieldID, idx: usize, key: BorrowedPrimitive<'a>)
+        -> Result<(), Error> {
+
+        let _ = (id, idx, key);
+        Err(Error::FieldTypeMismatch)
+    }
+}
+
+/// A `SliceDecoder` decodes a record directly from a byte slice, handing out `Bytes` and `String`
+/// fields as slices of it. Like `Decoder`, it should only be used where it is passed to the
+/// `decode_record` method of a `BorrowedDecodable`.
+pub struct SliceDecoder<'a: 'x, 'x> {
+    // `buf` is the unread remainder of the input. It is advanced as the record is decoded.
+    buf: &'x mut &'a [u8],
+
+    // `rec` is the record type that this `SliceDecoder` knows how to decode.
+    rec: &'x RecordEncoding,
+
+    // `deps` is a (full) slice of the `depends` field of the `CompleteEncoding` that `rec` is a
+    // member of.
+    deps: &'x [RecordEncoding],
+
+    // `limits` are the limits that the record is checked against as it's decoded.
+    limits: &'x DecodeLimits,
+
+    // `depth` is how deeply `rec` is nested in the outermost record being decoded.
+    depth: usize,
+}
+
+/// `decode_slice` decodes a record from the front of `buf` into `d`, according to `enc`. On
+/// success, `buf` is advanced past the end of the record.
+pub fn decode_slice<'a, 'x, D>(enc: &'x CompleteEncoding, buf: &'x mut &'a [u8], d: &mut D)
+    -> Result<(), Error>
+    where D: BorrowedDecodable<'a> {
+
+    decode_slice_with_limits(enc, buf, d, &UNLIMITED)
+}
+
+/// `decode_slice_with_limits` is like `decode_slice`, but fails with `CountLimitExceeded` or
+/// `DepthLimitExceeded` if the record exceeds `limits`. Nothing is copied out of `buf`, so
+/// `max_bytes` and `max_length` don't apply.
+pub fn decode_slice_with_limits<'a, 'x, D>(enc:    &'x CompleteEncoding,
+                                           buf:    &'x mut &'a [u8],
+                                           d:      &mut D,
+                                           limits: &'x DecodeLimits) -> Result<(), Error>
+    where D: BorrowedDecodable<'a> {
+
+    let mut dec = SliceDecoder {
+        buf:    buf,
+        rec:    &enc.target,
+        deps:   &enc.depends[..],
+        limits: limits,
+        depth:  0,
+    };
+
+    dec.decode(d)
+}
+
+impl<'a, 'x> SliceDecoder<'a, 'x> {
+    /// `record_encoding` returns the encoding of the record type that this `SliceDecoder` decodes.
+    pub fn record_encoding(&self) -> &'x RecordEncoding {
+        self.rec
+    }
+
+    /// `decode` decodes the next record in the buffer into `d`.
+    pub fn decode<D>(&mut self, d: &mut D) -> Result<(), Error>
+        where D: BorrowedDecodable<'a> {
+
+        use encoding::Quantifier::*;
+
+        for req_field in self.rec.req_fields.iter() {
+            try!(self.decode_array(d, req_field, 0));
+        }
+
+        let rec = self.rec;
+
+        let mut opt_rep_itr = rec.opt_rep_fields.iter();
+        let mut next_field  = opt_rep_itr.next();
+        let mut next_id     = FieldID(try!(read_uvarint(self.buf)));
+
+        let mut arms: Vec<&str> = vec![];
+
+        while next_id != FieldID(0) {
+            match next_field {
+                Some(field) => if field.id < next_id {
+                    next_field = opt_rep_itr.next();
+                } else if field.id > next_id {
+                    try!(self.skip_field());
+                    next_id = FieldID(try!(read_uvarint(self.buf)));
+                } else {
+                    if let Some(ref union) = field.union {
+                        if arms.contains(&&union[..]) {
+                            return Err(Error::UnionConflict(union.clone()));
+                        }
+
+                        arms.push(&union[..]);
+                        try!(d.set_union_arm(&union[..], field.id));
+                    }
+
+                    match field.quant {
+                        Required => return Err(Error::EncodingInvalid),
+                        Optional => try!(self.decode_optional(d, field)),
+                        Repeated => try!(self.decode_repeated(d, field)),
+                    }
+                    next_field = opt_rep_itr.next();
+                    next_id    = FieldID(try!(read_uvarint(self.buf)));
+                },
+                None => {
+                    try!(self.skip_field());
+                    next_id = FieldID(try!(read_uvarint(self.buf)));
+                },
+            }
+        }
+
+        for union in rec.unions().into_iter() {
+            if !arms.contains(&union) {
+                return Err(Error::UnionEmpty(union.to_string()));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn skip_field(&mut self) -> Result<(), Error> {
+        let len = try!(read_uvarint(self.buf)) as usize;
+        take(self.buf, len).map(|_| ())
+    }
+
+    fn child<'y>(&'y mut self, index: usize) -> Result<SliceDecoder<'a, 'y>, Error> {
+        if self.depth >= self.limits.max_depth {
+            return Err(Error::DepthLimitExceeded);
+        }
+
+        if index < self.deps.len() {
+            Ok( SliceDecoder {
+                buf:    &mut *self.buf,
+                rec:    &self.deps[index],
+                deps:   self.deps,
+                limits: self.limits,
+                depth:  self.depth + 1,
+            })
+        } else {
+            Err(Error::EncodingInvalid)
+        }
+    }
+
+    fn decode_optional<D>(&mut self, d: &mut D, f: &FieldEncoding) -> Result<(), Error>
+        where D: BorrowedDecodable<'a> {
+
+        let size = try!(read_uvarint(self.buf)) as usize;
+
+        if !try!(d.alloc_field(f.id, 1)) {
+            return take(self.buf, size).map(|_| ());
+        }
+
+        self.decode_array(d, f, 0)
+    }
+
+    fn decode_repeated<D>(&mut self, d: &mut D, f: &FieldEncoding) -> Result<(), Error>
+        where D: BorrowedDecodable<'a> {
+
+        let size  = try!(read_uvarint(self.buf)) as usize;
+        let start = self.buf.len();
+        let len   = try!(read_uvarint(self.buf));
+
+        if len > self.limits.max_count {
+            return Err(Error::CountLimitExceeded(len));
+        }
+
+        let len = len as usize;
+
+        if !try!(d.alloc_field(f.id, len)) {
+            // The size prefix counts the element count too, which we've already read.
+            let counted = start - self.buf.len();
+            return match size.checked_sub(counted) {
+                Some(rest) => take(self.buf, rest).map(|_| ()),
+                None       => Err(Error::EncodingInvalid),
+            };
+        }
+
+        for idx in 0..len {
+            try!(self.decode_array(d, f, idx));
+        }
+
+        Ok(())
+    }
+
+    fn decode_array<D>(&mut self, d: &mut D, f: &FieldEncoding, idx: usize) -> Result<(), Error>
+        where D: BorrowedDecodable<'a> {
+
+        if let Some(max) = f.bounds {
+            for arr_index in 0..max {
+                try!(self.decode_field(d, f, idx*max + arr_index));
+            }
+        } else {
+            try!(self.decode_field(d, f, idx));
+        }
+
+        Ok(())
+    }
+
+    fn decode_field<D>(&mut self, d: &mut D, f: &FieldEncoding, idx: usize) -> Result<(), Error>
+        where D: BorrowedDecodable<'a> {
+
+        self.decode_value(d, f.id, f.typ, idx)
+    }
+
+    fn decode_value<D>(&mut self, d: &mut D, id: FieldID, typ: Type, idx: usize)
+        -> Result<(), Error>
+        where D: BorrowedDecodable<'a> {
+
+        match typ {
+            // Records work a little differently. Create a child decoder and have the
+            // `BorrowedDecodable` run it on its own record field.
+            Type::Record{index: dep_index} => {
+                let child = try!(self.child(dep_index));
+                d.decode_record(child, id, idx)
+            },
+
+            Type::Map{index: entry_index} => self.decode_entry(d, id, entry_index, idx),
+
+            typ => {
+                let prim = try!(read_primitive(self.buf, typ));
+                d.set_primitive(id, idx, prim)
+            },
+        }
+    }
+
+    fn decode_entry<D>(&mut self, d: &mut D, id: FieldID, entry_index: usize, idx: usize)
+        -> Result<(), Error>
+        where D: BorrowedDecodable<'a> {
+
+        let deps = self.deps;
+
+        let (key, value) = match deps.get(entry_index).and_then(|rec| rec.map_entry()) {
+            Some(entry) => entry,
+            None        => return Err(Error::EncodingInvalid),
+        };
+
+        let prim = try!(read_primitive(self.buf, key.typ));
+        try!(d.set_map_key(id, idx, prim));
+        try!(self.decode_value(d, id, value.typ, idx));
+
+        loop {
+            match try!(read_uvarint(self.buf)) {
+                0 => return Ok(()),
+                _ => try!(self.skip_field()),
+            }
+        }
+    }
+}
+
+/// `read_primitive` reads a single value of the primitive type `typ` from the front of `buf`,
+/// advancing `buf` past it. It fails with `FieldTypeMismatch` if `typ` is a record type.
+pub fn read_primitive<'a>(buf: &mut &'a [u8], typ: Type) -> Result<BorrowedPrimitive<'a>, Error> {
+    Ok( match typ {
+        Type::UInt8  => BorrowedPrimitive::UInt8(try!(read_u8(buf))),
+        Type::UInt16 => BorrowedPrimitive::UInt16(try!(read_le_u16(buf))),
+        Type::UInt32 => BorrowedPrimitive::UInt32(try!(read_uvarint(buf)) as u32),
+        Type::UInt64 => BorrowedPrimitive::UInt64(try!(read_uvarint(buf))),
+
+        Type::Int8  => BorrowedPrimitive::Int8(try!(read_i8(buf))),
+        Type::Int16 => BorrowedPrimitive::Int16(try!(read_le_i16(buf))),
+        Type::Int32 => BorrowedPrimitive::Int32(try!(read_varint(buf)) as i32),
+        Type::Int64 => BorrowedPrimitive::Int64(try!(read_varint(buf))),
+
+        Type::Fixed32 => BorrowedPrimitive::Fixed32(try!(read_le_u32(buf))),
+        Type::Fixed64 => BorrowedPrimitive::Fixed64(try!(read_le_u64(buf))),
+
+        Type::Float32 => BorrowedPrimitive::Float32(try!(read_le_f32(buf))),
+        Type::Float64 => BorrowedPrimitive::Float64(try!(read_le_f64(buf))),
+
+        Type::Bool => BorrowedPrimitive::Bool(match try!(read_u8(buf)) {
+            0xFF => true,
+            0x00 => false,
+            _    => return Err(Error::BadBool),
+        }),
+
+        Type::Bytes => BorrowedPrimitive::Bytes({
+            let len = try!(read_uvarint(buf)) as usize;
+            try!(take(buf, len))
+        }),
+
+        Type::String => BorrowedPrimitive::String({
+            let len = try!(read_uvarint(buf)) as usize;
+            let utf8 = try!(take(buf, len));
+
+            match str::from_utf8(utf8) {
+                Ok(s)  => s,
+                // Go through `String` to get an error of the type that `Error` expects.
+                Err(_) => return Err(Error::Utf8Error(
+                    String::from_utf8(utf8.to_vec()).unwrap_err())),
+            }
+        }),
+
+        Type::Enum => BorrowedPrimitive::Enum(try!(read_varint(buf))),
+
+        Type::Record{..} | Type::Map{..} => return Err(Error::FieldTypeMismatch),
+    })
+}
+
+/// `take` splits `len` bytes off the front of `buf`.
+pub fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+    let all: &'a [u8] = *buf;
+
+    if all.len() < len {
+        return Err(Error::EOF);
+    }
+
+    *buf = &all[len..];
+
+    Ok(&all[..len])
+}
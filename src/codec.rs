@@ -0,0 +1,220 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use decoder;
+use decoder::read_uvarint;
+use encoder::write_uvarint;
+
+/// `NONE` is the id of the `Identity` codec, which doesn't compress at all.
+pub const NONE: u64 = 0;
+
+/// `LZ` is the id of the `Lz` codec.
+pub const LZ: u64 = 1;
+
+/// A `Codec` compresses encoded records. Each codec has an id, which is recorded in the headers of
+/// containers, streams and archives so that readers know how to decompress their contents.
+///
+/// Ids below 64 are reserved for codecs built into this library; custom codecs should use larger
+/// ids.
+pub trait Codec {
+    /// `id` returns the id which identifies this codec in file headers.
+    fn id(&self) -> u64;
+
+    /// `compress` returns a compressed copy of `data`.
+    fn compress(&self, data: &[u8]) -> Vec<u8>;
+
+    /// `decompress` reverses `compress`. It fails with `BadCompression` if `data` wasn't produced
+    /// by this codec, and with `SizeLimitExceeded` if it would decompress to more than `max_len`
+    /// bytes. Since `data` may come from an untrusted source, the size should be checked before
+    /// anything is inflated.
+    fn decompress(&self, data: &[u8], max_len: u64) -> Result<Vec<u8>, decoder::Error>;
+}
+
+/// `find` returns the built-in codec with id `id`, if there is one. The `Lz` codec is only
+/// available with the `lz` feature.
+pub fn find(id: u64) -> Option<Box<Codec>> {
+    match id {
+        NONE => Some(Box::new(Identity)),
+        LZ   => lz_codec(),
+        _    => None,
+    }
+}
+
+#[cfg(feature = "lz")]
+fn lz_codec() -> Option<Box<Codec>> {
+    Some(Box::new(Lz))
+}
+
+#[cfg(not(feature = "lz"))]
+fn lz_codec() -> Option<Box<Codec>> {
+    None
+}
+
+/// The `Identity` codec stores data uncompressed.
+#[derive(Copy,Clone,Debug)]
+pub struct Identity;
+
+impl Codec for Identity {
+    fn id(&self) -> u64 {
+        NONE
+    }
+
+    fn compress(&self, data: &[u8]) -> Vec<u8> {
+        data.to_vec()
+    }
+
+    fn decompress(&self, data: &[u8], max_len: u64) -> Result<Vec<u8>, decoder::Error> {
+        if data.len() as u64 > max_len {
+            return Err(decoder::Error::SizeLimitExceeded);
+        }
+
+        Ok(data.to_vec())
+    }
+}
+
+/// The `Lz` codec is a simple LZ77 compressor. It's fast rather than thorough, which suits the
+/// long runs of repeated structure in mesh and animation data. Only available with the `lz`
+/// feature.
+///
+/// The compressed form is the length of the uncompressed data as a varint, followed by a sequence
+/// of varint tokens. A token `t` with the low bit clear is followed by `t >> 1` literal bytes. A
+/// token with the low bit set is followed by a varint distance, and copies `(t >> 1) + MIN_MATCH`
+/// bytes starting that far back in the output.
+#[cfg(feature = "lz")]
+#[derive(Copy,Clone,Debug)]
+pub struct Lz;
+
+// The shortest match which is worth encoding.
+#[cfg(feature = "lz")]
+const MIN_MATCH: usize = 4;
+
+// The number of bits in a hash table index.
+#[cfg(feature = "lz")]
+const HASH_BITS: usize = 12;
+
+#[cfg(feature = "lz")]
+fn hash(bytes: &[u8]) -> usize {
+    let x = (bytes[0] as u64)
+          | (bytes[1] as u64) << 8
+          | (bytes[2] as u64) << 16
+          | (bytes[3] as u64) << 24;
+
+    // Knuth's multiplicative hash. `x` is only 32 bits, so this can't overflow.
+    ((x * 2654435761) >> (32 - HASH_BITS)) as usize & ((1 << HASH_BITS) - 1)
+}
+
+#[cfg(feature = "lz")]
+fn write_literals(out: &mut Vec<u8>, lits: &[u8]) {
+    if !lits.is_empty() {
+        // Writes to a `Vec` can't fail.
+        let _ = write_uvarint(out, (lits.len() as u64) << 1);
+        out.extend(lits.iter().cloned());
+    }
+}
+
+#[cfg(feature = "lz")]
+impl Codec for Lz {
+    fn id(&self) -> u64 {
+        LZ
+    }
+
+    fn compress(&self, data: &[u8]) -> Vec<u8> {
+        let mut out = vec![];
+        let _ = write_uvarint(&mut out, data.len() as u64);
+
+        // `table` maps the hash of 4 bytes to one more than the last position they were seen at,
+        // with 0 meaning never.
+        let mut table = vec![0usize; 1 << HASH_BITS];
+
+        let mut lit_start = 0;
+        let mut i         = 0;
+
+        while i + MIN_MATCH <= data.len() {
+            let h    = hash(&data[i..]);
+            let cand = table[h];
+            table[h] = i + 1;
+
+            if cand > 0 && data[cand - 1 .. cand - 1 + MIN_MATCH] == data[i .. i + MIN_MATCH] {
+                let start = cand - 1;
+                let mut len = MIN_MATCH;
+
+                while i + len < data.len() && data[start + len] == data[i + len] {
+                    len += 1;
+                }
+
+                write_literals(&mut out, &data[lit_start..i]);
+                let _ = write_uvarint(&mut out, ((len - MIN_MATCH) as u64) << 1 | 1);
+                let _ = write_uvarint(&mut out, (i - start) as u64);
+
+                i        += len;
+                lit_start = i;
+            } else {
+                i += 1;
+            }
+        }
+
+        write_literals(&mut out, &data[lit_start..]);
+
+        out
+    }
+
+    fn decompress(&self, data: &[u8], max_len: u64) -> Result<Vec<u8>, decoder::Error> {
+        let mut rd = data;
+
+        // The length is checked before anything is inflated, since it bounds the output below.
+        let len = try!(read_uvarint(&mut rd));
+        if len > max_len {
+            return Err(decoder::Error::SizeLimitExceeded);
+        }
+
+        let len     = len as usize;
+        let mut out = Vec::new();
+
+        while !rd.is_empty() {
+            let tok = try!(read_uvarint(&mut rd));
+            let n   = (tok >> 1) as usize;
+
+            if tok & 1 == 0 {
+                if n > rd.len() || out.len() + n > len {
+                    return Err(decoder::Error::BadCompression);
+                }
+
+                out.extend(rd[..n].iter().cloned());
+                rd = &rd[n..];
+            } else {
+                let n    = n + MIN_MATCH;
+                let dist = try!(read_uvarint(&mut rd)) as usize;
+
+                if dist == 0 || dist > out.len() || out.len() + n > len {
+                    return Err(decoder::Error::BadCompression);
+                }
+
+                // The source and destination may overlap, so this has to go a byte at a time.
+                for _ in 0..n {
+                    let b = out[out.len() - dist];
+                    out.push(b);
+                }
+            }
+        }
+
+        if out.len() != len {
+            return Err(decoder::Error::BadCompression);
+        }
+
+        Ok(out)
+    }
+}
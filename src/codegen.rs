@@ -0,0 +1,914 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use compiler;
+use compiler::ast::{File, EnumDecl, RecordDecl, FieldDecl};
+use encoding::{CompleteEncoding, RecordEncoding, FieldEncoding, Type, Quantifier};
+use primitive;
+use primitive::Primitive;
+
+/// `Error` is used to report errors that occur while generating code from a .rex file.
+#[derive(Debug)]
+pub enum Error {
+    /// `Compile` indicates that the .rex file could not be parsed or compiled.
+    Compile(compiler::Error),
+
+    /// `IoError` is used to pass through `std::io` errors from reading the schema or writing the
+    /// generated code.
+    IoError(io::Error),
+}
+
+impl error::FromError<compiler::Error> for Error {
+    fn from_error(err: compiler::Error) -> Error {
+        Error::Compile(err)
+    }
+}
+
+impl error::FromError<io::Error> for Error {
+    fn from_error(err: io::Error) -> Error {
+        Error::IoError(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Compile(ref err) => write!(f, "{}", err),
+            Error::IoError(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Compile(ref err) => error::Error::description(err),
+            Error::IoError(ref err) => error::Error::description(err),
+        }
+    }
+}
+
+/// `generate_file` reads the .rex file at `src` and writes Rust code for it to `dst`. It's
+/// intended to be called from a build script, e.g.
+///
+/// ```ignore
+/// let out = Path::new(&env::var("OUT_DIR").unwrap()).join("assets.rs");
+/// rex::codegen::generate_file(Path::new("src/assets.rex"), &out).unwrap();
+/// ```
+///
+/// and the result pulled in with `include!(concat!(env!("OUT_DIR"), "/assets.rs"))`.
+pub fn generate_file(src: &Path, dst: &Path) -> Result<(), Error> {
+    let mut text = String::new();
+    try!(try!(fs::File::open(src)).read_to_string(&mut text));
+
+    let code = try!(generate(&text[..]));
+
+    try!(try!(fs::File::create(dst)).write_all(code.as_bytes()));
+
+    Ok(())
+}
+
+/// `generate` compiles the text of a .rex file and returns Rust source for it. The generated code
+/// contains:
+///
+/// * An enum for each `enum` declaration, with `from_i64`/`to_i64` conversions.
+/// * A struct for each `record` declaration. Required fields are stored directly, `opt` fields as
+///   `Option`s and `rep` fields as `Vec`s. Array fields are flattened into a `Vec` holding all of
+///   their elements. Map fields become a `Vec` of `(key, value)` pairs, in the order they were
+///   encoded.
+/// * An enum for each `union` block, named after the record and the union (so `shape` in
+///   `Collider` becomes `ColliderShape`), with a variant for each member. The struct holds it in a
+///   member named after the union.
+/// * `Encodable` and `Decodable` impls for each struct. Array and repeated fields of fixed-width
+///   types are encoded and decoded in bulk, through `get_slice` and `slice_mut`. Optional fields
+///   with a default are set to it when they're absent from the input.
+/// * An `encoding()` function on each struct, returning its `CompleteEncoding`.
+///
+/// The generated code refers to this crate as `::rex`.
+pub fn generate(src: &str) -> Result<String, compiler::Error> {
+    let file = try!(compiler::parse(src));
+    let encs = try!(compiler::compile(&file));
+
+    let mut gen = Gen {
+        file: &file,
+        out:  String::new(),
+    };
+
+    try!(gen.gen_file(&encs[..]));
+
+    Ok(gen.out)
+}
+
+struct Gen<'x> {
+    file: &'x File,
+    out:  String,
+}
+
+// A member of the struct generated for a record: either a field, or a union and its members.
+enum Member<'x> {
+    Field(&'x FieldDecl),
+    Union(&'x str, Vec<&'x FieldDecl>),
+}
+
+// The members of the struct for `rec`, in declaration order. Each union goes where its first
+// member was declared.
+fn members(rec: &RecordDecl) -> Vec<Member> {
+    let mut members = vec![];
+
+    for f in rec.fields.iter() {
+        let union = match f.union {
+            Some(ref u) => &u[..],
+            None        => { members.push(Member::Field(f)); continue; },
+        };
+
+        let seen = members.iter().any(|m| match *m {
+            Member::Union(u, _) => u == union,
+            _                   => false,
+        });
+
+        if !seen {
+            let arms = rec.fields.iter().filter(|g| g.union.as_ref() == f.union.as_ref()).collect();
+            members.push(Member::Union(union, arms));
+        }
+    }
+
+    members
+}
+
+// How the type of a field is represented in Rust.
+enum Kind<'x> {
+    Builtin(Type),
+    Enum(&'x EnumDecl),
+    Record(&'x RecordDecl),
+}
+
+impl<'x> Gen<'x> {
+    fn line(&mut self, indent: usize, text: &str) {
+        for _ in 0..indent {
+            self.out.push_str("    ");
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    // The kind of the elements of `f`. For map fields, this is the kind of the values.
+    fn kind(&self, f: &FieldDecl) -> Kind<'x> {
+        self.kind_of(&f.typ[..])
+    }
+
+    fn kind_of(&self, name: &str) -> Kind<'x> {
+        if let Some(t) = compiler::builtin_type(name) {
+            Kind::Builtin(t)
+        } else if let Some(e) = self.file.find_enum(name) {
+            Kind::Enum(e)
+        } else {
+            // `compiler::compile` has already checked that the type exists.
+            Kind::Record(self.file.find_record(name).unwrap())
+        }
+    }
+
+    fn gen_file(&mut self, encs: &[CompleteEncoding]) -> Result<(), compiler::Error> {
+        let file = self.file;
+
+        self.line(0, "// This file was generated by rex. Do not edit it by hand.");
+        self.line(0, "");
+
+        for e in file.enums.iter() {
+            try!(self.enum_decl(e));
+        }
+
+        for (rec, enc) in file.records.iter().zip(encs.iter()) {
+            self.record_decl(rec, enc);
+        }
+
+        Ok(())
+    }
+
+    fn enum_decl(&mut self, e: &EnumDecl) -> Result<(), compiler::Error> {
+        if e.values.is_empty() {
+            return Err(compiler::Error::new(e.pos,
+                format!("enum '{}' must have at least one value", e.name)));
+        }
+
+        let mut seen: HashMap<i64, &str> = HashMap::new();
+
+        for val in e.values.iter() {
+            if let Some(prev) = seen.insert(val.value, &val.name[..]) {
+                return Err(compiler::Error::new(val.pos,
+                    format!("'{}.{}' has the same value as '{}.{}'", e.name, val.name, e.name, prev)));
+            }
+        }
+
+        self.line(0, "#[derive(Copy,Clone,Debug,PartialEq,Eq)]");
+        self.line(0, "#[repr(i64)]");
+        self.line(0, &format!("pub enum {} {{", e.name)[..]);
+        for val in e.values.iter() {
+            self.line(1, &format!("{} = {},", val.name, val.value)[..]);
+        }
+        self.line(0, "}");
+        self.line(0, "");
+
+        self.line(0, &format!("impl {} {{", e.name)[..]);
+        self.line(1, &format!("pub fn from_i64(x: i64) -> Option<{}> {{", e.name)[..]);
+        self.line(2, "match x {");
+        for val in e.values.iter() {
+            self.line(3, &format!("{} => Some({}::{}),", val.value, e.name, val.name)[..]);
+        }
+        self.line(3, "_ => None,");
+        self.line(2, "}");
+        self.line(1, "}");
+        self.line(0, "");
+        self.line(1, "pub fn to_i64(&self) -> i64 {");
+        self.line(2, "*self as i64");
+        self.line(1, "}");
+        self.line(0, "}");
+        self.line(0, "");
+
+        self.line(0, &format!("impl Default for {} {{", e.name)[..]);
+        self.line(1, &format!("fn default() -> {} {{", e.name)[..]);
+        self.line(2, &format!("{}::{}", e.name, e.values[0].name)[..]);
+        self.line(1, "}");
+        self.line(0, "}");
+        self.line(0, "");
+
+        Ok(())
+    }
+
+    // The Rust type of a value of the .rex type `name`.
+    fn type_name(&self, name: &str) -> String {
+        match self.kind_of(name) {
+            Kind::Builtin(t) => rust_type(t).to_string(),
+            Kind::Enum(e)    => e.name.clone(),
+            Kind::Record(r)  => r.name.clone(),
+        }
+    }
+
+    // The Rust type of one member of `f`, which is an element or a whole array.
+    fn arm_type(&self, f: &FieldDecl) -> String {
+        let elem = self.type_name(&f.typ[..]);
+        if f.bounds.is_empty() { elem } else { format!("Vec<{}>", elem) }
+    }
+
+    // The Rust type of the struct member which holds `f`.
+    fn field_type(&self, f: &FieldDecl) -> String {
+        if let Some(ref key) = f.key {
+            return format!("Vec<({}, {})>", self.type_name(&key[..]), self.type_name(&f.typ[..]));
+        }
+
+        let arr = self.arm_type(f);
+
+        match f.quant {
+            Quantifier::Required => arr,
+            Quantifier::Optional => format!("Option<{}>", arr),
+            Quantifier::Repeated => format!("Vec<{}>", arr),
+        }
+    }
+
+    // An expression which creates a default value for one element of an optional or repeated
+    // field, or for a required field.
+    fn default_value(&self, f: &FieldDecl) -> String {
+        match bounds(f) {
+            Some(n) => format!("(0..{}).map(|_| Default::default()).collect()", n),
+            None    => "Default::default()".to_string(),
+        }
+    }
+
+    fn union_decl(&mut self, rec: &RecordDecl, union: &str, arms: &[&FieldDecl]) {
+        let name = union_type(rec, union);
+
+        self.line(0, "#[derive(Clone,Debug,PartialEq)]");
+        self.line(0, &format!("pub enum {} {{", name)[..]);
+        for f in arms.iter() {
+            let line = format!("{}({}),", camel_case(&f.name[..]), self.arm_type(f));
+            self.line(1, &line[..]);
+        }
+        self.line(0, "}");
+        self.line(0, "");
+
+        // The parser doesn't allow empty unions, so there's always a first arm.
+        let init = format!("{}({})", arm_path(rec, arms[0]), self.default_value(arms[0]));
+
+        self.line(0, &format!("impl Default for {} {{", name)[..]);
+        self.line(1, &format!("fn default() -> {} {{", name)[..]);
+        self.line(2, &init[..]);
+        self.line(1, "}");
+        self.line(0, "}");
+        self.line(0, "");
+    }
+
+    fn record_decl(&mut self, rec: &RecordDecl, enc: &CompleteEncoding) {
+        let members = members(rec);
+
+        for m in members.iter() {
+            if let Member::Union(union, ref arms) = *m {
+                self.union_decl(rec, union, &arms[..]);
+            }
+        }
+
+        self.line(0, "#[derive(Clone,Debug,PartialEq)]");
+        self.line(0, &format!("pub struct {} {{", rec.name)[..]);
+        for m in members.iter() {
+            let line = match *m {
+                Member::Field(f)    => format!("pub {}: {},", member(f), self.field_type(f)),
+                Member::Union(u, _) => format!("pub {}: {},", member_name(u), union_type(rec, u)),
+            };
+            self.line(1, &line[..]);
+        }
+        self.line(0, "}");
+        self.line(0, "");
+
+        self.line(0, &format!("impl Default for {} {{", rec.name)[..]);
+        self.line(1, &format!("fn default() -> {} {{", rec.name)[..]);
+        self.line(2, &format!("{} {{", rec.name)[..]);
+        for m in members.iter() {
+            let f = match *m {
+                Member::Field(f)    => f,
+                Member::Union(u, _) => {
+                    self.line(3, &format!("{}: Default::default(),", member_name(u))[..]);
+                    continue;
+                }
+            };
+
+            let init = match f.quant {
+                _ if f.key.is_some() => "Vec::new()".to_string(),
+                Quantifier::Required => self.default_value(f),
+                Quantifier::Optional => "None".to_string(),
+                Quantifier::Repeated => "Vec::new()".to_string(),
+            };
+            self.line(3, &format!("{}: {},", member(f), init)[..]);
+        }
+        self.line(2, "}");
+        self.line(1, "}");
+        self.line(0, "}");
+        self.line(0, "");
+
+        self.line(0, &format!("impl {} {{", rec.name)[..]);
+        self.line(1, &format!("/// `encoding` returns the `CompleteEncoding` for `{}`.", rec.name)[..]);
+        self.line(1, "pub fn encoding() -> ::rex::encoding::CompleteEncoding {");
+        self.complete_encoding(2, enc);
+        self.line(1, "}");
+        self.line(0, "}");
+        self.line(0, "");
+
+        self.encodable(rec);
+        self.decodable(rec);
+    }
+
+    // Whether the elements of `f` are stored contiguously, with a type that can go in a
+    // `::rex::Slice`. Single-element fields aren't worth it, and repeated arrays are stored as a
+    // `Vec` of `Vec`s.
+    fn sliceable(&self, f: &FieldDecl) -> bool {
+        let fixed = match self.kind(f) {
+            Kind::Builtin(t) => primitive::fixed_width(t).is_some(),
+            _                => false,
+        };
+
+        fixed && f.union.is_none() && match f.quant {
+            Quantifier::Repeated => f.bounds.is_empty(),
+            _                    => !f.bounds.is_empty(),
+        }
+    }
+
+    fn encodable(&mut self, rec: &RecordDecl) {
+        self.line(0, &format!("impl ::rex::Encodable for {} {{", rec.name)[..]);
+
+        self.line(1, "fn get_primitive(&self, id: ::rex::encoding::FieldID, idx: usize)");
+        self.line(2, "-> Result<::rex::Primitive, ::rex::EncError> {");
+        self.line(0, "");
+        self.line(2, "let _ = idx;");
+        self.line(0, "");
+        self.line(2, "match id.0 {");
+        for f in rec.fields.iter() {
+            let prim = match self.kind(f) {
+                Kind::Builtin(Type::Bytes)  => "Bytes(x.clone())".to_string(),
+                Kind::Builtin(Type::String) => "String(x.clone())".to_string(),
+                Kind::Builtin(t)            => format!("{}(*x)", prim_name(t)),
+                Kind::Enum(_)               => "Enum(x.to_i64())".to_string(),
+                Kind::Record(_)             => continue,
+            };
+            self.line(3, &format!("{} => match {} {{", f.id, elem_ref(rec, f, false))[..]);
+            self.line(4, &format!("Some(x) => Ok(::rex::Primitive::{}),", prim)[..]);
+            self.line(4, "None    => Err(::rex::EncError::FieldTypeMismatch),");
+            self.line(3, "},");
+        }
+        self.line(3, "_ => Err(::rex::EncError::FieldTypeMismatch),");
+        self.line(2, "}");
+        self.line(1, "}");
+        self.line(0, "");
+
+        self.line(1, "fn encode_record(&self, mut e: ::rex::Encoder, id: ::rex::encoding::FieldID, idx: usize)");
+        self.line(2, "-> Result<usize, ::rex::EncError> {");
+        self.line(0, "");
+        self.line(2, "let _ = idx;");
+        self.line(0, "");
+        self.line(2, "match id.0 {");
+        for f in rec.fields.iter() {
+            if let Kind::Record(_) = self.kind(f) {
+                self.line(3, &format!("{} => match {} {{", f.id, elem_ref(rec, f, false))[..]);
+                self.line(4, "Some(x) => e.encode(x),");
+                self.line(4, "None    => Err(::rex::EncError::FieldTypeMismatch),");
+                self.line(3, "},");
+            }
+        }
+        self.line(3, "_ => Err(::rex::EncError::FieldTypeMismatch),");
+        self.line(2, "}");
+        self.line(1, "}");
+        self.line(0, "");
+
+        self.line(1, "fn count_field(&self, id: ::rex::encoding::FieldID) -> Result<usize, ::rex::EncError> {");
+        self.line(2, "match id.0 {");
+        for f in rec.fields.iter() {
+            let count = match f.quant {
+                _ if f.key.is_some() => format!("self.{}.len()", member(f)),
+                _ if f.union.is_some() => format!("if let {}(..) = self.{} {{ 1 }} else {{ 0 }}",
+                                                  arm_path(rec, f), union_member(f)),
+                Quantifier::Required => continue,
+                Quantifier::Optional => format!("if self.{}.is_some() {{ 1 }} else {{ 0 }}", member(f)),
+                Quantifier::Repeated => format!("self.{}.len()", member(f)),
+            };
+            self.line(3, &format!("{} => Ok({}),", f.id, count)[..]);
+        }
+        self.line(3, "_ => Err(::rex::EncError::FieldTypeMismatch),");
+        self.line(2, "}");
+        self.line(1, "}");
+
+        let maps: Vec<&FieldDecl> = rec.fields.iter().filter(|f| f.key.is_some()).collect();
+
+        if !maps.is_empty() {
+            self.line(0, "");
+            self.line(1, "fn get_map_key(&self, id: ::rex::encoding::FieldID, idx: usize)");
+            self.line(2, "-> Result<::rex::Primitive, ::rex::EncError> {");
+            self.line(0, "");
+            self.line(2, "match id.0 {");
+            for f in maps.iter() {
+                let prim = match self.kind_of(&f.key.as_ref().unwrap()[..]) {
+                    Kind::Builtin(Type::Bytes)  => "Bytes(x.0.clone())".to_string(),
+                    Kind::Builtin(Type::String) => "String(x.0.clone())".to_string(),
+                    Kind::Builtin(t)            => format!("{}(x.0)", prim_name(t)),
+                    Kind::Enum(_)               => "Enum(x.0.to_i64())".to_string(),
+                    Kind::Record(_)             => unreachable!(),
+                };
+                self.line(3, &format!("{} => match self.{}.get(idx) {{", f.id, member(f))[..]);
+                self.line(4, &format!("Some(x) => Ok(::rex::Primitive::{}),", prim)[..]);
+                self.line(4, "None    => Err(::rex::EncError::FieldTypeMismatch),");
+                self.line(3, "},");
+            }
+            self.line(3, "_ => Err(::rex::EncError::FieldTypeMismatch),");
+            self.line(2, "}");
+            self.line(1, "}");
+        }
+
+        let sliceable: Vec<&FieldDecl> = rec.fields.iter().filter(|f| self.sliceable(f)).collect();
+
+        if !sliceable.is_empty() {
+            self.line(0, "");
+            self.line(1, "fn get_slice(&self, id: ::rex::encoding::FieldID) -> Option<::rex::Slice> {");
+            self.line(2, "match id.0 {");
+            for f in sliceable.iter() {
+                let t = compiler::builtin_type(&f.typ[..]).unwrap();
+                let slice = match f.quant {
+                    Quantifier::Optional => format!("self.{}.as_ref().map(|v| ::rex::Slice::{}(&v[..]))",
+                                                    member(f), prim_name(t)),
+                    _                    => format!("Some(::rex::Slice::{}(&self.{}[..]))",
+                                                    prim_name(t), member(f)),
+                };
+                self.line(3, &format!("{} => {},", f.id, slice)[..]);
+            }
+            self.line(3, "_ => None,");
+            self.line(2, "}");
+            self.line(1, "}");
+        }
+
+        self.line(0, "}");
+        self.line(0, "");
+    }
+
+    fn decodable(&mut self, rec: &RecordDecl) {
+        self.line(0, &format!("impl ::rex::Decodable for {} {{", rec.name)[..]);
+
+        self.line(1, "fn set_primitive(&mut self, id: ::rex::encoding::FieldID, idx: usize, prim: ::rex::Primitive)");
+        self.line(2, "-> Result<(), ::rex::DecError> {");
+        self.line(0, "");
+        self.line(2, "let _ = idx;");
+        self.line(0, "");
+        self.line(2, "match (id.0, prim) {");
+        for f in rec.fields.iter() {
+            match self.kind(f) {
+                Kind::Builtin(t) => {
+                    self.line(3, &format!("({}, ::rex::Primitive::{}(v)) => match {} {{",
+                                          f.id, prim_name(t), elem_ref(rec, f, true))[..]);
+                    self.line(4, "Some(x) => { *x = v; Ok(()) },");
+                    self.line(4, "None    => Err(::rex::DecError::FieldTypeMismatch),");
+                    self.line(3, "},");
+                }
+                Kind::Enum(e) => {
+                    self.line(3, &format!("({}, ::rex::Primitive::Enum(v)) => match ({}, {}::from_i64(v)) {{",
+                                          f.id, elem_ref(rec, f, true), e.name)[..]);
+                    self.line(4, "(Some(x), Some(v)) => { *x = v; Ok(()) },");
+                    self.line(4, "(Some(_), None)    => Err(::rex::DecError::BadEnum(v)),");
+                    self.line(4, "(None, _)          => Err(::rex::DecError::FieldTypeMismatch),");
+                    self.line(3, "},");
+                }
+                Kind::Record(_) => {}
+            }
+        }
+        self.line(3, "_ => Err(::rex::DecError::FieldTypeMismatch),");
+        self.line(2, "}");
+        self.line(1, "}");
+        self.line(0, "");
+
+        self.line(1, "fn decode_record<'x, R>(&mut self, mut d: ::rex::Decoder<'x, R>, id: ::rex::encoding::FieldID, idx: usize)");
+        self.line(2, "-> Result<(), ::rex::DecError> where R: ::std::io::Read + 'x {");
+        self.line(0, "");
+        self.line(2, "let _ = idx;");
+        self.line(0, "");
+        self.line(2, "match id.0 {");
+        for f in rec.fields.iter() {
+            if let Kind::Record(_) = self.kind(f) {
+                self.line(3, &format!("{} => match {} {{", f.id, elem_ref(rec, f, true))[..]);
+                self.line(4, "Some(x) => d.decode(x),");
+                self.line(4, "None    => Err(::rex::DecError::FieldTypeMismatch),");
+                self.line(3, "},");
+            }
+        }
+        self.line(3, "_ => Err(::rex::DecError::FieldTypeMismatch),");
+        self.line(2, "}");
+        self.line(1, "}");
+        self.line(0, "");
+
+        self.line(1, "fn alloc_field(&mut self, id: ::rex::encoding::FieldID, count: usize) -> Result<bool, ::rex::DecError> {");
+        self.line(2, "let _ = count;");
+        self.line(0, "");
+        self.line(2, "match id.0 {");
+        for f in rec.fields.iter() {
+            // `count` comes off the wire, so only a bounded amount is reserved for repeated fields
+            // and their elements are created as they're decoded. Sliceable fields are only filled
+            // in up front if they're small, so that the decoder falls back to `set_primitive` for
+            // larger ones (see `slice_mut`).
+            let reserve = format!("self.{}.clear(); self.{}.reserve(::std::cmp::min(count, ::rex::PREALLOC_MAX));",
+                                  member(f), member(f));
+            let alloc = match f.quant {
+                _ if f.key.is_some() => reserve,
+                _ if f.union.is_some() => format!("self.{} = {}({});",
+                                                  union_member(f), arm_path(rec, f), self.default_value(f)),
+                Quantifier::Required => continue,
+                Quantifier::Optional => format!("self.{} = Some({});", member(f), self.default_value(f)),
+                Quantifier::Repeated if self.sliceable(f) =>
+                    format!("self.{} = if count <= ::rex::PREALLOC_MAX {{ (0..count).map(|_| Default::default()).collect() }} else {{ vec![] }};",
+                            member(f)),
+                Quantifier::Repeated => reserve,
+            };
+            self.line(3, &format!("{} => {{ {} Ok(true) }},", f.id, alloc)[..]);
+        }
+        self.line(3, "_ => Ok(false),");
+        self.line(2, "}");
+        self.line(1, "}");
+
+        let maps: Vec<&FieldDecl> = rec.fields.iter().filter(|f| f.key.is_some()).collect();
+
+        if !maps.is_empty() {
+            self.line(0, "");
+            self.line(1, "fn set_map_key(&mut self, id: ::rex::encoding::FieldID, idx: usize, key: ::rex::Primitive)");
+            self.line(2, "-> Result<(), ::rex::DecError> {");
+            self.line(0, "");
+            self.line(2, "match (id.0, key) {");
+            for f in maps.iter() {
+                let m = member(f);
+                match self.kind_of(&f.key.as_ref().unwrap()[..]) {
+                    Kind::Builtin(t) => {
+                        self.line(3, &format!("({}, ::rex::Primitive::{}(v)) => match {} {{",
+                                              f.id, prim_name(t), new_entry(&m[..]))[..]);
+                        self.line(4, "Some(x) => { x.0 = v; Ok(()) },");
+                        self.line(4, "None    => Err(::rex::DecError::FieldTypeMismatch),");
+                        self.line(3, "},");
+                    }
+                    Kind::Enum(e) => {
+                        self.line(3, &format!("({}, ::rex::Primitive::Enum(v)) => match ({}, {}::from_i64(v)) {{",
+                                              f.id, new_entry(&m[..]), e.name)[..]);
+                        self.line(4, "(Some(x), Some(v)) => { x.0 = v; Ok(()) },");
+                        self.line(4, "(Some(_), None)    => Err(::rex::DecError::BadEnum(v)),");
+                        self.line(4, "(None, _)          => Err(::rex::DecError::FieldTypeMismatch),");
+                        self.line(3, "},");
+                    }
+                    Kind::Record(_) => unreachable!(),
+                }
+            }
+            self.line(3, "_ => Err(::rex::DecError::FieldTypeMismatch),");
+            self.line(2, "}");
+            self.line(1, "}");
+        }
+
+        let sliceable: Vec<&FieldDecl> = rec.fields.iter().filter(|f| self.sliceable(f)).collect();
+
+        if !sliceable.is_empty() {
+            self.line(0, "");
+            self.line(1, "fn slice_mut(&mut self, id: ::rex::encoding::FieldID) -> Option<::rex::SliceMut> {");
+            self.line(2, "match id.0 {");
+            for f in sliceable.iter() {
+                let t = compiler::builtin_type(&f.typ[..]).unwrap();
+                let slice = match f.quant {
+                    Quantifier::Optional => format!("self.{}.as_mut().map(|v| ::rex::SliceMut::{}(&mut v[..]))",
+                                                    member(f), prim_name(t)),
+                    Quantifier::Repeated => format!("if self.{}.is_empty() {{ None }} else {{ Some(::rex::SliceMut::{}(&mut self.{}[..])) }}",
+                                                    member(f), prim_name(t), member(f)),
+                    _                    => format!("Some(::rex::SliceMut::{}(&mut self.{}[..]))",
+                                                    prim_name(t), member(f)),
+                };
+                self.line(3, &format!("{} => {},", f.id, slice)[..]);
+            }
+            self.line(3, "_ => None,");
+            self.line(2, "}");
+            self.line(1, "}");
+        }
+
+        if rec.fields.iter().any(|f| f.default.is_some()) {
+            self.line(0, "");
+            self.line(1, "fn fill_defaults(&self) -> bool {");
+            self.line(2, "true");
+            self.line(1, "}");
+        }
+
+        self.line(0, "}");
+        self.line(0, "");
+    }
+
+    fn complete_encoding(&mut self, indent: usize, enc: &CompleteEncoding) {
+        self.line(indent, "::rex::encoding::CompleteEncoding {");
+        self.line(indent + 1, "target:");
+        self.record_encoding(indent + 2, &enc.target);
+        self.line(indent + 1, "depends: vec![");
+        for dep in enc.depends.iter() {
+            self.record_encoding(indent + 2, dep);
+        }
+        self.line(indent + 1, "],");
+        self.line(indent, "}");
+    }
+
+    fn record_encoding(&mut self, indent: usize, rec: &RecordEncoding) {
+        self.line(indent, "::rex::encoding::RecordEncoding {");
+        self.line(indent + 1, &format!("name: {:?}.to_string(),", rec.name)[..]);
+        self.line(indent + 1, "req_fields: vec![");
+        for f in rec.req_fields.iter() {
+            self.field_encoding(indent + 2, f);
+        }
+        self.line(indent + 1, "],");
+        self.line(indent + 1, "opt_rep_fields: vec![");
+        for f in rec.opt_rep_fields.iter() {
+            self.field_encoding(indent + 2, f);
+        }
+        self.line(indent + 1, "],");
+        self.line(indent, "},");
+    }
+
+    fn field_encoding(&mut self, indent: usize, f: &FieldEncoding) {
+        let typ = match f.typ {
+            Type::Record{index} => format!("Record{{index: {}}}", index),
+            Type::Map{index}    => format!("Map{{index: {}}}", index),
+            t                   => format!("{:?}", t),
+        };
+
+        self.line(indent, "::rex::encoding::FieldEncoding {");
+        self.line(indent + 1, &format!("id: ::rex::encoding::FieldID({}),", f.id.0)[..]);
+        self.line(indent + 1, &format!("name: {:?}.to_string(),", f.name)[..]);
+        self.line(indent + 1, &format!("quant: ::rex::encoding::Quantifier::{:?},", f.quant)[..]);
+        self.line(indent + 1, &format!("typ: ::rex::encoding::Type::{},", typ)[..]);
+        self.line(indent + 1, &format!("bounds: {:?},", f.bounds)[..]);
+        self.line(indent + 1, &match f.union {
+            Some(ref u) => format!("union: Some({:?}.to_string()),", u),
+            None        => "union: None,".to_string(),
+        }[..]);
+        self.line(indent + 1, &match f.default {
+            Some(ref p) => format!("default: Some(::rex::Primitive::{}),", prim_literal(p)),
+            None        => "default: None,".to_string(),
+        }[..]);
+        self.line(indent, "},");
+    }
+}
+
+// A Rust expression for the contents of the `Primitive` variant `p`, e.g. `Float32(1.5f32)`.
+fn prim_literal(p: &Primitive) -> String {
+    match *p {
+        // Non-finite values don't have literals, so they're written as constants instead.
+        Primitive::Float32(x) if !x.is_finite() => {
+            format!("Float32({})", float_const("f32", x as f64))
+        }
+        Primitive::Float64(x) if !x.is_finite() => {
+            format!("Float64({})", float_const("f64", x))
+        }
+
+        Primitive::Float32(x)    => format!("Float32({:?}f32)", x),
+        Primitive::Float64(x)    => format!("Float64({:?}f64)", x),
+        Primitive::Bytes(ref x)  => format!("Bytes(vec!{:?})", x),
+        Primitive::String(ref x) => format!("String({:?}.to_string())", x),
+        ref p                    => format!("{:?}", p),
+    }
+}
+
+// The path of the `std` constant for a non-finite float `x` of type `t` ("f32" or "f64").
+fn float_const(t: &str, x: f64) -> String {
+    let name = if x.is_nan() { "NAN" } else if x > 0.0 { "INFINITY" } else { "NEG_INFINITY" };
+    format!("::std::{}::{}", t, name)
+}
+
+// The product of the bounds of an array field, or `None` for non-array fields.
+fn bounds(f: &FieldDecl) -> Option<usize> {
+    if f.bounds.is_empty() {
+        None
+    } else {
+        Some(f.bounds.iter().fold(1, |x, y| x * *y))
+    }
+}
+
+// The name of the struct member for `f`.
+fn member(f: &FieldDecl) -> String {
+    member_name(&f.name[..])
+}
+
+// The name of the struct member which holds the union that `f` is a member of.
+fn union_member(f: &FieldDecl) -> String {
+    member_name(&f.union.as_ref().unwrap()[..])
+}
+
+// The name of the struct member for a field or union called `name`. Names which are Rust keywords
+// get a trailing underscore.
+fn member_name(name: &str) -> String {
+    const KEYWORDS: &'static [&'static str] = &[
+        "abstract", "alignof", "as", "be", "box", "break", "const", "continue", "crate", "do",
+        "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl", "in", "let", "loop",
+        "macro", "match", "mod", "move", "mut", "offsetof", "override", "priv", "proc", "pub",
+        "pure", "ref", "return", "self", "sizeof", "static", "struct", "super", "trait", "true",
+        "type", "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+    ];
+
+    if KEYWORDS.contains(&name) {
+        format!("{}_", name)
+    } else {
+        name.to_string()
+    }
+}
+
+// The name of the enum generated for `union` in `rec`.
+fn union_type(rec: &RecordDecl, union: &str) -> String {
+    format!("{}{}", rec.name, camel_case(union))
+}
+
+// The path to the enum variant for the union member `f`.
+fn arm_path(rec: &RecordDecl, f: &FieldDecl) -> String {
+    format!("{}::{}", union_type(rec, &f.union.as_ref().unwrap()[..]), camel_case(&f.name[..]))
+}
+
+// Convert a .rex name like `bounding_box` to `BoundingBox`.
+fn camel_case(name: &str) -> String {
+    let mut out   = String::new();
+    let mut upper = true;
+
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            // Only ASCII letters are capitalized, which covers any sensible name.
+            out.push(if c >= 'a' && c <= 'z' { (c as u8 - b'a' + b'A') as char } else { c });
+            upper = false;
+        } else {
+            out.push(c);
+        }
+    }
+
+    out
+}
+
+// An expression giving an `Option<&T>` (or `Option<&mut T>` if `mutable`) to element `idx` of
+// field `f`. The elements of a map field are the values of its entries, and a union member only
+// has elements while it's the variant held by its union.
+fn elem_ref(rec: &RecordDecl, f: &FieldDecl, mutable: bool) -> String {
+    let (get, as_ref, amp) = if mutable {
+        ("get_mut", "as_mut", "&mut ")
+    } else {
+        ("get", "as_ref", "&")
+    };
+
+    let m = member(f);
+
+    if f.union.is_some() {
+        let binding = if mutable { "ref mut" } else { "ref" };
+        let elem    = match bounds(f) {
+            Some(_) => format!("v.{}(idx)", get),
+            None    => "Some(v)".to_string(),
+        };
+
+        return format!("match self.{} {{ {}({} v) => {}, _ => None }}",
+                       union_member(f), arm_path(rec, f), binding, elem);
+    }
+
+    if f.key.is_some() {
+        return if mutable {
+            format!("{}.map(|x| &mut x.1)", new_entry(&m[..]))
+        } else {
+            format!("self.{}.get(idx).map(|x| &x.1)", m)
+        };
+    }
+
+    match (f.quant, bounds(f)) {
+        (Quantifier::Required, None)    => format!("Some({}self.{})", amp, m),
+        (Quantifier::Required, Some(_)) => format!("self.{}.{}(idx)", m, get),
+        (Quantifier::Optional, None)    => format!("self.{}.{}()", m, as_ref),
+        (Quantifier::Optional, Some(_)) => format!("self.{}.{}().and_then(|v| v.{}(idx))", m, as_ref, get),
+
+        // Elements of repeated fields are created as they're decoded; see `alloc_field`.
+        (Quantifier::Repeated, None) if mutable =>
+            format!("::rex::next_elem(&mut self.{}, idx, Default::default)", m),
+        (Quantifier::Repeated, Some(n)) if mutable =>
+            format!("::rex::next_elem(&mut self.{}, idx / {}, || (0..{}).map(|_| Default::default()).collect())\
+                     .and_then(|v| v.get_mut(idx % {}))", m, n, n, n),
+
+        (Quantifier::Repeated, None)    => format!("self.{}.get(idx)", m),
+        (Quantifier::Repeated, Some(n)) =>
+            format!("self.{}.get(idx / {}).and_then(|v| v.get(idx % {}))", m, n, n),
+    }
+}
+
+// An expression for a mutable reference to entry `idx` of the map field `m`, creating it if it's
+// the next one.
+fn new_entry(m: &str) -> String {
+    format!("::rex::next_elem(&mut self.{}, idx, Default::default)", m)
+}
+
+// The Rust type used to represent a primitive rex type.
+fn rust_type(t: Type) -> &'static str {
+    match t {
+        Type::Int8    => "i8",
+        Type::Int16   => "i16",
+        Type::Int32   => "i32",
+        Type::Int64   => "i64",
+
+        Type::UInt8   => "u8",
+        Type::UInt16  => "u16",
+        Type::UInt32  => "u32",
+        Type::UInt64  => "u64",
+
+        Type::Fixed32 => "u32",
+        Type::Fixed64 => "u64",
+
+        Type::Float32 => "f32",
+        Type::Float64 => "f64",
+
+        Type::Bytes   => "Vec<u8>",
+        Type::String  => "String",
+
+        Type::Bool    => "bool",
+
+        Type::Enum | Type::Record{..} | Type::Map{..} => unreachable!(),
+    }
+}
+
+// The name of the `Primitive` variant for a primitive rex type.
+fn prim_name(t: Type) -> &'static str {
+    match t {
+        Type::Int8    => "Int8",
+        Type::Int16   => "Int16",
+        Type::Int32   => "Int32",
+        Type::Int64   => "Int64",
+
+        Type::UInt8   => "UInt8",
+        Type::UInt16  => "UInt16",
+        Type::UInt32  => "UInt32",
+        Type::UInt64  => "UInt64",
+
+        Type::Fixed32 => "Fixed32",
+        Type::Fixed64 => "Fixed64",
+
+        Type::Float32 => "Float32",
+        Type::Float64 => "Float64",
+
+        Type::Bytes   => "Bytes",
+        Type::String  => "String",
+
+        Type::Bool    => "Bool",
+
+        Type::Enum    => "Enum",
+
+        Type::Record{..} | Type::Map{..} => unreachable!(),
+    }
+}
@@ -0,0 +1,320 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use encoding::{CompleteEncoding, RecordEncoding, FieldEncoding, FieldID, Quantifier, Type};
+use primitive::Primitive;
+
+/// `Compatibility` classifies a single change between two encodings. A change is *backward
+/// compatible* if data written with the old encoding can be read with the new one, and *forward
+/// compatible* if data written with the new encoding can be read with the old one.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Compatibility {
+    /// The change is both backward and forward compatible.
+    Full,
+
+    /// Data written with the old encoding can be read with the new one, but not vice versa.
+    Backward,
+
+    /// Data written with the new encoding can be read with the old one, but not vice versa.
+    Forward,
+
+    /// Neither encoding can read data written with the other.
+    Breaking,
+}
+
+impl Compatibility {
+    /// `and` returns the compatibility of making both the change classified by `self` and the one
+    /// classified by `other`.
+    pub fn and(self, other: Compatibility) -> Compatibility {
+        use self::Compatibility::*;
+
+        match (self, other) {
+            (Full, x) | (x, Full)    => x,
+            (Backward, Backward)     => Backward,
+            (Forward, Forward)       => Forward,
+            _                        => Breaking,
+        }
+    }
+}
+
+impl fmt::Display for Compatibility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match *self {
+            Compatibility::Full     => "compatible",
+            Compatibility::Backward => "backward compatible",
+            Compatibility::Forward  => "forward compatible",
+            Compatibility::Breaking => "breaking",
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+/// A `Change` is a single difference found between two encodings.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Change {
+    /// Path to the record or field which changed, e.g. `Mesh.submeshes.material`. Paths are given
+    /// in terms of the names in the old encoding.
+    pub path: String,
+
+    /// How the change affects compatibility.
+    pub compat: Compatibility,
+
+    /// Human readable description of the change.
+    pub msg: String,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} ({})", self.path, self.msg, self.compat)
+    }
+}
+
+/// `check` compares `old` with `new`, and returns every change it finds between them. Records are
+/// matched up by following fields with the same `FieldID` from the targets of both encodings, so
+/// renamed records are still compared with their old versions.
+pub fn check(old: &CompleteEncoding, new: &CompleteEncoding) -> Vec<Change> {
+    let mut c = Checker {
+        old:     old,
+        new:     new,
+        seen:    HashSet::new(),
+        changes: vec![],
+    };
+
+    c.check_record(old.target.name.clone(), None, None);
+
+    c.changes
+}
+
+/// `overall` returns the compatibility of making all of `changes` at once.
+pub fn overall(changes: &[Change]) -> Compatibility {
+    changes.iter().fold(Compatibility::Full, |so_far, ch| so_far.and(ch.compat))
+}
+
+struct Checker<'x> {
+    old: &'x CompleteEncoding,
+    new: &'x CompleteEncoding,
+
+    // Pairs of (old, new) records which have already been compared, as indices into `depends`
+    // (`None` is the target).
+    seen: HashSet<(Option<usize>, Option<usize>)>,
+
+    changes: Vec<Change>,
+}
+
+// Look up a record in `enc` by its index in `depends`, with `None` meaning the target.
+fn record(enc: &CompleteEncoding, index: Option<usize>) -> Option<&RecordEncoding> {
+    match index {
+        Some(i) => enc.depends.get(i),
+        None    => Some(&enc.target),
+    }
+}
+
+fn quant_name(q: Quantifier) -> &'static str {
+    match q {
+        Quantifier::Required => "required",
+        Quantifier::Optional => "optional",
+        Quantifier::Repeated => "repeated",
+    }
+}
+
+// Varint types which share a wire representation, ranked by width. Widening a field within the same
+// family only loses values when reading data written with the wider type.
+fn varint_family(t: Type) -> Option<(u8, u8)> {
+    match t {
+        Type::UInt32 => Some((0, 32)),
+        Type::UInt64 => Some((0, 64)),
+
+        Type::Int32  => Some((1, 32)),
+        Type::Int64  => Some((1, 64)),
+        Type::Enum   => Some((1, 64)),
+
+        _ => None,
+    }
+}
+
+impl<'x> Checker<'x> {
+    fn push(&mut self, path: &str, compat: Compatibility, msg: String) {
+        self.changes.push(Change {
+            path:   path.to_string(),
+            compat: compat,
+            msg:    msg,
+        });
+    }
+
+    fn check_record(&mut self, path: String, old_idx: Option<usize>, new_idx: Option<usize>) {
+        if !self.seen.insert((old_idx, new_idx)) {
+            return;
+        }
+
+        let (old_rec, new_rec) = match (record(self.old, old_idx), record(self.new, new_idx)) {
+            (Some(o), Some(n)) => (o, n),
+            _                  => {
+                self.push(&path[..], Compatibility::Breaking,
+                          "record refers to a missing dependency".to_string());
+                return;
+            }
+        };
+
+        if old_rec.name != new_rec.name {
+            self.push(&path[..], Compatibility::Full,
+                      format!("record '{}' renamed to '{}'", old_rec.name, new_rec.name));
+        }
+
+        let new_fields: HashMap<FieldID, &FieldEncoding> = new_rec.req_fields.iter()
+            .chain(new_rec.opt_rep_fields.iter())
+            .map(|f| (f.id, f))
+            .collect();
+
+        let mut old_ids = HashSet::new();
+
+        for of in old_rec.req_fields.iter().chain(old_rec.opt_rep_fields.iter()) {
+            old_ids.insert(of.id);
+
+            let fpath = format!("{}.{}", path, of.name);
+
+            match new_fields.get(&of.id) {
+                Some(nf) => self.check_field(fpath, of, *nf),
+
+                None if of.quant == Quantifier::Required =>
+                    self.push(&fpath[..], Compatibility::Breaking,
+                              format!("required field {} removed", of.id.0)),
+
+                // Old data might only have the removed member set, and then the new encoding
+                // would find none.
+                None if of.union.is_some() =>
+                    self.push(&fpath[..], Compatibility::Forward,
+                              format!("union member {} removed", of.id.0)),
+
+                None =>
+                    self.push(&fpath[..], Compatibility::Full,
+                              format!("{} field {} removed", quant_name(of.quant), of.id.0)),
+            }
+        }
+
+        for nf in new_rec.req_fields.iter().chain(new_rec.opt_rep_fields.iter()) {
+            if old_ids.contains(&nf.id) {
+                continue;
+            }
+
+            let fpath = format!("{}.{}", path, nf.name);
+
+            if nf.union.is_some() {
+                self.push(&fpath[..], Compatibility::Backward,
+                          format!("union member {} added", nf.id.0));
+                continue;
+            }
+
+            let compat = if nf.quant == Quantifier::Required {
+                Compatibility::Breaking
+            } else {
+                Compatibility::Full
+            };
+
+            self.push(&fpath[..], compat,
+                      format!("{} field {} added", quant_name(nf.quant), nf.id.0));
+        }
+    }
+
+    fn check_field(&mut self, path: String, of: &FieldEncoding, nf: &FieldEncoding) {
+        if of.name != nf.name {
+            self.push(&path[..], Compatibility::Full,
+                      format!("field {} renamed to '{}'", of.id.0, nf.name));
+        }
+
+        // Required fields are written without a tag, and repeated fields carry a count, so any
+        // change of quantifier changes the layout on the wire.
+        if of.quant != nf.quant {
+            let msg = match (of.quant, nf.quant) {
+                (Quantifier::Optional, Quantifier::Required) =>
+                    "optional field promoted to required".to_string(),
+                (Quantifier::Required, Quantifier::Optional) =>
+                    "required field demoted to optional".to_string(),
+                (o, n) =>
+                    format!("field changed from {} to {}", quant_name(o), quant_name(n)),
+            };
+
+            self.push(&path[..], Compatibility::Breaking, msg);
+        }
+
+        if of.union != nf.union {
+            let show = |u: &Option<String>| match *u {
+                Some(ref u) => format!("union {}", u),
+                None        => "no union".to_string(),
+            };
+
+            self.push(&path[..], Compatibility::Breaking,
+                      format!("moved from {} to {}", show(&of.union), show(&nf.union)));
+        }
+
+        if of.bounds != nf.bounds {
+            let show = |b: Option<usize>| match b {
+                Some(n) => format!("[{}]", n),
+                None    => "no bounds".to_string(),
+            };
+
+            self.push(&path[..], Compatibility::Breaking,
+                      format!("bounds changed from {} to {}", show(of.bounds), show(nf.bounds)));
+        }
+
+        // Absent fields are read as their default, and may have been left out because they were
+        // equal to it, so readers using different defaults disagree about the value.
+        if of.default != nf.default {
+            let show = |d: &Option<Primitive>| match *d {
+                Some(ref p) => format!("{:?}", p),
+                None        => "none".to_string(),
+            };
+
+            self.push(&path[..], Compatibility::Breaking,
+                      format!("default changed from {} to {}",
+                              show(&of.default), show(&nf.default)));
+        }
+
+        match (of.typ, nf.typ) {
+            // A map is written as a repeated field of entry records, so a map and a repeated record
+            // field with the same layout as its entries can read each other's data.
+            (Type::Record{index: o}, Type::Record{index: n}) |
+            (Type::Map{index: o}, Type::Map{index: n})       |
+            (Type::Record{index: o}, Type::Map{index: n})    |
+            (Type::Map{index: o}, Type::Record{index: n})    =>
+                self.check_record(path, Some(o), Some(n)),
+
+            (o, n) if o == n => (),
+
+            (o, n) => {
+                let compat = match (varint_family(o), varint_family(n)) {
+                    (Some((fam_o, wo)), Some((fam_n, wn))) if fam_o == fam_n => {
+                        if wo == wn {
+                            Compatibility::Full
+                        } else if wo < wn {
+                            Compatibility::Backward
+                        } else {
+                            Compatibility::Forward
+                        }
+                    }
+                    _ => Compatibility::Breaking,
+                };
+
+                self.push(&path[..], compat,
+                          format!("type of field {} changed from {:?} to {:?}", of.id.0, o, n));
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::fmt;
+
+use encoding::Quantifier;
+
+/// A `Pos` gives a (1-based) line and column within a .rex file.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct Pos {
+    /// Line number, starting at 1.
+    pub line: usize,
+
+    /// Column number (in characters), starting at 1.
+    pub col: usize,
+}
+
+impl fmt::Display for Pos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+/// A `File` is the parsed (but unresolved) contents of a .rex file.
+#[derive(Clone,Debug)]
+pub struct File {
+    /// The name given in the `package` declaration, if there was one.
+    pub package: Option<String>,
+
+    /// Enum declarations, in the order they appear in the file.
+    pub enums: Vec<EnumDecl>,
+
+    /// Record declarations, in the order they appear in the file.
+    pub records: Vec<RecordDecl>,
+}
+
+impl File {
+    /// `find_enum` returns the enum declaration with the given name, if there is one.
+    pub fn find_enum(&self, name: &str) -> Option<&EnumDecl> {
+        self.enums.iter().find(|e| e.name == name)
+    }
+
+    /// `find_record` returns the record declaration with the given name, if there is one.
+    pub fn find_record(&self, name: &str) -> Option<&RecordDecl> {
+        self.records.iter().find(|r| r.name == name)
+    }
+}
+
+/// An `EnumDecl` is an `enum Name { ... }` declaration.
+#[derive(Clone,Debug)]
+#[allow(missing_docs)]
+pub struct EnumDecl {
+    pub name:   String,
+    pub values: Vec<EnumValue>,
+    pub pos:    Pos,
+}
+
+/// An `EnumValue` is a single `Name = value` line of an enum declaration.
+#[derive(Clone,Debug)]
+#[allow(missing_docs)]
+pub struct EnumValue {
+    pub name:  String,
+    pub value: i64,
+    pub pos:   Pos,
+}
+
+/// A `RecordDecl` is a `record Name { ... }` declaration.
+#[derive(Clone,Debug)]
+#[allow(missing_docs)]
+pub struct RecordDecl {
+    pub name:   String,
+    pub fields: Vec<FieldDecl>,
+    pub pos:    Pos,
+}
+
+/// A `FieldDecl` is a single field of a record declaration, e.g.
+///
+///     3 matrices : rep [3][3]float32
+///     4 bones    : map<string, Bone>
+///     5 scale    : opt float32 = 1.0
+#[derive(Clone,Debug)]
+#[allow(missing_docs)]
+pub struct FieldDecl {
+    pub id:    u64,
+    pub name:  String,
+    pub quant: Quantifier,
+
+    /// The array bounds of this field, outermost first. Empty for non-array fields.
+    pub bounds: Vec<usize>,
+
+    /// For a map field, the name of the key type. `typ` is then the value type.
+    pub key: Option<String>,
+
+    /// The name of the field's type as it was written, e.g. `float32` or `FieldEncoding`.
+    pub typ: String,
+
+    /// For a member of a `union { ... }` block, the name of the union. Union members are always
+    /// optional.
+    pub union: Option<String>,
+
+    /// The literal given after '=' as the default value of an optional field, and its position.
+    pub default: Option<(Literal, Pos)>,
+
+    pub pos:     Pos,
+    pub typ_pos: Pos,
+}
+
+/// A `Literal` is a constant written in a .rex file. Its type isn't known until it's checked
+/// against the type of the field it belongs to.
+#[derive(Clone,Debug,PartialEq)]
+pub enum Literal {
+    /// An integer literal, split into sign and magnitude so that the full range of both `int64`
+    /// and `uint64` can be written.
+    Int {
+        /// True if the literal was preceded by '-'.
+        negative:  bool,
+
+        /// The absolute value of the literal.
+        magnitude: u64,
+    },
+
+    /// A floating point literal.
+    Float(f64),
+
+    /// A string literal.
+    Str(String),
+
+    /// A bare identifier: `true`, `false`, or the name of an enum value.
+    Ident(String),
+}
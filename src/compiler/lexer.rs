@@ -0,0 +1,245 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+use compiler::Error;
+use compiler::ast::Pos;
+
+/// A `Token` is a single lexical element of a .rex file.
+#[derive(Clone,Debug,PartialEq)]
+pub enum Token {
+    /// An identifier or keyword, e.g. `record` or `uint64`.
+    Ident(String),
+
+    /// A non-negative integer literal.
+    Int(u64),
+
+    /// A non-negative floating point literal, e.g. `1.5`.
+    Float(f64),
+
+    /// A double-quoted string literal, with its escapes already processed.
+    Str(String),
+
+    Colon,
+    Semi,
+    Comma,
+    Equals,
+    Minus,
+
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    LAngle,
+    RAngle,
+
+    /// The end of the input. The lexer will keep returning this once the input is exhausted.
+    EOF,
+}
+
+/// A `Lexer` turns the text of a .rex file into a stream of `Token`s, keeping track of the
+/// position of each one for diagnostics.
+pub struct Lexer<'x> {
+    chars: Peekable<Chars<'x>>,
+
+    // Position of the next character returned by `chars`.
+    line: usize,
+    col:  usize,
+}
+
+impl<'x> Lexer<'x> {
+    /// Create a `Lexer` which reads from `src`.
+    pub fn new(src: &'x str) -> Lexer<'x> {
+        Lexer {
+            chars: src.chars().peekable(),
+            line:  1,
+            col:   1,
+        }
+    }
+
+    fn pos(&self) -> Pos {
+        Pos { line: self.line, col: self.col }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|c| *c)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next();
+
+        match c {
+            Some('\n') => { self.line += 1; self.col = 1; }
+            Some(_)    => { self.col += 1; }
+            None       => {}
+        }
+
+        c
+    }
+
+    // Skip whitespace and comments, leaving `chars` at the start of the next token (or EOF).
+    fn skip_trivia(&mut self) -> Result<(), Error> {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_whitespace() => { self.bump(); },
+
+                Some('/') => {
+                    let start = self.pos();
+                    self.bump();
+
+                    match self.bump() {
+                        Some('/') => while let Some(c) = self.bump() {
+                            if c == '\n' { break; }
+                        },
+
+                        Some('*') => {
+                            let mut prev = ' ';
+                            loop {
+                                match self.bump() {
+                                    Some('/') if prev == '*' => break,
+                                    Some(c)                  => prev = c,
+                                    None => return Err(Error::new(start,
+                                        "unterminated block comment".to_string())),
+                                }
+                            }
+                        }
+
+                        _ => return Err(Error::new(start, "unexpected character '/'".to_string())),
+                    }
+                }
+
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    // Read a string literal starting at `pos`. The supported escapes are `\\`, `\"`, `\n` and `\t`.
+    fn string_literal(&mut self, pos: Pos) -> Result<(Token, Pos), Error> {
+        self.bump();
+
+        let mut s = String::new();
+
+        loop {
+            match self.bump() {
+                Some('"')  => return Ok((Token::Str(s), pos)),
+                Some('\\') => match self.bump() {
+                    Some('\\') => s.push('\\'),
+                    Some('"')  => s.push('"'),
+                    Some('n')  => s.push('\n'),
+                    Some('t')  => s.push('\t'),
+                    Some(c)    => return Err(Error::new(pos,
+                        format!("unknown escape '\\{}' in string literal", c))),
+                    None       => return Err(Error::new(pos,
+                        "unterminated string literal".to_string())),
+                },
+                Some(c)    => s.push(c),
+                None       => return Err(Error::new(pos,
+                    "unterminated string literal".to_string())),
+            }
+        }
+    }
+
+    /// `next_token` returns the next token in the input, along with the position at which it
+    /// starts.
+    pub fn next_token(&mut self) -> Result<(Token, Pos), Error> {
+        try!(self.skip_trivia());
+
+        let pos = self.pos();
+
+        let c = match self.peek() {
+            Some(c) => c,
+            None    => return Ok((Token::EOF, pos)),
+        };
+
+        if c.is_alphabetic() || c == '_' {
+            let mut ident = String::new();
+
+            while let Some(c) = self.peek() {
+                if c.is_alphanumeric() || c == '_' {
+                    ident.push(c);
+                    self.bump();
+                } else {
+                    break;
+                }
+            }
+
+            return Ok((Token::Ident(ident), pos));
+        }
+
+        if c.is_digit(10) {
+            let mut text = String::new();
+
+            while let Some(c) = self.peek() {
+                if c.is_digit(10) {
+                    text.push(c);
+                    self.bump();
+                } else {
+                    break;
+                }
+            }
+
+            if self.peek() == Some('.') {
+                text.push('.');
+                self.bump();
+
+                while let Some(c) = self.peek() {
+                    if c.is_digit(10) {
+                        text.push(c);
+                        self.bump();
+                    } else {
+                        break;
+                    }
+                }
+
+                return match text.parse::<f64>() {
+                    Ok(x)  => Ok((Token::Float(x), pos)),
+                    Err(_) => Err(Error::new(pos, format!("invalid float literal '{}'", text))),
+                };
+            }
+
+            return match text.parse::<u64>() {
+                Ok(x)  => Ok((Token::Int(x), pos)),
+                Err(_) => Err(Error::new(pos, "integer literal is too large".to_string())),
+            };
+        }
+
+        if c == '"' {
+            return self.string_literal(pos);
+        }
+
+        self.bump();
+
+        let tok = match c {
+            ':' => Token::Colon,
+            ';' => Token::Semi,
+            ',' => Token::Comma,
+            '=' => Token::Equals,
+            '-' => Token::Minus,
+            '{' => Token::LBrace,
+            '}' => Token::RBrace,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            '<' => Token::LAngle,
+            '>' => Token::RAngle,
+            _   => return Err(Error::new(pos, format!("unexpected character '{}'", c))),
+        };
+
+        Ok((tok, pos))
+    }
+}
@@ -0,0 +1,499 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+use encoding::{CompleteEncoding, RecordEncoding, FieldEncoding, FieldID, Type, Quantifier};
+
+use compiler::ast::{File, RecordDecl, FieldDecl, Literal, Pos};
+use primitive::Primitive;
+
+/// The `ast` module defines the syntax tree produced by the parser.
+pub mod ast;
+
+/// The `lexer` module splits .rex source into tokens.
+mod lexer;
+
+/// The `parser` module turns a stream of tokens into an `ast::File`.
+mod parser;
+
+pub use compiler::parser::parse;
+
+/// An `Error` is a diagnostic produced while parsing or compiling a .rex file. It gives the
+/// position in the file at which the problem was found.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Error {
+    /// Line and column at which the error was detected.
+    pub pos: Pos,
+
+    /// Human readable description of the error.
+    pub msg: String,
+}
+
+impl Error {
+    /// Create an `Error` at `pos` with the message `msg`.
+    pub fn new(pos: Pos, msg: String) -> Error {
+        Error {
+            pos: pos,
+            msg: msg,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.pos, self.msg)
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        &self.msg[..]
+    }
+}
+
+/// `compile_str` parses and compiles the text of a .rex file. See `compile`.
+pub fn compile_str(src: &str) -> Result<Vec<CompleteEncoding>, Error> {
+    compile(&try!(parse(src)))
+}
+
+/// `compile` resolves the names in `file` and produces a `CompleteEncoding` for each record
+/// declared in it, in declaration order. The `target` of each encoding is the record itself, and
+/// its `depends` contain every record reachable from it (in breadth-first order of first
+/// reference), followed by the entry records of any map fields.
+pub fn compile(file: &File) -> Result<Vec<CompleteEncoding>, Error> {
+    try!(check(file));
+
+    let mut encs = vec![];
+
+    for rec in file.records.iter() {
+        encs.push(try!(compile_record(file, rec)));
+    }
+
+    Ok(encs)
+}
+
+/// `find_encoding` returns the encoding in `encs` whose target is named `name`.
+pub fn find_encoding<'x>(encs: &'x [CompleteEncoding], name: &str) -> Option<&'x CompleteEncoding> {
+    encs.iter().find(|enc| enc.target.name == name)
+}
+
+/// `builtin_type` maps the name of a primitive type in a .rex file to its `Type`.
+pub fn builtin_type(name: &str) -> Option<Type> {
+    Some( match name {
+        "int8"    => Type::Int8,
+        "int16"   => Type::Int16,
+        "int32"   => Type::Int32,
+        "int64"   => Type::Int64,
+
+        "uint8"   => Type::UInt8,
+        "uint16"  => Type::UInt16,
+        "uint32"  => Type::UInt32,
+        "uint64"  => Type::UInt64,
+
+        "fixed32" => Type::Fixed32,
+        "fixed64" => Type::Fixed64,
+
+        "float32" => Type::Float32,
+        "float64" => Type::Float64,
+
+        "bytes"   => Type::Bytes,
+        "string"  => Type::String,
+
+        "bool"    => Type::Bool,
+
+        _ => return None,
+    })
+}
+
+// Check the declarations in `file` for duplicates and unknown types.
+fn check(file: &File) -> Result<(), Error> {
+    let mut names: HashMap<&str, Pos> = HashMap::new();
+
+    let decls = file.enums.iter().map(|e| (&e.name[..], e.pos))
+        .chain(file.records.iter().map(|r| (&r.name[..], r.pos)));
+
+    for (name, pos) in decls {
+        if builtin_type(name).is_some() {
+            return Err(Error::new(pos, format!("'{}' is the name of a builtin type", name)));
+        }
+
+        if let Some(prev) = names.insert(name, pos) {
+            return Err(Error::new(pos, format!("'{}' is already declared at {}", name, prev)));
+        }
+    }
+
+    for e in file.enums.iter() {
+        let mut seen: HashMap<&str, Pos> = HashMap::new();
+
+        for val in e.values.iter() {
+            if let Some(prev) = seen.insert(&val.name[..], val.pos) {
+                return Err(Error::new(val.pos,
+                    format!("'{}.{}' is already declared at {}", e.name, val.name, prev)));
+            }
+        }
+    }
+
+    for rec in file.records.iter() {
+        let mut ids:    HashMap<u64, Pos>  = HashMap::new();
+        let mut fnames: HashMap<&str, Pos> = HashMap::new();
+
+        for f in rec.fields.iter() {
+            if f.id == 0 {
+                return Err(Error::new(f.pos,
+                    "field id 0 is reserved to mark the end of a record".to_string()));
+            }
+
+            if let Some(prev) = ids.insert(f.id, f.pos) {
+                return Err(Error::new(f.pos,
+                    format!("field id {} is already used at {}", f.id, prev)));
+            }
+
+            if let Some(prev) = fnames.insert(&f.name[..], f.pos) {
+                return Err(Error::new(f.pos,
+                    format!("field '{}' is already declared at {}", f.name, prev)));
+            }
+
+            if builtin_type(&f.typ[..]).is_none() && !names.contains_key(&f.typ[..]) {
+                return Err(Error::new(f.typ_pos, format!("unknown type '{}'", f.typ)));
+            }
+
+            if let Some(ref key) = f.key {
+                let valid = match builtin_type(&key[..]) {
+                    Some(Type::Float32) | Some(Type::Float64) => false,
+                    Some(_)                                   => true,
+                    None                                      => file.find_enum(&key[..]).is_some(),
+                };
+
+                if !valid {
+                    return Err(Error::new(f.typ_pos,
+                        format!("'{}' can't be the key type of a map", key)));
+                }
+            }
+        }
+
+        // Generated code holds each union in a member named after it, so the name of a union
+        // can't be shared with a field.
+        for f in rec.fields.iter() {
+            if let Some(ref union) = f.union {
+                if let Some(prev) = fnames.get(&union[..]) {
+                    return Err(Error::new(f.pos,
+                        format!("union '{}' has the same name as the field at {}", union, prev)));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn compile_record(file: &File, target: &RecordDecl) -> Result<CompleteEncoding, Error> {
+    // First, discover every record reachable from `target`, assigning each one an index into
+    // `depends` in the order we first see it.
+    let mut order:    Vec<&RecordDecl>      = vec![];
+    let mut index_of: HashMap<&str, usize> = HashMap::new();
+
+    discover(file, target, &mut order, &mut index_of);
+
+    let mut next = 0;
+    while next < order.len() {
+        let rec = order[next];
+        discover(file, rec, &mut order, &mut index_of);
+        next += 1;
+    }
+
+    // Then build the encodings with all of the references resolved. The entry records of maps
+    // are collected as they're found, and go after the declared records.
+    let mut depends = vec![];
+    let mut entries = vec![];
+
+    for rec in order.iter() {
+        depends.push(try!(record_encoding(file, *rec, &index_of, &mut entries)));
+    }
+
+    let target = try!(record_encoding(file, target, &index_of, &mut entries));
+    depends.extend(entries.into_iter());
+
+    let mut enc = CompleteEncoding {
+        target:  target,
+        depends: depends,
+    };
+
+    enc.sort_fields();
+
+    Ok(enc)
+}
+
+// Add any records referenced by the fields of `rec` to `order`, if they aren't there already.
+fn discover<'x>(file:     &'x File,
+                rec:      &'x RecordDecl,
+                order:    &mut Vec<&'x RecordDecl>,
+                index_of: &mut HashMap<&'x str, usize>) {
+
+    for f in rec.fields.iter() {
+        if let Some(dep) = file.find_record(&f.typ[..]) {
+            if !index_of.contains_key(&dep.name[..]) {
+                index_of.insert(&dep.name[..], order.len());
+                order.push(dep);
+            }
+        }
+    }
+}
+
+fn record_encoding(file:     &File,
+                   rec:      &RecordDecl,
+                   index_of: &HashMap<&str, usize>,
+                   entries:  &mut Vec<RecordEncoding>) -> Result<RecordEncoding, Error> {
+
+    let mut req_fields     = vec![];
+    let mut opt_rep_fields = vec![];
+
+    for f in rec.fields.iter() {
+        let fenc = try!(field_encoding(file, rec, f, index_of, entries));
+
+        match fenc.quant {
+            Quantifier::Required => req_fields.push(fenc),
+            _                    => opt_rep_fields.push(fenc),
+        }
+    }
+
+    Ok(RecordEncoding {
+        name:           rec.name.clone(),
+        req_fields:     req_fields,
+        opt_rep_fields: opt_rep_fields,
+    })
+}
+
+fn resolve_type(file: &File, name: &str, pos: Pos, index_of: &HashMap<&str, usize>)
+    -> Result<Type, Error> {
+
+    if let Some(t) = builtin_type(name) {
+        Ok(t)
+    } else if file.find_enum(name).is_some() {
+        Ok(Type::Enum)
+    } else if let Some(index) = index_of.get(name) {
+        Ok(Type::Record{index: *index})
+    } else {
+        Err(Error::new(pos, format!("unknown type '{}'", name)))
+    }
+}
+
+fn field_encoding(file:     &File,
+                  rec:      &RecordDecl,
+                  f:        &FieldDecl,
+                  index_of: &HashMap<&str, usize>,
+                  entries:  &mut Vec<RecordEncoding>) -> Result<FieldEncoding, Error> {
+
+    let typ = try!(resolve_type(file, &f.typ[..], f.typ_pos, index_of));
+
+    if let Some(ref key) = f.key {
+        if let Some((_, pos)) = f.default {
+            return Err(Error::new(pos, "map fields can't have a default".to_string()));
+        }
+
+        let key_typ = try!(resolve_type(file, &key[..], f.typ_pos, index_of));
+
+        // The entry record is named after the field, which can't clash with a declared record.
+        let name = format!("{}.{}", rec.name, f.name);
+
+        let pos = match entries.iter().position(|e| e.name == name) {
+            Some(pos) => pos,
+            None      => {
+                entries.push(RecordEncoding {
+                    name:           name,
+                    req_fields:     vec![entry_field(1, "key", key_typ),
+                                         entry_field(2, "value", typ)],
+                    opt_rep_fields: vec![],
+                });
+                entries.len() - 1
+            },
+        };
+
+        return Ok(FieldEncoding {
+            id:      FieldID(f.id),
+            name:    f.name.clone(),
+            quant:   Quantifier::Repeated,
+            typ:     Type::Map{index: index_of.len() + pos},
+            bounds:  None,
+            union:   None,
+            default: None,
+        });
+    }
+
+    let bounds = if f.bounds.is_empty() {
+        None
+    } else {
+        let mut product = 1usize;
+
+        for b in f.bounds.iter() {
+            product = match product.checked_mul(*b) {
+                Some(p) => p,
+                None    => return Err(Error::new(f.pos,
+                    format!("array bounds of '{}' are too large", f.name))),
+            };
+        }
+
+        Some(product)
+    };
+
+    let default = match f.default {
+        Some((ref lit, pos)) => {
+            if f.quant != Quantifier::Optional || f.union.is_some() || bounds.is_some() {
+                return Err(Error::new(pos,
+                    "only optional fields outside of unions can have a default".to_string()));
+            }
+
+            Some(try!(default_value(file, f, typ, lit, pos)))
+        }
+        None => None,
+    };
+
+    Ok(FieldEncoding {
+        id:      FieldID(f.id),
+        name:    f.name.clone(),
+        quant:   f.quant,
+        typ:     typ,
+        bounds:  bounds,
+        union:   f.union.clone(),
+        default: default,
+    })
+}
+
+// Convert the literal `lit` into a value of type `typ`, which is the type of `f`.
+fn default_value(file: &File, f: &FieldDecl, typ: Type, lit: &Literal, pos: Pos)
+    -> Result<Primitive, Error> {
+
+    let mismatch = || -> Result<Primitive, Error> { Err(Error::new(pos,
+        format!("default value of '{}' doesn't match its type '{}'", f.name, f.typ))) };
+
+    let range = || -> Result<Primitive, Error> { Err(Error::new(pos,
+        format!("default value of '{}' is out of range for '{}'", f.name, f.typ))) };
+
+    let int = match *lit {
+        Literal::Int{negative: true, magnitude} if magnitude <= (1u64 << 63) => {
+            // Written this way so that -2^63 doesn't overflow.
+            if magnitude == 0 { Some(0) } else { Some(-((magnitude - 1) as i64) - 1) }
+        }
+        Literal::Int{negative: false, magnitude} if magnitude < (1u64 << 63) => {
+            Some(magnitude as i64)
+        }
+        _ => None,
+    };
+
+    let uint = match *lit {
+        Literal::Int{negative: false, magnitude} => Some(magnitude),
+        Literal::Int{negative: true,  magnitude} if magnitude == 0 => Some(0),
+        _ => None,
+    };
+
+    let is_int = match *lit {
+        Literal::Int{..} => true,
+        _                => false,
+    };
+
+    // A value is in range for a type if converting it there and back gives the same value.
+    macro_rules! int_default {
+        ($val:expr, $variant:ident, $t:ty, $wide:ty) => {
+            match $val {
+                Some(x) if x as $t as $wide == x => Ok(Primitive::$variant(x as $t)),
+                _ if is_int                      => range(),
+                _                                => mismatch(),
+            }
+        }
+    }
+
+    match typ {
+        Type::Int8    => int_default!(int, Int8,  i8,  i64),
+        Type::Int16   => int_default!(int, Int16, i16, i64),
+        Type::Int32   => int_default!(int, Int32, i32, i64),
+        Type::Int64   => int_default!(int, Int64, i64, i64),
+
+        Type::UInt8   => int_default!(uint, UInt8,   u8,  u64),
+        Type::UInt16  => int_default!(uint, UInt16,  u16, u64),
+        Type::UInt32  => int_default!(uint, UInt32,  u32, u64),
+        Type::UInt64  => int_default!(uint, UInt64,  u64, u64),
+        Type::Fixed32 => int_default!(uint, Fixed32, u32, u64),
+        Type::Fixed64 => int_default!(uint, Fixed64, u64, u64),
+
+        Type::Float32 | Type::Float64 => {
+            let x = match *lit {
+                Literal::Float(x)                 => x,
+                Literal::Int{negative, magnitude} => {
+                    if negative { -(magnitude as f64) } else { magnitude as f64 }
+                }
+                _ => return mismatch(),
+            };
+
+            // Literals too big for an f64 are parsed as infinity, so they're out of range too.
+            if !x.is_finite() || (typ == Type::Float32 && x.abs() > ::std::f32::MAX as f64) {
+                return range();
+            }
+
+            if typ == Type::Float32 {
+                Ok(Primitive::Float32(x as f32))
+            } else {
+                Ok(Primitive::Float64(x))
+            }
+        }
+
+        Type::Bool => match *lit {
+            Literal::Ident(ref s) if s == "true"  => Ok(Primitive::Bool(true)),
+            Literal::Ident(ref s) if s == "false" => Ok(Primitive::Bool(false)),
+            _                                     => mismatch(),
+        },
+
+        Type::String => match *lit {
+            Literal::Str(ref s) => Ok(Primitive::String(s.clone())),
+            _                   => mismatch(),
+        },
+
+        Type::Bytes => match *lit {
+            Literal::Str(ref s) => Ok(Primitive::Bytes(s.clone().into_bytes())),
+            _                   => mismatch(),
+        },
+
+        Type::Enum => {
+            let e = file.find_enum(&f.typ[..]).unwrap();
+
+            match *lit {
+                Literal::Ident(ref s) => match e.values.iter().find(|v| v.name == *s) {
+                    Some(v) => Ok(Primitive::Enum(v.value)),
+                    None    => Err(Error::new(pos,
+                        format!("'{}' is not a value of enum '{}'", s, e.name))),
+                },
+                _ => mismatch(),
+            }
+        }
+
+        Type::Record{..} | Type::Map{..} => Err(Error::new(pos,
+            format!("record field '{}' can't have a default", f.name))),
+    }
+}
+
+fn entry_field(id: u64, name: &str, typ: Type) -> FieldEncoding {
+    FieldEncoding {
+        id:      FieldID(id),
+        name:    name.to_string(),
+        quant:   Quantifier::Required,
+        typ:     typ,
+        bounds:  None,
+        union:   None,
+        default: None,
+    }
+}
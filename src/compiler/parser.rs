@@ -0,0 +1,367 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::mem;
+
+use compiler::Error;
+use compiler::ast::*;
+use compiler::lexer::{Lexer, Token};
+use encoding::Quantifier;
+
+/// A `Parser` is a simple recursive descent parser for .rex files. It holds a single token of
+/// lookahead.
+pub struct Parser<'x> {
+    lex: Lexer<'x>,
+
+    tok: Token,
+    pos: Pos,
+}
+
+/// `parse` parses the text of a .rex file into a `File`. No name resolution is done at this stage,
+/// see `compiler::compile` for that.
+pub fn parse(src: &str) -> Result<File, Error> {
+    let mut p = try!(Parser::new(src));
+    p.parse_file()
+}
+
+impl<'x> Parser<'x> {
+    fn new(src: &'x str) -> Result<Parser<'x>, Error> {
+        let mut lex = Lexer::new(src);
+        let (tok, pos) = try!(lex.next_token());
+
+        Ok(Parser {
+            lex: lex,
+            tok: tok,
+            pos: pos,
+        })
+    }
+
+    // Advance to the next token, returning the current one.
+    fn bump(&mut self) -> Result<Token, Error> {
+        let (tok, pos) = try!(self.lex.next_token());
+        self.pos = pos;
+        Ok(mem::replace(&mut self.tok, tok))
+    }
+
+    fn err<T>(&self, msg: String) -> Result<T, Error> {
+        Err(Error::new(self.pos, msg))
+    }
+
+    fn unexpected<T>(&self, wanted: &str) -> Result<T, Error> {
+        self.err(format!("expected {}, found {}", wanted, describe(&self.tok)))
+    }
+
+    fn expect(&mut self, tok: Token, wanted: &str) -> Result<(), Error> {
+        if self.tok == tok {
+            try!(self.bump());
+            Ok(())
+        } else {
+            self.unexpected(wanted)
+        }
+    }
+
+    // If the current token is `tok`, consume it and return true.
+    fn eat(&mut self, tok: Token) -> Result<bool, Error> {
+        if self.tok == tok {
+            try!(self.bump());
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn is_keyword(&self, kw: &str) -> bool {
+        match self.tok {
+            Token::Ident(ref s) => &s[..] == kw,
+            _                   => false,
+        }
+    }
+
+    fn parse_ident(&mut self, wanted: &str) -> Result<String, Error> {
+        match self.tok {
+            Token::Ident(_) => match try!(self.bump()) {
+                Token::Ident(s) => Ok(s),
+                _               => unreachable!(),
+            },
+            _ => self.unexpected(wanted),
+        }
+    }
+
+    fn parse_int(&mut self, wanted: &str) -> Result<u64, Error> {
+        match self.tok {
+            Token::Int(x) => { try!(self.bump()); Ok(x) }
+            _             => self.unexpected(wanted),
+        }
+    }
+
+    fn parse_file(&mut self) -> Result<File, Error> {
+        let mut file = File {
+            package: None,
+            enums:   vec![],
+            records: vec![],
+        };
+
+        if self.is_keyword("package") {
+            try!(self.bump());
+            file.package = Some(try!(self.parse_ident("package name")));
+            try!(self.expect(Token::Semi, "';'"));
+        }
+
+        loop {
+            if self.tok == Token::EOF {
+                return Ok(file);
+            } else if self.is_keyword("enum") {
+                file.enums.push(try!(self.parse_enum()));
+            } else if self.is_keyword("record") {
+                file.records.push(try!(self.parse_record()));
+            } else if self.is_keyword("package") {
+                return self.err("package declaration must come first".to_string());
+            } else {
+                return self.unexpected("'enum' or 'record'");
+            }
+        }
+    }
+
+    fn parse_enum(&mut self) -> Result<EnumDecl, Error> {
+        let pos = self.pos;
+        try!(self.bump());
+
+        let name = try!(self.parse_ident("enum name"));
+        try!(self.expect(Token::LBrace, "'{'"));
+
+        let mut values = vec![];
+
+        while !try!(self.eat(Token::RBrace)) {
+            let val_pos = self.pos;
+            let val_name = try!(self.parse_ident("enum value name or '}'"));
+            try!(self.expect(Token::Equals, "'='"));
+
+            let neg = try!(self.eat(Token::Minus));
+            let mag = try!(self.parse_int("integer"));
+
+            let value = if neg {
+                if mag > (1u64 << 63) {
+                    return Err(Error::new(val_pos, format!("value of {} is out of range", val_name)));
+                }
+                // Written this way so that -2^63 doesn't overflow.
+                if mag == 0 { 0 } else { -((mag - 1) as i64) - 1 }
+            } else {
+                if mag > (1u64 << 63) - 1 {
+                    return Err(Error::new(val_pos, format!("value of {} is out of range", val_name)));
+                }
+                mag as i64
+            };
+
+            values.push(EnumValue {
+                name:  val_name,
+                value: value,
+                pos:   val_pos,
+            });
+
+            // Values may be separated by commas, newlines, or both.
+            try!(self.eat(Token::Comma));
+        }
+
+        Ok(EnumDecl {
+            name:   name,
+            values: values,
+            pos:    pos,
+        })
+    }
+
+    fn parse_record(&mut self) -> Result<RecordDecl, Error> {
+        let pos = self.pos;
+        try!(self.bump());
+
+        let name = try!(self.parse_ident("record name"));
+        try!(self.expect(Token::LBrace, "'{'"));
+
+        let mut fields = vec![];
+        let mut unions: Vec<String> = vec![];
+
+        while !try!(self.eat(Token::RBrace)) {
+            if self.is_keyword("union") {
+                let union_pos = self.pos;
+                let union     = try!(self.parse_union(&mut fields));
+
+                if unions.contains(&union) {
+                    return Err(Error::new(union_pos,
+                        format!("union '{}' is already declared in {}", union, name)));
+                }
+
+                unions.push(union);
+            } else {
+                fields.push(try!(self.parse_field()));
+            }
+        }
+
+        Ok(RecordDecl {
+            name:   name,
+            fields: fields,
+            pos:    pos,
+        })
+    }
+
+    // union := 'union' IDENT '{' field+ '}'
+    //
+    // The members of the union are added to `fields`, and its name is returned.
+    fn parse_union(&mut self, fields: &mut Vec<FieldDecl>) -> Result<String, Error> {
+        try!(self.bump());
+
+        let name = try!(self.parse_ident("union name"));
+        try!(self.expect(Token::LBrace, "'{'"));
+
+        if self.tok == Token::RBrace {
+            return self.err(format!("union '{}' must have at least one member", name));
+        }
+
+        while !try!(self.eat(Token::RBrace)) {
+            let mut f = try!(self.parse_field());
+
+            if f.quant != Quantifier::Required || f.key.is_some() {
+                return Err(Error::new(f.pos,
+                    "union members can't be optional, repeated or maps".to_string()));
+            }
+
+            f.quant = Quantifier::Optional;
+            f.union = Some(name.clone());
+
+            fields.push(f);
+        }
+
+        Ok(name)
+    }
+
+    // field := INT IDENT ':' quant? ('[' INT ']')* IDENT ('=' literal)?
+    //        | INT IDENT ':' 'map' '<' IDENT ',' IDENT '>'
+    fn parse_field(&mut self) -> Result<FieldDecl, Error> {
+        let pos  = self.pos;
+        let id   = try!(self.parse_int("field id or '}'"));
+        let name = try!(self.parse_ident("field name"));
+
+        try!(self.expect(Token::Colon, "':'"));
+
+        let quant = if self.is_keyword("opt") {
+            try!(self.bump());
+            Quantifier::Optional
+        } else if self.is_keyword("rep") {
+            try!(self.bump());
+            Quantifier::Repeated
+        } else {
+            Quantifier::Required
+        };
+
+        let mut bounds = vec![];
+
+        while try!(self.eat(Token::LBracket)) {
+            let bound_pos = self.pos;
+            let bound     = try!(self.parse_int("array bound"));
+
+            if bound == 0 {
+                return Err(Error::new(bound_pos, "array bounds must be non-zero".to_string()));
+            }
+
+            bounds.push(bound as usize);
+            try!(self.expect(Token::RBracket, "']'"));
+        }
+
+        let typ_pos = self.pos;
+        let mut typ = try!(self.parse_ident("type name"));
+        let mut key = None;
+
+        // `map` is only a keyword when it's followed by '<', so it can still name a record.
+        if typ == "map" && try!(self.eat(Token::LAngle)) {
+            if quant != Quantifier::Required || !bounds.is_empty() {
+                return Err(Error::new(typ_pos,
+                    "map fields can't be optional, repeated or arrays".to_string()));
+            }
+
+            key = Some(try!(self.parse_ident("key type name")));
+            try!(self.expect(Token::Comma, "','"));
+            typ = try!(self.parse_ident("value type name"));
+            try!(self.expect(Token::RAngle, "'>'"));
+        }
+
+        let default = if try!(self.eat(Token::Equals)) {
+            let lit_pos = self.pos;
+            Some((try!(self.parse_literal()), lit_pos))
+        } else {
+            None
+        };
+
+        Ok(FieldDecl {
+            id:      id,
+            name:    name,
+            quant:   quant,
+            bounds:  bounds,
+            key:     key,
+            typ:     typ,
+            union:   None,
+            default: default,
+            pos:     pos,
+            typ_pos: typ_pos,
+        })
+    }
+
+    // literal := '-'? INT | '-'? FLOAT | STRING | IDENT
+    fn parse_literal(&mut self) -> Result<Literal, Error> {
+        let negative = try!(self.eat(Token::Minus));
+
+        match self.tok {
+            Token::Int(x) => {
+                try!(self.bump());
+                Ok(Literal::Int { negative: negative, magnitude: x })
+            }
+
+            Token::Float(x) => {
+                try!(self.bump());
+                Ok(Literal::Float(if negative { -x } else { x }))
+            }
+
+            Token::Str(_) | Token::Ident(_) if !negative => match try!(self.bump()) {
+                Token::Str(s)   => Ok(Literal::Str(s)),
+                Token::Ident(s) => Ok(Literal::Ident(s)),
+                _               => unreachable!(),
+            },
+
+            _ if negative => self.unexpected("number"),
+            _             => self.unexpected("default value"),
+        }
+    }
+}
+
+// Describe a token for use in an error message.
+fn describe(tok: &Token) -> String {
+    match *tok {
+        Token::Ident(ref s) => format!("'{}'", s),
+        Token::Int(x)       => format!("'{}'", x),
+        Token::Float(x)     => format!("'{}'", x),
+        Token::Str(ref s)   => format!("{:?}", s),
+        Token::Colon        => "':'".to_string(),
+        Token::Semi         => "';'".to_string(),
+        Token::Comma        => "','".to_string(),
+        Token::Equals       => "'='".to_string(),
+        Token::Minus        => "'-'".to_string(),
+        Token::LBrace       => "'{'".to_string(),
+        Token::RBrace       => "'}'".to_string(),
+        Token::LBracket     => "'['".to_string(),
+        Token::RBracket     => "']'".to_string(),
+        Token::LAngle       => "'<'".to_string(),
+        Token::RAngle       => "'>'".to_string(),
+        Token::EOF          => "end of file".to_string(),
+    }
+}
@@ -0,0 +1,315 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::error;
+use std::error::FromError;
+use std::fmt;
+use std::io;
+use std::io::{Read, ReadExt, Write};
+
+use codec;
+use codec::{Codec, Identity};
+use decoder;
+use decoder::{Decodable, DecodeLimits, decode_from_with_limits};
+use encoder;
+use encoder::{Encodable, encode_to};
+use encoding::{CompleteEncoding, COMPLETE_ENC};
+use value::DynamicRecord;
+
+/// `MAGIC` is the first four bytes of every self-describing rex file.
+pub const MAGIC: &'static [u8] = b"REX\0";
+
+/// `FORMAT_VERSION` is the version of the container format written by `write_self_describing`.
+pub const FORMAT_VERSION: u64 = 1;
+
+/// `Error` is used to report errors that occur while reading or writing a self-describing file.
+#[derive(Debug)]
+pub enum Error {
+    /// `BadMagic` indicates that the input doesn't start with `MAGIC`, so it probably isn't a rex
+    /// file at all.
+    BadMagic,
+
+    /// `UnsupportedVersion` indicates that the file was written with a newer version of the
+    /// container format than this library understands.
+    UnsupportedVersion(u64),
+
+    /// `UnknownCodec` indicates that the payload was compressed with a codec which isn't available.
+    UnknownCodec(u64),
+
+    /// `IoError` is used to pass through `std::io` errors.
+    IoError(io::Error),
+
+    /// `EncError` is used to pass through errors from encoding the schema or payload.
+    EncError(encoder::Error),
+
+    /// `DecError` is used to pass through errors from decoding the schema or payload.
+    DecError(decoder::Error),
+}
+
+impl FromError<encoder::Error> for Error {
+    fn from_error(err: encoder::Error) -> Error {
+        Error::EncError(err)
+    }
+}
+
+impl FromError<decoder::Error> for Error {
+    fn from_error(err: decoder::Error) -> Error {
+        Error::DecError(err)
+    }
+}
+
+impl FromError<io::Error> for Error {
+    fn from_error(err: io::Error) -> Error {
+        Error::IoError(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnsupportedVersion(v) => write!(f, "unsupported container version {}", v),
+            Error::UnknownCodec(id)      => write!(f, "unknown codec {}", id),
+            Error::IoError(ref err)      => write!(f, "i/o error: {}", err),
+            Error::EncError(ref err)     => write!(f, "error encoding: {}", err),
+            Error::DecError(ref err)     => write!(f, "error decoding: {}", err),
+            ref err                      => write!(f, "{}", error::Error::description(err)),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::BadMagic              => "not a self-describing rex file",
+            Error::UnsupportedVersion(_) => "unsupported container version",
+            Error::UnknownCodec(_)       => "unknown codec",
+            Error::IoError(_)            => "i/o error",
+            Error::EncError(ref err)     => error::Error::description(err),
+            Error::DecError(ref err)     => error::Error::description(err),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::IoError(ref err)  => Some(err as &error::Error),
+            Error::EncError(ref err) => Some(err as &error::Error),
+            Error::DecError(ref err) => Some(err as &error::Error),
+            _                        => None,
+        }
+    }
+}
+
+/// A `Header` is the part of a self-describing file which comes before the payload.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Header {
+    /// The encoding of the payload.
+    pub encoding: CompleteEncoding,
+
+    /// The id of the codec that the payload was compressed with. See the `codec` module.
+    pub codec: u64,
+}
+
+/// `write_self_describing` writes an uncompressed self-describing file to `w`. See
+/// `write_compressed`.
+pub fn write_self_describing<W, E>(w: &mut W, enc: &CompleteEncoding, e: &E) -> Result<(), Error>
+    where W: io::Write,
+          E: Encodable {
+
+    write_compressed(w, enc, e, &Identity)
+}
+
+/// `write_compressed` writes a self-describing file to `w`, with its payload compressed by
+/// `codec`. The file consists of
+///
+/// 1. The four bytes of `MAGIC`.
+/// 2. `FORMAT_VERSION`, as a varint.
+/// 3. The id of `codec`, as a varint.
+/// 4. `enc`, encoded according to `COMPLETE_ENC`.
+/// 5. `e`, encoded according to `enc`. Unless the codec is `codec::NONE`, this is compressed and
+///    preceded by its compressed size as a varint.
+///
+/// # Errors
+///
+/// As with `encode_to`, if the error is not an i/o error then nothing has been written to `w`.
+pub fn write_compressed<W, E>(w: &mut W, enc: &CompleteEncoding, e: &E, codec: &Codec)
+    -> Result<(), Error>
+    where W: io::Write,
+          E: Encodable {
+
+    // Encode the schema and payload before writing anything, so that an encoding error doesn't
+    // leave a half-written file behind.
+    let mut schema  = vec![];
+    let mut payload = vec![];
+
+    try!(encode_to(&COMPLETE_ENC, &mut schema, enc));
+    try!(encode_to(enc, &mut payload, e));
+
+    try!(w.write_all(MAGIC));
+    try!(encoder::write_uvarint(w, FORMAT_VERSION));
+    try!(encoder::write_uvarint(w, codec.id()));
+    try!(w.write_all(&schema[..]));
+
+    if codec.id() == codec::NONE {
+        try!(w.write_all(&payload[..]));
+    } else {
+        let packed = codec.compress(&payload[..]);
+        try!(encoder::write_uvarint(w, packed.len() as u64));
+        try!(w.write_all(&packed[..]));
+    }
+
+    Ok(())
+}
+
+/// `read_header` reads the magic number, version, codec and schema of a self-describing file from
+/// `r`, leaving `r` positioned at the start of the payload. The payload can then be read with
+/// `read_payload`. The schema is decoded with the default `DecodeLimits`.
+pub fn read_header<R>(r: &mut R) -> Result<Header, Error>
+    where R: io::Read {
+
+    read_header_with_limits(r, &Default::default())
+}
+
+/// `read_header_with_limits` is like `read_header`, but decodes the schema with `limits`.
+pub fn read_header_with_limits<R>(r: &mut R, limits: &DecodeLimits) -> Result<Header, Error>
+    where R: io::Read {
+
+    for b in MAGIC.iter() {
+        if try!(decoder::read_u8(r)) != *b {
+            return Err(Error::BadMagic);
+        }
+    }
+
+    let version = try!(decoder::read_uvarint(r));
+    if version > FORMAT_VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+
+    let codec = try!(decoder::read_uvarint(r));
+
+    let mut enc: CompleteEncoding = Default::default();
+    try!(decode_from_with_limits(&COMPLETE_ENC, r, &mut enc, limits));
+
+    Ok(Header {
+        encoding: enc,
+        codec:    codec,
+    })
+}
+
+/// `read_payload` reads the payload which follows `header` from `r`, decompressing it if
+/// necessary, and decodes it into `d` with the default `DecodeLimits`. Only the built-in codecs
+/// are understood; see `read_payload_with` for others.
+pub fn read_payload<R, D>(r: &mut R, header: &Header, d: &mut D) -> Result<(), Error>
+    where R: io::Read,
+          D: Decodable {
+
+    read_payload_with_limits(r, header, d, &Default::default())
+}
+
+/// `read_payload_with_limits` is like `read_payload`, but decodes the payload with `limits`. The
+/// compressed and decompressed sizes of a compressed payload are also held to `limits.max_bytes`.
+pub fn read_payload_with_limits<R, D>(r: &mut R, header: &Header, d: &mut D, limits: &DecodeLimits)
+    -> Result<(), Error>
+    where R: io::Read,
+          D: Decodable {
+
+    match codec::find(header.codec) {
+        Some(c) => read_payload_with(r, header, &*c, d, limits),
+        None    => Err(Error::UnknownCodec(header.codec)),
+    }
+}
+
+/// `read_payload_with` is like `read_payload_with_limits`, but decompresses the payload with
+/// `codec`, which must have the id given in `header`.
+pub fn read_payload_with<R, D>(r:      &mut R,
+                               header: &Header,
+                               codec:  &Codec,
+                               d:      &mut D,
+                               limits: &DecodeLimits) -> Result<(), Error>
+    where R: io::Read,
+          D: Decodable {
+
+    if codec.id() != header.codec {
+        return Err(Error::UnknownCodec(header.codec));
+    }
+
+    if header.codec == codec::NONE {
+        try!(decode_from_with_limits(&header.encoding, r, d, limits));
+        return Ok(());
+    }
+
+    let len = try!(decoder::read_uvarint(r));
+    if len > limits.max_bytes {
+        return Err(Error::DecError(decoder::Error::SizeLimitExceeded));
+    }
+
+    let mut packed = vec![];
+    try!(r.take(len).read_to_end(&mut packed));
+
+    if (packed.len() as u64) < len {
+        return Err(Error::DecError(decoder::Error::EOF));
+    }
+
+    let payload = try!(codec.decompress(&packed[..], limits.max_bytes));
+    try!(decode_from_with_limits(&header.encoding, &mut &payload[..], d, limits));
+
+    Ok(())
+}
+
+/// `read_self_describing` reads a self-describing file from `r`, decoding the payload into `d`
+/// using the schema embedded in the file. The schema is returned, so that callers can check that
+/// it describes the record type that they expected. The default `DecodeLimits` are used.
+pub fn read_self_describing<R, D>(r: &mut R, d: &mut D) -> Result<CompleteEncoding, Error>
+    where R: io::Read,
+          D: Decodable {
+
+    read_self_describing_with_limits(r, d, &Default::default())
+}
+
+/// `read_self_describing_with_limits` is like `read_self_describing`, but decodes both the schema
+/// and the payload with `limits`.
+pub fn read_self_describing_with_limits<R, D>(r: &mut R, d: &mut D, limits: &DecodeLimits)
+    -> Result<CompleteEncoding, Error>
+    where R: io::Read,
+          D: Decodable {
+
+    let header = try!(read_header_with_limits(r, limits));
+    try!(read_payload_with_limits(r, &header, d, limits));
+
+    Ok(header.encoding)
+}
+
+/// `read_dynamic` reads a self-describing file from `r` without needing a `Decodable` type for its
+/// payload. It returns the embedded schema along with the payload as a `DynamicRecord`. The
+/// default `DecodeLimits` are used.
+pub fn read_dynamic<R>(r: &mut R) -> Result<(CompleteEncoding, DynamicRecord), Error>
+    where R: io::Read {
+
+    read_dynamic_with_limits(r, &Default::default())
+}
+
+/// `read_dynamic_with_limits` is like `read_dynamic`, but decodes both the schema and the payload
+/// with `limits`.
+pub fn read_dynamic_with_limits<R>(r: &mut R, limits: &DecodeLimits)
+    -> Result<(CompleteEncoding, DynamicRecord), Error>
+    where R: io::Read {
+
+    let header  = try!(read_header_with_limits(r, limits));
+    let mut rec = DynamicRecord::new(&header.encoding.target);
+    try!(read_payload_with_limits(r, &header, &mut rec, limits));
+
+    Ok((header.encoding, rec))
+}
@@ -0,0 +1,123 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::fmt;
+
+use encoding::{RecordEncoding, FieldEncoding, FieldID, Quantifier};
+
+/// An `ErrorContext` says where in a record an encoding or decoding error occurred. It's carried by
+/// the `InField` variants of `EncError` and `DecError`.
+#[derive(Clone,Debug,PartialEq)]
+pub struct ErrorContext {
+    /// Name of the record type containing the field in which the error occurred.
+    pub record: String,
+
+    /// Name of the field in which the error occurred.
+    pub field: String,
+
+    /// Id of the field in which the error occurred.
+    pub id: FieldID,
+
+    /// Index of the element of the field in which the error occurred, if it's known. For array
+    /// fields this indexes the flattened elements, as with `get_primitive` and `set_primitive`.
+    pub index: Option<usize>,
+
+    /// Name of the outermost record type being encoded or decoded.
+    pub root: String,
+
+    /// Fields leading from `root` to the field in which the error occurred. The last element is
+    /// always `field`.
+    pub path: Vec<PathElem>,
+
+    /// Offset, in bytes from the start of the record, at which a decoding error was detected. It's
+    /// `None` for encoding errors.
+    pub offset: Option<u64>,
+}
+
+/// A `PathElem` is a single step in the path of an `ErrorContext`.
+#[derive(Clone,Debug,PartialEq)]
+pub struct PathElem {
+    /// Name of the field.
+    pub field: String,
+
+    /// Index of the element of the field. This is only present for repeated and array fields.
+    pub index: Option<usize>,
+}
+
+impl ErrorContext {
+    /// `new` creates an `ErrorContext` for element `index` of the field `f` of `rec`, where `rec`
+    /// is also the outermost record. Use `enter` to add the records that contain it.
+    pub fn new(rec: &RecordEncoding, f: &FieldEncoding, index: Option<usize>) -> ErrorContext {
+        ErrorContext {
+            record: rec.name.clone(),
+            field:  f.name.clone(),
+            id:     f.id,
+            index:  index,
+            root:   rec.name.clone(),
+            path:   vec![PathElem::new(f, index)],
+            offset: None,
+        }
+    }
+
+    /// `enter` records that the error occurred inside of element `index` of the field `f` of
+    /// `rec`, which becomes the outermost record.
+    pub fn enter(&mut self, rec: &RecordEncoding, f: &FieldEncoding, index: Option<usize>) {
+        self.root = rec.name.clone();
+        self.path.insert(0, PathElem::new(f, index));
+    }
+
+    /// `path_string` renders the path from the outermost record to the field in which the error
+    /// occurred, e.g. `Mesh.submeshes[3].material.name`.
+    pub fn path_string(&self) -> String {
+        let mut s = self.root.clone();
+
+        for elem in self.path.iter() {
+            s.push('.');
+            s.push_str(&elem.field[..]);
+
+            if let Some(index) = elem.index {
+                s.push_str(&format!("[{}]", index)[..]);
+            }
+        }
+
+        s
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let FieldID(id) = self.id;
+
+        try!(write!(f, "{} (field {} of {})", self.path_string(), id, self.record));
+
+        match self.offset {
+            Some(offset) => write!(f, " at byte {}", offset),
+            None         => Ok(()),
+        }
+    }
+}
+
+impl PathElem {
+    fn new(f: &FieldEncoding, index: Option<usize>) -> PathElem {
+        let indexed = f.quant == Quantifier::Repeated || f.bounds.is_some();
+
+        PathElem {
+            field: f.name.clone(),
+            index: if indexed { index } else { None },
+        }
+    }
+}
@@ -0,0 +1,69 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+lazy_static! {
+    // `TABLE[b]` is the CRC of the single byte `b`, used to process a byte at a time.
+    static ref TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+
+        for b in 0..256 {
+            let mut crc = b as u32;
+
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 { (crc >> 1) ^ POLY } else { crc >> 1 };
+            }
+
+            table[b] = crc;
+        }
+
+        table
+    };
+}
+
+// The CRC-32C (Castagnoli) polynomial, in reversed bit order.
+const POLY: u32 = 0x82F63B78;
+
+/// A `Crc32c` computes a CRC-32C checksum incrementally.
+pub struct Crc32c {
+    crc: u32,
+}
+
+impl Crc32c {
+    /// `new` creates a checksum of no data.
+    pub fn new() -> Crc32c {
+        Crc32c { crc: !0 }
+    }
+
+    /// `update` adds `data` to the checksum.
+    pub fn update(&mut self, data: &[u8]) {
+        for b in data.iter() {
+            self.crc = TABLE[((self.crc ^ *b as u32) & 0xFF) as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    /// `finish` returns the checksum of all of the data added so far.
+    pub fn finish(&self) -> u32 {
+        !self.crc
+    }
+}
+
+/// `crc32c` returns the CRC-32C checksum of `data`.
+pub fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = Crc32c::new();
+    crc.update(data);
+    crc.finish()
+}
@@ -18,19 +18,27 @@
 // Allow dead code to silence warnings until things stabilize.
 #![allow(dead_code)]
 
+use std::cmp;
+use std::error;
 use std::error::FromError;
+use std::fmt;
 use std::io;
-use std::io::ReadExt;
+use std::io::{Read, ReadExt};
 use std::mem;
 use std::result;
 use std::string::FromUtf8Error;
+use std::{u64, usize};
 
-use encoding::{CompleteEncoding, RecordEncoding, FieldEncoding, Type, FieldID};
-use primitive::Primitive;
+use context::ErrorContext;
+use crc::crc32c;
+use encoder::write_uvarint;
+use encoding::{CompleteEncoding, RecordEncoding, FieldEncoding, Type, FieldID, Quantifier};
+use primitive::{Primitive, SliceMut, fixed_width, slice_bytes_mut};
 
-use iter::{RexIterExt, ResultIterExt};
+use iter::ResultIterExt;
 
 /// `Error` is used to report errors that occur during the decoding process.
+#[derive(Debug)]
 pub enum Error {
     /// `EOF` indicates that record source ended before it could be fully decoded.
     EOF,
@@ -51,11 +59,73 @@ pub enum Error {
     /// `0x00`, indicating that the decoder is incorrect in expecting it to be a bool.
     BadBool,
 
+    /// `BadVarint` indicates that a varint was read which was too long to fit in 64 bits.
+    BadVarint,
+
+    /// `BadEnum` indicates that an enum value was read which the `Decodable` doesn't recognize.
+    BadEnum(i64),
+
+    /// `BadCompression` indicates that compressed data couldn't be decompressed, either because
+    /// it's corrupt or because it was compressed with a different codec.
+    BadCompression,
+
+    /// `ChecksumMismatch` indicates that a record read by `decode_from_checked` doesn't match its
+    /// checksum, so it was corrupted after it was written.
+    ChecksumMismatch,
+
+    /// `SizeLimitExceeded` indicates that a record is larger than the `max_bytes` of the
+    /// `DecodeLimits` it was decoded with.
+    SizeLimitExceeded,
+
+    /// `LengthLimitExceeded` indicates that a bytes or string field is longer than the
+    /// `max_length` of the `DecodeLimits` it was decoded with. It carries the length read off the
+    /// wire.
+    LengthLimitExceeded(u64),
+
+    /// `CountLimitExceeded` indicates that a repeated field has more elements than the
+    /// `max_count` of the `DecodeLimits` it was decoded with. It carries the count read off the
+    /// wire.
+    CountLimitExceeded(u64),
+
+    /// `DepthLimitExceeded` indicates that records are nested more deeply than the `max_depth`
+    /// of the `DecodeLimits` they were decoded with.
+    DepthLimitExceeded,
+
+    /// `UnionConflict` indicates that a record has more than one member of a union. It carries
+    /// the name of the union.
+    UnionConflict(String),
+
+    /// `UnionEmpty` indicates that a record has no member of a union. It carries the name of the
+    /// union.
+    UnionEmpty(String),
+
     /// `Utf8Error` is used to pass through `std::str::FromUtf8Error`s.
     Utf8Error(FromUtf8Error),
 
     /// `IoError` is used to pass through `std::io` errors.
     IoError(io::Error),
+
+    /// `InField` wraps an error which occurred while decoding a particular field, and says where
+    /// that field is. The wrapped error is never itself `InField`.
+    InField(Box<ErrorContext>, Box<Error>),
+}
+
+impl Error {
+    /// `root` returns the error without any `InField` context.
+    pub fn root(&self) -> &Error {
+        match *self {
+            Error::InField(_, ref err) => &**err,
+            ref err                    => err,
+        }
+    }
+
+    /// `context` returns the location of the error, if it occurred in a field.
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match *self {
+            Error::InField(ref ctx, _) => Some(&**ctx),
+            _                          => None,
+        }
+    }
 }
 
 impl FromError<io::Error> for Error {
@@ -70,6 +140,100 @@ impl FromError<FromUtf8Error> for Error {
     }
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::BadEnum(x)                => write!(f, "invalid enum value {}", x),
+            Error::LengthLimitExceeded(x)    => write!(f, "length {} exceeds the length limit", x),
+            Error::CountLimitExceeded(x)     => write!(f, "count {} exceeds the count limit", x),
+            Error::UnionConflict(ref u)      => write!(f, "more than one member of union {}", u),
+            Error::UnionEmpty(ref u)         => write!(f, "no member of union {}", u),
+            Error::Utf8Error(ref err)        => write!(f, "invalid utf-8 in string: {}", err),
+            Error::IoError(ref err)          => write!(f, "i/o error: {}", err),
+            Error::InField(ref ctx, ref err) => write!(f, "{}: {}", ctx, err),
+            ref err                          => write!(f, "{}", error::Error::description(err)),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::EOF                    => "unexpected end of input",
+            Error::FieldTypeMismatch      => "field type mismatch",
+            Error::EncodingInvalid        => "the encoding is invalid",
+            Error::BadBool                => "invalid bool",
+            Error::BadVarint              => "invalid varint",
+            Error::BadEnum(_)             => "invalid enum value",
+            Error::BadCompression         => "invalid compressed data",
+            Error::ChecksumMismatch       => "checksum mismatch",
+            Error::SizeLimitExceeded      => "record exceeds the size limit",
+            Error::LengthLimitExceeded(_) => "value exceeds the length limit",
+            Error::CountLimitExceeded(_)  => "repeated field exceeds the count limit",
+            Error::DepthLimitExceeded     => "records nested too deeply",
+            Error::UnionConflict(_)       => "more than one member of a union",
+            Error::UnionEmpty(_)          => "no member of a union",
+            Error::Utf8Error(_)           => "invalid utf-8 in string",
+            Error::IoError(_)             => "i/o error",
+            Error::InField(_, ref err)    => error::Error::description(&**err),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Utf8Error(ref err)  => Some(err as &error::Error),
+            Error::IoError(ref err)    => Some(err as &error::Error),
+            Error::InField(_, ref err) => Some(&**err as &error::Error),
+            _                          => None,
+        }
+    }
+}
+
+/// `DecodeLimits` bounds the resources that decoding a single record may use, so that records from
+/// untrusted sources can't exhaust memory or the stack. The `Default` limits are generous enough
+/// for any reasonable record, while `unlimited` places no bounds at all (this is what
+/// `decode_from` uses).
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub struct DecodeLimits {
+    /// `max_bytes` is the largest number of bytes that may be read for the whole record.
+    pub max_bytes: u64,
+
+    /// `max_length` is the longest that any bytes or string value may be. It also applies to
+    /// unknown fields kept with `Decodable::keep_unknown_fields`.
+    pub max_length: u64,
+
+    /// `max_count` is the largest number of elements that any repeated field may have.
+    pub max_count: u64,
+
+    /// `max_depth` is the deepest that records may be nested. The outermost record has depth 0.
+    pub max_depth: usize,
+}
+
+pub static UNLIMITED: DecodeLimits = DecodeLimits {
+    max_bytes:  u64::MAX,
+    max_length: u64::MAX,
+    max_count:  u64::MAX,
+    max_depth:  usize::MAX,
+};
+
+impl DecodeLimits {
+    /// `unlimited` returns a `DecodeLimits` which doesn't limit anything.
+    pub fn unlimited() -> DecodeLimits {
+        UNLIMITED
+    }
+}
+
+impl Default for DecodeLimits {
+    fn default() -> DecodeLimits {
+        DecodeLimits {
+            max_bytes:  64 << 20,
+            max_length: 16 << 20,
+            max_count:  1 << 20,
+            max_depth:  64,
+        }
+    }
+}
+
 /// The `Decodable` trait allows an object to be decoded from a rex record.
 pub trait Decodable {
     /// `set_primitive` sets the value of a single element of a field with primitive type.
@@ -83,6 +247,55 @@ pub trait Decodable {
     /// The boolean return value can be `false` to indicate that the receiver is uninterested in
     /// this field.
     fn alloc_field(&mut self, id: FieldID, count: usize) -> Result<bool, Error>;
+
+    /// `slice_mut` may return storage for all of the elements of a field with a fixed-width type
+    /// (see `SliceMut`), which the decoder then fills in directly. For optional and repeated fields
+    /// it is called after `alloc_field`. If it returns `None`, which is the default, the elements
+    /// are passed one at a time to `set_primitive` instead.
+    fn slice_mut(&mut self, id: FieldID) -> Option<SliceMut> {
+        let _ = id;
+        None
+    }
+
+    /// `set_union_arm` reports which member of a union is present in the record being decoded. It's
+    /// called with the name of the union and the id of the member before the member itself is
+    /// decoded. The default does nothing, since the member is also passed to `alloc_field`.
+    fn set_union_arm(&mut self, union: &str, id: FieldID) -> Result<(), Error> {
+        let _ = (union, id);
+        Ok(())
+    }
+
+    /// `set_map_key` sets the key of entry `idx` of a map field. It's called before the value of
+    /// the entry is passed to `set_primitive` or `decode_record` with the same `idx`. The default
+    /// returns `FieldTypeMismatch`, which is fine for types without map fields.
+    fn set_map_key(&mut self, id: FieldID, idx: usize, key: Primitive) -> Result<(), Error> {
+        let _ = (id, idx, key);
+        Err(Error::FieldTypeMismatch)
+    }
+
+    /// `fill_defaults` should return true if optional fields which are absent but have a `default`
+    /// in their `FieldEncoding` should be set to it. The field is passed to `alloc_field` and then
+    /// `set_primitive`, just as if the default had been read off the wire. The default is false,
+    /// which leaves absent fields alone.
+    fn fill_defaults(&self) -> bool {
+        false
+    }
+
+    /// `keep_unknown_fields` should return true if optional and repeated fields which aren't in
+    /// the encoding being used should be passed to `add_unknown_field` instead of being skipped.
+    /// The default is false.
+    fn keep_unknown_fields(&self) -> bool {
+        false
+    }
+
+    /// `add_unknown_field` receives the raw data of a field which isn't in the encoding being
+    /// used, if `keep_unknown_fields` returns true. `data` is everything following the field's
+    /// byte-size prefix. Unknown fields are passed in the order they appear on the wire, which is
+    /// id order, and returning them from `Encodable::unknown_fields` writes them back out.
+    fn add_unknown_field(&mut self, id: FieldID, data: Vec<u8>) -> Result<(), Error> {
+        let _ = (id, data);
+        Ok(())
+    }
 }
 
 /// A `Decoder` is a struct that knows how to decode a particular record field. `Decoder`s should
@@ -97,6 +310,58 @@ pub struct Decoder<'x, R: io::Read + 'x> {
     // `deps` is a (full) slice of the `depends` field of the `CompleteEncoding` that `rec` is a
     // member of.
     deps: &'x [RecordEncoding],
+
+    // `limits` are the bounds being enforced on this record, and `depth` is how deeply `rec` is
+    // nested inside of the outermost record.
+    limits: &'x DecodeLimits,
+    depth:  usize,
+}
+
+/// Values of bytes and strings are read incrementally past this size, so that a bogus length can't
+/// cause a huge allocation up front. `Decodable`s should likewise cap what they reserve for
+/// repeated fields in `alloc_field` at this many elements, and create the elements as they're
+/// decoded (see `next_elem`).
+pub const PREALLOC_MAX: usize = 4096;
+
+/// `next_elem` returns element `idx` of `v`, first pushing one made by `new` if `idx` is just past
+/// the end. Elements of repeated fields are decoded in order, so a `Decodable` can use this to
+/// create them as they arrive rather than trusting the count given to `alloc_field`.
+pub fn next_elem<T, F>(v: &mut Vec<T>, idx: usize, new: F) -> Option<&mut T>
+    where F: FnOnce() -> T {
+
+    if idx == v.len() {
+        v.push(new());
+    }
+
+    v.get_mut(idx)
+}
+
+// A `Limited` reads at most `remaining` bytes from `r`, and then reports EOF. It remembers whether
+// it did so, so that the resulting error can be reported as `SizeLimitExceeded`.
+struct Limited<'x, R: io::Read + 'x> {
+    r:         &'x mut R,
+    remaining: u64,
+    exceeded:  bool,
+}
+
+impl<'x, R> io::Read for Limited<'x, R>
+    where R: io::Read + 'x {
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            if buf.len() > 0 {
+                self.exceeded = true;
+            }
+            return Ok(0);
+        }
+
+        let max = cmp::min(buf.len() as u64, self.remaining) as usize;
+        let n   = try!(self.r.read(&mut buf[..max]));
+
+        self.remaining -= n as u64;
+
+        Ok(n)
+    }
 }
 
 /// `decode_from` reads a record from `r` and decodes it into `d`, according to `enc`. In order for
@@ -110,103 +375,359 @@ pub fn decode_from<'x, R, D>(enc: &'x CompleteEncoding, r: &'x mut R, d: &'x mut
     where R: io::Read + 'x,
           D: Decodable {
 
-    let mut dec = Decoder {
-        r:    r,
-        rec:  &enc.target,
-        deps: &enc.depends[],
+    decode_from_with_limits(enc, r, d, &UNLIMITED)
+}
+
+/// `decode_from_with_limits` is like `decode_from`, but fails with one of `SizeLimitExceeded`,
+/// `LengthLimitExceeded`, `CountLimitExceeded` or `DepthLimitExceeded` if the record exceeds
+/// `limits`. Limits are checked before anything is allocated for a field, so this is the function
+/// to use for records from untrusted sources.
+pub fn decode_from_with_limits<'x, R, D>(enc:    &'x CompleteEncoding,
+                                         r:      &'x mut R,
+                                         d:      &'x mut D,
+                                         limits: &'x DecodeLimits) -> Result<(), Error>
+    where R: io::Read + 'x,
+          D: Decodable {
+
+    let mut lim = Limited {
+        r:         r,
+        remaining: limits.max_bytes,
+        exceeded:  false,
+    };
+
+    let res = {
+        let mut dec = Decoder {
+            r:      &mut lim,
+            rec:    &enc.target,
+            deps:   &enc.depends[],
+            limits: limits,
+            depth:  0,
+        };
+
+        dec.decode(d)
     };
 
-    dec.decode(d)
+    let offset   = limits.max_bytes - lim.remaining;
+    let exceeded = lim.exceeded;
+
+    res.map_err(|err| {
+        let err = at_offset(err, offset);
+
+        // Running out of bytes looks like EOF to the `Decoder`, so it needs to be translated.
+        if exceeded {
+            map_root(err, |err| match err {
+                Error::EOF => Error::SizeLimitExceeded,
+                err        => err,
+            })
+        } else {
+            err
+        }
+    })
+}
+
+/// `decode_from_checked` reads a record written by `encode_to_checked` from `r`, and decodes it
+/// into `d` according to `enc`. The whole record is read and its checksum verified before any
+/// methods of `d` are called, so corrupt data is reported as `ChecksumMismatch` rather than as
+/// whatever error it happens to cause in decoding.
+///
+/// Checked records are meant for data which might have been damaged, so unlike `decode_from` this
+/// applies the `Default` limits; see `decode_from_checked_with_limits`.
+pub fn decode_from_checked<'x, R, D>(enc: &'x CompleteEncoding, r: &'x mut R, d: &'x mut D)
+    -> Result<(), Error>
+    where R: io::Read + 'x,
+          D: Decodable {
+
+    decode_from_checked_with_limits(enc, r, d, &Default::default())
+}
+
+/// `decode_from_checked_with_limits` is like `decode_from_checked`, but enforces `limits` as
+/// `decode_from_with_limits` does. A length prefix larger than `max_bytes` is reported as
+/// `SizeLimitExceeded` before the record is read.
+pub fn decode_from_checked_with_limits<'x, R, D>(enc:    &'x CompleteEncoding,
+                                                 r:      &'x mut R,
+                                                 d:      &'x mut D,
+                                                 limits: &'x DecodeLimits) -> Result<(), Error>
+    where R: io::Read + 'x,
+          D: Decodable {
+
+    let len = try!(read_uvarint(r));
+
+    if len > limits.max_bytes {
+        return Err(Error::SizeLimitExceeded);
+    }
+
+    let mut frame = vec![];
+    try!(write_uvarint(&mut frame, len));
+
+    let start = frame.len();
+    try!((&mut *r).take(len).read_to_end(&mut frame));
+
+    if ((frame.len() - start) as u64) < len {
+        return Err(Error::EOF);
+    }
+
+    let expected = try!(read_le_u32(r));
+
+    if crc32c(&frame[..]) != expected {
+        return Err(Error::ChecksumMismatch);
+    }
+
+    let mut rd = &frame[start..];
+
+    let res = {
+        let mut dec = Decoder {
+            r:      &mut rd,
+            rec:    &enc.target,
+            deps:   &enc.depends[],
+            limits: limits,
+            depth:  0,
+        };
+
+        dec.decode(d)
+    };
+
+    let offset = (frame.len() - rd.len()) as u64;
+    try!(res.map_err(|err| at_offset(err, offset)));
+
+    // The length prefix is covered by the checksum, so a disagreement here means that `enc` is
+    // the wrong encoding.
+    if !rd.is_empty() {
+        return Err(Error::EncodingInvalid);
+    }
+
+    Ok(())
 }
 
 impl<'x, R> Decoder<'x, R>
     where R: io::Read + 'x {
 
+    /// `record_encoding` returns the encoding of the record type that this `Decoder` decodes.
+    pub fn record_encoding(&self) -> &'x RecordEncoding {
+        self.rec
+    }
+
     /// `decode` decodes the next record on the wire into `d`.
     pub fn decode<D>(&mut self, d: &mut D) -> Result<(), Error>
         where D: Decodable {
 
         use encoding::Quantifier::*;
 
+        let rec = self.rec;
+
         for req_field in self.rec.req_fields.iter() {
-            try!(self.decode_required(d, req_field));
+            try!(self.decode_required(d, req_field).map_err(|err| in_field(err, rec, req_field)));
         }
 
-        let mut opt_rep_itr = self.rec.opt_rep_fields.iter();
+        let mut opt_rep_itr = rec.opt_rep_fields.iter();
         let mut next_field  = opt_rep_itr.next();
         let mut next_id     = FieldID(try!(read_uvarint(self.r)));
 
+        // The unions which have had a member decoded so far.
+        let mut arms: Vec<&str> = vec![];
+
+        // The fields with defaults which were present.
+        let mut present: Vec<FieldID> = vec![];
+
         while next_id != FieldID(0) {
             match next_field {
                 Some(field) => if field.id < next_id {
                     next_field = opt_rep_itr.next();
                 } else if field.id > next_id {
-                    try!(self.skip_field());
+                    try!(self.skip_unknown(d, next_id));
                     next_id = FieldID(try!(read_uvarint(self.r)));
                 } else {
-                    match field.quant {
-                        Required => return Err(Error::EncodingInvalid),
-                        Optional => try!(self.decode_optional(d, field)),
-                        Repeated => try!(self.decode_repeated(d, field)),
+                    if let Some(ref union) = field.union {
+                        if arms.contains(&&union[..]) {
+                            return Err(in_field(Error::UnionConflict(union.clone()), rec, field));
+                        }
+
+                        arms.push(&union[..]);
+                        try!(d.set_union_arm(&union[..], field.id)
+                             .map_err(|err| in_field(err, rec, field)));
                     }
+
+                    if field.default.is_some() {
+                        present.push(field.id);
+                    }
+
+                    try!( match field.quant {
+                        Required => Err(Error::EncodingInvalid),
+                        Optional => self.decode_optional(d, field),
+                        Repeated => self.decode_repeated(d, field),
+                    }.map_err(|err| in_field(err, rec, field)));
                     next_field = opt_rep_itr.next();
                     next_id    = FieldID(try!(read_uvarint(self.r)));
                 },
-                None => next_id = FieldID(try!(read_uvarint(self.r))),
+                None => {
+                    try!(self.skip_unknown(d, next_id));
+                    next_id = FieldID(try!(read_uvarint(self.r)));
+                },
+            }
+        }
+
+        // A missing union is reported against its first member.
+        for union in rec.unions().into_iter() {
+            if !arms.contains(&union) {
+                let err = Error::UnionEmpty(union.to_string());
+
+                return Err(match rec.opt_rep_fields.iter().find(|f| f.in_union(union)) {
+                    Some(field) => in_field(err, rec, field),
+                    None        => err,
+                });
+            }
+        }
+
+        if d.fill_defaults() {
+            for field in rec.opt_rep_fields.iter() {
+                if let Some(ref default) = field.default {
+                    if !present.contains(&field.id) {
+                        try!(fill_default(d, field, default)
+                             .map_err(|err| in_field(err, rec, field)));
+                    }
+                }
             }
         }
 
         Ok(())
     }
 
+    // Skip a field which isn't in `rec`, unless `d` wants to keep it.
+    fn skip_unknown<D>(&mut self, d: &mut D, id: FieldID) -> Result<(), Error>
+        where D: Decodable {
+
+        if !d.keep_unknown_fields() {
+            return self.skip_field();
+        }
+
+        // The byte-size prefix of a field is laid out just like the length of a bytes value.
+        let data = try!(self.read_bytes());
+        d.add_unknown_field(id, data)
+    }
+
     fn skip_field(&mut self) -> Result<(), Error> {
         let len = try!(read_uvarint(self.r)) as usize;
+        self.skip_bytes(len)
+    }
+
+    fn skip_bytes(&mut self, len: usize) -> Result<(), Error> {
         result::fold(self.r.bytes().take_or_err(len, Error::EOF), (), |(), _| ())
     }
 
     fn child(&mut self, index: usize) -> Result<Decoder<R>, Error> {
+        if self.depth >= self.limits.max_depth {
+            return Err(Error::DepthLimitExceeded);
+        }
+
         if index < self.deps.len() {
             Ok( Decoder {
                 r:      self.r,
                 rec:    &self.deps[index],
                 deps:   self.deps,
+                limits: self.limits,
+                depth:  self.depth + 1,
             })
         } else {
             Err(Error::EncodingInvalid)
         }
     }
 
+    // Read a length-prefixed bytes or string value, checking the length against `max_length`.
+    fn read_bytes(&mut self) -> Result<Vec<u8>, Error> {
+        let len = try!(read_uvarint(self.r));
+
+        if len > self.limits.max_length {
+            return Err(Error::LengthLimitExceeded(len));
+        }
+
+        let len = len as usize;
+
+        result::fold(
+            self.r.bytes().take_or_err(len, Error::EOF),
+            Vec::with_capacity(cmp::min(len, PREALLOC_MAX)),
+            |mut v, elem| {
+                v.push(elem);
+                v
+        })
+    }
+
     fn decode_required<D>(&mut self, d: &mut D, f: &FieldEncoding) -> Result<(), Error>
         where D: Decodable {
 
-        self.decode_array(d, f, 0)
+        self.decode_values(d, f, 1)
     }
 
     fn decode_optional<D>(&mut self, d: &mut D, f: &FieldEncoding) -> Result<(), Error>
         where D: Decodable {
 
-        // Discard the byte-size prefix. We don't need it.
-        try!(read_uvarint(self.r));
+        // The byte-size prefix is only needed if the `Decodable` isn't interested in this field.
+        let size = try!(read_uvarint(self.r)) as usize;
+
+        if !try!(d.alloc_field(f.id, 1)) {
+            return self.skip_bytes(size);
+        }
 
-        self.decode_array(d, f, 0)
+        self.decode_values(d, f, 1)
     }
 
     fn decode_repeated<D>(&mut self, d: &mut D, f: &FieldEncoding) -> Result<(), Error>
         where D: Decodable {
 
-        // Discard the byte-size prefix. We don't need it.
-        try!(read_uvarint(self.r));
+        use primitive::uvarint_size;
 
-        let len = try!(read_uvarint(self.r)) as usize;
+        // The byte-size prefix is only needed if the `Decodable` isn't interested in this field.
+        let size = try!(read_uvarint(self.r));
+        let len  = try!(read_uvarint(self.r));
 
-        for idx in 0..len {
-            try!(self.decode_array(d, f, idx));
+        if len > self.limits.max_count {
+            return Err(Error::CountLimitExceeded(len));
         }
 
-        Ok(())
+        if !try!(d.alloc_field(f.id, len as usize)) {
+            return match size.checked_sub(uvarint_size(len) as u64) {
+                Some(rest) => self.skip_bytes(rest as usize),
+                None       => Err(Error::EncodingInvalid),
+            };
+        }
+
+        self.decode_values(d, f, len as usize)
     }
 
+    // Decode `count` values of `f` (each of which is a whole array, if `f` has bounds). If `d` can
+    // provide storage for the elements as a `SliceMut`, they're read in bulk.
+    fn decode_values<D>(&mut self, d: &mut D, f: &FieldEncoding, count: usize) -> Result<(), Error>
+        where D: Decodable {
+
+        if fixed_width(f.typ).is_some() {
+            if let Some(mut slice) = d.slice_mut(f.id) {
+                let len = match count.checked_mul(f.bounds.unwrap_or(1)) {
+                    Some(len) => len,
+                    None      => return Err(Error::CountLimitExceeded(count as u64)),
+                };
+
+                if !slice.has_type(f.typ) || slice.len() != len {
+                    return Err(Error::FieldTypeMismatch);
+                }
+
+                let (bytes, width) = slice_bytes_mut(&mut slice);
+                try!(read_full(self.r, bytes));
+
+                // The wire format is little endian, so each element needs its bytes reversed.
+                if cfg!(target_endian = "big") {
+                    for chunk in bytes.chunks_mut(width) {
+                        chunk.reverse();
+                    }
+                }
+
+                return Ok(());
+            }
+        }
+
+        for idx in 0..count {
+            try!(self.decode_array(d, f, idx));
+        }
 
+        Ok(())
+    }
 
     fn decode_array<D>(&mut self, d: &mut D, f: &FieldEncoding, idx: usize) -> Result<(), Error>
         where D: Decodable {
@@ -225,7 +746,75 @@ impl<'x, R> Decoder<'x, R>
     fn decode_field<D>(&mut self, d: &mut D, f: &FieldEncoding, idx: usize) -> Result<(), Error>
         where D: Decodable {
 
-        let prim = match f.typ {
+        let rec = self.rec;
+
+        self.decode_element(d, f, idx).map_err(|err| match err {
+            Error::InField(mut ctx, err) => {
+                ctx.enter(rec, f, Some(idx));
+                Error::InField(ctx, err)
+            },
+            err => Error::InField(Box::new(ErrorContext::new(rec, f, Some(idx))), Box::new(err)),
+        })
+    }
+
+    fn decode_element<D>(&mut self, d: &mut D, f: &FieldEncoding, idx: usize) -> Result<(), Error>
+        where D: Decodable {
+
+        self.decode_value(d, f.id, f.typ, idx)
+    }
+
+    // Decode element `idx` of the field `id`, which has type `typ`. For map fields `typ` may be the
+    // type of the values rather than of the field.
+    fn decode_value<D>(&mut self, d: &mut D, id: FieldID, typ: Type, idx: usize)
+        -> Result<(), Error>
+        where D: Decodable {
+
+        match typ {
+            // Records work a little differently. Create a child decoder and have the `Decodable`
+            // run it on its own record field.
+            Type::Record{index: dep_index} => {
+                let child = try!(self.child(dep_index));
+                d.decode_record(child, id, idx)
+            },
+
+            Type::Map{index: entry_index} => self.decode_entry(d, id, entry_index, idx),
+
+            _ => {
+                let prim = try!(self.read_primitive(typ));
+                d.set_primitive(id, idx, prim)
+            },
+        }
+    }
+
+    // Decode entry `idx` of the map field `id`, whose entry type is at `entry_index` in `deps`.
+    // The key is passed to `set_map_key` before the value is decoded.
+    fn decode_entry<D>(&mut self, d: &mut D, id: FieldID, entry_index: usize, idx: usize)
+        -> Result<(), Error>
+        where D: Decodable {
+
+        let deps = self.deps;
+
+        let (key, value) = match deps.get(entry_index).and_then(|rec| rec.map_entry()) {
+            Some(entry) => entry,
+            None        => return Err(Error::EncodingInvalid),
+        };
+
+        let prim = try!(self.read_primitive(key.typ));
+        try!(d.set_map_key(id, idx, prim));
+        try!(self.decode_value(d, id, value.typ, idx));
+
+        // Entry records have no optional fields, but a newer writer might have added some.
+        loop {
+            match try!(read_uvarint(self.r)) {
+                0 => return Ok(()),
+                _ => try!(self.skip_field()),
+            }
+        }
+    }
+
+    // Read a single value of the primitive type `typ`.
+    fn read_primitive(&mut self, typ: Type) -> Result<Primitive, Error> {
+        Ok( match typ {
             Type::UInt8  => Primitive::UInt8(try!(read_u8(self.r))),
             Type::UInt16 => Primitive::UInt16(try!(read_le_u16(self.r))),
             Type::UInt32 => Primitive::UInt32(try!(read_uvarint(self.r)) as u32),
@@ -248,72 +837,121 @@ impl<'x, R> Decoder<'x, R>
                 _    => return Err(Error::BadBool),
             }),
 
-            Type::Bytes => Primitive::Bytes({
-                let len = try!(read_uvarint(self.r)) as usize;
-                try!(result::fold(
-                    self.r.bytes().take_or_err(len, Error::EOF),
-                    Vec::with_capacity(len),
-                    |mut v, elem| {
-                        v.push(elem);
-                        v
-                }))
-            }),
-
-            Type::String => Primitive::String({
-                let len = try!(read_uvarint(self.r)) as usize;
-                let utf8 = try!(result::fold(
-                    self.r.bytes().take_or_err(len, Error::EOF),
-                    Vec::with_capacity(len),
-                    |mut v, elem| {
-                        v.push(elem);
-                        v
-                }));
-
-                try!(String::from_utf8(utf8))
-            }),
+            Type::Bytes  => Primitive::Bytes(try!(self.read_bytes())),
+            Type::String => Primitive::String(try!(String::from_utf8(try!(self.read_bytes())))),
 
             Type::Enum => Primitive::Enum(try!(read_varint(self.r))),
 
-            // Records work a little differently. Create a child decoder and have the `Decodable`
-            // run it on its own record field.
-            Type::Record{index: dep_index} => {
-                let child = try!(self.child(dep_index));
-                return d.decode_record(child, f.id, idx);
-            },
-        };
+            Type::Record{..} | Type::Map{..} => return Err(Error::EncodingInvalid),
+        })
+    }
+}
 
-        d.set_primitive(f.id, idx, prim)
+// Apply `f` to the error wrapped by `err`, keeping its context.
+fn map_root<F>(err: Error, f: F) -> Error
+    where F: FnOnce(Error) -> Error {
+
+    match err {
+        Error::InField(ctx, err) => Error::InField(ctx, Box::new(f(*err))),
+        err                      => f(err),
+    }
+}
+
+// Record in the context of `err` that it was detected `offset` bytes into the record.
+fn at_offset(err: Error, offset: u64) -> Error {
+    match err {
+        Error::InField(mut ctx, err) => {
+            ctx.offset = Some(offset);
+            Error::InField(ctx, err)
+        },
+        err => err,
+    }
+}
+
+// Set the absent field `f` of `d` to its default.
+fn fill_default<D>(d: &mut D, f: &FieldEncoding, default: &Primitive) -> Result<(), Error>
+    where D: Decodable {
+
+    if f.quant != Quantifier::Optional || f.bounds.is_some() {
+        return Err(Error::EncodingInvalid);
+    }
+
+    if try!(d.alloc_field(f.id, 1)) {
+        try!(d.set_primitive(f.id, 0, default.clone()));
+    }
+
+    Ok(())
+}
+
+// Attach the location of `f` to `err`, unless it already has one. This is for errors which aren't
+// tied to a particular element of `f`.
+fn in_field(err: Error, rec: &RecordEncoding, f: &FieldEncoding) -> Error {
+    match err {
+        Error::InField(ctx, err) => Error::InField(ctx, err),
+        err                      => Error::InField(Box::new(ErrorContext::new(rec, f, None)),
+                                                   Box::new(err)),
     }
 }
 
 /// `read_uvarint` reads a varint encoded `u64` from `r`.
-fn read_uvarint<R>(r: &mut R) -> Result<u64, Error>
+pub fn read_uvarint<R>(r: &mut R) -> Result<u64, Error>
     where R: io::Read {
 
-    let itr = r.bytes()
-        .take_while_incl(|x| match x { &Ok(x) => x >= 0x80, _ => true });
+    let mut x     = 0u64;
+    let mut shift = 0usize;
+
+    loop {
+        let b = try!(read_u8(r));
 
-    result::fold(itr, 0, |sum, x| (sum << 7) + (x & 0x7F) as u64)
-        .map_err(FromError::from_error)
+        // A 64-bit varint can be at most 10 bytes long, and only the lowest bit of the 10th byte
+        // fits.
+        if shift == 63 && b > 1 {
+            return Err(Error::BadVarint);
+        }
+
+        x |= ((b & 0x7F) as u64) << shift;
+
+        if b < 0x80 {
+            return Ok(x);
+        }
+
+        shift += 7;
+    }
 }
 
 /// `read_varint` reads a zig-zag varint encoded `i64` from `r`.
-fn read_varint<R>(r: &mut R) -> Result<i64, Error>
+pub fn read_varint<R>(r: &mut R) -> Result<i64, Error>
     where R: io::Read {
 
     let ux = try!(read_uvarint(r));
 
     Ok( if ux & 1 != 0 {
-        let x = (!ux >> 1) as i64;
-        -x
+        let x = !(ux >> 1) as i64;
+        x
     } else {
         let x = (ux >> 1) as i64;
         x
     })
 }
 
+/// `read_full` fills `buf` with bytes read from `r`.
+fn read_full<R>(r: &mut R, buf: &mut [u8]) -> Result<(), Error>
+    where R: io::Read {
+
+    let mut pos = 0;
+
+    while pos < buf.len() {
+        match try!(r.read(&mut buf[pos..])) {
+            0 => return Err(Error::EOF),
+            n => pos += n,
+        }
+    }
+
+    Ok(())
+}
+
 /// `read_u8` reads a single byte from `r`.
-fn read_u8<R>(r: &mut R) -> Result<u8, Error>
+pub fn read_u8<R>(r: &mut R) -> Result<u8, Error>
     where R: io::Read {
 
     r.bytes()
@@ -324,55 +962,61 @@ fn read_u8<R>(r: &mut R) -> Result<u8, Error>
 }
 
 /// `read_le_u16` reads 2 bytes as a little endian `u16` from 'r'
-fn read_le_u16<R>(r: &mut R) -> Result<u16, Error>
+pub fn read_le_u16<R>(r: &mut R) -> Result<u16, Error>
     where R: io::Read {
 
     let itr = r.bytes().take_or_err(2, Error::EOF);
 
-    result::fold(itr, 0, |so_far, next| (so_far << 8) + next as u16)
+    // The least significant byte comes first.
+    result::fold(itr, (0, 0usize), |(so_far, shift), next| (so_far | (next as u16) << shift, shift + 8))
+        .map(|(x, _)| x)
 }
 
 /// `read_le_u32` reads 4 bytes as a little endian `u32` from 'r'
-fn read_le_u32<R>(r: &mut R) -> Result<u32, Error>
+pub fn read_le_u32<R>(r: &mut R) -> Result<u32, Error>
     where R: io::Read {
 
     let itr = r.bytes().take_or_err(4, Error::EOF);
 
-    result::fold(itr, 0, |so_far, next| (so_far << 8) + next as u32)
+    // The least significant byte comes first.
+    result::fold(itr, (0, 0usize), |(so_far, shift), next| (so_far | (next as u32) << shift, shift + 8))
+        .map(|(x, _)| x)
 }
 
 /// `read_le_u64` reads 8 bytes as a little endian `u64` from 'r'
-fn read_le_u64<R>(r: &mut R) -> Result<u64, Error>
+pub fn read_le_u64<R>(r: &mut R) -> Result<u64, Error>
     where R: io::Read {
 
     let itr = r.bytes().take_or_err(8, Error::EOF);
 
-    result::fold(itr, 0, |so_far, next| (so_far << 8) + next as u64)
+    // The least significant byte comes first.
+    result::fold(itr, (0, 0usize), |(so_far, shift), next| (so_far | (next as u64) << shift, shift + 8))
+        .map(|(x, _)| x)
 }
 
 /// `read_i8` reads a single byte from `r`, as a 2's complement `i8`
-fn read_i8<R>(r: &mut R) -> Result<i8, Error>
+pub fn read_i8<R>(r: &mut R) -> Result<i8, Error>
     where R: io::Read {
 
     Ok(try!(read_u8(r)) as i8)
 }
 
 /// `read_le_i16` reads 2 bytes as a little endian 2's complement `i16` from `r`.
-fn read_le_i16<R>(r: &mut R) -> Result<i16, Error>
+pub fn read_le_i16<R>(r: &mut R) -> Result<i16, Error>
     where R: io::Read {
 
     Ok(try!(read_le_u16(r)) as i16)
 }
 
 /// `read_le_i32` reads 4 bytes as a little endian 2's complement `i32` from `r`.
-fn read_le_i32<R>(r: &mut R) -> Result<i32, Error>
+pub fn read_le_i32<R>(r: &mut R) -> Result<i32, Error>
     where R: io::Read {
 
     Ok(try!(read_le_u32(r)) as i32)
 }
 
 /// `read_le_i64` reads 8 bytes as a little endian 2's complement `i64` from `r`.
-fn read_le_i64<R>(r: &mut R) -> Result<i64, Error>
+pub fn read_le_i64<R>(r: &mut R) -> Result<i64, Error>
     where R: io::Read {
 
     Ok(try!(read_le_u64(r)) as i64)
@@ -380,7 +1024,7 @@ fn read_le_i64<R>(r: &mut R) -> Result<i64, Error>
 
 
 /// `read_le_f32` reads 8 bytes as a little endian ieee-754 binary32 encoded `f32` from `r`.
-fn read_le_f32<R>(r: &mut R) -> Result<f32, Error>
+pub fn read_le_f32<R>(r: &mut R) -> Result<f32, Error>
     where R: io::Read {
 
     let u = try!(read_le_u32(r));
@@ -389,7 +1033,7 @@ fn read_le_f32<R>(r: &mut R) -> Result<f32, Error>
 }
 
 /// `read_le_f64` reads 8 bytes as a little endian ieee-754 binary64 encoded `f64` from `r`.
-fn read_le_f64<R>(r: &mut R) -> Result<f64, Error>
+pub fn read_le_f64<R>(r: &mut R) -> Result<f64, Error>
     where R: io::Read {
 
     let u = try!(read_le_u64(r));
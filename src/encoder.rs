@@ -17,14 +17,19 @@
 
 #![allow(dead_code)]
 
+use std::error;
 use std::error::FromError;
+use std::fmt;
 use std::io;
 use std::mem;
 
+use context::ErrorContext;
+use crc::crc32c;
 use encoding::{CompleteEncoding, RecordEncoding, FieldEncoding, Type, FieldID};
-use primitive::Primitive;
+use primitive::{Primitive, Slice, fixed_width, slice_bytes};
 
 /// `Error` is used to report errors that occur during the encoding process.
+#[derive(Debug)]
 pub enum Error {
     /// `EncodingInvalid` indicates that there is an inconsistency in the `CompleteEncoding` being
     /// used. A probable fix is to regenerate the encoding (or inspect the encoding compiler for
@@ -38,8 +43,41 @@ pub enum Error {
     /// 2. The `Encodable` implementation is not consistent with the record definition.
     FieldTypeMismatch,
 
+    /// `SizeMismatch` indicates that an `Encodable` produced different data on the two passes made
+    /// over it by `encode_streaming`.
+    SizeMismatch,
+
+    /// `UnionConflict` indicates that more than one member of a union is set. It carries the name
+    /// of the union.
+    UnionConflict(String),
+
+    /// `UnionEmpty` indicates that no member of a union is set. It carries the name of the union.
+    UnionEmpty(String),
+
     /// `IoError` allows propogation of i/o errors which are unrelated to the encoding process.
     IoError(io::Error),
+
+    /// `InField` wraps an error which occurred while encoding a particular field, and says where
+    /// that field is. The wrapped error is never itself `InField`.
+    InField(Box<ErrorContext>, Box<Error>),
+}
+
+impl Error {
+    /// `root` returns the error without any `InField` context.
+    pub fn root(&self) -> &Error {
+        match *self {
+            Error::InField(_, ref err) => &**err,
+            ref err                    => err,
+        }
+    }
+
+    /// `context` returns the location of the error, if it occurred in a field.
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match *self {
+            Error::InField(ref ctx, _) => Some(&**ctx),
+            _                          => None,
+        }
+    }
 }
 
 impl FromError<io::Error> for Error {
@@ -48,6 +86,41 @@ impl FromError<io::Error> for Error {
     }
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnionConflict(ref u)      =>
+                write!(f, "more than one member of union {} is set", u),
+            Error::UnionEmpty(ref u)         => write!(f, "no member of union {} is set", u),
+            Error::IoError(ref err)          => write!(f, "i/o error: {}", err),
+            Error::InField(ref ctx, ref err) => write!(f, "{}: {}", ctx, err),
+            ref err                          => write!(f, "{}", error::Error::description(err)),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::EncodingInvalid     => "the encoding is invalid",
+            Error::FieldTypeMismatch   => "field type mismatch",
+            Error::SizeMismatch        => "record changed between passes",
+            Error::UnionConflict(_)    => "more than one member of a union is set",
+            Error::UnionEmpty(_)       => "no member of a union is set",
+            Error::IoError(_)          => "i/o error",
+            Error::InField(_, ref err) => error::Error::description(&**err),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::IoError(ref err)    => Some(err as &error::Error),
+            Error::InField(_, ref err) => Some(&**err as &error::Error),
+            _                          => None,
+        }
+    }
+}
+
 /// The `Encodable` trait allows an object to be encoded as a rex record.
 pub trait Encodable {
     /// `get_primitive` should return the value of a single element of a field with primitive type.
@@ -58,13 +131,37 @@ pub trait Encodable {
 
     /// `count_field` should return the number of members of an optional or repeated field.
     fn count_field(&self, id: FieldID) -> Result<usize, Error>;
-}
 
-/// A `Chunk` gives the offset into an `Encoder`'s `data` buffer at which a byte-size prefix needs
-/// to be written in the final output stream.
-struct Chunk {
-    offset: usize,
-    size:   usize,
+    /// `get_slice` may return all of the elements of a field with a fixed-width type (see `Slice`)
+    /// at once, as they would be indexed by `get_primitive`. If it returns `None`, which is the
+    /// default, the elements are fetched one at a time with `get_primitive` instead.
+    fn get_slice(&self, id: FieldID) -> Option<Slice> {
+        let _ = id;
+        None
+    }
+
+    /// `get_map_key` should return the key of entry `idx` of a map field. The value of the entry
+    /// is fetched with `get_primitive` or `encode_record`, using the same `idx`. The default
+    /// returns `FieldTypeMismatch`, which is fine for types without map fields.
+    fn get_map_key(&self, id: FieldID, idx: usize) -> Result<Primitive, Error> {
+        let _ = (id, idx);
+        Err(Error::FieldTypeMismatch)
+    }
+
+    /// `unknown_fields` returns fields which aren't in the encoding being used, as `(id, data)`
+    /// pairs like those passed to `Decodable::add_unknown_field`. They're written in id order
+    /// among the optional and repeated fields of the record. Any with the id of a field in the
+    /// encoding are ignored. The default is to return none.
+    fn unknown_fields(&self) -> &[(FieldID, Vec<u8>)] {
+        &[]
+    }
+
+    /// If `elide_defaults` returns true, optional fields whose value is equal to the `default` of
+    /// their `FieldEncoding` aren't written, since a reader will get the same value either way.
+    /// The default is false, so that every field which is set is written.
+    fn elide_defaults(&self) -> bool {
+        false
+    }
 }
 
 /// An `Encoder` is a struct that knows how to encode a particular record field. `Encoder`s should
@@ -77,70 +174,299 @@ pub struct Encoder<'x> {
     // member of.
     deps: &'x [RecordEncoding],
 
-    // `data` is the buffer into which the encoded data *other than byte-size prefixes* will be
-    // written. It is a staging area, since not all byte-size prefixes can be computed before the
-    // encoding is done.
-    data: &'x mut Vec<u8>,
-
-    // `chunks` is an unsorted list of byte-size prefixes along with the indices into `data` at
-    // which they should be written.
-    chunks: &'x mut Vec<Chunk>,
+    // `sink` receives the encoded data, and is told where the byte-size prefixes go.
+    sink: &'x mut (Sink + 'x),
 }
 
 /// `encode_to` encodes `e` as a record according to `enc`, and then writes the result to `w`. In
 /// order for this function to succeed, `enc` and `e` must correspond to the same record type.
 ///
+/// The whole record is staged in memory before anything is written. See `encode_streaming` for a
+/// way to avoid that.
+///
 /// # Errors
 ///
-/// If the error is not an `Error::IoError`, then no data was written to `w` before failure.
+/// If the `root` of the error is not an `Error::IoError`, then no data was written to `w` before
+/// failure.
 pub fn encode_to<'x, W, E>(enc: &'x CompleteEncoding, w: &'x mut W, e: &'x E) -> Result<(), Error>
     where W: io::Write + 'x,
           E: Encodable {
 
-    let mut data   = vec![];
-    let mut chunks = vec![];
+    let mut sink = StagedSink {
+        data:   vec![],
+        chunks: vec![],
+        open:   vec![],
+    };
+
+    try!(Encoder::new(enc, &mut sink).encode(e));
+
+    sink.write_to(w)
+}
 
-    let mut enc = Encoder {
-        rec:    &enc.target,
-        deps:   &enc.depends[],
-        data:   &mut data,
-        chunks: &mut chunks,
+/// `encode_to_checked` is like `encode_to`, but frames the record so that corruption can be
+/// detected when it's read back with `decode_from_checked`. The output is
+///
+/// 1. The length of the record in bytes, as a varint.
+/// 2. The record, encoded according to `enc`.
+/// 3. The CRC-32C of the two fields above, as 4 little-endian bytes.
+///
+/// # Errors
+///
+/// As with `encode_to`, if the `root` of the error is not an `Error::IoError` then nothing was
+/// written to `w`.
+pub fn encode_to_checked<'x, W, E>(enc: &'x CompleteEncoding, w: &'x mut W, e: &'x E)
+    -> Result<(), Error>
+    where W: io::Write + 'x,
+          E: Encodable {
+
+    let mut rec = vec![];
+    try!(encode_to(enc, &mut rec, e));
+
+    let mut frame = vec![];
+    try!(write_uvarint(&mut frame, rec.len() as u64));
+    try!(io::Write::write_all(&mut frame, &rec[..]));
+
+    let crc = crc32c(&frame[..]);
+    try!(write_le_u32(&mut frame, crc));
+
+    try!(w.write_all(&frame[..]));
+
+    Ok(())
+}
+
+/// `encode_streaming` encodes `e` as a record according to `enc`, writing it straight to `w`. It
+/// makes two passes over `e`: the first only computes the sizes of the optional and repeated
+/// fields, and the second writes the data, so memory use doesn't grow with the size of the record.
+///
+/// The output is identical to that of `encode_to`, but `e` must produce the same data on both
+/// passes.
+///
+/// # Errors
+///
+/// Unlike `encode_to`, this function may have written part of the record to `w` when it fails.
+/// If `e` changes between the passes, the `root` of the error is `Error::SizeMismatch`.
+pub fn encode_streaming<'x, W, E>(enc: &'x CompleteEncoding, w: &'x mut W, e: &'x E)
+    -> Result<(), Error>
+    where W: io::Write + 'x,
+          E: Encodable {
+
+    let mut sizer = SizingSink {
+        sizes: vec![],
+        open:  vec![],
     };
 
-    try!(enc.encode(e));
+    try!(Encoder::new(enc, &mut sizer).encode(e));
+
+    let mut sink = StreamingSink {
+        w:     w,
+        sizes: &sizer.sizes[..],
+        next:  0,
+        open:  vec![],
+    };
+
+    try!(Encoder::new(enc, &mut sink).encode(e));
+
+    if sink.next != sink.sizes.len() {
+        return Err(Error::SizeMismatch);
+    }
+
+    Ok(())
+}
+
+/// A `Sink` is the destination of an `Encoder`'s output. Byte-size prefixes can't be written until
+/// the data they cover has been encoded, so instead of writing them the `Encoder` brackets the data
+/// with `begin_prefix` and `end_prefix`, leaving it to the `Sink` to put them in the right place.
+/// Prefixes nest, since records may contain optional and repeated fields of their own.
+trait Sink: io::Write {
+    /// `begin_prefix` marks the position of a byte-size prefix.
+    fn begin_prefix(&mut self) -> Result<(), Error>;
+
+    /// `end_prefix` closes the most recently begun prefix, giving the size of the data it covers.
+    fn end_prefix(&mut self, size: usize) -> Result<(), Error>;
+}
+
+/// A `Chunk` gives the offset into a `StagedSink`'s `data` buffer at which a byte-size prefix needs
+/// to be written in the final output stream.
+struct Chunk {
+    offset: usize,
+    size:   usize,
+}
+
+/// A `StagedSink` buffers the whole record, and then stitches the byte-size prefixes into it as it
+/// is written out.
+struct StagedSink {
+    // `data` is the buffer into which the encoded data *other than byte-size prefixes* is written.
+    data: Vec<u8>,
+
+    // `chunks` is an unsorted list of byte-size prefixes along with the indices into `data` at
+    // which they should be written.
+    chunks: Vec<Chunk>,
+
+    // `open` holds the offsets of prefixes which have begun but not ended.
+    open: Vec<usize>,
+}
+
+impl io::Write for StagedSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::Write::write(&mut self.data, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
 
-    // There is a special case where there are no chunks, in which we just write all of the data
-    // straight to w.
-    if enc.chunks.is_empty() {
-        try!(w.write_all(&enc.data[]));
-        return Ok(());
+impl Sink for StagedSink {
+    fn begin_prefix(&mut self) -> Result<(), Error> {
+        self.open.push(self.data.len());
+        Ok(())
     }
 
-    // Now we have all of the chunks filled out, but they're out of order. Time to fix that!
-    enc.chunks.sort_by(|x, y| x.offset.cmp(&y.offset));
+    fn end_prefix(&mut self, size: usize) -> Result<(), Error> {
+        match self.open.pop() {
+            Some(offset) => {
+                self.chunks.push(Chunk { offset: offset, size: size });
+                Ok(())
+            }
+            None => Err(Error::EncodingInvalid),
+        }
+    }
+}
 
-    // Write everything that comes before the first size prefix.
-    try!(w.write_all(&enc.data[..enc.chunks[0].offset]));
+impl StagedSink {
+    // Write the staged data to `w`, with the byte-size prefixes in place.
+    fn write_to<W>(&mut self, w: &mut W) -> Result<(), Error>
+        where W: io::Write {
 
-    for win in enc.chunks.windows(2) {
-        match win {
-            [ref low, ref high] => {
-                try!(write_uvarint(w, low.size as u64));
-                try!(w.write_all(&enc.data[low.offset..high.offset]));
+        // There is a special case where there are no chunks, in which we just write all of the
+        // data straight to w.
+        if self.chunks.is_empty() {
+            try!(w.write_all(&self.data[]));
+            return Ok(());
+        }
+
+        // Now we have all of the chunks filled out, but they're out of order. Time to fix that!
+        self.chunks.sort_by(|x, y| x.offset.cmp(&y.offset));
+
+        // Write everything that comes before the first size prefix.
+        try!(w.write_all(&self.data[..self.chunks[0].offset]));
+
+        for win in self.chunks.windows(2) {
+            match win {
+                [ref low, ref high] => {
+                    try!(write_uvarint(w, low.size as u64));
+                    try!(w.write_all(&self.data[low.offset..high.offset]));
+                }
+                _ => unreachable!(),
             }
-            _ => unreachable!(),
         }
+
+        let last = &self.chunks[self.chunks.len() - 1];
+
+        try!(write_uvarint(w, last.size as u64));
+        try!(w.write_all(&self.data[last.offset..]));
+
+        Ok(())
     }
+}
 
-    let last = &enc.chunks[enc.chunks.len() - 1];
+/// A `SizingSink` discards the data written to it, and records the size of each byte-size prefix
+/// in the order in which they begin (which is the order they appear in the output).
+struct SizingSink {
+    sizes: Vec<usize>,
 
-    try!(write_uvarint(w, last.size as u64));
-    try!(w.write_all(&enc.data[last.offset..]));
+    // `open` holds the indices into `sizes` of prefixes which have begun but not ended.
+    open: Vec<usize>,
+}
 
-    Ok(())
+impl io::Write for SizingSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Sink for SizingSink {
+    fn begin_prefix(&mut self) -> Result<(), Error> {
+        self.open.push(self.sizes.len());
+        self.sizes.push(0);
+        Ok(())
+    }
+
+    fn end_prefix(&mut self, size: usize) -> Result<(), Error> {
+        match self.open.pop() {
+            Some(idx) => {
+                self.sizes[idx] = size;
+                Ok(())
+            }
+            None => Err(Error::EncodingInvalid),
+        }
+    }
+}
+
+/// A `StreamingSink` writes straight through to `w`, taking the byte-size prefixes from the sizes
+/// computed by a `SizingSink`.
+struct StreamingSink<'x, W: io::Write + 'x> {
+    w: &'x mut W,
+
+    sizes: &'x [usize],
+
+    // `next` is the index into `sizes` of the next prefix to begin.
+    next: usize,
+
+    // `open` holds the indices into `sizes` of prefixes which have begun but not ended.
+    open: Vec<usize>,
+}
+
+impl<'x, W> io::Write for StreamingSink<'x, W>
+    where W: io::Write + 'x {
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.w.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+impl<'x, W> Sink for StreamingSink<'x, W>
+    where W: io::Write + 'x {
+
+    fn begin_prefix(&mut self) -> Result<(), Error> {
+        if self.next >= self.sizes.len() {
+            return Err(Error::SizeMismatch);
+        }
+
+        try!(write_uvarint(self.w, self.sizes[self.next] as u64));
+
+        self.open.push(self.next);
+        self.next += 1;
+
+        Ok(())
+    }
+
+    fn end_prefix(&mut self, size: usize) -> Result<(), Error> {
+        match self.open.pop() {
+            Some(idx) if self.sizes[idx] == size => Ok(()),
+            Some(_)                              => Err(Error::SizeMismatch),
+            None                                 => Err(Error::EncodingInvalid),
+        }
+    }
 }
 
 impl<'x> Encoder<'x> {
+    fn new(enc: &'x CompleteEncoding, sink: &'x mut (Sink + 'x)) -> Encoder<'x> {
+        Encoder {
+            rec:  &enc.target,
+            deps: &enc.depends[],
+            sink: sink,
+        }
+    }
+
     /// The `encode` method should be called by implementations of the `encode_record` method of
     /// the `Encodable` trait. See that method's documentation for example usage.
     ///
@@ -162,37 +488,73 @@ impl<'x> Encoder<'x> {
 
         use encoding::Quantifier::*;
 
+        let rec       = self.rec;
         let mut total = 0;
 
+        // Check the unions first, so that nothing is written for a record which can't be encoded.
+        try!(check_unions(e, rec));
+
         for req_field in self.rec.req_fields.iter() {
             total += try!( match req_field.quant {
                 Required            => self.encode_required(e, req_field),
                 Repeated | Optional => Err(Error::EncodingInvalid),
-            })
+            }.map_err(|err| in_field(err, rec, req_field)))
         }
 
+        let mut unknown: Vec<&(FieldID, Vec<u8>)> = e.unknown_fields().iter()
+            .filter(|&&(id, _)| !rec.req_fields.iter().any(|f| f.id == id))
+            .collect();
+        unknown.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut next = 0;
+
         for opt_rep_field in self.rec.opt_rep_fields.iter() {
-            total += match opt_rep_field.quant {
-                Optional => try!(self.encode_optional(e, opt_rep_field)),
-                Repeated => try!(self.encode_repeated(e, opt_rep_field)),
-                Required => return Err(Error::EncodingInvalid),
+            while next < unknown.len() && unknown[next].0 <= opt_rep_field.id {
+                if unknown[next].0 < opt_rep_field.id {
+                    total += try!(self.encode_unknown(unknown[next]));
+                }
+                next += 1;
             }
+
+            total += try!( match opt_rep_field.quant {
+                Optional => self.encode_optional(e, opt_rep_field),
+                Repeated => self.encode_repeated(e, opt_rep_field),
+                Required => Err(Error::EncodingInvalid),
+            }.map_err(|err| in_field(err, rec, opt_rep_field)))
+        }
+
+        for field in unknown[next..].iter() {
+            total += try!(self.encode_unknown(*field));
         }
 
         // Write the final 0-id, marking the end of the record.
-        total += try!(write_uvarint(self.data, 0));
+        total += try!(write_uvarint(self.sink, 0));
 
         Ok(total)
     }
 
-    // Create an encoder with the same `data`/`chunks` fields, but which
+    // Write a field returned by `Encodable::unknown_fields`, exactly as it was read.
+    fn encode_unknown(&mut self, field: &(FieldID, Vec<u8>)) -> Result<usize, Error> {
+        use primitive::uvarint_size;
+
+        let (FieldID(id), ref data) = *field;
+
+        let len_id_prefix = try!(write_uvarint(self.sink, id));
+
+        try!(self.sink.begin_prefix());
+        try!(io::Write::write_all(self.sink, &data[..]));
+        try!(self.sink.end_prefix(data.len()));
+
+        Ok(len_id_prefix + uvarint_size(data.len() as u64) + data.len())
+    }
+
+    // Create an encoder with the same `sink`, but which encodes the record at `index` in `deps`.
     fn child(&mut self, index: usize) -> Result<Encoder, Error> {
         if index < self.deps.len() {
             Ok( Encoder {
-                rec:    &self.deps[index],
-                deps:   self.deps,
-                data:   self.data,
-                chunks: self.chunks,
+                rec:  &self.deps[index],
+                deps: self.deps,
+                sink: &mut *self.sink,
             })
         } else {
             Err(Error::EncodingInvalid)
@@ -202,7 +564,7 @@ impl<'x> Encoder<'x> {
     fn encode_required<E>(&mut self, e: &E, f: &FieldEncoding) -> Result<usize, Error>
         where E: Encodable {
 
-        self.encode_array(e, f, 0)
+        self.encode_values(e, f, 1)
     }
 
     fn encode_optional<E>(&mut self, e: &E, f: &FieldEncoding) -> Result<usize, Error>
@@ -215,10 +577,23 @@ impl<'x> Encoder<'x> {
             return Ok(0);
         }
 
-        let len_data        = try!(self.encode_array(e, f, 0));
-        let len_id_prefix   = { let FieldID(id) = f.id; uvarint_size(id as u64) };
+        // Only fields of primitive type without bounds have defaults.
+        if let Some(ref default) = f.default {
+            if e.elide_defaults() && try!(e.get_primitive(f.id, 0)) == *default {
+                return Ok(0);
+            }
+        }
+
+        let len_id_prefix = { let FieldID(id) = f.id; try!(write_uvarint(self.sink, id)) };
+
+        // The byte-size prefix goes here, once we know what it is.
+        try!(self.sink.begin_prefix());
+
+        let len_data        = try!(self.encode_values(e, f, 1));
         let len_size_prefix = uvarint_size(len_data as u64);
 
+        try!(self.sink.end_prefix(len_data));
+
         Ok(len_id_prefix + len_size_prefix + len_data)
     }
 
@@ -232,18 +607,69 @@ impl<'x> Encoder<'x> {
             return Ok(0);
         }
 
+        let len_id_prefix = { let FieldID(id) = f.id; try!(write_uvarint(self.sink, id)) };
+
+        // The byte-size prefix goes here, once we know what it is. It covers the length prefix as
+        // well as the data.
+        try!(self.sink.begin_prefix());
+
+        let len_length_prefix = try!(write_uvarint(self.sink, max as u64));
+
         // Bytes required to encode the data itself
-        let mut len_data = 0;
+        let len_data = try!(self.encode_values(e, f, max));
+
+        let size            = len_length_prefix + len_data;
+        let len_size_prefix = uvarint_size(size as u64);
+
+        try!(self.sink.end_prefix(size));
+
+        Ok(len_id_prefix + len_size_prefix + size)
+    }
+
+    // Encode `count` values of `f` (each of which is a whole array, if `f` has bounds). If `e` can
+    // provide the elements as a `Slice`, they're written in bulk.
+    fn encode_values<E>(&mut self, e: &E, f: &FieldEncoding, count: usize) -> Result<usize, Error>
+        where E: Encodable {
+
+        if fixed_width(f.typ).is_some() {
+            if let Some(slice) = e.get_slice(f.id) {
+                let len = count.checked_mul(f.bounds.unwrap_or(1));
+
+                if !slice.has_type(f.typ) || Some(slice.len()) != len {
+                    return Err(Error::FieldTypeMismatch);
+                }
+
+                return self.encode_slice(slice);
+            }
+        }
+
+        let mut total = 0;
 
-        for index in 0..max {
-            len_data += try!(self.encode_array(e, f, index));
+        for index in 0..count {
+            total += try!(self.encode_array(e, f, index));
         }
 
-        let len_id_prefix     = { let FieldID(id) = f.id; uvarint_size(id as u64) };
-        let len_length_prefix = uvarint_size(max as u64);
-        let len_size_prefix   = uvarint_size((len_length_prefix + len_data) as u64);
+        Ok(total)
+    }
 
-        Ok(len_id_prefix + len_size_prefix + len_length_prefix + len_data)
+    fn encode_slice(&mut self, slice: Slice) -> Result<usize, Error> {
+        let (bytes, width) = slice_bytes(&slice);
+
+        if cfg!(target_endian = "little") {
+            try!(io::Write::write_all(self.sink, bytes));
+        } else {
+            // The wire format is little endian, so each element needs its bytes reversed.
+            let mut elem = [0u8; 8];
+
+            for chunk in bytes.chunks(width) {
+                for (i, b) in chunk.iter().rev().enumerate() {
+                    elem[i] = *b;
+                }
+                try!(io::Write::write_all(self.sink, &elem[..width]));
+            }
+        }
+
+        Ok(bytes.len())
     }
 
     fn encode_array<E>(&mut self, e: &E, f: &FieldEncoding, index: usize) -> Result<usize, Error>
@@ -263,60 +689,155 @@ impl<'x> Encoder<'x> {
     fn encode_field<E>(&mut self, e: &E, f: &FieldEncoding, index: usize) -> Result<usize, Error>
         where E: Encodable {
 
-        if let Type::Record{index: child_index} = f.typ {
-            e.encode_record(try!(self.child(child_index)), f.id, index)
-        } else {
-            let prim = try!(e.get_primitive(f.id, index));
-            if !prim.has_type(f.typ) {
-                return Err(Error::FieldTypeMismatch);
-            }
+        let rec = self.rec;
 
-            self.encode_primitive(prim)
+        self.encode_element(e, f, index).map_err(|err| match err {
+            Error::InField(mut ctx, err) => {
+                ctx.enter(rec, f, Some(index));
+                Error::InField(ctx, err)
+            },
+            err => Error::InField(Box::new(ErrorContext::new(rec, f, Some(index))), Box::new(err)),
+        })
+    }
+
+    fn encode_element<E>(&mut self, e: &E, f: &FieldEncoding, index: usize) -> Result<usize, Error>
+        where E: Encodable {
+
+        self.encode_value(e, f.id, f.typ, index)
+    }
+
+    // Encode element `index` of the field `id`, which has type `typ`. For map fields `typ` may be
+    // the type of the values rather than of the field.
+    fn encode_value<E>(&mut self, e: &E, id: FieldID, typ: Type, index: usize)
+        -> Result<usize, Error>
+        where E: Encodable {
+
+        match typ {
+            Type::Record{index: child_index} => {
+                let child = try!(self.child(child_index));
+                e.encode_record(child, id, index)
+            },
+            Type::Map{index: entry_index} => self.encode_entry(e, id, entry_index, index),
+            _ => {
+                let prim = try!(e.get_primitive(id, index));
+                if !prim.has_type(typ) {
+                    return Err(Error::FieldTypeMismatch);
+                }
+
+                self.encode_primitive(prim)
+            },
         }
     }
 
-    fn encode_primitive(&mut self, prim: Primitive) -> Result<usize, Error> {
+    // Encode entry `index` of the map field `id`. It's written as a record of the entry type at
+    // `entry_index` in `deps`, with the key and the value as its two required fields.
+    fn encode_entry<E>(&mut self, e: &E, id: FieldID, entry_index: usize, index: usize)
+        -> Result<usize, Error>
+        where E: Encodable {
+
+        let deps = self.deps;
 
-        Ok( match prim {
-            Primitive::UInt8(x)  => try!(write_u8(self.data, x)),
-            Primitive::UInt16(x) => try!(write_le_u16(self.data, x)),
-            Primitive::UInt32(x) => try!(write_uvarint(self.data, x as u64)),
-            Primitive::UInt64(x) => try!(write_uvarint(self.data, x)),
+        let (key, value) = match deps.get(entry_index).and_then(|rec| rec.map_entry()) {
+            Some(entry) => entry,
+            None        => return Err(Error::EncodingInvalid),
+        };
 
-            Primitive::Int8(x)  => try!(write_i8(self.data, x)),
-            Primitive::Int16(x) => try!(write_le_i16(self.data, x)),
-            Primitive::Int32(x) => try!(write_varint(self.data, x as i64)),
-            Primitive::Int64(x) => try!(write_varint(self.data, x)),
+        let prim = try!(e.get_map_key(id, index));
+        if !prim.has_type(key.typ) {
+            return Err(Error::FieldTypeMismatch);
+        }
 
-            Primitive::Fixed32(x) => try!(write_le_u32(self.data, x)),
-            Primitive::Fixed64(x) => try!(write_le_u64(self.data, x)),
+        let mut total = try!(self.encode_primitive(prim));
+        total += try!(self.encode_value(e, id, value.typ, index));
 
-            Primitive::Float32(x) => try!(write_le_f32(self.data, x)),
-            Primitive::Float64(x) => try!(write_le_f64(self.data, x)),
+        // Write the 0-id which ends the entry record.
+        total += try!(write_uvarint(self.sink, 0));
 
-            Primitive::Bool(x) => try!(write_u8(self.data, if x { 0xFF } else { 0x00 })),
+        Ok(total)
+    }
 
-            Primitive::Bytes(x) => {
-                try!(write_uvarint(self.data, x.len() as u64));
-                try!(io::Write::write_all(self.data, &x));
-                x.len()
-            }
+    fn encode_primitive(&mut self, prim: Primitive) -> Result<usize, Error> {
+        write_primitive(self.sink, prim)
+    }
+}
+
+// Check that exactly one member of each union in `rec` is set in `e`.
+fn check_unions<E>(e: &E, rec: &RecordEncoding) -> Result<(), Error>
+    where E: Encodable {
 
-            Primitive::String(x) => {
-                let utf8 = x.as_bytes();
-                try!(write_uvarint(self.data, utf8.len() as u64));
-                try!(io::Write::write_all(self.data, utf8));
-                utf8.len()
+    for union in rec.unions().into_iter() {
+        let mut set = 0;
+
+        for f in rec.opt_rep_fields.iter().filter(|f| f.in_union(union)) {
+            if try!(e.count_field(f.id).map_err(|err| in_field(err, rec, f))) > 0 {
+                set += 1;
             }
+        }
 
-            Primitive::Enum(x) => try!(write_varint(self.data, x)),
-        })
+        match set {
+            0 => return Err(Error::UnionEmpty(union.to_string())),
+            1 => (),
+            _ => return Err(Error::UnionConflict(union.to_string())),
+        }
     }
+
+    Ok(())
+}
+
+// Attach the location of `f` to `err`, unless it already has one. This is for errors which aren't
+// tied to a particular element of `f`.
+fn in_field(err: Error, rec: &RecordEncoding, f: &FieldEncoding) -> Error {
+    match err {
+        Error::InField(ctx, err) => Error::InField(ctx, err),
+        err                      => Error::InField(Box::new(ErrorContext::new(rec, f, None)),
+                                                   Box::new(err)),
+    }
+}
+
+/// `write_primitive` writes `prim` to `w`, in the same way that a field element is written by an
+/// `Encoder`.
+pub fn write_primitive<W>(w: &mut W, prim: Primitive) -> Result<usize, Error>
+    where W: io::Write + ?Sized {
+
+    Ok( match prim {
+        Primitive::UInt8(x)  => try!(write_u8(w, x)),
+        Primitive::UInt16(x) => try!(write_le_u16(w, x)),
+        Primitive::UInt32(x) => try!(write_uvarint(w, x as u64)),
+        Primitive::UInt64(x) => try!(write_uvarint(w, x)),
+
+        Primitive::Int8(x)  => try!(write_i8(w, x)),
+        Primitive::Int16(x) => try!(write_le_i16(w, x)),
+        Primitive::Int32(x) => try!(write_varint(w, x as i64)),
+        Primitive::Int64(x) => try!(write_varint(w, x)),
+
+        Primitive::Fixed32(x) => try!(write_le_u32(w, x)),
+        Primitive::Fixed64(x) => try!(write_le_u64(w, x)),
+
+        Primitive::Float32(x) => try!(write_le_f32(w, x)),
+        Primitive::Float64(x) => try!(write_le_f64(w, x)),
+
+        Primitive::Bool(x) => try!(write_u8(w, if x { 0xFF } else { 0x00 })),
+
+        Primitive::Bytes(x) => {
+            let len_prefix = try!(write_uvarint(w, x.len() as u64));
+            try!(io::Write::write_all(w, &x));
+            len_prefix + x.len()
+        }
+
+        Primitive::String(x) => {
+            let utf8 = x.as_bytes();
+            let len_prefix = try!(write_uvarint(w, utf8.len() as u64));
+            try!(io::Write::write_all(w, utf8));
+            len_prefix + utf8.len()
+        }
+
+        Primitive::Enum(x) => try!(write_varint(w, x)),
+    })
 }
 
 /// `write_uvarint` writes 'x' to 'w' encoded as a varint.
-fn write_uvarint<W>(w: &mut W, mut x: u64) -> io::Result<usize>
-    where W: io::Write {
+pub fn write_uvarint<W>(w: &mut W, mut x: u64) -> io::Result<usize>
+    where W: io::Write + ?Sized {
 
     // A 64-bit varint can be at most 10 bytes long.
     let mut buf = [0u8; 10];
@@ -330,14 +851,14 @@ fn write_uvarint<W>(w: &mut W, mut x: u64) -> io::Result<usize>
 
     buf[idx] = x as u8;
 
-    try!(w.write_all(&buf[idx..]));
+    try!(w.write_all(&buf[..idx + 1]));
 
     Ok((idx + 1) as usize)
 }
 
 /// `write_varint` writes 'x' to 'w' as a zig-zag encoded signed varint.
 fn write_varint<W>(w: &mut W, x: i64) -> io::Result<usize>
-    where W: io::Write {
+    where W: io::Write + ?Sized {
 
     let ux = (x as u64) << 1;
 
@@ -346,7 +867,7 @@ fn write_varint<W>(w: &mut W, x: i64) -> io::Result<usize>
 
 /// `write_u8` writes `x` to `w` as a single byte.
 fn write_u8<W>(w: &mut W, x: u8) -> io::Result<usize>
-    where W: io::Write {
+    where W: io::Write + ?Sized {
 
     let buf = [x];
     try!(w.write_all(&buf));
@@ -355,7 +876,7 @@ fn write_u8<W>(w: &mut W, x: u8) -> io::Result<usize>
 
 /// `write_le_u16` writes `x` to `w` as 2 bytes in little-endian byte order.
 fn write_le_u16<W>(w: &mut W, x: u16) -> io::Result<usize>
-    where W: io::Write {
+    where W: io::Write + ?Sized {
 
     let buf = [
         ((x >> 0) & 0xFF) as u8,
@@ -368,7 +889,7 @@ fn write_le_u16<W>(w: &mut W, x: u16) -> io::Result<usize>
 
 /// `write_le_u32` writes `x` to `w` as 4 bytes in little-endian byte order.
 fn write_le_u32<W>(w: &mut W, x: u32) -> io::Result<usize>
-    where W: io::Write {
+    where W: io::Write + ?Sized {
 
     let buf = [
         ((x >>  0) & 0xFF) as u8,
@@ -383,7 +904,7 @@ fn write_le_u32<W>(w: &mut W, x: u32) -> io::Result<usize>
 
 /// `write_le_u64` writes `x` to `w` as 8 bytes in little-endian byte order.
 fn write_le_u64<W>(w: &mut W, x: u64) -> io::Result<usize>
-    where W: io::Write {
+    where W: io::Write + ?Sized {
 
     let buf = [
         ((x >>  0) & 0xFF) as u8,
@@ -402,28 +923,28 @@ fn write_le_u64<W>(w: &mut W, x: u64) -> io::Result<usize>
 
 /// `write_i8` writes `x` to `w` as a single, 2's complement encoded byte.
 fn write_i8<W>(w: &mut W, x: i8) -> io::Result<usize>
-    where W: io::Write {
+    where W: io::Write + ?Sized {
 
     write_u8(w, x as u8)
 }
 
 /// `write_le_i16` writes `x` to `w` as 2 bytes, 2's complement encoded in little-endian byte order.
 fn write_le_i16<W>(w: &mut W, x: i16) -> io::Result<usize>
-    where W: io::Write {
+    where W: io::Write + ?Sized {
 
     write_le_u16(w, x as u16)
 }
 
 /// `write_le_i32` writes `x` to `w` as 4 bytes, 2's complement encoded in little-endian byte order.
 fn write_le_i32<W>(w: &mut W, x: i32) -> io::Result<usize>
-    where W: io::Write {
+    where W: io::Write + ?Sized {
 
     write_le_u32(w, x as u32)
 }
 
 /// `write_le_i64` writes `x` to `w` as 8 bytes, 2's complement encoded in little-endian byte order.
 fn write_le_i64<W>(w: &mut W, x: i64) -> io::Result<usize>
-    where W: io::Write {
+    where W: io::Write + ?Sized {
 
     write_le_u64(w, x as u64)
 }
@@ -431,7 +952,7 @@ fn write_le_i64<W>(w: &mut W, x: i64) -> io::Result<usize>
 /// `write_le_f32` writes `x` to `w` as 4 bytes, ieee-754 binary32 encoded in little-endian byte
 /// order.
 fn write_le_f32<W>(w: &mut W, x: f32) -> io::Result<usize>
-    where W: io::Write {
+    where W: io::Write + ?Sized {
 
     write_le_u32(w, unsafe { mem::transmute(x) })
 }
@@ -439,7 +960,7 @@ fn write_le_f32<W>(w: &mut W, x: f32) -> io::Result<usize>
 /// `write_le_f64` writes `x` to `w` as 8 bytes, ieee-754 binary64 encoded in little-endian byte
 /// order.
 fn write_le_f64<W>(w: &mut W, x: f64) -> io::Result<usize>
-    where W: io::Write {
+    where W: io::Write + ?Sized {
 
     write_le_u64(w, unsafe { mem::transmute(x) })
 }
@@ -18,9 +18,18 @@
 // TODO: Remove this when things stabilize.
 #![allow(dead_code)]
 
+use std::cmp;
+use std::io;
+
+use borrowed::read_primitive;
+use decoder::{Decoder, Decodable, PREALLOC_MAX, next_elem};
+use decoder::Error as DecError;
+use encoder::{Encoder, Encodable, write_primitive};
+use encoder::Error as EncError;
 use encoding::Quantifier::*;
+use primitive::Primitive;
 
-#[derive(Copy,Eq,PartialEq)]
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
 #[allow(missing_docs)]
 /// A `Type` indicates the type of a record field.
 pub enum Type {
@@ -51,10 +60,15 @@ pub enum Type {
     // The `index` field gives an index into the `depends` field of the CompleteEncoding which
     // provides encoding information for this type.
     Record{index: usize},
+
+    // A `Map` is encoded just like a `Record`, but with the `is_map` field of its `FieldEncoding`
+    // set. The record at `index` is the entry type of the map (see `RecordEncoding::map_entry`),
+    // and the field is a repeated field of entries.
+    Map{index: usize},
 }
 
 /// A `FieldID` represents the id of a record field.
-#[derive(Eq,PartialEq,Ord,PartialOrd,Copy)]
+#[derive(Eq,PartialEq,Ord,PartialOrd,Copy,Clone,Debug,Hash)]
 pub struct FieldID(pub u64);
 
 impl Type {
@@ -89,12 +103,44 @@ impl Type {
             xx => Type::Record{index: (xx - first_unused) as usize},
         }
     }
+
+    /// `to_u64` converts a `Type` to a `u64`. It is the inverse of `from_u64`.
+    pub fn to_u64(&self) -> u64 {
+        let first_unused = 16;
+
+        match *self {
+            Type::Int8    =>  0,
+            Type::Int16   =>  1,
+            Type::Int32   =>  2,
+            Type::Int64   =>  3,
+
+            Type::UInt8   =>  4,
+            Type::UInt16  =>  5,
+            Type::UInt32  =>  6,
+            Type::UInt64  =>  7,
+
+            Type::Fixed32 =>  8,
+            Type::Fixed64 =>  9,
+
+            Type::Float32 => 10,
+            Type::Float64 => 11,
+
+            Type::Bytes   => 12,
+            Type::String  => 13,
+
+            Type::Bool    => 14,
+
+            Type::Enum    => 15,
+
+            Type::Record{index} | Type::Map{index} => first_unused + index as u64,
+        }
+    }
 }
 
 
 /// The Quantifier type gives the multiplicity of a field. A Required field has exactly 1 element, an
 /// Optional field has 0 or 1 elements, and a Repeated field has 0 or more elements.
-#[derive(Debug,Copy,PartialEq,Eq)]
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
 #[allow(missing_docs)]
 pub enum Quantifier {
     Required = 0,
@@ -102,10 +148,28 @@ pub enum Quantifier {
     Repeated = 2,
 }
 
+impl Quantifier {
+    /// `from_i64` converts an enum value to a `Quantifier`, using the mapping established in
+    /// `encoding.rex`.
+    pub fn from_i64(x: i64) -> Option<Quantifier> {
+        match x {
+            0 => Some(Required),
+            1 => Some(Optional),
+            2 => Some(Repeated),
+            _ => None,
+        }
+    }
+
+    /// `to_i64` converts a `Quantifier` to its enum value in `encoding.rex`.
+    pub fn to_i64(&self) -> i64 {
+        *self as i64
+    }
+}
+
 /// A `FieldEncoding` describes the encoding of a single field of a record. The `Type` field may be
 /// a reference to a `RecordEncoding` which can be resolved by consulting the `depends` field of
 /// the containing `CompleteEncoding`.
-#[derive(PartialEq,Eq)]
+#[derive(Clone,Debug,PartialEq)]
 pub struct FieldEncoding {
     /// Integer id of this field within its containing record.
     pub id: FieldID,
@@ -127,13 +191,40 @@ pub struct FieldEncoding {
     ///
     /// The bounds field is not present for non-array types.
     pub bounds: Option<usize>,
+
+    /// The name of the union this field is a member of, if any. Union members are optional fields,
+    /// and exactly one member of each union is present in a record.
+    pub union: Option<String>,
+
+    /// The value of this field when it's absent, if it has one. Only optional fields of primitive
+    /// type without bounds can have defaults. It's used by `Decodable`s which opt in with
+    /// `fill_defaults`, and by `Encodable`s which opt in with `elide_defaults`.
+    pub default: Option<Primitive>,
+}
+
+impl FieldEncoding {
+    /// `is_map` returns true if this is a map field.
+    pub fn is_map(&self) -> bool {
+        match self.typ {
+            Type::Map{..} => true,
+            _             => false,
+        }
+    }
+
+    /// `in_union` returns true if this field is a member of the union named `union`.
+    pub fn in_union(&self, union: &str) -> bool {
+        match self.union {
+            Some(ref u) => &u[..] == union,
+            None        => false,
+        }
+    }
 }
 
 
 /// A `RecordEncoding` describes the encoding of a particular record type. It may contain
 /// references to other `RecordEncoding`s which can be resolved by consulting the `depends` field
 /// of the containing `CompleteEncoding`.
-#[derive(PartialEq,Eq)]
+#[derive(Clone,Debug,PartialEq)]
 pub struct RecordEncoding {
     /// Name of the record type in the .rex file, not used in the encoding.
     pub name: String,
@@ -146,15 +237,57 @@ pub struct RecordEncoding {
 }
 
 impl RecordEncoding {
+    /// `map_entry` returns the `key` and `value` fields of this record, if it's the entry type of
+    /// a map. An entry type has exactly two fields, both required: the key with id 1 and the value
+    /// with id 2. The key must have a primitive type and the value may have any type but `Map`;
+    /// neither may be an array.
+    pub fn map_entry(&self) -> Option<(&FieldEncoding, &FieldEncoding)> {
+        if self.req_fields.len() != 2 || !self.opt_rep_fields.is_empty() {
+            return None;
+        }
+
+        let (key, value) = (&self.req_fields[0], &self.req_fields[1]);
+
+        let valid = key.id == FieldID(1) && value.id == FieldID(2)
+            && key.bounds.is_none() && value.bounds.is_none()
+            && key.quant == Required && value.quant == Required
+            && !is_composite(key.typ) && !value.is_map();
+
+        if valid { Some((key, value)) } else { None }
+    }
+
+    /// `unions` returns the name of each union in this record, in the order that their first
+    /// members appear in `opt_rep_fields`.
+    pub fn unions(&self) -> Vec<&str> {
+        let mut unions: Vec<&str> = vec![];
+
+        for f in self.opt_rep_fields.iter() {
+            if let Some(ref u) = f.union {
+                if !unions.contains(&&u[..]) {
+                    unions.push(&u[..]);
+                }
+            }
+        }
+
+        unions
+    }
+
     fn sort_fields(&mut self) {
         self.req_fields.sort_by(|x, y| x.id.cmp(&y.id));
         self.opt_rep_fields.sort_by(|x, y| x.id.cmp(&y.id));
     }
 }
 
+fn is_composite(t: Type) -> bool {
+    match t {
+        Type::Record{..} | Type::Map{..} => true,
+        _                                => false,
+    }
+}
+
 /// A `CompleteEncoding` provides all of the information necessary to encode or decode a particular
 /// record type (and every record type that it can contain).
-#[derive(PartialEq,Eq)]
+#[derive(Clone,Debug,PartialEq)]
 pub struct CompleteEncoding {
     /// The record type that this CompleteEncoding describes.
     pub target:  RecordEncoding,
@@ -176,6 +309,338 @@ impl CompleteEncoding {
     }
 }
 
+// The `Default`, `Encodable` and `Decodable` impls below follow the definitions in encoding.rex, and
+// allow encodings to be written and read using `COMPLETE_ENC`.
+
+impl Default for FieldEncoding {
+    fn default() -> FieldEncoding {
+        FieldEncoding {
+            id:      FieldID(0),
+            name:    String::new(),
+            quant:   Required,
+            typ:     Type::Int8,
+            bounds:  None,
+            union:   None,
+            default: None,
+        }
+    }
+}
+
+impl Default for RecordEncoding {
+    fn default() -> RecordEncoding {
+        RecordEncoding {
+            name:           String::new(),
+            req_fields:     vec![],
+            opt_rep_fields: vec![],
+        }
+    }
+}
+
+impl Default for CompleteEncoding {
+    fn default() -> CompleteEncoding {
+        CompleteEncoding {
+            target:  Default::default(),
+            depends: vec![],
+        }
+    }
+}
+
+impl Encodable for FieldEncoding {
+    fn get_primitive(&self, id: FieldID, _: usize) -> Result<Primitive, EncError> {
+        Ok( match id {
+            FieldID(1) => { let FieldID(x) = self.id; Primitive::UInt64(x) },
+            FieldID(2) => Primitive::String(self.name.clone()),
+            FieldID(3) => Primitive::Enum(self.quant.to_i64()),
+            FieldID(4) => Primitive::Enum(self.typ.to_u64() as i64),
+            FieldID(5) => match self.bounds {
+                Some(x) => Primitive::UInt64(x as u64),
+                None    => return Err(EncError::FieldTypeMismatch),
+            },
+            FieldID(6) => Primitive::Bool(self.is_map()),
+            FieldID(7) => match self.union {
+                Some(ref u) => Primitive::String(u.clone()),
+                None        => return Err(EncError::FieldTypeMismatch),
+            },
+
+            // Defaults are written the same way as the field itself would be.
+            FieldID(8) => match self.default {
+                Some(ref d) => {
+                    let mut buf = vec![];
+                    try!(write_primitive(&mut buf, d.clone()));
+                    Primitive::Bytes(buf)
+                },
+                None => return Err(EncError::FieldTypeMismatch),
+            },
+            _ => return Err(EncError::FieldTypeMismatch),
+        })
+    }
+
+    fn encode_record(&self, _: Encoder, _: FieldID, _: usize) -> Result<usize, EncError> {
+        Err(EncError::FieldTypeMismatch)
+    }
+
+    fn count_field(&self, id: FieldID) -> Result<usize, EncError> {
+        match id {
+            FieldID(5) => Ok(if self.bounds.is_some() { 1 } else { 0 }),
+            FieldID(6) => Ok(if self.is_map() { 1 } else { 0 }),
+            FieldID(7) => Ok(if self.union.is_some() { 1 } else { 0 }),
+            FieldID(8) => Ok(if self.default.is_some() { 1 } else { 0 }),
+            _          => Err(EncError::FieldTypeMismatch),
+        }
+    }
+}
+
+impl Decodable for FieldEncoding {
+    fn set_primitive(&mut self, id: FieldID, _: usize, prim: Primitive) -> Result<(), DecError> {
+        match (id, prim) {
+            (FieldID(1), Primitive::UInt64(x)) => self.id = FieldID(x),
+            (FieldID(2), Primitive::String(x)) => self.name = x,
+            (FieldID(3), Primitive::Enum(x))   => self.quant = match Quantifier::from_i64(x) {
+                Some(q) => q,
+                None    => return Err(DecError::BadEnum(x)),
+            },
+            (FieldID(4), Primitive::Enum(x))   => self.typ = if x >= 0 {
+                Type::from_u64(x as u64)
+            } else {
+                return Err(DecError::BadEnum(x));
+            },
+            (FieldID(5), Primitive::UInt64(x)) => self.bounds = Some(x as usize),
+
+            // `typ` is required, so it has already been decoded.
+            (FieldID(6), Primitive::Bool(x))   => self.typ = match (x, self.typ) {
+                (true, Type::Record{index}) => Type::Map{index},
+                (false, typ)                => typ,
+                _                           => return Err(DecError::EncodingInvalid),
+            },
+            (FieldID(7), Primitive::String(x)) => self.union = Some(x),
+
+            // `typ` is needed to read the default, but it's required so it's already there.
+            (FieldID(8), Primitive::Bytes(x))  => {
+                let mut buf = &x[..];
+                let default = try!(read_primitive(&mut buf, self.typ)).to_primitive();
+
+                if !buf.is_empty() {
+                    return Err(DecError::EncodingInvalid);
+                }
+
+                self.default = Some(default);
+            },
+            _ => return Err(DecError::FieldTypeMismatch),
+        }
+
+        Ok(())
+    }
+
+    fn decode_record<'x, R>(&mut self, _: Decoder<'x, R>, _: FieldID, _: usize) -> Result<(), DecError>
+        where R: io::Read + 'x {
+
+        Err(DecError::FieldTypeMismatch)
+    }
+
+    fn alloc_field(&mut self, id: FieldID, _: usize) -> Result<bool, DecError> {
+        match id {
+            FieldID(5) | FieldID(6) | FieldID(7) | FieldID(8) => Ok(true),
+            _                                                 => Ok(false),
+        }
+    }
+}
+
+impl Encodable for RecordEncoding {
+    fn get_primitive(&self, id: FieldID, _: usize) -> Result<Primitive, EncError> {
+        match id {
+            FieldID(1) => Ok(Primitive::String(self.name.clone())),
+            _          => Err(EncError::FieldTypeMismatch),
+        }
+    }
+
+    fn encode_record(&self, mut e: Encoder, id: FieldID, idx: usize) -> Result<usize, EncError> {
+        let field = match id {
+            FieldID(2) => self.req_fields.get(idx),
+            FieldID(3) => self.opt_rep_fields.get(idx),
+            _          => None,
+        };
+
+        match field {
+            Some(field) => e.encode(field),
+            None        => Err(EncError::FieldTypeMismatch),
+        }
+    }
+
+    fn count_field(&self, id: FieldID) -> Result<usize, EncError> {
+        match id {
+            FieldID(2) => Ok(self.req_fields.len()),
+            FieldID(3) => Ok(self.opt_rep_fields.len()),
+            _          => Err(EncError::FieldTypeMismatch),
+        }
+    }
+}
+
+impl Decodable for RecordEncoding {
+    fn set_primitive(&mut self, id: FieldID, _: usize, prim: Primitive) -> Result<(), DecError> {
+        match (id, prim) {
+            (FieldID(1), Primitive::String(x)) => { self.name = x; Ok(()) },
+            _                                  => Err(DecError::FieldTypeMismatch),
+        }
+    }
+
+    fn decode_record<'x, R>(&mut self, mut d: Decoder<'x, R>, id: FieldID, idx: usize) -> Result<(), DecError>
+        where R: io::Read + 'x {
+
+        let field = match id {
+            FieldID(2) => next_elem(&mut self.req_fields, idx, Default::default),
+            FieldID(3) => next_elem(&mut self.opt_rep_fields, idx, Default::default),
+            _          => None,
+        };
+
+        match field {
+            Some(field) => d.decode(field),
+            None        => Err(DecError::FieldTypeMismatch),
+        }
+    }
+
+    fn alloc_field(&mut self, id: FieldID, count: usize) -> Result<bool, DecError> {
+        let fields = match id {
+            FieldID(2) => &mut self.req_fields,
+            FieldID(3) => &mut self.opt_rep_fields,
+            _          => return Ok(false),
+        };
+
+        *fields = Vec::with_capacity(cmp::min(count, PREALLOC_MAX));
+
+        Ok(true)
+    }
+}
+
+impl Encodable for CompleteEncoding {
+    fn get_primitive(&self, _: FieldID, _: usize) -> Result<Primitive, EncError> {
+        Err(EncError::FieldTypeMismatch)
+    }
+
+    fn encode_record(&self, mut e: Encoder, id: FieldID, idx: usize) -> Result<usize, EncError> {
+        let rec = match id {
+            FieldID(1) => Some(&self.target),
+            FieldID(2) => self.depends.get(idx),
+            _          => None,
+        };
+
+        match rec {
+            Some(rec) => e.encode(rec),
+            None      => Err(EncError::FieldTypeMismatch),
+        }
+    }
+
+    fn count_field(&self, id: FieldID) -> Result<usize, EncError> {
+        match id {
+            FieldID(2) => Ok(self.depends.len()),
+            _          => Err(EncError::FieldTypeMismatch),
+        }
+    }
+}
+
+impl Decodable for CompleteEncoding {
+    fn set_primitive(&mut self, _: FieldID, _: usize, _: Primitive) -> Result<(), DecError> {
+        Err(DecError::FieldTypeMismatch)
+    }
+
+    fn decode_record<'x, R>(&mut self, mut d: Decoder<'x, R>, id: FieldID, idx: usize) -> Result<(), DecError>
+        where R: io::Read + 'x {
+
+        let rec = match id {
+            FieldID(1) => Some(&mut self.target),
+            FieldID(2) => next_elem(&mut self.depends, idx, Default::default),
+            _          => None,
+        };
+
+        match rec {
+            Some(rec) => d.decode(rec),
+            None      => Err(DecError::FieldTypeMismatch),
+        }
+    }
+
+    fn alloc_field(&mut self, id: FieldID, count: usize) -> Result<bool, DecError> {
+        match id {
+            FieldID(2) => {
+                self.depends = Vec::with_capacity(cmp::min(count, PREALLOC_MAX));
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+/// The `RecordType` trait is implemented by Rust types which know their own record encoding, such
+/// as those using `#[derive(RexEncodable)]` from the `rex_macros` crate.
+pub trait RecordType {
+    /// `record_name` returns the name of the record type, which is used as the `name` of its
+    /// `RecordEncoding`.
+    fn record_name() -> &'static str;
+
+    /// `record_path` returns the full path of the Rust type, e.g. `assets::mesh::Vertex`. Record
+    /// types are identified by path when building `depends`, so that types with the same name in
+    /// different modules don't collide.
+    fn record_path() -> &'static str;
+
+    /// `record_encoding` returns the `RecordEncoding` for this type. Fields of record type should
+    /// get their `Type::Record` index from `deps.index`.
+    fn record_encoding(deps: &mut Depends) -> RecordEncoding;
+}
+
+/// `Depends` collects the `depends` of a `CompleteEncoding` which is being built from
+/// `RecordType`s by `CompleteEncoding::of`.
+pub struct Depends {
+    paths:     Vec<&'static str>,
+    encodings: Vec<Option<RecordEncoding>>,
+}
+
+impl Depends {
+    /// `index` returns the index into `depends` of the encoding for `R`, adding it (and everything
+    /// it depends on) if it isn't there yet.
+    pub fn index<R>(&mut self) -> usize
+        where R: RecordType {
+
+        let path = R::record_path();
+
+        if let Some(idx) = self.paths.iter().position(|p| *p == path) {
+            return idx;
+        }
+
+        // Reserve the slot before building the encoding, so that recursive record types find
+        // themselves rather than recursing forever.
+        let idx = self.paths.len();
+        self.paths.push(path);
+        self.encodings.push(None);
+
+        let enc = R::record_encoding(self);
+        self.encodings[idx] = Some(enc);
+
+        idx
+    }
+}
+
+impl CompleteEncoding {
+    /// `of` builds the `CompleteEncoding` for the record type `R`.
+    pub fn of<R>() -> CompleteEncoding
+        where R: RecordType {
+
+        let mut deps = Depends {
+            paths:     vec![],
+            encodings: vec![],
+        };
+
+        let target = R::record_encoding(&mut deps);
+
+        let mut enc = CompleteEncoding {
+            target:  target,
+
+            // Every slot is filled in by the time `index` returns.
+            depends: deps.encodings.into_iter().map(|e| e.unwrap()).collect(),
+        };
+
+        enc.sort_fields();
+        enc
+    }
+}
+
 pub use encoding::doc_workaround::COMPLETE_ENC;
 
 mod doc_workaround {
@@ -205,22 +670,26 @@ lazy_static! {
             req_fields: vec![
 
                 FieldEncoding {
-                    id:     FieldID(1),
-                    name:   "target".to_string(),
-                    quant:  Required,
-                    typ:    RECORD_ENCODING_TYP,
-                    bounds: None
+                    id:      FieldID(1),
+                    name:    "target".to_string(),
+                    quant:   Required,
+                    typ:     RECORD_ENCODING_TYP,
+                    bounds:  None,
+                    union:   None,
+                    default: None
                 },
             ],
 
             opt_rep_fields: vec![
 
                 FieldEncoding {
-                    id:     FieldID(2),
-                    quant:  Repeated,
-                    name:   "depends".to_string(),
-                    typ:    RECORD_ENCODING_TYP,
-                    bounds: None
+                    id:      FieldID(2),
+                    quant:   Repeated,
+                    name:    "depends".to_string(),
+                    typ:     RECORD_ENCODING_TYP,
+                    bounds:  None,
+                    union:   None,
+                    default: None
                 },
             ],
         },
@@ -232,75 +701,123 @@ lazy_static! {
                 req_fields: vec![
 
                     FieldEncoding {
-                        id:     FieldID(1),
-                        name:   "id".to_string(),
-                        quant:  Required,
-                        typ:    Type::UInt64,
-                        bounds: None
+                        id:      FieldID(1),
+                        name:    "id".to_string(),
+                        quant:   Required,
+                        typ:     Type::UInt64,
+                        bounds:  None,
+                        union:   None,
+                        default: None
+                    },
+
+                    FieldEncoding {
+                        id:      FieldID(2),
+                        name:    "name".to_string(),
+                        quant:   Required,
+                        typ:     Type::String,
+                        bounds:  None,
+                        union:   None,
+                        default: None
                     },
 
                     FieldEncoding {
-                        id:     FieldID(2),
-                        name:   "name".to_string(),
-                        quant:  Required,
-                        typ:    Type::String,
-                        bounds: None
+                        id:      FieldID(3),
+                        name:    "quant".to_string(),
+                        quant:   Required,
+                        typ:     Type::Enum,
+                        bounds:  None,
+                        union:   None,
+                        default: None
                     },
 
                     FieldEncoding {
-                        id:     FieldID(3),
-                        name:   "quant".to_string(),
-                        quant:  Required,
-                        typ:    Type::Enum,
-                        bounds: None
+                        id:      FieldID(4),
+                        name:    "typ".to_string(),
+                        quant:   Required,
+                        typ:     Type::Enum,
+                        bounds:  None,
+                        union:   None,
+                        default: None
                     },
 
+                ],
+                opt_rep_fields: vec![
+
                     FieldEncoding {
-                        id:     FieldID(4),
-                        name:   "typ".to_string(),
-                        quant:  Required,
-                        typ:    Type::Enum,
-                        bounds: None
+                        id:      FieldID(5),
+                        name:    "bounds".to_string(),
+                        quant:   Optional,
+                        typ:     Type::UInt64,
+                        bounds:  None,
+                        union:   None,
+                        default: None
                     },
 
                     FieldEncoding {
-                        id:     FieldID(5),
-                        name:   "bounds".to_string(),
-                        quant:  Required,
-                        typ:    Type::UInt64,
-                        bounds: None
+                        id:      FieldID(6),
+                        name:    "is_map".to_string(),
+                        quant:   Optional,
+                        typ:     Type::Bool,
+                        bounds:  None,
+                        union:   None,
+                        default: None
                     },
-                ],
-                opt_rep_fields: vec![]
+
+                    FieldEncoding {
+                        id:      FieldID(7),
+                        name:    "union".to_string(),
+                        quant:   Optional,
+                        typ:     Type::String,
+                        bounds:  None,
+                        union:   None,
+                        default: None
+                    },
+
+                    FieldEncoding {
+                        id:      FieldID(8),
+                        name:    "default".to_string(),
+                        quant:   Optional,
+                        typ:     Type::Bytes,
+                        bounds:  None,
+                        union:   None,
+                        default: None
+                    },
+                ]
             },
 
             RecordEncoding {
                 name: "RecordEncoding".to_string(),
                 req_fields: vec![
                     FieldEncoding {
-                        id:     FieldID(1),
-                        name:   "name".to_string(),
-                        quant:  Required,
-                        typ:    Type::String,
-                        bounds: None
+                        id:      FieldID(1),
+                        name:    "name".to_string(),
+                        quant:   Required,
+                        typ:     Type::String,
+                        bounds:  None,
+                        union:   None,
+                        default: None
                     },
                 ],
 
                 opt_rep_fields: vec![
                     FieldEncoding {
-                        id:     FieldID(2),
-                        name:   "req_fields".to_string(),
-                        quant:  Repeated,
-                        typ:    FIELD_ENCODING_TYP,
-                        bounds: None
+                        id:      FieldID(2),
+                        name:    "req_fields".to_string(),
+                        quant:   Repeated,
+                        typ:     FIELD_ENCODING_TYP,
+                        bounds:  None,
+                        union:   None,
+                        default: None
                     },
 
                     FieldEncoding {
-                        id:     FieldID(3),
-                        name:   "opt_rep_fields".to_string(),
-                        quant:  Repeated,
-                        typ:    FIELD_ENCODING_TYP,
-                        bounds: None
+                        id:      FieldID(3),
+                        name:    "opt_rep_fields".to_string(),
+                        quant:   Repeated,
+                        typ:     FIELD_ENCODING_TYP,
+                        bounds:  None,
+                        union:   None,
+                        default: None
                     },
                 ],
             },
@@ -0,0 +1,461 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::BTreeMap;
+use std::error;
+use std::error::FromError;
+use std::fmt;
+use std::io;
+
+use rustc_serialize::base64::{FromBase64, ToBase64, STANDARD};
+use rustc_serialize::json::{Json, ParserError};
+
+use decoder::Error as DecError;
+use encoder::Error as EncError;
+use encoder::encode_to;
+use encoding::{CompleteEncoding, RecordEncoding, FieldEncoding, Quantifier, Type};
+use primitive::Primitive;
+use value::{DynamicRecord, Value};
+
+/// Integers with a magnitude above `MAX_SAFE_INT` can't be represented exactly by a JSON number
+/// (which most consumers parse as an ieee-754 double), so they are written as strings instead.
+pub const MAX_SAFE_INT: u64 = (1 << 53) - 1;
+
+/// `Error` is used to report errors that occur while converting between JSON and rex records.
+#[derive(Debug)]
+pub enum Error {
+    /// `Syntax` indicates that the input was not valid JSON.
+    Syntax(ParserError),
+
+    /// `Field` indicates that a JSON value didn't fit the field it was meant for. `path` gives the
+    /// location of the field, e.g. `Mesh.submeshes[3].material.name`.
+    Field {
+        /// Path from the root record to the offending field.
+        path: String,
+
+        /// Description of the problem.
+        msg: String,
+    },
+
+    /// `EncError` is used to pass through errors from encoding the converted record.
+    EncError(EncError),
+
+    /// `DecError` is used to pass through errors from decoding a record to convert.
+    DecError(DecError),
+}
+
+impl FromError<ParserError> for Error {
+    fn from_error(err: ParserError) -> Error {
+        Error::Syntax(err)
+    }
+}
+
+impl FromError<EncError> for Error {
+    fn from_error(err: EncError) -> Error {
+        Error::EncError(err)
+    }
+}
+
+impl FromError<DecError> for Error {
+    fn from_error(err: DecError) -> Error {
+        Error::DecError(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Syntax(ref err)           => write!(f, "invalid JSON: {:?}", err),
+            Error::Field{ref path, ref msg}  => write!(f, "{}: {}", path, msg),
+            Error::EncError(ref err)         => write!(f, "error encoding record: {}", err),
+            Error::DecError(ref err)         => write!(f, "error decoding record: {}", err),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Syntax(_)         => "invalid JSON",
+            Error::Field{..}         => "invalid field",
+            Error::EncError(ref err) => error::Error::description(err),
+            Error::DecError(ref err) => error::Error::description(err),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::EncError(ref err) => Some(err as &error::Error),
+            Error::DecError(ref err) => Some(err as &error::Error),
+            _                        => None,
+        }
+    }
+}
+
+fn field_err<T>(path: &str, msg: String) -> Result<T, Error> {
+    Err(Error::Field {
+        path: path.to_string(),
+        msg:  msg,
+    })
+}
+
+/// `binary_to_json` decodes a record from `r` according to `enc` and converts it to JSON text.
+pub fn binary_to_json<R>(enc: &CompleteEncoding, r: &mut R) -> Result<String, Error>
+    where R: io::Read {
+
+    let rec = try!(DynamicRecord::decode_from(enc, r));
+    Ok(format!("{}", to_json(&rec).pretty()))
+}
+
+/// `json_to_binary` parses JSON text as a record of the type described by `enc`, and writes it
+/// to `w` in the binary format.
+pub fn json_to_binary<W>(enc: &CompleteEncoding, src: &str, w: &mut W) -> Result<(), Error>
+    where W: io::Write {
+
+    let json = try!(Json::from_str(src));
+    let rec  = try!(from_json(enc, &json));
+
+    try!(encode_to(enc, w, &rec));
+    Ok(())
+}
+
+/// `to_json` converts `rec` to JSON. A record becomes an object with a member for each field
+/// which is present. Repeated fields and array fields become JSON arrays (a repeated array field
+/// becomes an array of arrays). Primitives are mapped as follows:
+///
+/// * Integers and enums become numbers, except that 64-bit integers outside of
+///   `[-MAX_SAFE_INT, MAX_SAFE_INT]` become decimal strings.
+/// * Floats become numbers, except for NaN and the infinities, which become the strings `"NaN"`,
+///   `"Infinity"` and `"-Infinity"`.
+/// * Bytes become base64 strings.
+///
+/// Map fields become arrays of objects with a `key` and a `value` member, since the keys might not
+/// be strings.
+pub fn to_json(rec: &DynamicRecord) -> Json {
+    let mut obj = BTreeMap::new();
+
+    for f in rec.fields.iter() {
+        if f.values.is_empty() {
+            continue;
+        }
+
+        let members: Vec<Json> = match f.encoding.bounds {
+            _ if f.encoding.is_map() => f.keys.iter()
+                .zip(f.values.iter())
+                .map(|(key, val)| entry_to_json(key, val))
+                .collect(),
+            Some(n) => f.values
+                .chunks(n)
+                .map(|member| Json::Array(member.iter().map(value_to_json).collect()))
+                .collect(),
+            None => f.values.iter().map(value_to_json).collect(),
+        };
+
+        let json = match f.encoding.quant {
+            Quantifier::Repeated => Json::Array(members),
+            _                    => members.into_iter().next().unwrap(),
+        };
+
+        obj.insert(f.encoding.name.clone(), json);
+    }
+
+    Json::Object(obj)
+}
+
+fn entry_to_json(key: &Primitive, val: &Value) -> Json {
+    let mut obj = BTreeMap::new();
+
+    obj.insert("key".to_string(), primitive_to_json(key));
+    obj.insert("value".to_string(), value_to_json(val));
+
+    Json::Object(obj)
+}
+
+fn value_to_json(val: &Value) -> Json {
+    match *val {
+        Value::Record(ref rec)     => to_json(rec),
+        Value::Primitive(ref prim) => primitive_to_json(prim),
+    }
+}
+
+fn primitive_to_json(prim: &Primitive) -> Json {
+    match *prim {
+        Primitive::UInt8(x)   => Json::U64(x as u64),
+        Primitive::UInt16(x)  => Json::U64(x as u64),
+        Primitive::UInt32(x)  => Json::U64(x as u64),
+        Primitive::UInt64(x)  => unsigned_to_json(x),
+
+        Primitive::Int8(x)    => Json::I64(x as i64),
+        Primitive::Int16(x)   => Json::I64(x as i64),
+        Primitive::Int32(x)   => Json::I64(x as i64),
+        Primitive::Int64(x)   => signed_to_json(x),
+
+        Primitive::Fixed32(x) => Json::U64(x as u64),
+        Primitive::Fixed64(x) => unsigned_to_json(x),
+
+        Primitive::Float32(x) => float_to_json(x as f64),
+        Primitive::Float64(x) => float_to_json(x),
+
+        Primitive::Bool(x)    => Json::Boolean(x),
+
+        Primitive::Bytes(ref x)  => Json::String(x.to_base64(STANDARD)),
+        Primitive::String(ref x) => Json::String(x.clone()),
+
+        Primitive::Enum(x)    => signed_to_json(x),
+    }
+}
+
+fn unsigned_to_json(x: u64) -> Json {
+    if x <= MAX_SAFE_INT {
+        Json::U64(x)
+    } else {
+        Json::String(format!("{}", x))
+    }
+}
+
+fn signed_to_json(x: i64) -> Json {
+    if x <= MAX_SAFE_INT as i64 && x >= -(MAX_SAFE_INT as i64) {
+        Json::I64(x)
+    } else {
+        Json::String(format!("{}", x))
+    }
+}
+
+fn float_to_json(x: f64) -> Json {
+    if x.is_nan() {
+        Json::String("NaN".to_string())
+    } else if x.is_infinite() {
+        Json::String(if x > 0.0 { "Infinity" } else { "-Infinity" }.to_string())
+    } else {
+        Json::F64(x)
+    }
+}
+
+/// `from_json` converts JSON (in the form produced by `to_json`) to a record of the type described
+/// by `enc`. Integers are accepted either as numbers or as decimal strings, and `null` is treated
+/// the same as an absent field. Members of `json` which don't name a field are an error.
+pub fn from_json(enc: &CompleteEncoding, json: &Json) -> Result<DynamicRecord, Error> {
+    record_from_json(enc, &enc.target, json, &enc.target.name[..])
+}
+
+fn record_from_json(enc: &CompleteEncoding, renc: &RecordEncoding, json: &Json, path: &str)
+    -> Result<DynamicRecord, Error> {
+
+    let obj = match *json {
+        Json::Object(ref obj) => obj,
+        _                     => return field_err(path, "expected an object".to_string()),
+    };
+
+    let mut rec = DynamicRecord::new(renc);
+
+    for (name, member) in obj.iter() {
+        let fpath = format!("{}.{}", path, name);
+
+        let f = match rec.field_by_name_mut(&name[..]) {
+            Some(f) => f,
+            None    => return field_err(&fpath[..], format!("{} has no such field", renc.name)),
+        };
+
+        if let Json::Null = *member {
+            continue;
+        }
+
+        let members = match f.encoding.quant {
+            Quantifier::Repeated => match *member {
+                Json::Array(ref elems) => elems.iter().collect(),
+                _ => return field_err(&fpath[..], "expected an array".to_string()),
+            },
+            _ => vec![member],
+        };
+
+        for (i, m) in members.iter().enumerate() {
+            let mpath = if f.encoding.quant == Quantifier::Repeated {
+                format!("{}[{}]", fpath, i)
+            } else {
+                fpath.clone()
+            };
+
+            if let Type::Map{index} = f.encoding.typ {
+                let (key, val) = try!(entry_from_json(enc, index, *m, &mpath[..]));
+                f.keys.push(key);
+                f.values.push(val);
+                continue;
+            }
+
+            match f.encoding.bounds {
+                Some(n) => {
+                    let elems = match **m {
+                        Json::Array(ref elems) if elems.len() == n => elems,
+                        _ => return field_err(&mpath[..], format!("expected an array of {} elements", n)),
+                    };
+
+                    for (j, elem) in elems.iter().enumerate() {
+                        let epath = format!("{}[{}]", mpath, j);
+                        let val   = try!(value_from_json(enc, &f.encoding, elem, &epath[..]));
+                        f.values.push(val);
+                    }
+                }
+                None => {
+                    let val = try!(value_from_json(enc, &f.encoding, *m, &mpath[..]));
+                    f.values.push(val);
+                }
+            }
+        }
+    }
+
+    for f in rec.fields.iter() {
+        if f.encoding.quant == Quantifier::Required && f.values.is_empty() {
+            let fpath = format!("{}.{}", path, f.encoding.name);
+            return field_err(&fpath[..], "required field is missing".to_string());
+        }
+    }
+
+    Ok(rec)
+}
+
+fn entry_from_json(enc: &CompleteEncoding, index: usize, json: &Json, path: &str)
+    -> Result<(Primitive, Value), Error> {
+
+    let (kenc, venc) = match enc.depends.get(index).and_then(|rec| rec.map_entry()) {
+        Some(entry) => entry,
+        None        => return Err(Error::EncError(EncError::EncodingInvalid)),
+    };
+
+    let obj = match *json {
+        Json::Object(ref obj) => obj,
+        _                     => return field_err(path, "expected an object".to_string()),
+    };
+
+    for name in obj.keys() {
+        if name != "key" && name != "value" {
+            let mpath = format!("{}.{}", path, name);
+            return field_err(&mpath[..], "map entries only have a key and a value".to_string());
+        }
+    }
+
+    let key = match obj.get("key") {
+        Some(k) => match try!(value_from_json(enc, kenc, k, &format!("{}.key", path)[..])) {
+            Value::Primitive(prim) => prim,
+            Value::Record(_)       => return Err(Error::EncError(EncError::EncodingInvalid)),
+        },
+        None => return field_err(path, "map entry has no key".to_string()),
+    };
+
+    let val = match obj.get("value") {
+        Some(v) => try!(value_from_json(enc, venc, v, &format!("{}.value", path)[..])),
+        None    => return field_err(path, "map entry has no value".to_string()),
+    };
+
+    Ok((key, val))
+}
+
+fn value_from_json(enc: &CompleteEncoding, f: &FieldEncoding, json: &Json, path: &str)
+    -> Result<Value, Error> {
+
+    if let Type::Record{index} = f.typ {
+        return match enc.depends.get(index) {
+            Some(renc) => Ok(Value::Record(try!(record_from_json(enc, renc, json, path)))),
+            None       => Err(Error::EncError(EncError::EncodingInvalid)),
+        };
+    }
+
+    let prim = match f.typ {
+        Type::UInt8   => json_unsigned(json, 0xFF).map(|x| Primitive::UInt8(x as u8)),
+        Type::UInt16  => json_unsigned(json, 0xFFFF).map(|x| Primitive::UInt16(x as u16)),
+        Type::UInt32  => json_unsigned(json, 0xFFFFFFFF).map(|x| Primitive::UInt32(x as u32)),
+        Type::UInt64  => json_unsigned(json, !0).map(Primitive::UInt64),
+
+        Type::Int8    => json_signed(json, 0x7F).map(|x| Primitive::Int8(x as i8)),
+        Type::Int16   => json_signed(json, 0x7FFF).map(|x| Primitive::Int16(x as i16)),
+        Type::Int32   => json_signed(json, 0x7FFFFFFF).map(|x| Primitive::Int32(x as i32)),
+        Type::Int64   => json_signed(json, 0x7FFFFFFFFFFFFFFF).map(Primitive::Int64),
+
+        Type::Fixed32 => json_unsigned(json, 0xFFFFFFFF).map(|x| Primitive::Fixed32(x as u32)),
+        Type::Fixed64 => json_unsigned(json, !0).map(Primitive::Fixed64),
+
+        Type::Float32 => json_float(json).map(|x| Primitive::Float32(x as f32)),
+        Type::Float64 => json_float(json).map(Primitive::Float64),
+
+        Type::Bool    => match *json {
+            Json::Boolean(x) => Some(Primitive::Bool(x)),
+            _                => None,
+        },
+
+        Type::Bytes   => match *json {
+            Json::String(ref s) => match s.from_base64() {
+                Ok(bytes) => Some(Primitive::Bytes(bytes)),
+                Err(_)    => return field_err(path, "invalid base64".to_string()),
+            },
+            _ => None,
+        },
+
+        Type::String  => match *json {
+            Json::String(ref s) => Some(Primitive::String(s.clone())),
+            _                   => None,
+        },
+
+        Type::Enum    => json_signed(json, 0x7FFFFFFFFFFFFFFF).map(Primitive::Enum),
+
+        // Entries of maps are handled by `entry_from_json`, so a map here means that a map entry
+        // has a map as its key or value, which no valid encoding does.
+        Type::Record{..} | Type::Map{..} => return Err(Error::EncError(EncError::EncodingInvalid)),
+    };
+
+    match prim {
+        Some(prim) => Ok(Value::Primitive(prim)),
+        None       => field_err(path, format!("expected a value of type {:?}, found {}", f.typ, json)),
+    }
+}
+
+// Read an unsigned integer no greater than `max` from a JSON number or string.
+fn json_unsigned(json: &Json, max: u64) -> Option<u64> {
+    let x = match *json {
+        Json::U64(x)                 => Some(x),
+        Json::I64(x) if x >= 0       => Some(x as u64),
+        Json::String(ref s)          => s.parse().ok(),
+        _                            => None,
+    };
+
+    x.and_then(|x| if x <= max { Some(x) } else { None })
+}
+
+// Read a signed integer in `[-max - 1, max]` from a JSON number or string.
+fn json_signed(json: &Json, max: i64) -> Option<i64> {
+    let x = match *json {
+        Json::I64(x)                        => Some(x),
+        Json::U64(x) if x <= max as u64     => Some(x as i64),
+        Json::String(ref s)                 => s.parse().ok(),
+        _                                   => None,
+    };
+
+    x.and_then(|x| if x <= max && x >= -max - 1 { Some(x) } else { None })
+}
+
+fn json_float(json: &Json) -> Option<f64> {
+    match *json {
+        Json::F64(x)        => Some(x),
+        Json::I64(x)        => Some(x as f64),
+        Json::U64(x)        => Some(x as f64),
+        Json::String(ref s) => match &s[..] {
+            "NaN"       => Some(::std::f64::NAN),
+            "Infinity"  => Some(::std::f64::INFINITY),
+            "-Infinity" => Some(::std::f64::NEG_INFINITY),
+            _           => None,
+        },
+        _ => None,
+    }
+}
@@ -24,6 +24,11 @@
 #[macro_use]
 extern crate lazy_static;
 
+extern crate "rustc-serialize" as rustc_serialize;
+
+#[cfg(feature = "mmap")]
+extern crate memmap;
+
 /// The `encoding` module defines the structures which are used to describe record encodings. The
 /// data structures described in this module drive `Encoder`s and `Decoder`s.
 pub mod encoding;
@@ -34,18 +39,79 @@ mod encoder;
 /// The `decoder` module defines the `Decoder` and related types.
 mod decoder;
 
+/// The `context` module describes where in a record an encoding or decoding error occurred.
+pub mod context;
+
 /// The `primitive` module provides helper methods for working with primitive types in rex, and
 /// defines the `Primitive` type which provides rust representations for each of the primitive
 /// types.
 mod primitive;
 
+/// The `compiler` module parses .rex schema files and compiles them into `CompleteEncoding`s.
+pub mod compiler;
+
+/// The `codegen` module generates Rust types with `Encodable` and `Decodable` impls from .rex
+/// schema files.
+pub mod codegen;
+
+/// The `value` module defines `DynamicRecord`, which can hold a record of any type given only its
+/// encoding.
+pub mod value;
+
+/// The `text` module provides a human readable text format for records.
+pub mod text;
+
+/// The `json` module converts records to and from JSON.
+pub mod json;
+
+/// The `container` module defines a file format which carries its own `CompleteEncoding`, so that
+/// it can be read without knowing its schema in advance.
+pub mod container;
+
+/// The `compat` module checks two versions of an encoding for incompatible changes.
+pub mod compat;
+
+/// The `borrowed` module defines a decoder which borrows `Bytes` and `String` fields from the
+/// buffer being decoded instead of copying them.
+pub mod borrowed;
+
+/// The `view` module provides lazy, read-only access to encoded records, optionally backed by a
+/// memory-mapped file.
+pub mod view;
+
+/// The `stream` module reads and writes sequences of length-prefixed records, for logs and pipes.
+pub mod stream;
+
+/// The `archive` module defines an archive format holding many named records, any of which can be
+/// read without scanning the others.
+pub mod archive;
+
+/// The `codec` module defines the `Codec` trait for compressing encoded records, along with the
+/// built-in codecs.
+pub mod codec;
+
+/// The `migrate` module rewrites records from one version of an encoding to another.
+pub mod migrate;
+
+/// The `crc` module computes the CRC-32C checksums used by checked records.
+pub mod crc;
 
 mod iter;
 
 pub use encoding::CompleteEncoding as Encoding;
 
+pub use primitive::{Primitive, Slice, SliceMut};
+
+pub use value::{Value, DynamicRecord, DynamicField};
+
+pub use context::ErrorContext;
+
 pub use encoder::Error as EncError;
-pub use encoder::{Encoder, Encodable, encode_to};
+pub use encoder::{Encoder, Encodable, encode_to, encode_to_checked, encode_streaming};
 
 pub use decoder::Error as DecError;
-pub use decoder::{Decoder, Decodable, decode_from};
+pub use decoder::{Decoder, Decodable, DecodeLimits, PREALLOC_MAX, decode_from, decode_from_checked,
+                  decode_from_checked_with_limits, decode_from_with_limits, next_elem};
+
+pub use borrowed::{BorrowedPrimitive, BorrowedDecodable, SliceDecoder, decode_slice,
+                   decode_slice_with_limits};
@@ -0,0 +1,480 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::error;
+use std::error::FromError;
+use std::fmt;
+use std::io;
+use std::iter;
+
+use decoder::Error as DecError;
+use encoder::Error as EncError;
+use encoder::encode_to;
+use encoding::{CompleteEncoding, RecordEncoding, FieldEncoding, Quantifier, Type};
+use primitive::Primitive;
+use value::{DynamicRecord, DynamicField, Value};
+
+/// `Error` is used to report errors that occur while migrating a record between encodings.
+#[derive(Debug)]
+pub enum Error {
+    /// `Field` indicates that a field couldn't be migrated. `path` gives the location of the field
+    /// in the old record, e.g. `Mesh.submeshes[3].material.name`.
+    Field {
+        /// Path from the root record to the offending field.
+        path: String,
+
+        /// Description of the problem.
+        msg: String,
+    },
+
+    /// `EncError` is used to pass through errors from encoding the migrated record.
+    EncError(EncError),
+
+    /// `DecError` is used to pass through errors from decoding the record to migrate.
+    DecError(DecError),
+}
+
+impl FromError<EncError> for Error {
+    fn from_error(err: EncError) -> Error {
+        Error::EncError(err)
+    }
+}
+
+impl FromError<DecError> for Error {
+    fn from_error(err: DecError) -> Error {
+        Error::DecError(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Field{ref path, ref msg}  => write!(f, "{}: {}", path, msg),
+            Error::EncError(ref err)         => write!(f, "error encoding record: {}", err),
+            Error::DecError(ref err)         => write!(f, "error decoding record: {}", err),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Field{..}         => "invalid field",
+            Error::EncError(ref err) => error::Error::description(err),
+            Error::DecError(ref err) => error::Error::description(err),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::EncError(ref err) => Some(err as &error::Error),
+            Error::DecError(ref err) => Some(err as &error::Error),
+            _                        => None,
+        }
+    }
+}
+
+fn field_err<T>(path: &str, msg: String) -> Result<T, Error> {
+    Err(Error::Field {
+        path: path.to_string(),
+        msg:  msg,
+    })
+}
+
+// A `Transform` is a single change registered with a `Migration`. Records are named as they are in
+// the new encoding.
+enum Transform {
+    Rename {
+        record: String,
+        from:   String,
+        to:     String,
+    },
+
+    Retype {
+        record:  String,
+        field:   String,
+        convert: Box<Fn(Value) -> Result<Value, String>>,
+    },
+
+    Default {
+        record: String,
+        field:  String,
+        value:  Value,
+    },
+
+    Drop {
+        record: String,
+        field:  String,
+    },
+}
+
+/// A `Migration` rewrites records from one version of an encoding to another, without needing a
+/// Rust type for either version. Fields are matched up by name, and records by following the
+/// fields that contain them. Changes that can't be made automatically are described by
+/// transforms:
+///
+/// * `rename_field` for a field whose name changed.
+/// * `retype_field` for a field whose type changed, with a function converting each element.
+/// * `default_field` for a new field, most importantly a required one, giving its value.
+/// * `drop_field` for a field which was removed, so that its values may be discarded.
+///
+/// Apart from the identical types, only the widening conversions `uint32` to `uint64` and `int32`
+/// to `int64` are made without a `retype_field` transform. Fields which the old encoding itself
+/// didn't know about (see `DynamicRecord::unknown`) are carried over as they are, unless the new
+/// encoding has a field with the same id, in which case migrating the record is an error.
+pub struct Migration<'x> {
+    old: &'x CompleteEncoding,
+    new: &'x CompleteEncoding,
+
+    transforms: Vec<Transform>,
+}
+
+impl<'x> Migration<'x> {
+    /// `new` creates a `Migration` from `old` to `new` with no transforms.
+    pub fn new(old: &'x CompleteEncoding, new: &'x CompleteEncoding) -> Migration<'x> {
+        Migration {
+            old:        old,
+            new:        new,
+            transforms: vec![],
+        }
+    }
+
+    /// `rename_field` records that the field `from` of `record` is named `to` in the new encoding.
+    pub fn rename_field(&mut self, record: &str, from: &str, to: &str) {
+        self.transforms.push(Transform::Rename {
+            record: record.to_string(),
+            from:   from.to_string(),
+            to:     to.to_string(),
+        });
+    }
+
+    /// `retype_field` registers a function which converts each element of the field `field` of
+    /// `record` to its type in the new encoding. It's given the old element, and returns either
+    /// the new one or a description of why it can't be converted.
+    pub fn retype_field<F>(&mut self, record: &str, field: &str, convert: F)
+        where F: Fn(Value) -> Result<Value, String> + 'static {
+
+        self.transforms.push(Transform::Retype {
+            record:  record.to_string(),
+            field:   field.to_string(),
+            convert: Box::new(convert),
+        });
+    }
+
+    /// `default_field` gives the value for every element of the field `field` of `record` when
+    /// there's nothing to migrate into it, which is necessary for new required fields.
+    pub fn default_field(&mut self, record: &str, field: &str, value: Value) {
+        self.transforms.push(Transform::Default {
+            record: record.to_string(),
+            field:  field.to_string(),
+            value:  value,
+        });
+    }
+
+    /// `drop_field` allows the values of the field `field` of `record`, which isn't in the new
+    /// encoding, to be discarded. Without it, migrating a record which has values for the field is
+    /// an error.
+    pub fn drop_field(&mut self, record: &str, field: &str) {
+        self.transforms.push(Transform::Drop {
+            record: record.to_string(),
+            field:  field.to_string(),
+        });
+    }
+
+    /// `migrate` reads a record in the old encoding from `r`, and writes it to `w` in the new
+    /// encoding.
+    pub fn migrate<R, W>(&self, r: &mut R, w: &mut W) -> Result<(), Error>
+        where R: io::Read,
+              W: io::Write {
+
+        let old = try!(DynamicRecord::decode_from(self.old, r));
+        let new = try!(self.migrate_record(&old));
+
+        try!(encode_to(self.new, w, &new));
+
+        Ok(())
+    }
+
+    /// `migrate_record` converts `rec`, which must have been decoded with the old encoding, to the
+    /// new encoding.
+    pub fn migrate_record(&self, rec: &DynamicRecord) -> Result<DynamicRecord, Error> {
+        self.record(&self.new.target, rec, &rec.name[..])
+    }
+
+    fn record(&self, new: &RecordEncoding, rec: &DynamicRecord, path: &str)
+        -> Result<DynamicRecord, Error> {
+
+        let mut out  = DynamicRecord::new(new);
+        let mut used: Vec<String> = vec![];
+
+        for field in out.fields.iter_mut() {
+            let name = self.source_name(&new.name[..], &field.encoding.name[..]);
+
+            // A dropped field isn't the source of a new one, even if they have the same name.
+            let src = if self.is_dropped(&new.name[..], name) {
+                None
+            } else {
+                rec.field_by_name(name)
+            };
+
+            if src.is_some() {
+                used.push(name.to_string());
+            }
+
+            field.keys = match src {
+                Some(of) => try!(self.keys(of, &field.encoding, path)),
+                None     => vec![],
+            };
+
+            field.values = try!(self.field(&new.name[..], &field.encoding, src, path));
+        }
+
+        for f in rec.fields.iter() {
+            let name = &f.encoding.name[..];
+
+            let handled = used.iter().any(|u| u == name) || self.is_dropped(&new.name[..], name);
+
+            if f.values.is_empty() || handled {
+                continue;
+            }
+
+            return field_err(&format!("{}.{}", path, name)[..],
+                             "field isn't in the new encoding, so it must be renamed or dropped"
+                                 .to_string());
+        }
+
+        // An unknown field whose id the new encoding uses would be written out alongside that
+        // field's own values, and its data can't be read as them.
+        for &(id, _) in rec.unknown.iter() {
+            if let Some(f) = out.fields.iter().find(|f| f.encoding.id == id) {
+                return field_err(&format!("{}.{}", path, f.encoding.name)[..],
+                                 format!("the old record has an unknown field with id {}, which \
+                                          this field now uses", id.0));
+            }
+        }
+
+        out.unknown = rec.unknown.clone();
+
+        Ok(out)
+    }
+
+    // Migrate the values of `src` into the field `nf` of `record`.
+    fn field(&self, record: &str, nf: &FieldEncoding, src: Option<&DynamicField>, path: &str)
+        -> Result<Vec<Value>, Error> {
+
+        let per = nf.bounds.unwrap_or(1);
+
+        let (values, path) = match src {
+            Some(of) if !of.values.is_empty() => {
+                let path = format!("{}.{}", path, of.encoding.name);
+
+                if of.encoding.bounds != nf.bounds {
+                    return field_err(&path[..], "array bounds changed".to_string());
+                }
+
+                let indexed    = of.encoding.quant == Quantifier::Repeated || nf.bounds.is_some();
+                let mut values = Vec::with_capacity(of.values.len());
+
+                for (idx, val) in of.values.iter().enumerate() {
+                    let elem_path = if indexed {
+                        format!("{}[{}]", path, idx)
+                    } else {
+                        path.clone()
+                    };
+                    values.push(try!(self.convert(record, &of.encoding, nf, val, &elem_path[..])));
+                }
+
+                (values, path)
+            },
+
+            _ => {
+                let path = format!("{}.{}", path, nf.name);
+
+                let values = match self.default_value(record, &nf.name[..]) {
+                    Some(val) => {
+                        if !value_has_type(val, nf.typ) {
+                            return field_err(&path[..], "default has the wrong type".to_string());
+                        }
+                        iter::repeat(val.clone()).take(per).collect()
+                    },
+                    None => vec![],
+                };
+
+                (values, path)
+            },
+        };
+
+        let count = values.len() / per;
+
+        match nf.quant {
+            Quantifier::Required if count == 0 => field_err(&path[..],
+                "new required field has no value, so it needs a default".to_string()),
+
+            Quantifier::Required | Quantifier::Optional if count > 1 => field_err(&path[..],
+                format!("{} values for a field which can only have one", count)),
+
+            _ => Ok(values),
+        }
+    }
+
+    // Migrate the keys of `of`, if it's a map field, to the key type of the new map field `nf`.
+    fn keys(&self, of: &DynamicField, nf: &FieldEncoding, path: &str)
+        -> Result<Vec<Primitive>, Error> {
+
+        if of.values.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let path = format!("{}.{}", path, of.encoding.name);
+
+        match (of.encoding.is_map(), nf.is_map()) {
+            (false, false) => return Ok(vec![]),
+            (true, true)   => (),
+            _              => return field_err(&path[..],
+                "can't convert between map fields and other fields".to_string()),
+        }
+
+        let key_typ = try!(self.entry(nf)).0.typ;
+        let mut keys = Vec::with_capacity(of.keys.len());
+
+        for (idx, key) in of.keys.iter().enumerate() {
+            match widen(key, key_typ) {
+                Some(key) => keys.push(key),
+                None      => return field_err(&format!("{}[{}]", path, idx)[..],
+                    format!("can't convert map key {:?} to {:?}", key, key_typ)),
+            }
+        }
+
+        Ok(keys)
+    }
+
+    // The key and value fields of the entries of the new map field `nf`.
+    fn entry(&self, nf: &FieldEncoding) -> Result<(&FieldEncoding, &FieldEncoding), Error> {
+        let entry = match nf.typ {
+            Type::Map{index} => self.new.depends.get(index).and_then(|rec| rec.map_entry()),
+            _                => None,
+        };
+
+        match entry {
+            Some(entry) => Ok(entry),
+            None        => Err(Error::EncError(EncError::EncodingInvalid)),
+        }
+    }
+
+    // Convert a single element of the old field `of` to the type of the new field `nf`. The
+    // elements of map fields are their values, so they're converted to the value type.
+    fn convert(&self, record: &str, of: &FieldEncoding, nf: &FieldEncoding, val: &Value, path: &str)
+        -> Result<Value, Error> {
+
+        let typ = if nf.is_map() { try!(self.entry(nf)).1.typ } else { nf.typ };
+
+        if let Some(convert) = self.retype(record, &nf.name[..]) {
+            let new = match convert(val.clone()) {
+                Ok(new)  => new,
+                Err(msg) => return field_err(path, msg),
+            };
+
+            if !value_has_type(&new, typ) {
+                return field_err(path,
+                    "retype_field produced a value of the wrong type".to_string());
+            }
+
+            return Ok(new);
+        }
+
+        match (val, typ) {
+            (&Value::Record(ref child), Type::Record{index}) => match self.new.depends.get(index) {
+                Some(new) => Ok(Value::Record(try!(self.record(new, child, path)))),
+                None      => Err(Error::EncError(EncError::EncodingInvalid)),
+            },
+
+            (&Value::Primitive(ref prim), typ) => match widen(prim, typ) {
+                Some(prim) => Ok(Value::Primitive(prim)),
+                None       => field_err(path,
+                    format!("can't convert {:?} to {:?} without retype_field", of.typ, typ)),
+            },
+
+            (_, typ) => field_err(path,
+                format!("can't convert {:?} to {:?} without retype_field", of.typ, typ)),
+        }
+    }
+
+    // The name in the old encoding of the field `field` of `record`.
+    fn source_name<'y>(&'y self, record: &str, field: &'y str) -> &'y str {
+        for t in self.transforms.iter() {
+            if let Transform::Rename{record: ref r, ref from, ref to} = *t {
+                if r == record && to == field {
+                    return &from[..];
+                }
+            }
+        }
+
+        field
+    }
+
+    fn retype(&self, record: &str, field: &str) -> Option<&Fn(Value) -> Result<Value, String>> {
+        for t in self.transforms.iter() {
+            if let Transform::Retype{record: ref r, field: ref f, ref convert} = *t {
+                if r == record && f == field {
+                    return Some(&**convert);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn default_value(&self, record: &str, field: &str) -> Option<&Value> {
+        for t in self.transforms.iter() {
+            if let Transform::Default{record: ref r, field: ref f, ref value} = *t {
+                if r == record && f == field {
+                    return Some(value);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn is_dropped(&self, record: &str, field: &str) -> bool {
+        self.transforms.iter().any(|t| match *t {
+            Transform::Drop{record: ref r, field: ref f} => r == record && f == field,
+            _                                            => false,
+        })
+    }
+}
+
+fn value_has_type(val: &Value, typ: Type) -> bool {
+    match (val, typ) {
+        (&Value::Primitive(ref prim), typ)   => prim.has_type(typ),
+        (&Value::Record(_), Type::Record{..}) => true,
+        _                                     => false,
+    }
+}
+
+// Convert `prim` to `typ`, if that can be done without losing information.
+fn widen(prim: &Primitive, typ: Type) -> Option<Primitive> {
+    if prim.has_type(typ) {
+        return Some(prim.clone());
+    }
+
+    match (prim, typ) {
+        (&Primitive::UInt32(x), Type::UInt64) => Some(Primitive::UInt64(x as u64)),
+        (&Primitive::Int32(x),  Type::Int64)  => Some(Primitive::Int64(x as i64)),
+        _                                     => None,
+    }
+}
@@ -15,10 +15,14 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+use std::mem;
+use std::slice;
+
 use encoding::Type;
 
 /// A `Primitive` represents the primitive data types which make up all records. This is the format
 /// used to communicate data between `Encodable`/`Decodable` types and an `Encoder`/`Decoder`.
+#[derive(Clone,Debug,PartialEq)]
 #[allow(missing_docs)]
 pub enum Primitive {
     UInt8(u8),
@@ -52,12 +56,12 @@ impl Primitive {
 
         match (self, t) {
             (&Primitive::UInt8(..),  UInt8)  => true,
-            (&Primitive::UInt16(..), UInt8)  => true,
+            (&Primitive::UInt16(..), UInt16) => true,
             (&Primitive::UInt32(..), UInt32) => true,
             (&Primitive::UInt64(..), UInt64) => true,
 
             (&Primitive::Int8(..),  Int8)  => true,
-            (&Primitive::Int16(..), Int8)  => true,
+            (&Primitive::Int16(..), Int16) => true,
             (&Primitive::Int32(..), Int32) => true,
             (&Primitive::Int64(..), Int64) => true,
 
@@ -79,6 +83,150 @@ impl Primitive {
     }
 }
 
+/// A `Slice` holds a run of values of one of the fixed-width primitive types. `Encodable`s can hand
+/// these out for array and repeated fields, so that the whole field is written in one go rather
+/// than an element at a time.
+#[derive(Copy,Clone,Debug)]
+#[allow(missing_docs)]
+pub enum Slice<'a> {
+    UInt8(&'a [u8]),
+    UInt16(&'a [u16]),
+
+    Int8(&'a [i8]),
+    Int16(&'a [i16]),
+
+    Fixed32(&'a [u32]),
+    Fixed64(&'a [u64]),
+
+    Float32(&'a [f32]),
+    Float64(&'a [f64]),
+}
+
+/// A `SliceMut` is the mutable counterpart of `Slice`, which `Decodable`s can hand out for a
+/// decoder to fill in directly.
+#[derive(Debug)]
+#[allow(missing_docs)]
+pub enum SliceMut<'a> {
+    UInt8(&'a mut [u8]),
+    UInt16(&'a mut [u16]),
+
+    Int8(&'a mut [i8]),
+    Int16(&'a mut [i16]),
+
+    Fixed32(&'a mut [u32]),
+    Fixed64(&'a mut [u64]),
+
+    Float32(&'a mut [f32]),
+    Float64(&'a mut [f64]),
+}
+
+impl<'a> Slice<'a> {
+    /// `has_type` checks that the elements of `self` have type `t`.
+    pub fn has_type(&self, t: Type) -> bool {
+        match (*self, t) {
+            (Slice::UInt8(..),   Type::UInt8)   => true,
+            (Slice::UInt16(..),  Type::UInt16)  => true,
+            (Slice::Int8(..),    Type::Int8)    => true,
+            (Slice::Int16(..),   Type::Int16)   => true,
+            (Slice::Fixed32(..), Type::Fixed32) => true,
+            (Slice::Fixed64(..), Type::Fixed64) => true,
+            (Slice::Float32(..), Type::Float32) => true,
+            (Slice::Float64(..), Type::Float64) => true,
+
+            _ => false,
+        }
+    }
+
+    /// `len` returns the number of elements in `self`.
+    pub fn len(&self) -> usize {
+        let (bytes, width) = slice_bytes(self);
+        bytes.len() / width
+    }
+}
+
+impl<'a> SliceMut<'a> {
+    /// `has_type` checks that the elements of `self` have type `t`.
+    pub fn has_type(&self, t: Type) -> bool {
+        match (self, t) {
+            (&SliceMut::UInt8(..),   Type::UInt8)   => true,
+            (&SliceMut::UInt16(..),  Type::UInt16)  => true,
+            (&SliceMut::Int8(..),    Type::Int8)    => true,
+            (&SliceMut::Int16(..),   Type::Int16)   => true,
+            (&SliceMut::Fixed32(..), Type::Fixed32) => true,
+            (&SliceMut::Fixed64(..), Type::Fixed64) => true,
+            (&SliceMut::Float32(..), Type::Float32) => true,
+            (&SliceMut::Float64(..), Type::Float64) => true,
+
+            _ => false,
+        }
+    }
+
+    /// `len` returns the number of elements in `self`.
+    pub fn len(&self) -> usize {
+        match *self {
+            SliceMut::UInt8(ref s)   => s.len(),
+            SliceMut::UInt16(ref s)  => s.len(),
+            SliceMut::Int8(ref s)    => s.len(),
+            SliceMut::Int16(ref s)   => s.len(),
+            SliceMut::Fixed32(ref s) => s.len(),
+            SliceMut::Fixed64(ref s) => s.len(),
+            SliceMut::Float32(ref s) => s.len(),
+            SliceMut::Float64(ref s) => s.len(),
+        }
+    }
+}
+
+/// `fixed_width` returns the encoded size of the type `t`, if `t` is one of the types which can be
+/// held in a `Slice`.
+pub fn fixed_width(t: Type) -> Option<usize> {
+    match t {
+        Type::UInt8 | Type::Int8                     => Some(1),
+        Type::UInt16 | Type::Int16                   => Some(2),
+        Type::Fixed32 | Type::Float32                => Some(4),
+        Type::Fixed64 | Type::Float64                => Some(8),
+        _                                            => None,
+    }
+}
+
+/// `slice_bytes` returns the memory underlying `s`, along with the size of each element. The bytes
+/// are in native byte order.
+pub fn slice_bytes<'a>(s: &Slice<'a>) -> (&'a [u8], usize) {
+    fn raw<T>(s: &[T]) -> (&[u8], usize) {
+        let width = mem::size_of::<T>();
+        (unsafe { slice::from_raw_parts(s.as_ptr() as *const u8, s.len() * width) }, width)
+    }
+
+    match *s {
+        Slice::UInt8(s)   => raw(s),
+        Slice::UInt16(s)  => raw(s),
+        Slice::Int8(s)    => raw(s),
+        Slice::Int16(s)   => raw(s),
+        Slice::Fixed32(s) => raw(s),
+        Slice::Fixed64(s) => raw(s),
+        Slice::Float32(s) => raw(s),
+        Slice::Float64(s) => raw(s),
+    }
+}
+
+/// `slice_bytes_mut` is the mutable version of `slice_bytes`.
+pub fn slice_bytes_mut<'a, 'b>(s: &'b mut SliceMut<'a>) -> (&'b mut [u8], usize) {
+    fn raw<T>(s: &mut [T]) -> (&mut [u8], usize) {
+        let width = mem::size_of::<T>();
+        (unsafe { slice::from_raw_parts_mut(s.as_mut_ptr() as *mut u8, s.len() * width) }, width)
+    }
+
+    match *s {
+        SliceMut::UInt8(ref mut s)   => raw(&mut **s),
+        SliceMut::UInt16(ref mut s)  => raw(&mut **s),
+        SliceMut::Int8(ref mut s)    => raw(&mut **s),
+        SliceMut::Int16(ref mut s)   => raw(&mut **s),
+        SliceMut::Fixed32(ref mut s) => raw(&mut **s),
+        SliceMut::Fixed64(ref mut s) => raw(&mut **s),
+        SliceMut::Float32(ref mut s) => raw(&mut **s),
+        SliceMut::Float64(ref mut s) => raw(&mut **s),
+    }
+}
+
 /// `uvarint_size` returns the number of bytes required to encode `x` as a varint.
 pub fn uvarint_size(x: u64) -> usize {
     if x < 0x80 {
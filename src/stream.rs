@@ -0,0 +1,549 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::error;
+use std::error::FromError;
+use std::fmt;
+use std::io;
+use std::io::{Read, ReadExt};
+use std::marker::PhantomData;
+use std::mem;
+
+use codec;
+use codec::{Codec, Identity};
+use decoder;
+use decoder::{Decodable, DecodeLimits, decode_from_with_limits};
+use encoder;
+use encoder::{Encodable, encode_to};
+use encoding::CompleteEncoding;
+
+/// `MAGIC` is the first four bytes of every record stream.
+pub const MAGIC: &'static [u8] = b"REXS";
+
+/// `FORMAT_VERSION` is the version of the stream format written by `RecordWriter`.
+pub const FORMAT_VERSION: u64 = 1;
+
+/// A `SyncMarker` is written before every record in a stream which uses them. It lets a
+/// `RecordReader` find the start of the next record after the stream has been corrupted.
+pub type SyncMarker = [u8; 16];
+
+// Bits of the flags field in the stream header.
+const FLAG_SYNC: u64 = 1;
+
+/// `Error` is used to report errors that occur while reading or writing a record stream.
+#[derive(Debug)]
+pub enum Error {
+    /// `BadMagic` indicates that the input doesn't start with `MAGIC`, so it probably isn't a
+    /// record stream at all.
+    BadMagic,
+
+    /// `UnsupportedVersion` indicates that the stream was written with a newer version of the
+    /// stream format than this library understands.
+    UnsupportedVersion(u64),
+
+    /// `UnknownCodec` indicates that the stream was compressed with a codec which isn't available.
+    UnknownCodec(u64),
+
+    /// `Truncated` indicates that the stream ended part way through a record.
+    Truncated,
+
+    /// `BadSync` indicates that a record wasn't preceded by the stream's sync marker, so the stream
+    /// has been corrupted.
+    BadSync,
+
+    /// `TrailingData` indicates that a record was shorter than its length prefix claimed.
+    TrailingData,
+
+    /// `IoError` is used to pass through `std::io` errors.
+    IoError(io::Error),
+
+    /// `EncError` is used to pass through errors from encoding a record.
+    EncError(encoder::Error),
+
+    /// `DecError` is used to pass through errors from decoding a record.
+    DecError(decoder::Error),
+}
+
+impl FromError<encoder::Error> for Error {
+    fn from_error(err: encoder::Error) -> Error {
+        Error::EncError(err)
+    }
+}
+
+impl FromError<decoder::Error> for Error {
+    fn from_error(err: decoder::Error) -> Error {
+        Error::DecError(err)
+    }
+}
+
+impl FromError<io::Error> for Error {
+    fn from_error(err: io::Error) -> Error {
+        Error::IoError(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnsupportedVersion(v) => write!(f, "unsupported stream version {}", v),
+            Error::UnknownCodec(id)      => write!(f, "unknown codec {}", id),
+            Error::IoError(ref err)      => write!(f, "i/o error: {}", err),
+            Error::EncError(ref err)     => write!(f, "error encoding record: {}", err),
+            Error::DecError(ref err)     => write!(f, "error decoding record: {}", err),
+            ref err                      => write!(f, "{}", error::Error::description(err)),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::BadMagic              => "not a record stream",
+            Error::UnsupportedVersion(_) => "unsupported stream version",
+            Error::UnknownCodec(_)       => "unknown codec",
+            Error::Truncated             => "stream ended part way through a record",
+            Error::BadSync               => "missing sync marker",
+            Error::TrailingData          => "record shorter than its length prefix",
+            Error::IoError(_)            => "i/o error",
+            Error::EncError(ref err)     => error::Error::description(err),
+            Error::DecError(ref err)     => error::Error::description(err),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::IoError(ref err)  => Some(err as &error::Error),
+            Error::EncError(ref err) => Some(err as &error::Error),
+            Error::DecError(ref err) => Some(err as &error::Error),
+            _                        => None,
+        }
+    }
+}
+
+/// A `RecordWriter` writes a sequence of records to a stream. The stream starts with a header
+///
+/// 1. The four bytes of `MAGIC`.
+/// 2. `FORMAT_VERSION`, as a varint.
+/// 3. A varint of flags, saying whether sync markers are used.
+/// 4. The id of the codec that records are compressed with, as a varint.
+/// 5. The `SyncMarker`, if they are used.
+///
+/// and each record is then written as
+///
+/// 1. The `SyncMarker`, if they are used.
+/// 2. The length of the (compressed) record in bytes, as a varint.
+/// 3. The record, encoded according to the writer's `CompleteEncoding` and then compressed.
+///
+/// Records can be appended to an existing stream by creating the writer with `append`.
+pub struct RecordWriter<'x, W> {
+    w:     W,
+    enc:   &'x CompleteEncoding,
+    sync:  Option<SyncMarker>,
+    codec: Box<Codec>,
+}
+
+impl<'x, W> RecordWriter<'x, W>
+    where W: io::Write {
+
+    /// `new` writes a stream header without sync markers to `w`, and returns a writer for records
+    /// encoded with `enc`.
+    pub fn new(w: W, enc: &'x CompleteEncoding) -> Result<RecordWriter<'x, W>, Error> {
+        RecordWriter::create(w, enc, None, Box::new(Identity))
+    }
+
+    /// `with_sync` is like `new`, but the stream will have `sync` written before every record.
+    /// The marker should be chosen so that it's unlikely to turn up inside the records, e.g. by
+    /// generating it randomly.
+    pub fn with_sync(w: W, enc: &'x CompleteEncoding, sync: SyncMarker)
+        -> Result<RecordWriter<'x, W>, Error> {
+
+        RecordWriter::create(w, enc, Some(sync), Box::new(Identity))
+    }
+
+    /// `with_codec` is like `new`, but every record will be compressed with `codec`, and preceded
+    /// by `sync` if it's given.
+    pub fn with_codec(w: W, enc: &'x CompleteEncoding, sync: Option<SyncMarker>, codec: Box<Codec>)
+        -> Result<RecordWriter<'x, W>, Error> {
+
+        RecordWriter::create(w, enc, sync, codec)
+    }
+
+    /// `append` returns a writer which adds records to the end of an existing stream, without
+    /// writing a header. `sync` and `codec` must be the ones that the stream was created with.
+    pub fn append(w: W, enc: &'x CompleteEncoding, sync: Option<SyncMarker>, codec: Box<Codec>)
+        -> RecordWriter<'x, W> {
+
+        RecordWriter {
+            w:     w,
+            enc:   enc,
+            sync:  sync,
+            codec: codec,
+        }
+    }
+
+    fn create(mut w: W, enc: &'x CompleteEncoding, sync: Option<SyncMarker>, codec: Box<Codec>)
+        -> Result<RecordWriter<'x, W>, Error> {
+
+        let flags = if sync.is_some() { FLAG_SYNC } else { 0 };
+
+        try!(w.write_all(MAGIC));
+        try!(encoder::write_uvarint(&mut w, FORMAT_VERSION));
+        try!(encoder::write_uvarint(&mut w, flags));
+        try!(encoder::write_uvarint(&mut w, codec.id()));
+
+        if let Some(ref marker) = sync {
+            try!(w.write_all(&marker[..]));
+        }
+
+        Ok(RecordWriter::append(w, enc, sync, codec))
+    }
+
+    /// `write` appends `e` to the stream.
+    ///
+    /// # Errors
+    ///
+    /// As with `encode_to`, if the error is not an i/o error then nothing has been written.
+    pub fn write<E>(&mut self, e: &E) -> Result<(), Error>
+        where E: Encodable {
+
+        let mut buf = vec![];
+        try!(encode_to(self.enc, &mut buf, e));
+
+        if self.codec.id() != codec::NONE {
+            buf = self.codec.compress(&buf[..]);
+        }
+
+        if let Some(ref marker) = self.sync {
+            try!(self.w.write_all(&marker[..]));
+        }
+
+        try!(encoder::write_uvarint(&mut self.w, buf.len() as u64));
+        try!(self.w.write_all(&buf[..]));
+
+        Ok(())
+    }
+
+    /// `flush` flushes the underlying writer.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        try!(self.w.flush());
+        Ok(())
+    }
+
+    /// `into_inner` returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+}
+
+/// A `RecordReader` reads the records of a stream written by a `RecordWriter`.
+///
+/// An error in decoding a record doesn't affect the records after it, since the length prefix
+/// says where the next one starts. If the framing itself is damaged the reader loses its place;
+/// streams with sync markers then skip ahead to the next marker, and streams without them end.
+pub struct RecordReader<'x, R> {
+    r:     R,
+    enc:   &'x CompleteEncoding,
+    sync:  Option<SyncMarker>,
+    codec: Box<Codec>,
+
+    limits: DecodeLimits,
+
+    // `lost` is set when the framing of the stream is damaged, so that we don't know where the
+    // next record starts.
+    lost: bool,
+
+    // `synced` is set when the sync marker for the next record has already been read.
+    synced: bool,
+
+    // `unscanned` holds bytes which were read in place of a sync marker, and which `resync` must
+    // search before reading any more, since the next marker might start among them.
+    unscanned: Vec<u8>,
+}
+
+impl<'x, R> RecordReader<'x, R>
+    where R: io::Read {
+
+    /// `new` reads the stream header from `r`, and returns a reader for records encoded with
+    /// `enc`. Only streams compressed with the built-in codecs can be read this way; see
+    /// `with_codec` for others.
+    pub fn new(r: R, enc: &'x CompleteEncoding) -> Result<RecordReader<'x, R>, Error> {
+        RecordReader::open(r, enc, None)
+    }
+
+    /// `with_codec` is like `new`, but uses `codec` if the stream was compressed with it.
+    pub fn with_codec(r: R, enc: &'x CompleteEncoding, codec: Box<Codec>)
+        -> Result<RecordReader<'x, R>, Error> {
+
+        RecordReader::open(r, enc, Some(codec))
+    }
+
+    fn open(mut r: R, enc: &'x CompleteEncoding, custom: Option<Box<Codec>>)
+        -> Result<RecordReader<'x, R>, Error> {
+
+        for b in MAGIC.iter() {
+            if try!(decoder::read_u8(&mut r)) != *b {
+                return Err(Error::BadMagic);
+            }
+        }
+
+        let version = try!(decoder::read_uvarint(&mut r));
+        if version > FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        let flags = try!(decoder::read_uvarint(&mut r));
+
+        let id = try!(decoder::read_uvarint(&mut r));
+
+        let codec = match custom {
+            Some(c) if c.id() == id => c,
+            _                       => match codec::find(id) {
+                Some(c) => c,
+                None    => return Err(Error::UnknownCodec(id)),
+            },
+        };
+
+        let sync = if flags & FLAG_SYNC != 0 {
+            let mut marker = [0u8; 16];
+            if try!(read_full(&mut r, &mut marker[..])) < marker.len() {
+                return Err(Error::Truncated);
+            }
+            Some(marker)
+        } else {
+            None
+        };
+
+        Ok(RecordReader {
+            r:         r,
+            enc:       enc,
+            sync:      sync,
+            codec:     codec,
+            limits:    Default::default(),
+            lost:      false,
+            synced:    false,
+            unscanned: vec![],
+        })
+    }
+
+    /// `set_limits` sets the `DecodeLimits` that the following records are decoded with, in place
+    /// of the default ones. A record whose frame is longer than `limits.max_bytes` is treated as
+    /// damaged framing, since its length can't be trusted.
+    pub fn set_limits(&mut self, limits: DecodeLimits) {
+        self.limits = limits;
+    }
+
+    /// `sync_marker` returns the stream's sync marker, if it has one.
+    pub fn sync_marker(&self) -> Option<SyncMarker> {
+        self.sync
+    }
+
+    /// `read` decodes the next record in the stream into `d`. It returns `false` at the end of the
+    /// stream, and also if the stream has lost its place and has no sync markers to find it again.
+    pub fn read<D>(&mut self, d: &mut D) -> Result<bool, Error>
+        where D: Decodable {
+
+        if self.lost {
+            if !try!(self.resync()) {
+                return Ok(false);
+            }
+        }
+
+        let buf = match self.read_frame() {
+            Ok(Some(buf)) => buf,
+            Ok(None)      => return Ok(false),
+            Err(err)      => {
+                self.lost = true;
+                return Err(err);
+            }
+        };
+
+        let buf = if self.codec.id() != codec::NONE {
+            try!(self.codec.decompress(&buf[..], self.limits.max_bytes))
+        } else {
+            buf
+        };
+
+        let mut rd = &buf[..];
+        try!(decode_from_with_limits(self.enc, &mut rd, d, &self.limits));
+
+        if !rd.is_empty() {
+            return Err(Error::TrailingData);
+        }
+
+        Ok(true)
+    }
+
+    /// `records` returns an iterator over the remaining records in the stream.
+    pub fn records<'r, D>(&'r mut self) -> Records<'r, 'x, R, D>
+        where D: Decodable + Default {
+
+        Records {
+            reader:  self,
+            phantom: PhantomData,
+        }
+    }
+
+    /// `resync` skips forward to just after the next sync marker. It returns `false` if the end of
+    /// the stream is reached first, or if the stream doesn't have sync markers.
+    pub fn resync(&mut self) -> Result<bool, Error> {
+        let marker = match self.sync {
+            Some(marker) => marker,
+            None         => return Ok(false),
+        };
+
+        // `window` holds the last `marker.len()` bytes read, starting at `start`.
+        let mut window = [0u8; 16];
+        let mut start  = 0;
+        let mut filled = 0;
+
+        let mut unscanned = mem::replace(&mut self.unscanned, vec![]).into_iter();
+
+        loop {
+            let mut b = [0u8; 1];
+
+            match unscanned.next() {
+                Some(x) => b[0] = x,
+                None    => if try!(read_full(&mut self.r, &mut b[..])) == 0 {
+                    return Ok(false);
+                },
+            }
+
+            if filled < window.len() {
+                window[filled] = b[0];
+                filled += 1;
+            } else {
+                window[start] = b[0];
+                start = (start + 1) % window.len();
+            }
+
+            if filled == window.len() &&
+               (0..window.len()).all(|i| window[(start + i) % window.len()] == marker[i]) {
+
+                self.lost   = false;
+                self.synced = true;
+                return Ok(true);
+            }
+        }
+    }
+
+    // Read the sync marker and length prefix of the next record, and then the record itself.
+    // Returns `None` if the stream ends cleanly before the record starts.
+    fn read_frame(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let mut at_start = true;
+
+        if let Some(marker) = self.sync {
+            if !self.synced {
+                let mut found = [0u8; 16];
+
+                match try!(read_full(&mut self.r, &mut found[..])) {
+                    0                          => return Ok(None),
+                    n if n < found.len()       => return Err(Error::Truncated),
+                    _ if found != marker       => {
+                        // The marker didn't start where it should have, but might start part way
+                        // through what was read instead.
+                        self.unscanned = found[1..].to_vec();
+                        return Err(Error::BadSync);
+                    }
+                    _                          => (),
+                }
+
+                at_start = false;
+            }
+            self.synced = false;
+        }
+
+        // The length prefix is read by hand, so that the end of the stream can be told apart from
+        // a truncated prefix.
+        let mut len   = 0u64;
+        let mut shift = 0usize;
+
+        loop {
+            let mut b = [0u8; 1];
+            if try!(read_full(&mut self.r, &mut b[..])) == 0 {
+                return if at_start && shift == 0 { Ok(None) } else { Err(Error::Truncated) };
+            }
+
+            // A 64-bit varint can be at most 10 bytes long, and only the lowest bit of the 10th
+            // byte fits.
+            if shift == 63 && b[0] > 1 {
+                return Err(Error::DecError(decoder::Error::BadVarint));
+            }
+
+            len |= ((b[0] & 0x7F) as u64) << shift;
+
+            if b[0] < 0x80 {
+                break;
+            }
+
+            shift += 7;
+        }
+
+        if len > self.limits.max_bytes {
+            return Err(Error::DecError(decoder::Error::SizeLimitExceeded));
+        }
+
+        let mut buf = vec![];
+        try!((&mut self.r).take(len).read_to_end(&mut buf));
+
+        if (buf.len() as u64) < len {
+            return Err(Error::Truncated);
+        }
+
+        Ok(Some(buf))
+    }
+}
+
+/// `Records` is an iterator over the records of a `RecordReader`. It's created by the `records`
+/// method.
+pub struct Records<'r, 'x: 'r, R: 'r, D> {
+    reader:  &'r mut RecordReader<'x, R>,
+    phantom: PhantomData<D>,
+}
+
+impl<'r, 'x, R, D> Iterator for Records<'r, 'x, R, D>
+    where R: io::Read,
+          D: Decodable + Default {
+
+    type Item = Result<D, Error>;
+
+    fn next(&mut self) -> Option<Result<D, Error>> {
+        let mut d: D = Default::default();
+
+        match self.reader.read(&mut d) {
+            Ok(true)  => Some(Ok(d)),
+            Ok(false) => None,
+            Err(err)  => Some(Err(err)),
+        }
+    }
+}
+
+// Fill as much of `buf` as possible from `r`, returning the number of bytes read. This is only
+// less than `buf.len()` at the end of the stream.
+fn read_full<R>(r: &mut R, buf: &mut [u8]) -> Result<usize, Error>
+    where R: io::Read {
+
+    let mut pos = 0;
+
+    while pos < buf.len() {
+        match try!(r.read(&mut buf[pos..])) {
+            0 => break,
+            n => pos += n,
+        }
+    }
+
+    Ok(pos)
+}
@@ -0,0 +1,552 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use compiler::ast::Pos;
+use decoder::Error as DecError;
+use encoding::{CompleteEncoding, RecordEncoding, Quantifier, Type};
+use primitive::Primitive;
+use value::{DynamicRecord, Value};
+
+/// An `Error` describes a problem found while parsing the text format, and where it was found.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Error {
+    /// Line and column at which the error was detected.
+    pub pos: Pos,
+
+    /// Human readable description of the error.
+    pub msg: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.pos, self.msg)
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        &self.msg[..]
+    }
+}
+
+/// `dump` decodes a record from `r` according to `enc` and returns it in the text format.
+pub fn dump<R>(enc: &CompleteEncoding, r: &mut R) -> Result<String, DecError>
+    where R: io::Read {
+
+    Ok(print(&try!(DynamicRecord::decode_from(enc, r))))
+}
+
+/// `print` renders `rec` in the text format. Each member of a field is written on its own line as
+/// `name: value`, or `name { ... }` for records. Array fields are written as a bracketed list of
+/// all of their elements. For example:
+///
+/// ```text
+/// name: "cube"
+/// transform: [1, 0, 0, 0, 1, 0, 0, 0, 1]
+/// submeshes {
+///   material: "stone"
+///   indices: 0
+///   indices: 1
+/// }
+/// ```
+///
+/// Each entry of a map field is written like a record with a `key` and a `value` field:
+///
+/// ```text
+/// materials {
+///   key: "stone"
+///   value { roughness: 0.8 }
+/// }
+/// ```
+///
+/// Enums are written as integers, since encodings don't carry the names of enum values. Comments
+/// start with `#` and run to the end of the line.
+pub fn print(rec: &DynamicRecord) -> String {
+    let mut out = String::new();
+    print_record(&mut out, rec, 0);
+    out
+}
+
+fn indent(out: &mut String, depth: usize) {
+    for _ in 0..depth {
+        out.push_str("  ");
+    }
+}
+
+fn print_record(out: &mut String, rec: &DynamicRecord, depth: usize) {
+    for f in rec.fields.iter() {
+        match f.encoding.bounds {
+            Some(n) => for member in f.values.chunks(n) {
+                indent(out, depth);
+                out.push_str(&f.encoding.name[..]);
+                out.push_str(": [");
+
+                for (i, val) in member.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    print_value(out, val, depth);
+                }
+
+                out.push_str("]\n");
+            },
+
+            None if f.encoding.is_map() => for (key, val) in f.keys.iter().zip(f.values.iter()) {
+                indent(out, depth);
+                out.push_str(&f.encoding.name[..]);
+                out.push_str(" {\n");
+
+                indent(out, depth + 1);
+                out.push_str("key: ");
+                print_primitive(out, key);
+                out.push_str("\n");
+
+                indent(out, depth + 1);
+                if let &Value::Record(_) = val {
+                    out.push_str("value ");
+                } else {
+                    out.push_str("value: ");
+                }
+                print_value(out, val, depth + 1);
+                out.push_str("\n");
+
+                indent(out, depth);
+                out.push_str("}\n");
+            },
+
+            None => for val in f.values.iter() {
+                indent(out, depth);
+                out.push_str(&f.encoding.name[..]);
+
+                if let &Value::Record(_) = val {
+                    out.push_str(" ");
+                } else {
+                    out.push_str(": ");
+                }
+
+                print_value(out, val, depth);
+                out.push_str("\n");
+            },
+        }
+    }
+}
+
+fn print_value(out: &mut String, val: &Value, depth: usize) {
+    match *val {
+        Value::Record(ref rec) => {
+            out.push_str("{\n");
+            print_record(out, rec, depth + 1);
+            indent(out, depth);
+            out.push_str("}");
+        }
+        Value::Primitive(ref prim) => print_primitive(out, prim),
+    }
+}
+
+fn print_primitive(out: &mut String, prim: &Primitive) {
+    let text = match *prim {
+        Primitive::UInt8(x)   => format!("{}", x),
+        Primitive::UInt16(x)  => format!("{}", x),
+        Primitive::UInt32(x)  => format!("{}", x),
+        Primitive::UInt64(x)  => format!("{}", x),
+
+        Primitive::Int8(x)    => format!("{}", x),
+        Primitive::Int16(x)   => format!("{}", x),
+        Primitive::Int32(x)   => format!("{}", x),
+        Primitive::Int64(x)   => format!("{}", x),
+
+        Primitive::Fixed32(x) => format!("{}", x),
+        Primitive::Fixed64(x) => format!("{}", x),
+
+        Primitive::Float32(x) => format_float(x as f64, format!("{}", x)),
+        Primitive::Float64(x) => format_float(x, format!("{}", x)),
+
+        Primitive::Bool(x)    => format!("{}", x),
+
+        Primitive::Bytes(ref x)  => { out.push('b'); quote(x.iter().map(|b| *b as char), true) },
+        Primitive::String(ref x) => quote(x.chars(), false),
+
+        Primitive::Enum(x)    => format!("{}", x),
+    };
+
+    out.push_str(&text[..]);
+}
+
+// Format the float `x`, where `digits` is `x` formatted at its original precision. A float32 value
+// is widened to check for nan and infinities, but printing it widened would add spurious digits.
+fn format_float(x: f64, digits: String) -> String {
+    if x.is_nan() {
+        "nan".to_string()
+    } else if x.is_infinite() {
+        if x > 0.0 { "inf".to_string() } else { "-inf".to_string() }
+    } else {
+        digits
+    }
+}
+
+// Quote a sequence of characters. If `bytes` is true, each character is a single byte and anything
+// outside of printable ASCII is written as a `\xNN` escape.
+fn quote<I>(chars: I, bytes: bool) -> String
+    where I: Iterator<Item = char> {
+
+    let mut s = "\"".to_string();
+
+    for c in chars {
+        match c {
+            '"'  => s.push_str("\\\""),
+            '\\' => s.push_str("\\\\"),
+            '\n' => s.push_str("\\n"),
+            '\r' => s.push_str("\\r"),
+            '\t' => s.push_str("\\t"),
+            c if (c as u32) < 0x20 || c as u32 == 0x7F || (bytes && c as u32 > 0x7F) =>
+                s.push_str(&format!("\\x{:02x}", c as u32)[..]),
+            c => s.push(c),
+        }
+    }
+
+    s.push('"');
+    s
+}
+
+/// `parse` reads a record of the type described by `enc` from the text format (see `print`). The
+/// result can be written in the binary format with `encode_to(enc, w, &rec)`.
+pub fn parse(enc: &CompleteEncoding, src: &str) -> Result<DynamicRecord, Error> {
+    let mut p = Parser {
+        enc:   enc,
+        chars: src.chars().peekable(),
+        line:  1,
+        col:   1,
+    };
+
+    let rec = try!(p.parse_record(&enc.target, None));
+
+    if p.peek_token().is_some() {
+        return p.err("expected a field name".to_string());
+    }
+
+    Ok(rec)
+}
+
+struct Parser<'x> {
+    enc:   &'x CompleteEncoding,
+    chars: Peekable<Chars<'x>>,
+    line:  usize,
+    col:   usize,
+}
+
+impl<'x> Parser<'x> {
+    fn pos(&self) -> Pos {
+        Pos { line: self.line, col: self.col }
+    }
+
+    fn err<T>(&self, msg: String) -> Result<T, Error> {
+        Err(Error { pos: self.pos(), msg: msg })
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next();
+
+        match c {
+            Some('\n') => { self.line += 1; self.col = 1; }
+            Some(_)    => { self.col += 1; }
+            None       => {}
+        }
+
+        c
+    }
+
+    // Skip whitespace and comments and return the next character without consuming it.
+    fn peek_token(&mut self) -> Option<char> {
+        loop {
+            match self.chars.peek().map(|c| *c) {
+                Some('#') => while let Some(c) = self.bump() {
+                    if c == '\n' { break; }
+                },
+                Some(c) if c.is_whitespace() => { self.bump(); },
+                other => return other,
+            }
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), Error> {
+        if self.peek_token() == Some(c) {
+            self.bump();
+            Ok(())
+        } else {
+            self.err(format!("expected '{}'", c))
+        }
+    }
+
+    // A "word" is an identifier or the text of a number: anything up to the next delimiter.
+    fn word(&mut self) -> String {
+        let mut w = String::new();
+
+        self.peek_token();
+
+        while let Some(c) = self.chars.peek().map(|c| *c) {
+            if c.is_alphanumeric() || c == '_' || c == '-' || c == '+' || c == '.' {
+                w.push(c);
+                self.bump();
+            } else {
+                break;
+            }
+        }
+
+        w
+    }
+
+    // Parse fields until `close` (or the end of input if `close` is `None`).
+    fn parse_record(&mut self, renc: &RecordEncoding, close: Option<char>) -> Result<DynamicRecord, Error> {
+        let mut rec = DynamicRecord::new(renc);
+
+        loop {
+            let next = self.peek_token();
+
+            if next == close {
+                if close.is_some() {
+                    self.bump();
+                }
+                break;
+            }
+
+            let pos  = self.pos();
+            let name = self.word();
+
+            if name.is_empty() {
+                return self.err("expected a field name".to_string());
+            }
+
+            let (typ, quant, bounds) = match rec.field_by_name(&name[..]) {
+                Some(f) => (f.encoding.typ, f.encoding.quant, f.encoding.bounds),
+                None    => return Err(Error {
+                    pos: pos,
+                    msg: format!("record {} has no field named '{}'", renc.name, name),
+                }),
+            };
+
+            let mut vals = vec![];
+            let mut keys = vec![];
+
+            match bounds {
+                Some(n) => {
+                    try!(self.expect(':'));
+                    try!(self.expect('['));
+
+                    for i in 0..n {
+                        if i > 0 {
+                            try!(self.expect(','));
+                        }
+                        vals.push(try!(self.parse_value(typ)));
+                    }
+
+                    try!(self.expect(']'));
+                }
+                None => {
+                    // The ':' is optional before a record.
+                    if self.peek_token() == Some(':') {
+                        self.bump();
+                    }
+
+                    if let Type::Map{index} = typ {
+                        let (key, val) = try!(self.parse_entry(index));
+                        keys.push(key);
+                        vals.push(val);
+                    } else {
+                        vals.push(try!(self.parse_value(typ)));
+                    }
+                }
+            }
+
+            let f = rec.field_by_name_mut(&name[..]).unwrap();
+
+            if quant != Quantifier::Repeated && !f.values.is_empty() {
+                return Err(Error {
+                    pos: pos,
+                    msg: format!("field '{}' can only appear once", name),
+                });
+            }
+
+            f.values.extend(vals.into_iter());
+            f.keys.extend(keys.into_iter());
+        }
+
+        for f in rec.fields.iter() {
+            if f.encoding.quant == Quantifier::Required && f.values.is_empty() {
+                return self.err(format!("missing required field '{}' of record {}",
+                                        f.encoding.name, renc.name));
+            }
+        }
+
+        Ok(rec)
+    }
+
+    // Parse a map entry, which is written as a record of its entry type.
+    fn parse_entry(&mut self, index: usize) -> Result<(Primitive, Value), Error> {
+        let enc = self.enc;
+
+        let (renc, key_id, val_id) = match enc.depends.get(index) {
+            Some(renc) => match renc.map_entry() {
+                Some((key, val)) => (renc, key.id, val.id),
+                None             => return self.err("encoding is invalid".to_string()),
+            },
+            None => return self.err("encoding is invalid".to_string()),
+        };
+
+        try!(self.expect('{'));
+        let mut entry = try!(self.parse_record(renc, Some('}')));
+
+        let key = entry.field_mut(key_id).and_then(|f| f.values.pop());
+        let val = entry.field_mut(val_id).and_then(|f| f.values.pop());
+
+        match (key, val) {
+            (Some(Value::Primitive(key)), Some(val)) => Ok((key, val)),
+            _ => self.err("encoding is invalid".to_string()),
+        }
+    }
+
+    fn parse_value(&mut self, typ: Type) -> Result<Value, Error> {
+        if let Type::Record{index} = typ {
+            let enc = self.enc;
+
+            let renc = match enc.depends.get(index) {
+                Some(renc) => renc,
+                None       => return self.err("encoding is invalid".to_string()),
+            };
+
+            try!(self.expect('{'));
+            return Ok(Value::Record(try!(self.parse_record(renc, Some('}')))));
+        }
+
+        if typ == Type::String || typ == Type::Bytes {
+            let is_bytes = typ == Type::Bytes;
+            let next     = self.peek_token();
+
+            if is_bytes {
+                if next != Some('b') {
+                    return self.err("expected a bytes literal, e.g. b\"...\"".to_string());
+                }
+                self.bump();
+            }
+
+            let chars = try!(self.parse_quoted(is_bytes));
+
+            return Ok(Value::Primitive(if is_bytes {
+                Primitive::Bytes(chars.chars().map(|c| c as u8).collect())
+            } else {
+                Primitive::String(chars)
+            }));
+        }
+
+        let pos  = self.pos();
+        let word = self.word();
+
+        let prim = match typ {
+            Type::UInt8   => word.parse().ok().map(Primitive::UInt8),
+            Type::UInt16  => word.parse().ok().map(Primitive::UInt16),
+            Type::UInt32  => word.parse().ok().map(Primitive::UInt32),
+            Type::UInt64  => word.parse().ok().map(Primitive::UInt64),
+
+            Type::Int8    => word.parse().ok().map(Primitive::Int8),
+            Type::Int16   => word.parse().ok().map(Primitive::Int16),
+            Type::Int32   => word.parse().ok().map(Primitive::Int32),
+            Type::Int64   => word.parse().ok().map(Primitive::Int64),
+
+            Type::Fixed32 => word.parse().ok().map(Primitive::Fixed32),
+            Type::Fixed64 => word.parse().ok().map(Primitive::Fixed64),
+
+            Type::Float32 => parse_float(&word[..]).map(|x| Primitive::Float32(x as f32)),
+            Type::Float64 => parse_float(&word[..]).map(Primitive::Float64),
+
+            Type::Bool    => match &word[..] {
+                "true"  => Some(Primitive::Bool(true)),
+                "false" => Some(Primitive::Bool(false)),
+                _       => None,
+            },
+
+            Type::Enum    => word.parse().ok().map(Primitive::Enum),
+
+            // Map fields can't have bounds, so their entries are always parsed by `parse_entry`.
+            Type::Map{..} => return self.err("encoding is invalid".to_string()),
+
+            Type::String | Type::Bytes | Type::Record{..} => unreachable!(),
+        };
+
+        match prim {
+            Some(prim) => Ok(Value::Primitive(prim)),
+            None       => Err(Error {
+                pos: pos,
+                msg: format!("'{}' is not a valid {:?}", word, typ),
+            }),
+        }
+    }
+
+    // Parse a quoted string, handling escapes. For bytes literals, each char of the result is a
+    // single byte.
+    fn parse_quoted(&mut self, bytes: bool) -> Result<String, Error> {
+        if self.bump() != Some('"') {
+            return self.err("expected '\"'".to_string());
+        }
+
+        let mut s = String::new();
+
+        loop {
+            let c = match self.bump() {
+                Some(c) => c,
+                None    => return self.err("unterminated string".to_string()),
+            };
+
+            match c {
+                '"'  => return Ok(s),
+                '\\' => s.push( match self.bump() {
+                    Some('n')  => '\n',
+                    Some('r')  => '\r',
+                    Some('t')  => '\t',
+                    Some('"')  => '"',
+                    Some('\\') => '\\',
+                    Some('x')  => {
+                        let hi = self.bump().and_then(|c| c.to_digit(16));
+                        let lo = self.bump().and_then(|c| c.to_digit(16));
+
+                        match (hi, lo) {
+                            (Some(hi), Some(lo)) => ((hi << 4) | lo) as u8 as char,
+                            _ => return self.err("expected two hex digits after \\x".to_string()),
+                        }
+                    }
+                    _ => return self.err("unknown escape sequence".to_string()),
+                }),
+                c if bytes && c as u32 > 0x7F =>
+                    return self.err("bytes literals may only contain ASCII".to_string()),
+                c => s.push(c),
+            }
+        }
+    }
+}
+
+fn parse_float(word: &str) -> Option<f64> {
+    match word {
+        "nan"  => Some(::std::f64::NAN),
+        "inf"  => Some(::std::f64::INFINITY),
+        "-inf" => Some(::std::f64::NEG_INFINITY),
+        _      => word.parse().ok(),
+    }
+}
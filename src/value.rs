@@ -0,0 +1,248 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::cmp;
+use std::io;
+
+use decoder::{Decoder, Decodable, PREALLOC_MAX, decode_from};
+use decoder::Error as DecError;
+use encoder::{Encoder, Encodable};
+use encoder::Error as EncError;
+use encoding::{CompleteEncoding, RecordEncoding, FieldEncoding, FieldID};
+use primitive::Primitive;
+
+/// A `Value` is a single element of a field of a `DynamicRecord`.
+#[derive(Clone,Debug,PartialEq)]
+pub enum Value {
+    /// An element of a field with primitive type.
+    Primitive(Primitive),
+
+    /// An element of a field with record type.
+    Record(DynamicRecord),
+}
+
+/// A `DynamicField` holds the values of a single field of a `DynamicRecord`, along with its
+/// encoding.
+#[derive(Clone,Debug,PartialEq)]
+pub struct DynamicField {
+    /// The encoding of this field, taken from the `RecordEncoding` of the containing record.
+    pub encoding: FieldEncoding,
+
+    /// Every element of this field, flattened in the same way as the `idx` argument to
+    /// `Encodable`/`Decodable` methods. So a repeated field of `[3]float32` with 2 members has 6
+    /// values, and an absent optional field has none.
+    pub values: Vec<Value>,
+
+    /// The keys of a map field, one for each element of `values`. Empty for other fields.
+    pub keys: Vec<Primitive>,
+}
+
+impl DynamicField {
+    fn new(encoding: &FieldEncoding) -> DynamicField {
+        DynamicField {
+            encoding: encoding.clone(),
+            values:   vec![],
+            keys:     vec![],
+        }
+    }
+
+    /// `count` returns the number of members of this field (as opposed to the number of values,
+    /// which is larger for array fields).
+    pub fn count(&self) -> usize {
+        self.values.len() / self.encoding.bounds.unwrap_or(1)
+    }
+
+    fn set(&mut self, idx: usize, val: Value) {
+        if idx < self.values.len() {
+            self.values[idx] = val;
+        } else {
+            self.values.push(val);
+        }
+    }
+
+    fn set_key(&mut self, idx: usize, key: Primitive) {
+        if idx < self.keys.len() {
+            self.keys[idx] = key;
+        } else {
+            self.keys.push(key);
+        }
+    }
+
+    /// `get` returns the value whose key is `key`, if this is a map field with such an entry.
+    pub fn get(&self, key: &Primitive) -> Option<&Value> {
+        self.keys.iter().position(|k| k == key).and_then(|idx| self.values.get(idx))
+    }
+}
+
+/// A `DynamicRecord` can hold any rex record, given only its `RecordEncoding`. It can be decoded
+/// from and encoded to any record type, which makes it useful for tools which need to work with
+/// records whose types they don't know at compile time.
+#[derive(Clone,Debug,PartialEq)]
+pub struct DynamicRecord {
+    /// Name of the record type, from its `RecordEncoding`.
+    pub name: String,
+
+    /// The fields of the record, required fields first and then optional/repeated fields, each
+    /// group sorted by id (the same order as the `RecordEncoding`).
+    pub fields: Vec<DynamicField>,
+
+    /// Raw data of fields which weren't in the encoding this record was decoded with, as
+    /// `(id, data)` pairs. They're written back out when the record is encoded, so that tools can
+    /// rewrite records without losing fields added by newer versions of the schema.
+    pub unknown: Vec<(FieldID, Vec<u8>)>,
+}
+
+impl DynamicRecord {
+    /// `new` creates a `DynamicRecord` for the record type described by `rec`, with no values in
+    /// any of its fields.
+    pub fn new(rec: &RecordEncoding) -> DynamicRecord {
+        DynamicRecord {
+            name:    rec.name.clone(),
+            fields:  rec.req_fields.iter()
+                .chain(rec.opt_rep_fields.iter())
+                .map(DynamicField::new)
+                .collect(),
+            unknown: vec![],
+        }
+    }
+
+    /// `decode_from` reads a record from `r` according to `enc`.
+    pub fn decode_from<R>(enc: &CompleteEncoding, r: &mut R) -> Result<DynamicRecord, DecError>
+        where R: io::Read {
+
+        let mut rec = DynamicRecord::new(&enc.target);
+        try!(decode_from(enc, r, &mut rec));
+
+        Ok(rec)
+    }
+
+    /// `field` returns the field with id `id`, if there is one.
+    pub fn field(&self, id: FieldID) -> Option<&DynamicField> {
+        self.fields.iter().find(|f| f.encoding.id == id)
+    }
+
+    /// `field_mut` returns the field with id `id`, if there is one.
+    pub fn field_mut(&mut self, id: FieldID) -> Option<&mut DynamicField> {
+        self.fields.iter_mut().find(|f| f.encoding.id == id)
+    }
+
+    /// `field_by_name` returns the field named `name`, if there is one.
+    pub fn field_by_name(&self, name: &str) -> Option<&DynamicField> {
+        self.fields.iter().find(|f| f.encoding.name == name)
+    }
+
+    /// `field_by_name_mut` returns the field named `name`, if there is one.
+    pub fn field_by_name_mut(&mut self, name: &str) -> Option<&mut DynamicField> {
+        self.fields.iter_mut().find(|f| f.encoding.name == name)
+    }
+}
+
+impl Encodable for DynamicRecord {
+    fn get_primitive(&self, id: FieldID, idx: usize) -> Result<Primitive, EncError> {
+        match self.field(id).and_then(|f| f.values.get(idx)) {
+            Some(&Value::Primitive(ref prim)) => Ok(prim.clone()),
+            _                                 => Err(EncError::FieldTypeMismatch),
+        }
+    }
+
+    fn encode_record(&self, mut e: Encoder, id: FieldID, idx: usize) -> Result<usize, EncError> {
+        match self.field(id).and_then(|f| f.values.get(idx)) {
+            Some(&Value::Record(ref rec)) => e.encode(rec),
+            _                             => Err(EncError::FieldTypeMismatch),
+        }
+    }
+
+    fn get_map_key(&self, id: FieldID, idx: usize) -> Result<Primitive, EncError> {
+        match self.field(id).and_then(|f| f.keys.get(idx)) {
+            Some(key) => Ok(key.clone()),
+            None      => Err(EncError::FieldTypeMismatch),
+        }
+    }
+
+    fn count_field(&self, id: FieldID) -> Result<usize, EncError> {
+        match self.field(id) {
+            Some(f) => Ok(f.count()),
+            None    => Err(EncError::FieldTypeMismatch),
+        }
+    }
+
+    fn unknown_fields(&self) -> &[(FieldID, Vec<u8>)] {
+        &self.unknown[..]
+    }
+}
+
+impl Decodable for DynamicRecord {
+    fn set_primitive(&mut self, id: FieldID, idx: usize, prim: Primitive) -> Result<(), DecError> {
+        match self.field_mut(id) {
+            Some(f) => {
+                // The values of map fields are checked against the entry encoding by the Decoder.
+                if !f.encoding.is_map() && !prim.has_type(f.encoding.typ) {
+                    return Err(DecError::FieldTypeMismatch);
+                }
+
+                f.set(idx, Value::Primitive(prim));
+                Ok(())
+            }
+            None => Err(DecError::FieldTypeMismatch),
+        }
+    }
+
+    fn set_map_key(&mut self, id: FieldID, idx: usize, key: Primitive) -> Result<(), DecError> {
+        match self.field_mut(id) {
+            Some(ref mut f) if f.encoding.is_map() => { f.set_key(idx, key); Ok(()) },
+            _                                      => Err(DecError::FieldTypeMismatch),
+        }
+    }
+
+    fn decode_record<'x, R>(&mut self, mut d: Decoder<'x, R>, id: FieldID, idx: usize) -> Result<(), DecError>
+        where R: io::Read + 'x {
+
+        let mut rec = DynamicRecord::new(d.record_encoding());
+        try!(d.decode(&mut rec));
+
+        match self.field_mut(id) {
+            Some(f) => { f.set(idx, Value::Record(rec)); Ok(()) },
+            None    => Err(DecError::FieldTypeMismatch),
+        }
+    }
+
+    fn alloc_field(&mut self, id: FieldID, count: usize) -> Result<bool, DecError> {
+        match self.field_mut(id) {
+            Some(f) => {
+                // `count` comes straight off the wire, so don't trust it with the allocation.
+                let len = match count.checked_mul(f.encoding.bounds.unwrap_or(1)) {
+                    Some(len) => len,
+                    None      => return Err(DecError::CountLimitExceeded(count as u64)),
+                };
+
+                f.values = Vec::with_capacity(cmp::min(len, PREALLOC_MAX));
+                f.keys   = vec![];
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn keep_unknown_fields(&self) -> bool {
+        true
+    }
+
+    fn add_unknown_field(&mut self, id: FieldID, data: Vec<u8>) -> Result<(), DecError> {
+        self.unknown.push((id, data));
+        Ok(())
+    }
+}
@@ -0,0 +1,286 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use borrowed::{BorrowedPrimitive, read_primitive, take};
+use decoder::{Error, read_uvarint};
+use encoding::{CompleteEncoding, RecordEncoding, FieldEncoding, FieldID, Quantifier, Type};
+use primitive::fixed_width;
+
+#[cfg(feature = "mmap")]
+use std::io;
+
+#[cfg(feature = "mmap")]
+use std::path::Path;
+
+#[cfg(feature = "mmap")]
+use memmap::{Mmap, Protection};
+
+/// A `RecordView` is a read-only view of an encoded record. Nothing is decoded up front; fields are
+/// located on demand, skipping over the ones in between, and only the values which are asked for
+/// are decoded.
+#[derive(Copy,Clone)]
+pub struct RecordView<'a> {
+    // `buf` starts at the beginning of the record. It may run past the end of it.
+    buf: &'a [u8],
+
+    rec:  &'a RecordEncoding,
+    deps: &'a [RecordEncoding],
+}
+
+/// A `FieldView` is a read-only view of the values of a single field within a `RecordView`. Values
+/// are indexed in the same flattened way as the `idx` argument of `Decodable::set_primitive`.
+#[derive(Copy,Clone)]
+pub struct FieldView<'a> {
+    // `buf` starts at the first value of the field.
+    buf: &'a [u8],
+
+    // `len` is the total number of values, including every element of an array.
+    len: usize,
+
+    enc:  &'a FieldEncoding,
+    deps: &'a [RecordEncoding],
+}
+
+impl<'a> RecordView<'a> {
+    /// `new` creates a view of the record at the start of `buf`, which must have been encoded with
+    /// `enc`.
+    pub fn new(enc: &'a CompleteEncoding, buf: &'a [u8]) -> RecordView<'a> {
+        RecordView {
+            buf:  buf,
+            rec:  &enc.target,
+            deps: &enc.depends[..],
+        }
+    }
+
+    /// `record_encoding` returns the encoding of the record being viewed.
+    pub fn record_encoding(&self) -> &'a RecordEncoding {
+        self.rec
+    }
+
+    /// `field_by_name` is like `field`, but looks the field up by name.
+    pub fn field_by_name(&self, name: &str) -> Result<Option<FieldView<'a>>, Error> {
+        let id = self.rec.req_fields.iter()
+            .chain(self.rec.opt_rep_fields.iter())
+            .find(|f| f.name == name)
+            .map(|f| f.id);
+
+        match id {
+            Some(id) => self.field(id),
+            None     => Ok(None),
+        }
+    }
+
+    /// `field` locates the field with id `id`. It returns `None` if the record has no such field,
+    /// or if the field is optional or repeated and isn't present.
+    ///
+    /// Required fields are found by skipping the ones before them, and optional and repeated fields
+    /// by following the byte-size prefixes, so the cost of this depends on how many fields come
+    /// first rather than on how large they are.
+    pub fn field(&self, id: FieldID) -> Result<Option<FieldView<'a>>, Error> {
+        let mut buf = self.buf;
+
+        for f in self.rec.req_fields.iter() {
+            let start = buf;
+            try!(skip_values(&mut buf, f, 1, self.deps));
+
+            if f.id == id {
+                return Ok(Some(FieldView {
+                    buf:  start,
+                    len:  f.bounds.unwrap_or(1),
+                    enc:  f,
+                    deps: self.deps,
+                }));
+            }
+        }
+
+        loop {
+            let next_id = FieldID(try!(read_uvarint(&mut buf)));
+
+            if next_id == FieldID(0) || next_id > id {
+                return Ok(None);
+            }
+
+            let size = try!(read_uvarint(&mut buf)) as usize;
+            let mut data = try!(take(&mut buf, size));
+
+            if next_id < id {
+                continue;
+            }
+
+            let f = match self.rec.opt_rep_fields.iter().find(|f| f.id == id) {
+                Some(f) => f,
+                None    => return Ok(None),
+            };
+
+            let count = match f.quant {
+                Quantifier::Required => return Err(Error::EncodingInvalid),
+                Quantifier::Optional => 1,
+                Quantifier::Repeated => try!(read_uvarint(&mut data)) as usize,
+            };
+
+            return Ok(Some(FieldView {
+                buf:  data,
+                len:  try!(value_count(f, count)),
+                enc:  f,
+                deps: self.deps,
+            }));
+        }
+    }
+}
+
+impl<'a> FieldView<'a> {
+    /// `encoding` returns the encoding of the field being viewed.
+    pub fn encoding(&self) -> &'a FieldEncoding {
+        self.enc
+    }
+
+    /// `len` returns the number of values in the field, counting each element of an array.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// `primitive` decodes the value at `idx`. Bytes and strings are borrowed from the underlying
+    /// buffer.
+    pub fn primitive(&self, idx: usize) -> Result<BorrowedPrimitive<'a>, Error> {
+        let mut buf = try!(self.seek(idx));
+        read_primitive(&mut buf, self.enc.typ)
+    }
+
+    /// `record` returns a view of the record at `idx`. It fails with `FieldTypeMismatch` if the
+    /// field doesn't have a record type. The entries of a map field are viewed as records with a
+    /// `key` and a `value` field.
+    pub fn record(&self, idx: usize) -> Result<RecordView<'a>, Error> {
+        let index = match self.enc.typ {
+            Type::Record{index} | Type::Map{index} => index,
+            _                                      => return Err(Error::FieldTypeMismatch),
+        };
+
+        let rec = match self.deps.get(index) {
+            Some(rec) => rec,
+            None      => return Err(Error::EncodingInvalid),
+        };
+
+        Ok(RecordView {
+            buf:  try!(self.seek(idx)),
+            rec:  rec,
+            deps: self.deps,
+        })
+    }
+
+    // Return the buffer starting at the value at `idx`.
+    fn seek(&self, idx: usize) -> Result<&'a [u8], Error> {
+        if idx >= self.len {
+            return Err(Error::EOF);
+        }
+
+        let mut buf = self.buf;
+
+        match fixed_width(self.enc.typ) {
+            Some(size) => match idx.checked_mul(size) {
+                Some(off) => { try!(take(&mut buf, off)); }
+                None      => return Err(Error::EOF),
+            },
+            None       => for _ in 0..idx {
+                try!(skip_value(&mut buf, self.enc.typ, self.deps));
+            },
+        }
+
+        Ok(buf)
+    }
+}
+
+// The number of elements in `count` values of the field `f`, counting each element of an array.
+fn value_count(f: &FieldEncoding, count: usize) -> Result<usize, Error> {
+    match count.checked_mul(f.bounds.unwrap_or(1)) {
+        Some(n) => Ok(n),
+        None    => Err(Error::CountLimitExceeded(count as u64)),
+    }
+}
+
+// Skip `count` values of the field `f` (each of which is a whole array if `f` has bounds).
+fn skip_values(buf: &mut &[u8], f: &FieldEncoding, count: usize, deps: &[RecordEncoding])
+    -> Result<(), Error> {
+
+    let n = try!(value_count(f, count));
+
+    match fixed_width(f.typ) {
+        Some(size) => match n.checked_mul(size) {
+            Some(len) => take(buf, len).map(|_| ()),
+            None      => Err(Error::EOF),
+        },
+        None       => {
+            for _ in 0..n {
+                try!(skip_value(buf, f.typ, deps));
+            }
+            Ok(())
+        }
+    }
+}
+
+fn skip_value(buf: &mut &[u8], typ: Type, deps: &[RecordEncoding]) -> Result<(), Error> {
+    match typ {
+        Type::Record{index} | Type::Map{index} => match deps.get(index) {
+            Some(rec) => skip_record(buf, rec, deps),
+            None      => Err(Error::EncodingInvalid),
+        },
+
+        typ => read_primitive(buf, typ).map(|_| ()),
+    }
+}
+
+fn skip_record(buf: &mut &[u8], rec: &RecordEncoding, deps: &[RecordEncoding]) -> Result<(), Error> {
+    for f in rec.req_fields.iter() {
+        try!(skip_values(buf, f, 1, deps));
+    }
+
+    while try!(read_uvarint(buf)) != 0 {
+        let size = try!(read_uvarint(buf)) as usize;
+        try!(take(buf, size));
+    }
+
+    Ok(())
+}
+
+/// A `MappedRecord` is a record file which has been mapped into memory, so that it can be viewed
+/// with a `RecordView` without reading the whole file. Only available with the `mmap` feature.
+#[cfg(feature = "mmap")]
+pub struct MappedRecord {
+    map: Mmap,
+    enc: CompleteEncoding,
+}
+
+#[cfg(feature = "mmap")]
+impl MappedRecord {
+    /// `open` maps the file at `path`, which must contain a single record encoded with `enc`.
+    pub fn open(path: &Path, enc: CompleteEncoding) -> io::Result<MappedRecord> {
+        let map = try!(Mmap::open_path(path, Protection::Read));
+
+        Ok(MappedRecord {
+            map: map,
+            enc: enc,
+        })
+    }
+
+    /// `view` returns a view of the mapped record.
+    pub fn view(&self) -> RecordView {
+        // The mapping is read-only, so the slice can't change underneath us as long as nobody
+        // modifies the file while it's mapped.
+        let buf = unsafe { self.map.as_slice() };
+
+        RecordView::new(&self.enc, buf)
+    }
+}
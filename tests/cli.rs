@@ -0,0 +1,209 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+const SCHEMA: &'static str = "
+    record Point {
+        1 name: string
+        2 pos:  [2]int32
+        3 tags: rep string
+    }";
+
+const TEXT: &'static str = "name: \"origin\"\npos: [0, -1]\ntags: \"a\"\ntags: \"b\"\n";
+
+// A `Scratch` is a directory for the files used by one test, next to the test binary.
+struct Scratch {
+    dir: PathBuf,
+}
+
+impl Scratch {
+    fn new(name: &str) -> Scratch {
+        let exe = env::current_exe().unwrap();
+        let dir = exe.parent().unwrap().join("rex-cli-scratch").join(name);
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        Scratch { dir: dir }
+    }
+
+    fn path(&self, name: &str) -> String {
+        self.dir.join(name).to_str().unwrap().to_string()
+    }
+
+    fn write(&self, name: &str, data: &[u8]) -> String {
+        let path = self.path(name);
+        File::create(&path).unwrap().write_all(data).unwrap();
+        path
+    }
+
+    fn read(&self, name: &str) -> Vec<u8> {
+        let mut buf = vec![];
+        File::open(&self.path(name)).unwrap().read_to_end(&mut buf).unwrap();
+        buf
+    }
+}
+
+// Run the `rex` binary, which cargo builds next to the directory holding this test (or in it).
+fn rex(args: &[&str]) -> Output {
+    let exe  = env::current_exe().unwrap();
+    let name = if cfg!(windows) { "rex.exe" } else { "rex" };
+
+    let dir  = exe.parent().unwrap();
+    let bin  = [dir.join(name), dir.parent().unwrap().join(name)].iter()
+        .find(|p| fs::metadata(p).is_ok())
+        .cloned()
+        .expect("couldn't find the rex binary");
+
+    Command::new(&bin).args(args).output().unwrap()
+}
+
+fn stderr(out: &Output) -> String {
+    String::from_utf8_lossy(&out.stderr[..]).into_owned()
+}
+
+#[test]
+fn convert_round_trips_through_every_format() {
+    let s      = Scratch::new("convert");
+    let schema = s.write("point.rex", SCHEMA.as_bytes());
+    let text   = s.write("point.txt", TEXT.as_bytes());
+
+    let out = rex(&["convert", &schema[..], "Point", "text", "binary", &text[..],
+                    &s.path("a.bin")[..]]);
+    assert!(out.status.success(), "{}", stderr(&out));
+
+    let out = rex(&["convert", &schema[..], "Point", "binary", "json", &s.path("a.bin")[..],
+                    &s.path("a.json")[..]]);
+    assert!(out.status.success(), "{}", stderr(&out));
+
+    let out = rex(&["convert", &schema[..], "Point", "json", "text", &s.path("a.json")[..],
+                    &s.path("b.txt")[..]]);
+    assert!(out.status.success(), "{}", stderr(&out));
+
+    assert_eq!(String::from_utf8(s.read("b.txt")).unwrap(), TEXT);
+
+    let out = rex(&["dump", &schema[..], "Point", &s.path("a.bin")[..]]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), TEXT);
+}
+
+#[test]
+fn compiled_encodings_can_be_used_as_schemas() {
+    let s      = Scratch::new("compile");
+    let schema = s.write("point.rex", SCHEMA.as_bytes());
+    let text   = s.write("point.txt", TEXT.as_bytes());
+    let enc    = s.path("point.enc");
+
+    let out = rex(&["compile", &schema[..], "Point", &enc[..]]);
+    assert!(out.status.success(), "{}", stderr(&out));
+
+    let out = rex(&["convert", &enc[..], "Point", "text", "binary", &text[..],
+                    &s.path("a.bin")[..]]);
+    assert!(out.status.success(), "{}", stderr(&out));
+
+    let out = rex(&["validate", &enc[..], "Point", &s.path("a.bin")[..]]);
+    assert!(out.status.success(), "{}", stderr(&out));
+
+    let out = rex(&["validate", &enc[..], "Other", &s.path("a.bin")[..]]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(stderr(&out).contains("encoding is for record 'Point', not 'Other'"));
+}
+
+#[test]
+fn trailing_bytes_are_rejected() {
+    let s      = Scratch::new("trailing");
+    let schema = s.write("point.rex", SCHEMA.as_bytes());
+    let text   = s.write("point.txt", TEXT.as_bytes());
+    let bin    = s.path("a.bin");
+
+    let out = rex(&["convert", &schema[..], "Point", "text", "binary", &text[..], &bin[..]]);
+    assert!(out.status.success(), "{}", stderr(&out));
+
+    let mut data = s.read("a.bin");
+    data.extend(b"xyz".iter().cloned());
+    let padded = s.write("padded.bin", &data[..]);
+
+    for args in [vec!["validate", &schema[..], "Point", &padded[..]],
+                 vec!["dump", &schema[..], "Point", &padded[..]],
+                 vec!["convert", &schema[..], "Point", "binary", "text", &padded[..],
+                      &s.path("out.txt")[..]]].iter() {
+
+        let out = rex(&args[..]);
+        assert_eq!(out.status.code(), Some(1));
+        assert!(stderr(&out).contains("3 bytes of trailing data after the record"), "{}",
+                stderr(&out));
+    }
+}
+
+#[test]
+fn bad_usage_and_input_fail() {
+    let s      = Scratch::new("usage");
+    let schema = s.write("point.rex", SCHEMA.as_bytes());
+    let text   = s.write("point.txt", TEXT.as_bytes());
+    let bad    = s.write("bad.txt", b"name: \"unterminated\n");
+
+    let out = rex(&["frobnicate"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert!(stderr(&out).starts_with("usage: rex"));
+
+    let out = rex(&["convert", &schema[..], "Point", "text", "yaml", &text[..], &s.path("x")[..]]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(stderr(&out).contains("unknown format 'yaml'"));
+
+    let out = rex(&["convert", &schema[..], "Point", "text", "binary", &bad[..], &s.path("x")[..]]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(stderr(&out).contains("unterminated string"), "{}", stderr(&out));
+
+    let out = rex(&["dump", &schema[..], "Nope", &bad[..]]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(stderr(&out).contains("no record named 'Nope'"));
+}
+
+#[test]
+fn decode_limits_can_be_set() {
+    let s      = Scratch::new("limits");
+    let schema = s.write("point.rex", SCHEMA.as_bytes());
+    let text   = s.write("point.txt", TEXT.as_bytes());
+    let bin    = s.path("a.bin");
+
+    let out = rex(&["convert", &schema[..], "Point", "text", "binary", &text[..], &bin[..]]);
+    assert!(out.status.success(), "{}", stderr(&out));
+
+    let out = rex(&["--max-bytes", "4", "validate", &schema[..], "Point", &bin[..]]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(stderr(&out).contains("record exceeds the size limit"), "{}", stderr(&out));
+
+    let out = rex(&["--max-count", "1", "dump", &schema[..], "Point", &bin[..]]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(stderr(&out).contains("count 2 exceeds the count limit"), "{}", stderr(&out));
+
+    let out = rex(&["--max-count", "1", "--unlimited", "validate", &schema[..], "Point", &bin[..]]);
+    assert!(out.status.success(), "{}", stderr(&out));
+
+    let out = rex(&["--max-depth", "many", "validate", &schema[..], "Point", &bin[..]]);
+    assert_eq!(out.status.code(), Some(2));
+    assert!(stderr(&out).contains("--max-depth needs a number"), "{}", stderr(&out));
+
+    let out = rex(&["--max-size", "4", "validate", &schema[..], "Point", &bin[..]]);
+    assert_eq!(out.status.code(), Some(2));
+    assert!(stderr(&out).contains("unknown option '--max-size'"), "{}", stderr(&out));
+}
@@ -0,0 +1,123 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+extern crate rex;
+
+use rex::{encode_to, decode_from, DynamicRecord};
+use rex::codegen;
+
+use scene::*;
+
+// The generated code is checked in, so that this file compiles it along with the tests.
+mod scene {
+    include!("codegen/scene.rs");
+}
+
+#[test]
+fn generated_code_is_up_to_date() {
+    let code = codegen::generate(include_str!("codegen/scene.rex")).unwrap();
+    assert!(code == include_str!("codegen/scene.rs"),
+            "tests/codegen/scene.rs is out of date, regenerate it from scene.rex");
+}
+
+fn scene() -> Node {
+    let mut leaf: Node = Default::default();
+    leaf.name    = "leaf".to_string();
+    leaf.layer   = Layer::Overlay;
+    leaf.opacity = Some(0.5);
+    leaf.shape   = NodeShape::Extent(vec![1.0, 2.0, 3.0]);
+
+    let mut transform: Transform = Default::default();
+    transform.matrix[0]  = 1.0;
+    transform.matrix[15] = 1.0;
+
+    Node {
+        name:      "root".to_string(),
+        layer:     Layer::World,
+        transform: Some(transform),
+        children:  vec![leaf.clone(), leaf],
+        weights:   vec![0.25, 0.75],
+        tags:      vec![("a".to_string(), 1), ("b".to_string(), 2)],
+        opacity:   Some(0.8),
+        blob:      Some(vec![0, 1, 0xFF]),
+        shape:     NodeShape::Radius(2.5),
+    }
+}
+
+#[test]
+fn generated_records_round_trip() {
+    let enc  = Node::encoding();
+    let root = scene();
+
+    let mut buf = vec![];
+    encode_to(&enc, &mut buf, &root).unwrap();
+
+    let mut out: Node = Default::default();
+    decode_from(&enc, &mut &buf[..], &mut out).unwrap();
+    assert_eq!(out, root);
+
+    // The generated types and `DynamicRecord` agree about the data.
+    let dynamic = DynamicRecord::decode_from(&enc, &mut &buf[..]).unwrap();
+
+    let mut again = vec![];
+    encode_to(&enc, &mut again, &dynamic).unwrap();
+    assert_eq!(again, buf);
+}
+
+#[test]
+fn generated_records_fill_defaults() {
+    let enc = Node::encoding();
+
+    let mut root = scene();
+    root.opacity = None;
+
+    let mut buf = vec![];
+    encode_to(&enc, &mut buf, &root).unwrap();
+
+    let mut out: Node = Default::default();
+    decode_from(&enc, &mut &buf[..], &mut out).unwrap();
+    assert_eq!(out.opacity, Some(1.0));
+}
+
+#[test]
+fn generated_encoding_matches_compiler() {
+    let encs = rex::compiler::compile_str(include_str!("codegen/scene.rex")).unwrap();
+    assert_eq!(*rex::compiler::find_encoding(&encs[..], "Node").unwrap(), Node::encoding());
+}
+
+#[test]
+fn generated_records_dont_trust_counts() {
+    let enc = Node::encoding();
+
+    let mut buf  = vec![];
+    let node: Node = Default::default();
+    encode_to(&enc, &mut buf, &node).unwrap();
+
+    // Keep the required fields, dropping the radius (its id, size and 8 bytes) and the final 0.
+    let required = buf.len() - 11;
+
+    // Each repeated field in turn claims 2^40 elements, but the record ends soon after. Decoding
+    // fails at the end of the data, rather than allocating all of the elements first.
+    for &id in [4u8, 5, 6].iter() {
+        let mut bad = buf[..required].to_vec();
+        bad.extend([id, 0x80, 0x80, 0x80, 0x80, 0x80, 0x40,
+                        0x80, 0x80, 0x80, 0x80, 0x80, 0x20, 1, 2, 3].iter().cloned());
+
+        let mut out: Node = Default::default();
+        assert!(decode_from(&enc, &mut &bad[..], &mut out).is_err());
+    }
+}
@@ -0,0 +1,635 @@
+// This file was generated by rex. Do not edit it by hand.
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+#[repr(i64)]
+pub enum Layer {
+    Background = 0,
+    World = 1,
+    Overlay = -1,
+}
+
+impl Layer {
+    pub fn from_i64(x: i64) -> Option<Layer> {
+        match x {
+            0 => Some(Layer::Background),
+            1 => Some(Layer::World),
+            -1 => Some(Layer::Overlay),
+            _ => None,
+        }
+    }
+
+    pub fn to_i64(&self) -> i64 {
+        *self as i64
+    }
+}
+
+impl Default for Layer {
+    fn default() -> Layer {
+        Layer::Background
+    }
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Transform {
+    pub matrix: Vec<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform {
+            matrix: (0..16).map(|_| Default::default()).collect(),
+        }
+    }
+}
+
+impl Transform {
+    /// `encoding` returns the `CompleteEncoding` for `Transform`.
+    pub fn encoding() -> ::rex::encoding::CompleteEncoding {
+        ::rex::encoding::CompleteEncoding {
+            target:
+                ::rex::encoding::RecordEncoding {
+                    name: "Transform".to_string(),
+                    req_fields: vec![
+                        ::rex::encoding::FieldEncoding {
+                            id: ::rex::encoding::FieldID(1),
+                            name: "matrix".to_string(),
+                            quant: ::rex::encoding::Quantifier::Required,
+                            typ: ::rex::encoding::Type::Float32,
+                            bounds: Some(16),
+                            union: None,
+                            default: None,
+                        },
+                    ],
+                    opt_rep_fields: vec![
+                    ],
+                },
+            depends: vec![
+            ],
+        }
+    }
+}
+
+impl ::rex::Encodable for Transform {
+    fn get_primitive(&self, id: ::rex::encoding::FieldID, idx: usize)
+        -> Result<::rex::Primitive, ::rex::EncError> {
+
+        let _ = idx;
+
+        match id.0 {
+            1 => match self.matrix.get(idx) {
+                Some(x) => Ok(::rex::Primitive::Float32(*x)),
+                None    => Err(::rex::EncError::FieldTypeMismatch),
+            },
+            _ => Err(::rex::EncError::FieldTypeMismatch),
+        }
+    }
+
+    fn encode_record(&self, mut e: ::rex::Encoder, id: ::rex::encoding::FieldID, idx: usize)
+        -> Result<usize, ::rex::EncError> {
+
+        let _ = idx;
+
+        match id.0 {
+            _ => Err(::rex::EncError::FieldTypeMismatch),
+        }
+    }
+
+    fn count_field(&self, id: ::rex::encoding::FieldID) -> Result<usize, ::rex::EncError> {
+        match id.0 {
+            _ => Err(::rex::EncError::FieldTypeMismatch),
+        }
+    }
+
+    fn get_slice(&self, id: ::rex::encoding::FieldID) -> Option<::rex::Slice> {
+        match id.0 {
+            1 => Some(::rex::Slice::Float32(&self.matrix[..])),
+            _ => None,
+        }
+    }
+}
+
+impl ::rex::Decodable for Transform {
+    fn set_primitive(&mut self, id: ::rex::encoding::FieldID, idx: usize, prim: ::rex::Primitive)
+        -> Result<(), ::rex::DecError> {
+
+        let _ = idx;
+
+        match (id.0, prim) {
+            (1, ::rex::Primitive::Float32(v)) => match self.matrix.get_mut(idx) {
+                Some(x) => { *x = v; Ok(()) },
+                None    => Err(::rex::DecError::FieldTypeMismatch),
+            },
+            _ => Err(::rex::DecError::FieldTypeMismatch),
+        }
+    }
+
+    fn decode_record<'x, R>(&mut self, mut d: ::rex::Decoder<'x, R>, id: ::rex::encoding::FieldID, idx: usize)
+        -> Result<(), ::rex::DecError> where R: ::std::io::Read + 'x {
+
+        let _ = idx;
+
+        match id.0 {
+            _ => Err(::rex::DecError::FieldTypeMismatch),
+        }
+    }
+
+    fn alloc_field(&mut self, id: ::rex::encoding::FieldID, count: usize) -> Result<bool, ::rex::DecError> {
+        let _ = count;
+
+        match id.0 {
+            _ => Ok(false),
+        }
+    }
+
+    fn slice_mut(&mut self, id: ::rex::encoding::FieldID) -> Option<::rex::SliceMut> {
+        match id.0 {
+            1 => Some(::rex::SliceMut::Float32(&mut self.matrix[..])),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum NodeShape {
+    Radius(f64),
+    Extent(Vec<f32>),
+}
+
+impl Default for NodeShape {
+    fn default() -> NodeShape {
+        NodeShape::Radius(Default::default())
+    }
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub struct Node {
+    pub name: String,
+    pub layer: Layer,
+    pub transform: Option<Transform>,
+    pub children: Vec<Node>,
+    pub weights: Vec<f32>,
+    pub tags: Vec<(String, u32)>,
+    pub opacity: Option<f32>,
+    pub blob: Option<Vec<u8>>,
+    pub shape: NodeShape,
+}
+
+impl Default for Node {
+    fn default() -> Node {
+        Node {
+            name: Default::default(),
+            layer: Default::default(),
+            transform: None,
+            children: Vec::new(),
+            weights: Vec::new(),
+            tags: Vec::new(),
+            opacity: None,
+            blob: None,
+            shape: Default::default(),
+        }
+    }
+}
+
+impl Node {
+    /// `encoding` returns the `CompleteEncoding` for `Node`.
+    pub fn encoding() -> ::rex::encoding::CompleteEncoding {
+        ::rex::encoding::CompleteEncoding {
+            target:
+                ::rex::encoding::RecordEncoding {
+                    name: "Node".to_string(),
+                    req_fields: vec![
+                        ::rex::encoding::FieldEncoding {
+                            id: ::rex::encoding::FieldID(1),
+                            name: "name".to_string(),
+                            quant: ::rex::encoding::Quantifier::Required,
+                            typ: ::rex::encoding::Type::String,
+                            bounds: None,
+                            union: None,
+                            default: None,
+                        },
+                        ::rex::encoding::FieldEncoding {
+                            id: ::rex::encoding::FieldID(2),
+                            name: "layer".to_string(),
+                            quant: ::rex::encoding::Quantifier::Required,
+                            typ: ::rex::encoding::Type::Enum,
+                            bounds: None,
+                            union: None,
+                            default: None,
+                        },
+                    ],
+                    opt_rep_fields: vec![
+                        ::rex::encoding::FieldEncoding {
+                            id: ::rex::encoding::FieldID(3),
+                            name: "transform".to_string(),
+                            quant: ::rex::encoding::Quantifier::Optional,
+                            typ: ::rex::encoding::Type::Record{index: 0},
+                            bounds: None,
+                            union: None,
+                            default: None,
+                        },
+                        ::rex::encoding::FieldEncoding {
+                            id: ::rex::encoding::FieldID(4),
+                            name: "children".to_string(),
+                            quant: ::rex::encoding::Quantifier::Repeated,
+                            typ: ::rex::encoding::Type::Record{index: 1},
+                            bounds: None,
+                            union: None,
+                            default: None,
+                        },
+                        ::rex::encoding::FieldEncoding {
+                            id: ::rex::encoding::FieldID(5),
+                            name: "weights".to_string(),
+                            quant: ::rex::encoding::Quantifier::Repeated,
+                            typ: ::rex::encoding::Type::Float32,
+                            bounds: None,
+                            union: None,
+                            default: None,
+                        },
+                        ::rex::encoding::FieldEncoding {
+                            id: ::rex::encoding::FieldID(6),
+                            name: "tags".to_string(),
+                            quant: ::rex::encoding::Quantifier::Repeated,
+                            typ: ::rex::encoding::Type::Map{index: 2},
+                            bounds: None,
+                            union: None,
+                            default: None,
+                        },
+                        ::rex::encoding::FieldEncoding {
+                            id: ::rex::encoding::FieldID(7),
+                            name: "opacity".to_string(),
+                            quant: ::rex::encoding::Quantifier::Optional,
+                            typ: ::rex::encoding::Type::Float32,
+                            bounds: None,
+                            union: None,
+                            default: Some(::rex::Primitive::Float32(1.0f32)),
+                        },
+                        ::rex::encoding::FieldEncoding {
+                            id: ::rex::encoding::FieldID(8),
+                            name: "blob".to_string(),
+                            quant: ::rex::encoding::Quantifier::Optional,
+                            typ: ::rex::encoding::Type::Bytes,
+                            bounds: None,
+                            union: None,
+                            default: None,
+                        },
+                        ::rex::encoding::FieldEncoding {
+                            id: ::rex::encoding::FieldID(9),
+                            name: "radius".to_string(),
+                            quant: ::rex::encoding::Quantifier::Optional,
+                            typ: ::rex::encoding::Type::Float64,
+                            bounds: None,
+                            union: Some("shape".to_string()),
+                            default: None,
+                        },
+                        ::rex::encoding::FieldEncoding {
+                            id: ::rex::encoding::FieldID(10),
+                            name: "extent".to_string(),
+                            quant: ::rex::encoding::Quantifier::Optional,
+                            typ: ::rex::encoding::Type::Float32,
+                            bounds: Some(3),
+                            union: Some("shape".to_string()),
+                            default: None,
+                        },
+                    ],
+                },
+            depends: vec![
+                ::rex::encoding::RecordEncoding {
+                    name: "Transform".to_string(),
+                    req_fields: vec![
+                        ::rex::encoding::FieldEncoding {
+                            id: ::rex::encoding::FieldID(1),
+                            name: "matrix".to_string(),
+                            quant: ::rex::encoding::Quantifier::Required,
+                            typ: ::rex::encoding::Type::Float32,
+                            bounds: Some(16),
+                            union: None,
+                            default: None,
+                        },
+                    ],
+                    opt_rep_fields: vec![
+                    ],
+                },
+                ::rex::encoding::RecordEncoding {
+                    name: "Node".to_string(),
+                    req_fields: vec![
+                        ::rex::encoding::FieldEncoding {
+                            id: ::rex::encoding::FieldID(1),
+                            name: "name".to_string(),
+                            quant: ::rex::encoding::Quantifier::Required,
+                            typ: ::rex::encoding::Type::String,
+                            bounds: None,
+                            union: None,
+                            default: None,
+                        },
+                        ::rex::encoding::FieldEncoding {
+                            id: ::rex::encoding::FieldID(2),
+                            name: "layer".to_string(),
+                            quant: ::rex::encoding::Quantifier::Required,
+                            typ: ::rex::encoding::Type::Enum,
+                            bounds: None,
+                            union: None,
+                            default: None,
+                        },
+                    ],
+                    opt_rep_fields: vec![
+                        ::rex::encoding::FieldEncoding {
+                            id: ::rex::encoding::FieldID(3),
+                            name: "transform".to_string(),
+                            quant: ::rex::encoding::Quantifier::Optional,
+                            typ: ::rex::encoding::Type::Record{index: 0},
+                            bounds: None,
+                            union: None,
+                            default: None,
+                        },
+                        ::rex::encoding::FieldEncoding {
+                            id: ::rex::encoding::FieldID(4),
+                            name: "children".to_string(),
+                            quant: ::rex::encoding::Quantifier::Repeated,
+                            typ: ::rex::encoding::Type::Record{index: 1},
+                            bounds: None,
+                            union: None,
+                            default: None,
+                        },
+                        ::rex::encoding::FieldEncoding {
+                            id: ::rex::encoding::FieldID(5),
+                            name: "weights".to_string(),
+                            quant: ::rex::encoding::Quantifier::Repeated,
+                            typ: ::rex::encoding::Type::Float32,
+                            bounds: None,
+                            union: None,
+                            default: None,
+                        },
+                        ::rex::encoding::FieldEncoding {
+                            id: ::rex::encoding::FieldID(6),
+                            name: "tags".to_string(),
+                            quant: ::rex::encoding::Quantifier::Repeated,
+                            typ: ::rex::encoding::Type::Map{index: 2},
+                            bounds: None,
+                            union: None,
+                            default: None,
+                        },
+                        ::rex::encoding::FieldEncoding {
+                            id: ::rex::encoding::FieldID(7),
+                            name: "opacity".to_string(),
+                            quant: ::rex::encoding::Quantifier::Optional,
+                            typ: ::rex::encoding::Type::Float32,
+                            bounds: None,
+                            union: None,
+                            default: Some(::rex::Primitive::Float32(1.0f32)),
+                        },
+                        ::rex::encoding::FieldEncoding {
+                            id: ::rex::encoding::FieldID(8),
+                            name: "blob".to_string(),
+                            quant: ::rex::encoding::Quantifier::Optional,
+                            typ: ::rex::encoding::Type::Bytes,
+                            bounds: None,
+                            union: None,
+                            default: None,
+                        },
+                        ::rex::encoding::FieldEncoding {
+                            id: ::rex::encoding::FieldID(9),
+                            name: "radius".to_string(),
+                            quant: ::rex::encoding::Quantifier::Optional,
+                            typ: ::rex::encoding::Type::Float64,
+                            bounds: None,
+                            union: Some("shape".to_string()),
+                            default: None,
+                        },
+                        ::rex::encoding::FieldEncoding {
+                            id: ::rex::encoding::FieldID(10),
+                            name: "extent".to_string(),
+                            quant: ::rex::encoding::Quantifier::Optional,
+                            typ: ::rex::encoding::Type::Float32,
+                            bounds: Some(3),
+                            union: Some("shape".to_string()),
+                            default: None,
+                        },
+                    ],
+                },
+                ::rex::encoding::RecordEncoding {
+                    name: "Node.tags".to_string(),
+                    req_fields: vec![
+                        ::rex::encoding::FieldEncoding {
+                            id: ::rex::encoding::FieldID(1),
+                            name: "key".to_string(),
+                            quant: ::rex::encoding::Quantifier::Required,
+                            typ: ::rex::encoding::Type::String,
+                            bounds: None,
+                            union: None,
+                            default: None,
+                        },
+                        ::rex::encoding::FieldEncoding {
+                            id: ::rex::encoding::FieldID(2),
+                            name: "value".to_string(),
+                            quant: ::rex::encoding::Quantifier::Required,
+                            typ: ::rex::encoding::Type::UInt32,
+                            bounds: None,
+                            union: None,
+                            default: None,
+                        },
+                    ],
+                    opt_rep_fields: vec![
+                    ],
+                },
+            ],
+        }
+    }
+}
+
+impl ::rex::Encodable for Node {
+    fn get_primitive(&self, id: ::rex::encoding::FieldID, idx: usize)
+        -> Result<::rex::Primitive, ::rex::EncError> {
+
+        let _ = idx;
+
+        match id.0 {
+            1 => match Some(&self.name) {
+                Some(x) => Ok(::rex::Primitive::String(x.clone())),
+                None    => Err(::rex::EncError::FieldTypeMismatch),
+            },
+            2 => match Some(&self.layer) {
+                Some(x) => Ok(::rex::Primitive::Enum(x.to_i64())),
+                None    => Err(::rex::EncError::FieldTypeMismatch),
+            },
+            5 => match self.weights.get(idx) {
+                Some(x) => Ok(::rex::Primitive::Float32(*x)),
+                None    => Err(::rex::EncError::FieldTypeMismatch),
+            },
+            6 => match self.tags.get(idx).map(|x| &x.1) {
+                Some(x) => Ok(::rex::Primitive::UInt32(*x)),
+                None    => Err(::rex::EncError::FieldTypeMismatch),
+            },
+            7 => match self.opacity.as_ref() {
+                Some(x) => Ok(::rex::Primitive::Float32(*x)),
+                None    => Err(::rex::EncError::FieldTypeMismatch),
+            },
+            8 => match self.blob.as_ref() {
+                Some(x) => Ok(::rex::Primitive::Bytes(x.clone())),
+                None    => Err(::rex::EncError::FieldTypeMismatch),
+            },
+            9 => match match self.shape { NodeShape::Radius(ref v) => Some(v), _ => None } {
+                Some(x) => Ok(::rex::Primitive::Float64(*x)),
+                None    => Err(::rex::EncError::FieldTypeMismatch),
+            },
+            10 => match match self.shape { NodeShape::Extent(ref v) => v.get(idx), _ => None } {
+                Some(x) => Ok(::rex::Primitive::Float32(*x)),
+                None    => Err(::rex::EncError::FieldTypeMismatch),
+            },
+            _ => Err(::rex::EncError::FieldTypeMismatch),
+        }
+    }
+
+    fn encode_record(&self, mut e: ::rex::Encoder, id: ::rex::encoding::FieldID, idx: usize)
+        -> Result<usize, ::rex::EncError> {
+
+        let _ = idx;
+
+        match id.0 {
+            3 => match self.transform.as_ref() {
+                Some(x) => e.encode(x),
+                None    => Err(::rex::EncError::FieldTypeMismatch),
+            },
+            4 => match self.children.get(idx) {
+                Some(x) => e.encode(x),
+                None    => Err(::rex::EncError::FieldTypeMismatch),
+            },
+            _ => Err(::rex::EncError::FieldTypeMismatch),
+        }
+    }
+
+    fn count_field(&self, id: ::rex::encoding::FieldID) -> Result<usize, ::rex::EncError> {
+        match id.0 {
+            3 => Ok(if self.transform.is_some() { 1 } else { 0 }),
+            4 => Ok(self.children.len()),
+            5 => Ok(self.weights.len()),
+            6 => Ok(self.tags.len()),
+            7 => Ok(if self.opacity.is_some() { 1 } else { 0 }),
+            8 => Ok(if self.blob.is_some() { 1 } else { 0 }),
+            9 => Ok(if let NodeShape::Radius(..) = self.shape { 1 } else { 0 }),
+            10 => Ok(if let NodeShape::Extent(..) = self.shape { 1 } else { 0 }),
+            _ => Err(::rex::EncError::FieldTypeMismatch),
+        }
+    }
+
+    fn get_map_key(&self, id: ::rex::encoding::FieldID, idx: usize)
+        -> Result<::rex::Primitive, ::rex::EncError> {
+
+        match id.0 {
+            6 => match self.tags.get(idx) {
+                Some(x) => Ok(::rex::Primitive::String(x.0.clone())),
+                None    => Err(::rex::EncError::FieldTypeMismatch),
+            },
+            _ => Err(::rex::EncError::FieldTypeMismatch),
+        }
+    }
+
+    fn get_slice(&self, id: ::rex::encoding::FieldID) -> Option<::rex::Slice> {
+        match id.0 {
+            5 => Some(::rex::Slice::Float32(&self.weights[..])),
+            _ => None,
+        }
+    }
+}
+
+impl ::rex::Decodable for Node {
+    fn set_primitive(&mut self, id: ::rex::encoding::FieldID, idx: usize, prim: ::rex::Primitive)
+        -> Result<(), ::rex::DecError> {
+
+        let _ = idx;
+
+        match (id.0, prim) {
+            (1, ::rex::Primitive::String(v)) => match Some(&mut self.name) {
+                Some(x) => { *x = v; Ok(()) },
+                None    => Err(::rex::DecError::FieldTypeMismatch),
+            },
+            (2, ::rex::Primitive::Enum(v)) => match (Some(&mut self.layer), Layer::from_i64(v)) {
+                (Some(x), Some(v)) => { *x = v; Ok(()) },
+                (Some(_), None)    => Err(::rex::DecError::BadEnum(v)),
+                (None, _)          => Err(::rex::DecError::FieldTypeMismatch),
+            },
+            (5, ::rex::Primitive::Float32(v)) => match ::rex::next_elem(&mut self.weights, idx, Default::default) {
+                Some(x) => { *x = v; Ok(()) },
+                None    => Err(::rex::DecError::FieldTypeMismatch),
+            },
+            (6, ::rex::Primitive::UInt32(v)) => match ::rex::next_elem(&mut self.tags, idx, Default::default).map(|x| &mut x.1) {
+                Some(x) => { *x = v; Ok(()) },
+                None    => Err(::rex::DecError::FieldTypeMismatch),
+            },
+            (7, ::rex::Primitive::Float32(v)) => match self.opacity.as_mut() {
+                Some(x) => { *x = v; Ok(()) },
+                None    => Err(::rex::DecError::FieldTypeMismatch),
+            },
+            (8, ::rex::Primitive::Bytes(v)) => match self.blob.as_mut() {
+                Some(x) => { *x = v; Ok(()) },
+                None    => Err(::rex::DecError::FieldTypeMismatch),
+            },
+            (9, ::rex::Primitive::Float64(v)) => match match self.shape { NodeShape::Radius(ref mut v) => Some(v), _ => None } {
+                Some(x) => { *x = v; Ok(()) },
+                None    => Err(::rex::DecError::FieldTypeMismatch),
+            },
+            (10, ::rex::Primitive::Float32(v)) => match match self.shape { NodeShape::Extent(ref mut v) => v.get_mut(idx), _ => None } {
+                Some(x) => { *x = v; Ok(()) },
+                None    => Err(::rex::DecError::FieldTypeMismatch),
+            },
+            _ => Err(::rex::DecError::FieldTypeMismatch),
+        }
+    }
+
+    fn decode_record<'x, R>(&mut self, mut d: ::rex::Decoder<'x, R>, id: ::rex::encoding::FieldID, idx: usize)
+        -> Result<(), ::rex::DecError> where R: ::std::io::Read + 'x {
+
+        let _ = idx;
+
+        match id.0 {
+            3 => match self.transform.as_mut() {
+                Some(x) => d.decode(x),
+                None    => Err(::rex::DecError::FieldTypeMismatch),
+            },
+            4 => match ::rex::next_elem(&mut self.children, idx, Default::default) {
+                Some(x) => d.decode(x),
+                None    => Err(::rex::DecError::FieldTypeMismatch),
+            },
+            _ => Err(::rex::DecError::FieldTypeMismatch),
+        }
+    }
+
+    fn alloc_field(&mut self, id: ::rex::encoding::FieldID, count: usize) -> Result<bool, ::rex::DecError> {
+        let _ = count;
+
+        match id.0 {
+            3 => { self.transform = Some(Default::default()); Ok(true) },
+            4 => { self.children.clear(); self.children.reserve(::std::cmp::min(count, ::rex::PREALLOC_MAX)); Ok(true) },
+            5 => { self.weights = if count <= ::rex::PREALLOC_MAX { (0..count).map(|_| Default::default()).collect() } else { vec![] }; Ok(true) },
+            6 => { self.tags.clear(); self.tags.reserve(::std::cmp::min(count, ::rex::PREALLOC_MAX)); Ok(true) },
+            7 => { self.opacity = Some(Default::default()); Ok(true) },
+            8 => { self.blob = Some(Default::default()); Ok(true) },
+            9 => { self.shape = NodeShape::Radius(Default::default()); Ok(true) },
+            10 => { self.shape = NodeShape::Extent((0..3).map(|_| Default::default()).collect()); Ok(true) },
+            _ => Ok(false),
+        }
+    }
+
+    fn set_map_key(&mut self, id: ::rex::encoding::FieldID, idx: usize, key: ::rex::Primitive)
+        -> Result<(), ::rex::DecError> {
+
+        match (id.0, key) {
+            (6, ::rex::Primitive::String(v)) => match ::rex::next_elem(&mut self.tags, idx, Default::default) {
+                Some(x) => { x.0 = v; Ok(()) },
+                None    => Err(::rex::DecError::FieldTypeMismatch),
+            },
+            _ => Err(::rex::DecError::FieldTypeMismatch),
+        }
+    }
+
+    fn slice_mut(&mut self, id: ::rex::encoding::FieldID) -> Option<::rex::SliceMut> {
+        match id.0 {
+            5 => if self.weights.is_empty() { None } else { Some(::rex::SliceMut::Float32(&mut self.weights[..])) },
+            _ => None,
+        }
+    }
+
+    fn fill_defaults(&self) -> bool {
+        true
+    }
+}
+
@@ -0,0 +1,2021 @@
+// Copyright (c) 2015, Sam Payson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT
+// NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+extern crate rex;
+extern crate "rustc-serialize" as rustc_serialize;
+
+use std::io;
+use std::iter;
+use std::u64;
+
+use rex::{encode_to, encode_to_checked, encode_streaming, decode_from, decode_from_checked,
+          decode_from_checked_with_limits, decode_from_with_limits, decode_slice,
+          decode_slice_with_limits, BorrowedDecodable, BorrowedPrimitive, DecError, DecodeLimits,
+          Decodable, Decoder, DynamicRecord, Encodable, Encoder, EncError, Encoding, Primitive,
+          Slice, SliceDecoder, SliceMut, Value};
+use rex::archive;
+use rex::archive::{ArchiveReader, ArchiveWriter};
+use rex::codec::Codec;
+use rex::compat;
+use rex::compat::Compatibility;
+use rex::codec::Identity;
+use rex::compiler;
+use rex::container;
+use rex::crc;
+use rex::json;
+use rex::encoding::{CompleteEncoding, Depends, FieldEncoding, FieldID, Quantifier, RecordEncoding,
+                    RecordType, Type, COMPLETE_ENC};
+use rex::migrate;
+use rex::migrate::Migration;
+use rex::stream;
+use rex::stream::{RecordReader, RecordWriter, SyncMarker};
+use rex::text;
+use rex::view::RecordView;
+use rustc_serialize::json::Json;
+
+// Encode `enc` using `COMPLETE_ENC`, decode it again, and check that nothing changed.
+fn round_trip(enc: &Encoding) {
+    let mut buf = vec![];
+    assert!(encode_to(&COMPLETE_ENC, &mut buf, enc).is_ok());
+
+    let mut out: Encoding = Default::default();
+    assert!(decode_from(&COMPLETE_ENC, &mut &buf[..], &mut out).is_ok());
+
+    assert_eq!(out, *enc);
+}
+
+#[test]
+fn complete_enc_round_trip() {
+    round_trip(&COMPLETE_ENC);
+}
+
+#[test]
+fn compiled_encodings_round_trip() {
+    let encs = compiler::compile_str(include_str!("../src/encoding.rex")).unwrap();
+
+    for enc in encs.iter() {
+        round_trip(enc);
+    }
+}
+
+// Compile `src`, which should fail, and return the line, column and message of the error.
+fn compile_error(src: &str) -> (usize, usize, String) {
+    let err = compiler::compile_str(src).err().unwrap();
+    (err.pos.line, err.pos.col, err.msg)
+}
+
+#[test]
+fn compiler_errors_have_positions() {
+    let (line, col, msg) = compile_error("record A {\n  1 x: int32\n  2 y: Missing\n}");
+    assert_eq!((line, col), (3, 8));
+    assert_eq!(&msg[..], "unknown type 'Missing'");
+
+    let (line, col, _) = compile_error("record A {\n  1 x: int32\n  1 y: int32\n}");
+    assert_eq!((line, col), (3, 3));
+
+    let (line, col, msg) = compile_error("record A {\n  1 x int32\n}");
+    assert_eq!((line, col), (2, 7));
+    assert_eq!(&msg[..], "expected ':', found 'int32'");
+
+    let (line, col, _) = compile_error("record A { 1 x: int32 }\nrecord A { 1 y: int32 }");
+    assert_eq!((line, col), (2, 1));
+
+    let (line, col, msg) = compile_error("record A {\n  1 x: $int32\n}");
+    assert_eq!((line, col), (2, 8));
+    assert_eq!(&msg[..], "unexpected character '$'");
+
+    let (line, col, msg) = compile_error("/* never closed\nrecord A {}");
+    assert_eq!((line, col), (1, 1));
+    assert_eq!(&msg[..], "unterminated block comment");
+
+    let (line, col, _) = compile_error("enum E { A = 1 }\nrecord R {\n  0 e: E\n}");
+    assert_eq!((line, col), (3, 3));
+}
+
+#[test]
+fn signed_varints_round_trip() {
+    let encs = compiler::compile_str("
+        record Signed {
+            1 a: rep int64
+            2 b: rep int32
+        }").unwrap();
+
+    let enc = &encs[0];
+
+    let rec = text::parse(enc, "
+        a: 0  a: -1  a: 1  a: -64  a: 64  a: -9223372036854775808  a: 9223372036854775807
+        b: -2147483648  b: 2147483647").unwrap();
+
+    let mut buf = vec![];
+    assert!(encode_to(enc, &mut buf, &rec).is_ok());
+    assert_eq!(DynamicRecord::decode_from(enc, &mut &buf[..]).unwrap(), rec);
+}
+
+#[test]
+fn overlong_varints_are_rejected() {
+    let encs = compiler::compile_str("record Big { 1 x: uint64 }").unwrap();
+    let enc  = &encs[0];
+
+    let rec = text::parse(enc, "x: 18446744073709551615").unwrap();
+
+    let mut buf = vec![];
+    assert!(encode_to(enc, &mut buf, &rec).is_ok());
+    assert_eq!(DynamicRecord::decode_from(enc, &mut &buf[..]).unwrap(), rec);
+
+    // The 10th byte of a varint only has room for the top bit of a u64.
+    let last = 9;
+    assert_eq!(&buf[..last + 1], &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 1][..]);
+
+    for b in [2u8, 0x7F].iter() {
+        buf[last] = *b;
+        let err = DynamicRecord::decode_from(enc, &mut &buf[..]).err().unwrap();
+        match *err.root() { DecError::BadVarint => (), _ => panic!("{:?}", err) }
+    }
+
+    // The same goes for the length prefixes of stream frames.
+    let encs = compiler::compile_str(EVENT_SCHEMA).unwrap();
+    let enc  = &encs[0];
+
+    let (buf, frames) = event_stream(enc, &[1], None);
+    let mut bad = buf[..frames[0]].to_vec();
+    bad.extend([0xFF; 9].iter().cloned());
+    bad.push(2);
+
+    match read_events(enc, &bad[..]).into_iter().next() {
+        Some(Err(stream::Error::DecError(DecError::BadVarint))) => (),
+        other => panic!("expected BadVarint, got {:?}", other),
+    }
+}
+
+#[test]
+fn arrays_and_options_round_trip() {
+    let src = "
+        record Vertex {
+            1 pos:    [3]float32
+            2 normal: opt [3]float32
+        }
+
+        record Mesh {
+            1 name:     string
+            2 verts:    rep Vertex
+            3 matrices: rep [4][4]float32
+            4 parent:   opt Mesh
+        }";
+
+    let encs = compiler::compile_str(src).unwrap();
+
+    for enc in encs.iter() {
+        round_trip(enc);
+    }
+}
+
+#[test]
+fn streaming_matches_staged() {
+    let encs = compiler::compile_str(include_str!("../src/encoding.rex")).unwrap();
+
+    for enc in encs.iter() {
+        let mut staged = vec![];
+        assert!(encode_to(&COMPLETE_ENC, &mut staged, enc).is_ok());
+
+        let mut streamed = vec![];
+        assert!(encode_streaming(&COMPLETE_ENC, &mut streamed, enc).is_ok());
+
+        assert_eq!(staged, streamed);
+    }
+}
+
+#[test]
+fn limits_are_enforced() {
+    let mut buf = vec![];
+    assert!(encode_to(&COMPLETE_ENC, &mut buf, &*COMPLETE_ENC).is_ok());
+
+    let decode = |limits: DecodeLimits| {
+        let mut out: Encoding = Default::default();
+        decode_from_with_limits(&COMPLETE_ENC, &mut &buf[..], &mut out, &limits)
+    };
+
+    assert!(decode(Default::default()).is_ok());
+
+    let mut limits: DecodeLimits = Default::default();
+    limits.max_bytes = buf.len() as u64 - 1;
+    let err = decode(limits).err().unwrap();
+    match *err.root() { DecError::SizeLimitExceeded => (), _ => panic!() }
+
+    let mut limits: DecodeLimits = Default::default();
+    limits.max_length = 1;
+    let err = decode(limits).err().unwrap();
+    match *err.root() { DecError::LengthLimitExceeded(_) => (), _ => panic!() }
+
+    let mut limits: DecodeLimits = Default::default();
+    limits.max_count = 1;
+    let err = decode(limits).err().unwrap();
+    match *err.root() { DecError::CountLimitExceeded(_) => (), _ => panic!() }
+
+    let mut limits: DecodeLimits = Default::default();
+    limits.max_depth = 0;
+    let err = decode(limits).err().unwrap();
+    match *err.root() { DecError::DepthLimitExceeded => (), _ => panic!() }
+}
+
+#[test]
+fn decode_errors_have_context() {
+    let mut buf = vec![];
+    assert!(encode_to(&COMPLETE_ENC, &mut buf, &*COMPLETE_ENC).is_ok());
+
+    // Cut the record off in the middle of the name of the target.
+    let mut out: Encoding = Default::default();
+    let err = decode_from(&COMPLETE_ENC, &mut &buf[..5], &mut out).err().unwrap();
+
+    match *err.root() { DecError::EOF => (), _ => panic!() }
+
+    let ctx = err.context().unwrap();
+    assert_eq!(ctx.record, "RecordEncoding");
+    assert_eq!(ctx.field, "name");
+    assert_eq!(ctx.path_string(), "CompleteEncoding.target.name");
+    assert_eq!(ctx.offset, Some(5));
+}
+
+#[test]
+fn unknown_fields_survive_re_encoding() {
+    let old = compiler::compile_str("
+        record Item {
+            1 name:  string
+            3 count: opt uint32
+        }").unwrap();
+
+    let new = compiler::compile_str("
+        record Tag {
+            1 name: string
+        }
+
+        record Item {
+            1 name:   string
+            2 weight: opt float32
+            3 count:  opt uint32
+            4 tags:   rep Tag
+        }").unwrap();
+
+    let new_enc = compiler::find_encoding(&new[..], "Item").unwrap();
+
+    let item = text::parse(new_enc, "
+        name:   \"sword\"
+        weight: 2.5
+        count:  3
+        tags {
+            name: \"sharp\"
+        }").unwrap();
+
+    let mut buf = vec![];
+    assert!(encode_to(new_enc, &mut buf, &item).is_ok());
+
+    // Round trip through the old encoding, which doesn't know about `weight` or `tags`.
+    let old_item = DynamicRecord::decode_from(&old[0], &mut &buf[..]).unwrap();
+    assert_eq!(old_item.unknown.len(), 2);
+
+    let mut rewritten = vec![];
+    assert!(encode_to(&old[0], &mut rewritten, &old_item).is_ok());
+    assert_eq!(rewritten, buf);
+
+    let new_item = DynamicRecord::decode_from(new_enc, &mut &rewritten[..]).unwrap();
+    assert_eq!(new_item, item);
+}
+
+#[test]
+fn migration_applies_transforms() {
+    let old = compiler::compile_str("
+        record Item {
+            1 name:   string
+            2 count:  uint32
+            3 legacy: opt string
+            4 weight: uint32
+        }").unwrap();
+
+    let new = compiler::compile_str("
+        record Item {
+            1 title:  string
+            2 count:  uint64
+            3 kind:   uint8
+            4 weight: float32
+            5 origin: opt string
+        }").unwrap();
+
+    let mut m = Migration::new(&old[0], &new[0]);
+    m.rename_field("Item", "name", "title");
+    m.default_field("Item", "kind", Value::Primitive(Primitive::UInt8(1)));
+    m.drop_field("Item", "legacy");
+    m.retype_field("Item", "weight", |val| match val {
+        Value::Primitive(Primitive::UInt32(g)) => {
+            Ok(Value::Primitive(Primitive::Float32(g as f32 / 1000.0)))
+        },
+        _ => Err("expected a weight in grams".to_string()),
+    });
+
+    let item = text::parse(&old[0], "
+        name:   \"sword\"
+        count:  3
+        legacy: \"unused\"
+        weight: 2500").unwrap();
+
+    let mut buf = vec![];
+    assert!(encode_to(&old[0], &mut buf, &item).is_ok());
+
+    let mut migrated = vec![];
+    assert!(m.migrate(&mut &buf[..], &mut migrated).is_ok());
+
+    let expected = text::parse(&new[0], "
+        title:  \"sword\"
+        count:  3
+        kind:   1
+        weight: 2.5").unwrap();
+
+    assert_eq!(DynamicRecord::decode_from(&new[0], &mut &migrated[..]).unwrap(), expected);
+
+    // Unknown fields are carried over, but not into an id which the new encoding has taken.
+    let mut item = item;
+    item.unknown = vec![(FieldID(9), vec![0x2a])];
+    assert_eq!(m.migrate_record(&item).unwrap().unknown, item.unknown);
+
+    item.unknown.push((FieldID(5), vec![0x2a]));
+    match m.migrate_record(&item) {
+        Err(migrate::Error::Field{ref path, ..}) => assert_eq!(path, "Item.origin"),
+        other => panic!("expected a field error, got {:?}", other),
+    }
+}
+
+#[test]
+fn map_fields_round_trip() {
+    let encs = compiler::compile_str("
+        record Material {
+            1 roughness: float32
+        }
+
+        record Mesh {
+            1 name:      string
+            2 materials: map<string, Material>
+            3 weights:   map<uint32, float64>
+        }").unwrap();
+
+    let enc = compiler::find_encoding(&encs[..], "Mesh").unwrap();
+
+    let mesh = text::parse(enc, "
+        name: \"cube\"
+        materials {
+            key: \"stone\"
+            value { roughness: 0.75 }
+        }
+        materials {
+            key: \"moss\"
+            value { roughness: 0.25 }
+        }
+        weights {
+            key: 7
+            value: 0.5
+        }").unwrap();
+
+    let mut buf = vec![];
+    assert!(encode_to(enc, &mut buf, &mesh).is_ok());
+
+    let decoded = DynamicRecord::decode_from(enc, &mut &buf[..]).unwrap();
+    assert_eq!(decoded, mesh);
+    assert_eq!(text::parse(enc, &text::print(&decoded)[..]).unwrap(), mesh);
+
+    let materials = decoded.field_by_name("materials").unwrap();
+    assert_eq!(materials.keys.len(), 2);
+
+    match materials.get(&Primitive::String("moss".to_string())) {
+        Some(&Value::Record(ref mat)) => assert_eq!(mat.field_by_name("roughness").unwrap().values,
+                                                    vec![Value::Primitive(Primitive::Float32(0.25))]),
+        _ => panic!("missing map entry"),
+    }
+}
+
+#[test]
+fn unions_have_exactly_one_member() {
+    let encs = compiler::compile_str("
+        record Collider {
+            1 name: string
+            union shape {
+                2 radius: float32
+                3 extent: [3]float32
+            }
+        }").unwrap();
+
+    let enc = &encs[0];
+
+    let sphere = text::parse(enc, "name: \"ball\"  radius: 0.5").unwrap();
+
+    let mut buf = vec![];
+    assert!(encode_to(enc, &mut buf, &sphere).is_ok());
+    assert_eq!(DynamicRecord::decode_from(enc, &mut &buf[..]).unwrap(), sphere);
+
+    let both = text::parse(enc, "name: \"odd\"  radius: 0.5  extent: [1, 2, 3]").unwrap();
+    match encode_to(enc, &mut vec![], &both) {
+        Err(EncError::UnionConflict(ref u)) => assert_eq!(&u[..], "shape"),
+        _                                   => panic!("expected UnionConflict"),
+    }
+
+    let neither = text::parse(enc, "name: \"none\"").unwrap();
+    match encode_to(enc, &mut vec![], &neither) {
+        Err(EncError::UnionEmpty(ref u)) => assert_eq!(&u[..], "shape"),
+        _                                => panic!("expected UnionEmpty"),
+    }
+
+    // Data written without the union can still set both members, which the decoder rejects.
+    let loose = compiler::compile_str("
+        record Collider {
+            1 name:   string
+            2 radius: opt float32
+            3 extent: opt [3]float32
+        }").unwrap();
+
+    let mut buf = vec![];
+    assert!(encode_to(&loose[0], &mut buf, &both).is_ok());
+
+    match DynamicRecord::decode_from(enc, &mut &buf[..]) {
+        Err(ref err) => match *err.root() {
+            DecError::UnionConflict(ref u) => {
+                assert_eq!(&u[..], "shape");
+                assert_eq!(&err.context().unwrap().field[..], "extent");
+            },
+            _ => panic!("expected UnionConflict"),
+        },
+        Ok(_) => panic!("expected UnionConflict"),
+    }
+
+    // A missing union is reported against its first member.
+    let mut buf = vec![];
+    assert!(encode_to(&loose[0], &mut buf, &neither).is_ok());
+
+    match DynamicRecord::decode_from(enc, &mut &buf[..]) {
+        Err(ref err) => match *err.root() {
+            DecError::UnionEmpty(ref u) => {
+                assert_eq!(&u[..], "shape");
+                assert_eq!(&err.context().unwrap().field[..], "radius");
+            },
+            _ => panic!("expected UnionEmpty"),
+        },
+        Ok(_) => panic!("expected UnionEmpty"),
+    }
+}
+
+// A `DynamicRecord` which opts in to filling and eliding defaults.
+struct Defaulted(DynamicRecord);
+
+impl Encodable for Defaulted {
+    fn get_primitive(&self, id: FieldID, idx: usize) -> Result<Primitive, EncError> {
+        self.0.get_primitive(id, idx)
+    }
+
+    fn encode_record(&self, e: Encoder, id: FieldID, idx: usize) -> Result<usize, EncError> {
+        self.0.encode_record(e, id, idx)
+    }
+
+    fn count_field(&self, id: FieldID) -> Result<usize, EncError> {
+        self.0.count_field(id)
+    }
+
+    fn elide_defaults(&self) -> bool {
+        true
+    }
+}
+
+impl Decodable for Defaulted {
+    fn set_primitive(&mut self, id: FieldID, idx: usize, prim: Primitive) -> Result<(), DecError> {
+        self.0.set_primitive(id, idx, prim)
+    }
+
+    fn decode_record<'x, R>(&mut self, d: Decoder<'x, R>, id: FieldID, idx: usize)
+        -> Result<(), DecError> where R: io::Read + 'x {
+
+        self.0.decode_record(d, id, idx)
+    }
+
+    fn alloc_field(&mut self, id: FieldID, count: usize) -> Result<bool, DecError> {
+        self.0.alloc_field(id, count)
+    }
+
+    fn fill_defaults(&self) -> bool {
+        true
+    }
+}
+
+#[test]
+fn defaults_fill_and_elide() {
+    let encs = compiler::compile_str("
+        record Settings {
+            1 name:    string
+            2 volume:  opt float32 = 0.8
+            3 title:   opt string = \"untitled\"
+            4 retries: opt int8 = -3
+        }").unwrap();
+
+    let enc = &encs[0];
+    round_trip(enc);
+
+    let retries = enc.target.opt_rep_fields.iter().find(|f| f.name == "retries").unwrap();
+    assert_eq!(retries.default, Some(Primitive::Int8(-3)));
+
+    let bare = text::parse(enc, "name: \"bare\"").unwrap();
+
+    let mut buf = vec![];
+    assert!(encode_to(enc, &mut buf, &bare).is_ok());
+
+    // Without opting in, absent fields stay absent.
+    let plain = DynamicRecord::decode_from(enc, &mut &buf[..]).unwrap();
+    assert!(plain.field_by_name("volume").unwrap().values.is_empty());
+
+    let mut filled = Defaulted(DynamicRecord::new(&enc.target));
+    assert!(decode_from(enc, &mut &buf[..], &mut filled).is_ok());
+    assert_eq!(filled.0.field_by_name("volume").unwrap().values,
+               vec![Value::Primitive(Primitive::Float32(0.8))]);
+    assert_eq!(filled.0.field_by_name("title").unwrap().values,
+               vec![Value::Primitive(Primitive::String("untitled".to_string()))]);
+
+    // Fields equal to their default aren't written when eliding.
+    let mut elided = vec![];
+    assert!(encode_to(enc, &mut elided, &filled).is_ok());
+    assert_eq!(elided, buf);
+
+    let mut full = vec![];
+    assert!(encode_to(enc, &mut full, &filled.0).is_ok());
+    assert!(full.len() > buf.len());
+
+    assert!(compiler::compile_str("record R { 1 x: opt int8 = 300 }").is_err());
+    assert!(compiler::compile_str("record R { 1 x: int8 = 1 }").is_err());
+    assert!(compiler::compile_str("record R { 1 x: opt string = 1 }").is_err());
+
+    // Floats must be finite, and float32 defaults must fit in an f32. The lexer has no exponents,
+    // so big literals are written out in full.
+    let zeros = |n: usize| iter::repeat('0').take(n).collect::<String>();
+    let src   = format!("record R {{ 1 x: opt float32 = -34{}.0 }}", zeros(37));
+    assert!(compiler::compile_str(&src[..]).is_ok());
+
+    let cases = [("float32", format!("1{}.0", zeros(39))),
+                 ("float32", format!("-1{}.0", zeros(39))),
+                 ("float64", format!("1{}.0", zeros(400)))];
+
+    for &(typ, ref lit) in cases.iter() {
+        let src = format!("record R {{\n  1 x: opt {} = {}\n}}", typ, lit);
+        let (line, col, msg) = compile_error(&src[..]);
+        assert_eq!((line, col), (2, 22));
+        assert_eq!(msg, format!("default value of 'x' is out of range for '{}'", typ));
+    }
+}
+
+// Two record types with the same name, as if they were declared in different modules.
+struct LocalName;
+struct OtherName;
+
+// A record with a field of each of the types above.
+struct Both;
+
+fn leaf_encoding(name: &str) -> RecordEncoding {
+    RecordEncoding {
+        name:           name.to_string(),
+        req_fields:     vec![],
+        opt_rep_fields: vec![],
+    }
+}
+
+impl RecordType for LocalName {
+    fn record_name() -> &'static str { "Name" }
+    fn record_path() -> &'static str { "local::Name" }
+    fn record_encoding(_: &mut Depends) -> RecordEncoding { leaf_encoding("Name") }
+}
+
+impl RecordType for OtherName {
+    fn record_name() -> &'static str { "Name" }
+    fn record_path() -> &'static str { "other::Name" }
+    fn record_encoding(_: &mut Depends) -> RecordEncoding { leaf_encoding("Name") }
+}
+
+impl RecordType for Both {
+    fn record_name() -> &'static str { "Both" }
+    fn record_path() -> &'static str { "Both" }
+
+    fn record_encoding(deps: &mut Depends) -> RecordEncoding {
+        let field = |id: u64, index: usize| FieldEncoding {
+            id:    FieldID(id),
+            name:  format!("f{}", id),
+            typ:   Type::Record{index: index},
+            quant: Quantifier::Required,
+            ..Default::default()
+        };
+
+        RecordEncoding {
+            name:           "Both".to_string(),
+            req_fields:     vec![field(1, deps.index::<LocalName>()),
+                                 field(2, deps.index::<OtherName>()),
+                                 field(3, deps.index::<LocalName>())],
+            opt_rep_fields: vec![],
+        }
+    }
+}
+
+#[test]
+fn record_types_are_identified_by_path() {
+    let enc = CompleteEncoding::of::<Both>();
+
+    assert_eq!(enc.depends.len(), 2);
+
+    let types: Vec<Type> = enc.target.req_fields.iter().map(|f| f.typ).collect();
+    assert_eq!(types, vec![Type::Record{index: 0}, Type::Record{index: 1}, Type::Record{index: 0}]);
+}
+
+// A codec which isn't built into the library, to check that unknown codecs are reported.
+struct Reverse;
+
+impl Codec for Reverse {
+    fn id(&self) -> u64 { 64 }
+
+    fn compress(&self, data: &[u8]) -> Vec<u8> {
+        data.iter().rev().cloned().collect()
+    }
+
+    fn decompress(&self, data: &[u8], max_len: u64) -> Result<Vec<u8>, DecError> {
+        if data.len() as u64 > max_len {
+            return Err(DecError::SizeLimitExceeded);
+        }
+
+        Ok(data.iter().rev().cloned().collect())
+    }
+}
+
+#[test]
+fn containers_round_trip() {
+    let encs = compiler::compile_str("
+        record Point {
+            1 name: string
+            2 pos:  [2]int32
+            3 tags: rep string
+        }").unwrap();
+
+    let enc = &encs[0];
+    let rec = text::parse(enc, "name: \"origin\"  pos: [0, -1]  tags: \"a\"  tags: \"b\"").unwrap();
+
+    let mut buf = vec![];
+    container::write_self_describing(&mut buf, enc, &rec).unwrap();
+    assert_eq!(&buf[..4], container::MAGIC);
+
+    let (schema, out) = container::read_dynamic(&mut &buf[..]).unwrap();
+    assert_eq!(schema, *enc);
+    assert_eq!(out, rec);
+
+    // A custom codec can be used to write a container, but only read with that codec in hand.
+    let mut packed = vec![];
+    container::write_compressed(&mut packed, enc, &rec, &Reverse).unwrap();
+
+    match container::read_dynamic(&mut &packed[..]) {
+        Err(container::Error::UnknownCodec(64)) => (),
+        other => panic!("expected UnknownCodec(64), got {:?}", other),
+    }
+
+    let r = &mut &packed[..];
+    let header = container::read_header(r).unwrap();
+    assert_eq!(header.codec, 64);
+
+    let mut out = DynamicRecord::new(&header.encoding.target);
+    container::read_payload_with(r, &header, &Reverse, &mut out, &Default::default()).unwrap();
+    assert_eq!(out, rec);
+}
+
+#[test]
+fn container_headers_are_checked() {
+    let encs = compiler::compile_str("record Empty {}").unwrap();
+
+    let mut buf = vec![];
+    container::write_self_describing(&mut buf, &encs[0], &DynamicRecord::new(&encs[0].target))
+        .unwrap();
+
+    let mut bad_magic = buf.clone();
+    bad_magic[0] = b'X';
+    match container::read_dynamic(&mut &bad_magic[..]) {
+        Err(container::Error::BadMagic) => (),
+        other => panic!("expected BadMagic, got {:?}", other),
+    }
+
+    // The version follows the magic number, and is a single byte varint for now.
+    let mut newer = buf.clone();
+    newer[4] = (container::FORMAT_VERSION + 1) as u8;
+    match container::read_dynamic(&mut &newer[..]) {
+        Err(container::Error::UnsupportedVersion(v)) => {
+            assert_eq!(v, container::FORMAT_VERSION + 1)
+        },
+        other => panic!("expected UnsupportedVersion, got {:?}", other),
+    }
+
+    match container::read_dynamic(&mut &buf[..buf.len() - 1]) {
+        Err(container::Error::DecError(_)) => (),
+        other => panic!("expected a decoding error, got {:?}", other),
+    }
+}
+
+#[test]
+fn text_round_trips() {
+    let encs = compiler::compile_str("
+        enum Kind {
+            Plain  = 0
+            Sunken = -1
+        }
+
+        record Cell {
+            1 kind:   Kind
+            2 corner: [2]int16
+        }
+
+        record Sheet {
+            1 title:  string
+            2 blob:   bytes
+            3 grid:   [2][3]uint8
+            4 scales: rep [2]float64
+            5 cells:  map<string, Cell>
+            6 notes:  map<int32, string>
+            7 done:   opt bool
+            8 parent: opt Sheet
+            9 ratio:  opt float32
+        }").unwrap();
+
+    let enc = compiler::find_encoding(&encs[..], "Sheet").unwrap();
+
+    let sheet = text::parse(enc, r#"
+        # Comments run to the end of the line.
+        title: "say \"hi\"\tback\\slash\nnew line \x01 \x7f é"
+        blob:  b"\x00\xff\"\\ plain"
+        grid:  [1, 2, 3, 4, 5, 255]
+        scales: [0.5, -2]
+        scales: [inf, -inf]
+        cells {
+            key: "a:1"
+            value { kind: -1  corner: [-32768, 32767] }
+        }
+        cells {
+            key: ""
+            value { kind: 0  corner: [0, 0] }
+        }
+        notes { key: -7  value: "minus \"seven\"" }
+        done: true
+        ratio: 0.1
+        parent {
+            title: "inner"
+            blob:  b""
+            grid:  [0, 0, 0, 0, 0, 0]
+        }"#).unwrap();
+
+    let printed = text::print(&sheet);
+    assert!(printed.contains(r#"title: "say \"hi\"\tback\\slash\nnew line \x01 \x7f é""#));
+    assert!(printed.contains(r#"blob: b"\x00\xff\"\\ plain""#));
+    assert!(printed.contains("grid: [1, 2, 3, 4, 5, 255]"));
+    assert!(printed.contains("scales: [inf, -inf]"));
+    assert!(printed.contains("ratio: 0.1\n"));
+
+    assert_eq!(text::parse(enc, &printed[..]).unwrap(), sheet);
+
+    let mut buf = vec![];
+    assert!(encode_to(enc, &mut buf, &sheet).is_ok());
+    assert_eq!(text::dump(enc, &mut &buf[..]).unwrap(), printed);
+
+    let blob = sheet.field_by_name("blob").unwrap();
+    assert_eq!(blob.values,
+               vec![Value::Primitive(Primitive::Bytes(b"\x00\xff\"\\ plain".to_vec()))]);
+
+    let bad_escape = text::parse(enc, r#"title: "\q""#).unwrap_err();
+    assert_eq!(bad_escape.msg, "unknown escape sequence");
+
+    let wide_bytes = text::parse(enc, r#"blob: b"é""#).unwrap_err();
+    assert_eq!(wide_bytes.msg, "bytes literals may only contain ASCII");
+}
+
+#[test]
+fn json_round_trips() {
+    let encs = compiler::compile_str("
+        record Part {
+            1 name: string
+            2 mass: float32
+        }
+
+        record Probe {
+            1 big:    rep uint64
+            2 signed: rep int64
+            3 small:  rep int8
+            4 blob:   bytes
+            5 floats: rep float64
+            6 parts:  rep Part
+            7 index:  map<uint64, Part>
+        }").unwrap();
+
+    let enc = compiler::find_encoding(&encs[..], "Probe").unwrap();
+
+    let src = r#"{
+        "big":    [9007199254740991, "9007199254740992", "18446744073709551615"],
+        "signed": [-9007199254740991, "-9007199254740992", "-9223372036854775808"],
+        "small":  [-128, "127"],
+        "blob":   "AP8QcmV4",
+        "floats": [0.5, "NaN", "Infinity", "-Infinity"],
+        "parts":  [{"name": "hull", "mass": 2}, {"name": "mast", "mass": 0.25}],
+        "index":  [{"key": "18446744073709551615", "value": {"name": "keel", "mass": 1}}]
+    }"#;
+
+    let rec = json::from_json(enc, &Json::from_str(src).unwrap()).unwrap();
+
+    let blob = rec.field_by_name("blob").unwrap();
+    assert_eq!(blob.values, vec![Value::Primitive(Primitive::Bytes(b"\x00\xff\x10rex".to_vec()))]);
+
+    let out    = json::to_json(&rec);
+    let member = |name: &str| out.find(name).unwrap().clone();
+
+    // Integers are only written as numbers if a double can represent them exactly.
+    assert_eq!(member("big"), Json::from_str(
+        r#"[9007199254740991, "9007199254740992", "18446744073709551615"]"#).unwrap());
+    assert_eq!(member("signed"), Json::from_str(
+        r#"[-9007199254740991, "-9007199254740992", "-9223372036854775808"]"#).unwrap());
+    assert_eq!(member("small"), Json::Array(vec![Json::I64(-128), Json::I64(127)]));
+    assert_eq!(member("blob"), Json::String("AP8QcmV4".to_string()));
+    assert_eq!(member("floats"),
+               Json::from_str(r#"[0.5, "NaN", "Infinity", "-Infinity"]"#).unwrap());
+
+    let floats = rec.field_by_name("floats").unwrap();
+    match floats.values[1] {
+        Value::Primitive(Primitive::Float64(x)) => assert!(x.is_nan()),
+        ref other => panic!("expected a float, got {:?}", other),
+    }
+
+    // NaN != NaN, so compare the binary encodings rather than the records.
+    let back = json::from_json(enc, &out).unwrap();
+    let (mut a, mut b) = (vec![], vec![]);
+    assert!(encode_to(enc, &mut a, &rec).is_ok());
+    assert!(encode_to(enc, &mut b, &back).is_ok());
+    assert_eq!(a, b);
+
+    let mut text = vec![];
+    assert!(json::json_to_binary(enc, src, &mut text).is_ok());
+    assert_eq!(text, a);
+}
+
+#[test]
+fn json_errors_have_paths() {
+    let encs = compiler::compile_str("
+        record Part {
+            1 name: string
+            2 mass: float32
+        }
+
+        record Probe {
+            1 small: rep int8
+            2 blob:  opt bytes
+            3 parts: rep Part
+            4 index: map<uint32, Part>
+            5 pos:   opt [3]float32
+        }").unwrap();
+
+    let enc = compiler::find_encoding(&encs[..], "Probe").unwrap();
+
+    let err = |src: &str| match json::from_json(enc, &Json::from_str(src).unwrap()) {
+        Err(json::Error::Field{path, ..}) => path,
+        other                             => panic!("expected a field error, got {:?}", other),
+    };
+
+    assert_eq!(err(r#"{"small": [1, 128]}"#), "Probe.small[1]");
+    assert_eq!(err(r#"{"small": [1, "-129"]}"#), "Probe.small[1]");
+    assert_eq!(err(r#"{"small": 1}"#), "Probe.small");
+    assert_eq!(err(r#"{"blob": "not base64!"}"#), "Probe.blob");
+    assert_eq!(err(r#"{"parts": [{"name": "a", "mass": 1}, {"name": 7, "mass": 1}]}"#),
+               "Probe.parts[1].name");
+    assert_eq!(err(r#"{"parts": [{"name": "a"}]}"#), "Probe.parts[0].mass");
+    assert_eq!(err(r#"{"parts": [{"name": "a", "mass": 1, "colour": 3}]}"#),
+               "Probe.parts[0].colour");
+    assert_eq!(err(r#"{"index": [{"key": -1, "value": {"name": "a", "mass": 1}}]}"#),
+               "Probe.index[0].key");
+    assert_eq!(err(r#"{"index": [{"key": 1, "value": {"name": "a", "mass": "heavy"}}]}"#),
+               "Probe.index[0].value.mass");
+    assert_eq!(err(r#"{"pos": [1, 2]}"#), "Probe.pos");
+    assert_eq!(err(r#"{"pos": [1, 2, true]}"#), "Probe.pos[2]");
+
+    // An encoding whose map entries have a map as their value isn't valid, and mustn't panic.
+    let mut bad = enc.clone();
+    let entry = match bad.target.opt_rep_fields.iter().find(|f| f.name == "index").unwrap().typ {
+        Type::Map{index} => index,
+        _                => panic!("index should be a map"),
+    };
+    bad.depends[entry].req_fields[1].typ = Type::Map{index: entry};
+
+    let src = r#"{"index": [{"key": 1, "value": [{"key": 2, "value": []}]}]}"#;
+    match json::from_json(&bad, &Json::from_str(src).unwrap()) {
+        Err(json::Error::EncError(EncError::EncodingInvalid)) => (),
+        other => panic!("expected EncodingInvalid, got {:?}", other),
+    }
+}
+
+#[test]
+fn compat_classifies_changes() {
+    let old = compiler::compile_str("
+        record Item {
+            1 weight: uint32
+            2 count:  int32
+        }
+
+        record Inventory {
+            1 owner:    string
+            2 items:    rep Item
+            3 label:    opt string
+            4 score:    uint64
+            5 flags:    opt uint32
+            6 old_note: opt string
+        }").unwrap();
+
+    let new = compiler::compile_str("
+        record Thing {
+            1 weight: uint64
+            2 amount: int32
+        }
+
+        record Inventory {
+            1 owner: string
+            2 items: rep Thing
+            3 label: string
+            4 score: uint32
+            5 flags: opt int32
+            7 extra: opt string
+        }").unwrap();
+
+    let old = compiler::find_encoding(&old[..], "Inventory").unwrap();
+    let new = compiler::find_encoding(&new[..], "Inventory").unwrap();
+
+    let changes = compat::check(old, new);
+    let found: Vec<(&str, Compatibility, &str)> = changes.iter()
+        .map(|c| (&c.path[..], c.compat, &c.msg[..]))
+        .collect();
+
+    let expected = [
+        ("Inventory.items", Compatibility::Full, "record 'Item' renamed to 'Thing'"),
+        ("Inventory.items.weight", Compatibility::Backward,
+         "type of field 1 changed from UInt32 to UInt64"),
+        ("Inventory.items.count", Compatibility::Full, "field 2 renamed to 'amount'"),
+        ("Inventory.label", Compatibility::Breaking, "optional field promoted to required"),
+        ("Inventory.score", Compatibility::Forward,
+         "type of field 4 changed from UInt64 to UInt32"),
+        ("Inventory.flags", Compatibility::Breaking,
+         "type of field 5 changed from UInt32 to Int32"),
+        ("Inventory.old_note", Compatibility::Full, "optional field 6 removed"),
+        ("Inventory.extra", Compatibility::Full, "optional field 7 added"),
+    ];
+
+    for e in expected.iter() {
+        assert!(found.contains(e), "missing {:?} in {:?}", e, found);
+    }
+    assert_eq!(found.len(), expected.len());
+
+    assert_eq!(compat::overall(&changes[..]), Compatibility::Breaking);
+
+    // Without the breaking changes, widening and narrowing together still can't go either way.
+    let mixed: Vec<compat::Change> = changes.iter()
+        .filter(|c| c.compat != Compatibility::Breaking)
+        .cloned()
+        .collect();
+    assert_eq!(compat::overall(&mixed[..]), Compatibility::Breaking);
+
+    let widened: Vec<compat::Change> = mixed.iter()
+        .filter(|c| c.compat != Compatibility::Forward)
+        .cloned()
+        .collect();
+    assert_eq!(compat::overall(&widened[..]), Compatibility::Backward);
+
+    assert!(compat::check(old, old).is_empty());
+}
+
+// Borrowed counterparts of the records in `borrowed_decode_points_into_the_buffer`.
+#[derive(Debug,Default,PartialEq)]
+struct Header<'a> {
+    kind: &'a str,
+}
+
+#[derive(Debug,Default,PartialEq)]
+struct Packet<'a> {
+    name:   &'a str,
+    data:   &'a [u8],
+    sum:    u32,
+    tags:   Vec<&'a str>,
+    header: Option<Header<'a>>,
+}
+
+impl<'a> BorrowedDecodable<'a> for Header<'a> {
+    fn set_primitive(&mut self, id: FieldID, _: usize, prim: BorrowedPrimitive<'a>)
+        -> Result<(), DecError> {
+
+        match (id, prim) {
+            (FieldID(1), BorrowedPrimitive::String(x)) => { self.kind = x; Ok(()) },
+            _                                           => Err(DecError::FieldTypeMismatch),
+        }
+    }
+
+    fn decode_record<'x>(&mut self, _: SliceDecoder<'a, 'x>, _: FieldID, _: usize)
+        -> Result<(), DecError> {
+
+        Err(DecError::FieldTypeMismatch)
+    }
+
+    fn alloc_field(&mut self, _: FieldID, _: usize) -> Result<bool, DecError> {
+        Ok(false)
+    }
+}
+
+impl<'a> BorrowedDecodable<'a> for Packet<'a> {
+    fn set_primitive(&mut self, id: FieldID, idx: usize, prim: BorrowedPrimitive<'a>)
+        -> Result<(), DecError> {
+
+        match (id, prim) {
+            (FieldID(1), BorrowedPrimitive::String(x)) => self.name = x,
+            (FieldID(2), BorrowedPrimitive::Bytes(x))  => self.data = x,
+            (FieldID(3), BorrowedPrimitive::UInt32(x)) => self.sum  = x,
+            (FieldID(4), BorrowedPrimitive::String(x)) if idx == self.tags.len() => {
+                self.tags.push(x)
+            },
+            _ => return Err(DecError::FieldTypeMismatch),
+        }
+
+        Ok(())
+    }
+
+    fn decode_record<'x>(&mut self, mut d: SliceDecoder<'a, 'x>, id: FieldID, _: usize)
+        -> Result<(), DecError> {
+
+        match (id, self.header.as_mut()) {
+            (FieldID(5), Some(h)) => d.decode(h),
+            _                     => Err(DecError::FieldTypeMismatch),
+        }
+    }
+
+    fn alloc_field(&mut self, id: FieldID, _: usize) -> Result<bool, DecError> {
+        match id {
+            FieldID(4) => { self.tags = vec![]; Ok(true) },
+            FieldID(5) => { self.header = Some(Default::default()); Ok(true) },
+            _          => Ok(false),
+        }
+    }
+}
+
+#[test]
+fn borrowed_decode_points_into_the_buffer() {
+    let encs = compiler::compile_str("
+        record Header {
+            1 kind: string
+        }
+
+        record Packet {
+            1 name:   string
+            2 data:   bytes
+            3 sum:    uint32
+            4 tags:   rep string
+            5 header: opt Header
+            6 extra:  opt float64
+        }").unwrap();
+
+    let enc = compiler::find_encoding(&encs[..], "Packet").unwrap();
+
+    let rec = text::parse(enc, "
+        name: \"ping\"  data: b\"\\x00\\x01\\x02\"  sum: 300
+        tags: \"a\"  tags: \"bc\"
+        header { kind: \"echo\" }
+        extra: 2.5").unwrap();
+
+    let mut buf = vec![];
+    assert!(encode_to(enc, &mut buf, &rec).is_ok());
+    let len = buf.len();
+    buf.push(0xAA);
+
+    let mut packet: Packet = Default::default();
+    let mut rd = &buf[..];
+    decode_slice(enc, &mut rd, &mut packet).unwrap();
+
+    // Unwanted fields are skipped, and the slice is left just past the record.
+    assert_eq!(rd, &[0xAA][..]);
+    assert_eq!(packet, Packet {
+        name:   "ping",
+        data:   &[0, 1, 2][..],
+        sum:    300,
+        tags:   vec!["a", "bc"],
+        header: Some(Header { kind: "echo" }),
+    });
+
+    let inside = |p: *const u8| {
+        let start = buf.as_ptr() as usize;
+        start <= p as usize && (p as usize) < start + len
+    };
+    assert!(inside(packet.name.as_ptr()));
+    assert!(inside(packet.data.as_ptr()));
+    assert!(inside(packet.tags[1].as_ptr()));
+    assert!(inside(packet.header.as_ref().unwrap().kind.as_ptr()));
+
+    // Truncated input is an error, however much is missing.
+    for cut in 0..len {
+        let mut packet: Packet = Default::default();
+        assert!(decode_slice(enc, &mut &buf[..cut], &mut packet).is_err());
+    }
+
+    // Limits are applied as they are by `decode_from_with_limits`.
+    let decode = |limits: DecodeLimits| {
+        let mut packet: Packet = Default::default();
+        decode_slice_with_limits(enc, &mut &buf[..], &mut packet, &limits)
+    };
+
+    assert!(decode(Default::default()).is_ok());
+
+    let mut limits: DecodeLimits = Default::default();
+    limits.max_count = 1;
+    let err = decode(limits).err().unwrap();
+    match *err.root() { DecError::CountLimitExceeded(2) => (), _ => panic!("{:?}", err) }
+
+    let mut limits: DecodeLimits = Default::default();
+    limits.max_depth = 0;
+    let err = decode(limits).err().unwrap();
+    match *err.root() { DecError::DepthLimitExceeded => (), _ => panic!("{:?}", err) }
+}
+
+const VIEW_SCHEMA: &'static str = "
+    record Joint {
+        1 name: string
+        2 pos:  [3]float32
+    }
+
+    record Rig {
+        1 name:    string
+        2 origin:  [3]float32
+        3 version: uint64
+        4 root:    opt Joint
+        5 parent:  opt string
+        6 joints:  rep Joint
+        7 weights: rep [2]int16
+        8 lookup:  map<string, uint32>
+    }";
+
+const VIEW_TEXT: &'static str = "
+    name: \"arm\"  origin: [1, 2, 3]  version: 70000
+    root { name: \"shoulder\"  pos: [0, 0, 0] }
+    joints { name: \"elbow\"  pos: [0, 1, 0] }
+    joints { name: \"wrist\"  pos: [0, 2, 0.5] }
+    weights: [1, -1]  weights: [300, -300]
+    lookup { key: \"elbow\"  value: 0 }
+    lookup { key: \"wrist\"  value: 1 }";
+
+// Check the fields of `VIEW_TEXT` through `view`.
+fn check_rig_view(view: &RecordView) {
+    let field = |name: &str| view.field_by_name(name).unwrap();
+
+    // Required fields, found by skipping the ones before them.
+    let name = field("name").unwrap();
+    assert_eq!((name.len(), name.primitive(0).unwrap()), (1, BorrowedPrimitive::String("arm")));
+
+    let origin = field("origin").unwrap();
+    assert_eq!(origin.len(), 3);
+    assert_eq!(origin.primitive(2).unwrap(), BorrowedPrimitive::Float32(3.0));
+    assert!(origin.primitive(3).is_err());
+
+    assert_eq!(field("version").unwrap().primitive(0).unwrap(), BorrowedPrimitive::UInt64(70000));
+
+    // Optional fields, which might not be present.
+    let root = field("root").unwrap().record(0).unwrap();
+    assert_eq!(root.record_encoding().name, "Joint");
+    assert_eq!(root.field(FieldID(1)).unwrap().unwrap().primitive(0).unwrap(),
+               BorrowedPrimitive::String("shoulder"));
+
+    assert!(field("parent").is_none());
+    assert!(field("no_such_field").is_none());
+    assert!(field("name").unwrap().record(0).is_err());
+
+    // Repeated fields, including arrays and maps.
+    let joints = field("joints").unwrap();
+    assert_eq!(joints.len(), 2);
+
+    let wrist = joints.record(1).unwrap();
+    let pos   = wrist.field_by_name("pos").unwrap().unwrap();
+    assert_eq!(pos.primitive(2).unwrap(), BorrowedPrimitive::Float32(0.5));
+    assert_eq!(wrist.field_by_name("name").unwrap().unwrap().primitive(0).unwrap(),
+               BorrowedPrimitive::String("wrist"));
+
+    let weights = field("weights").unwrap();
+    assert_eq!(weights.len(), 4);
+    assert_eq!(weights.primitive(3).unwrap(), BorrowedPrimitive::Int16(-300));
+
+    let lookup = field("lookup").unwrap();
+    assert_eq!(lookup.len(), 2);
+
+    let entry = lookup.record(1).unwrap();
+    assert_eq!(entry.field_by_name("key").unwrap().unwrap().primitive(0).unwrap(),
+               BorrowedPrimitive::String("wrist"));
+    assert_eq!(entry.field_by_name("value").unwrap().unwrap().primitive(0).unwrap(),
+               BorrowedPrimitive::UInt32(1));
+}
+
+#[test]
+fn record_views_find_fields() {
+    let encs = compiler::compile_str(VIEW_SCHEMA).unwrap();
+    let enc  = compiler::find_encoding(&encs[..], "Rig").unwrap();
+    let rec  = text::parse(enc, VIEW_TEXT).unwrap();
+
+    let mut buf = vec![];
+    assert!(encode_to(enc, &mut buf, &rec).is_ok());
+
+    check_rig_view(&RecordView::new(enc, &buf[..]));
+
+    // A view only reads as far as it needs to, so a truncated record fails on the fields which are
+    // cut off.
+    let cut  = &buf[..buf.len() - 4];
+    let view = RecordView::new(enc, cut);
+    assert!(view.field_by_name("name").unwrap().is_some());
+    assert!(view.field_by_name("lookup").is_err());
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn mapped_records_can_be_viewed() {
+    use rex::view::MappedRecord;
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+
+    let encs = compiler::compile_str(VIEW_SCHEMA).unwrap();
+    let enc  = compiler::find_encoding(&encs[..], "Rig").unwrap();
+    let rec  = text::parse(enc, VIEW_TEXT).unwrap();
+
+    let mut buf = vec![];
+    assert!(encode_to(enc, &mut buf, &rec).is_ok());
+
+    let path = env::temp_dir().join("rex-mapped-record-test.bin");
+    File::create(&path).unwrap().write_all(&buf[..]).unwrap();
+
+    let mapped = MappedRecord::open(&path, enc.clone()).unwrap();
+    check_rig_view(&mapped.view());
+}
+
+// A record holding a repeated `[3]float32` field as one flat vector, which is encoded and decoded
+// in bulk. Elements are never passed one at a time, so `get_primitive` and `set_primitive` fail.
+// `extra` is added to the length of the slices handed out, to check that bad lengths are caught.
+#[derive(Debug,Default,PartialEq)]
+struct Curve {
+    points: Vec<f32>,
+    extra:  usize,
+}
+
+impl Encodable for Curve {
+    fn get_primitive(&self, _: FieldID, _: usize) -> Result<Primitive, EncError> {
+        Err(EncError::FieldTypeMismatch)
+    }
+
+    fn encode_record(&self, _: Encoder, _: FieldID, _: usize) -> Result<usize, EncError> {
+        Err(EncError::FieldTypeMismatch)
+    }
+
+    fn count_field(&self, _: FieldID) -> Result<usize, EncError> {
+        Ok(self.points.len() / 3)
+    }
+
+    fn get_slice(&self, _: FieldID) -> Option<Slice> {
+        Some(Slice::Float32(&self.points[..self.points.len() - self.extra]))
+    }
+}
+
+impl Decodable for Curve {
+    fn set_primitive(&mut self, _: FieldID, _: usize, _: Primitive) -> Result<(), DecError> {
+        Err(DecError::FieldTypeMismatch)
+    }
+
+    fn decode_record<'x, R>(&mut self, _: Decoder<'x, R>, _: FieldID, _: usize)
+        -> Result<(), DecError> where R: io::Read + 'x {
+
+        Err(DecError::FieldTypeMismatch)
+    }
+
+    fn alloc_field(&mut self, _: FieldID, count: usize) -> Result<bool, DecError> {
+        self.points = vec![0.0; count * 3 + self.extra];
+        Ok(true)
+    }
+
+    fn slice_mut(&mut self, _: FieldID) -> Option<SliceMut> {
+        Some(SliceMut::Float32(&mut self.points[..]))
+    }
+}
+
+#[test]
+fn fixed_width_fields_are_sliced() {
+    let encs = compiler::compile_str("
+        record Curve {
+            1 points: rep [3]float32
+        }").unwrap();
+
+    let enc = &encs[0];
+
+    let curve = Curve {
+        points: vec![0.0, 1.0, 2.0, -0.5, 1e10, ::std::f32::INFINITY],
+        extra:  0,
+    };
+
+    // The bulk encoding is the same as the one made an element at a time.
+    let dynamic = text::parse(enc, "points: [0, 1, 2]  points: [-0.5, 1e10, inf]").unwrap();
+
+    let (mut bulk, mut single) = (vec![], vec![]);
+    assert!(encode_to(enc, &mut bulk, &curve).is_ok());
+    assert!(encode_to(enc, &mut single, &dynamic).is_ok());
+    assert_eq!(bulk, single);
+
+    let mut out: Curve = Default::default();
+    decode_from(enc, &mut &bulk[..], &mut out).unwrap();
+    assert_eq!(out, curve);
+
+    // A slice of the wrong length is rejected, rather than read or written past.
+    let short = Curve { points: curve.points.clone(), extra: 1 };
+    let err   = encode_to(enc, &mut vec![], &short).err().unwrap();
+    match *err.root() { EncError::FieldTypeMismatch => (), _ => panic!("{:?}", err) }
+
+    let mut long = Curve { points: vec![], extra: 1 };
+    let err      = decode_from(enc, &mut &bulk[..], &mut long).err().unwrap();
+    match *err.root() { DecError::FieldTypeMismatch => (), _ => panic!("{:?}", err) }
+
+    // Bounds so large that the number of elements overflows are rejected, rather than wrapping.
+    let mut huge = enc.clone();
+    huge.target.opt_rep_fields[0].bounds = Some(::std::usize::MAX / 2 + 1);
+
+    let err = encode_to(&huge, &mut vec![], &curve).err().unwrap();
+    match *err.root() { EncError::FieldTypeMismatch => (), _ => panic!("{:?}", err) }
+
+    let err = decode_from(&huge, &mut &bulk[..], &mut out).err().unwrap();
+    match *err.root() { DecError::CountLimitExceeded(2) => (), _ => panic!("{:?}", err) }
+
+    match RecordView::new(&huge, &bulk[..]).field(FieldID(1)) {
+        Err(DecError::CountLimitExceeded(2)) => (),
+        Err(err)                             => panic!("{:?}", err),
+        Ok(_)                                => panic!("expected CountLimitExceeded"),
+    }
+}
+
+const SYNC: SyncMarker = [0x52, 0x45, 0x58, 0x21, 0xd1, 0x7e, 0x3a, 0x90,
+                          0x0f, 0x55, 0xc2, 0x8b, 0x61, 0xee, 0x04, 0x97];
+
+// Write events with the given ids to a stream, and return it along with the offset at which each
+// record's frame (its sync marker, or its length if there isn't one) starts.
+fn event_stream(enc: &Encoding, ids: &[u32], sync: Option<SyncMarker>) -> (Vec<u8>, Vec<usize>) {
+    let mut buf    = RecordWriter::with_codec(vec![], enc, sync, Box::new(Identity)).unwrap()
+        .into_inner();
+    let mut frames = vec![];
+
+    for id in ids.iter() {
+        frames.push(buf.len());
+
+        let ev = text::parse(enc, &format!("id: {}  ok: true  note: \"event\"", id)[..]).unwrap();
+        RecordWriter::append(&mut buf, enc, sync, Box::new(Identity)).write(&ev).unwrap();
+    }
+
+    (buf, frames)
+}
+
+// Read every record from `stream`, returning the id of each one that was read and the error for
+// each that wasn't.
+fn read_events(enc: &Encoding, stream: &[u8]) -> Vec<Result<u64, stream::Error>> {
+    let mut r   = RecordReader::new(stream, enc).unwrap();
+    let mut out = vec![];
+
+    loop {
+        let mut ev = DynamicRecord::new(&enc.target);
+
+        match r.read(&mut ev) {
+            Ok(true)  => match ev.field_by_name("id").unwrap().values[0] {
+                Value::Primitive(Primitive::UInt32(id)) => out.push(Ok(id as u64)),
+                ref other                               => panic!("bad id {:?}", other),
+            },
+            Ok(false) => return out,
+            Err(err)  => out.push(Err(err)),
+        }
+    }
+}
+
+const EVENT_SCHEMA: &'static str = "
+    record Event {
+        1 id:   uint32
+        2 ok:   bool
+        3 note: string
+    }";
+
+#[test]
+fn streams_report_truncation() {
+    let encs = compiler::compile_str(EVENT_SCHEMA).unwrap();
+    let enc  = &encs[0];
+
+    for sync in [None, Some(SYNC)].iter() {
+        let (buf, frames) = event_stream(enc, &[1, 2, 3], *sync);
+
+        let ids = |n: usize| read_events(enc, &buf[..n]).into_iter()
+            .map(|r| r.ok())
+            .collect::<Vec<_>>();
+
+        assert_eq!(ids(buf.len()), vec![Some(1), Some(2), Some(3)]);
+
+        // Cutting the stream between records just ends it early.
+        assert_eq!(ids(frames[2]), vec![Some(1), Some(2)]);
+
+        // Cutting it part way through a record is an error, and then the stream ends.
+        for cut in frames[2] + 1..buf.len() {
+            let events = read_events(enc, &buf[..cut]);
+            assert_eq!(events.len(), 3);
+
+            match events[2] {
+                Err(stream::Error::Truncated) => (),
+                ref other                     => panic!("cut at {}: {:?}", cut, other),
+            }
+        }
+    }
+
+    let (buf, _) = event_stream(enc, &[1], Some(SYNC));
+    match RecordReader::new(&buf[..10], enc) {
+        Err(stream::Error::Truncated) => (),
+        Err(err)                      => panic!("expected Truncated, got {:?}", err),
+        Ok(_)                         => panic!("expected Truncated"),
+    }
+}
+
+#[test]
+fn streams_resync_after_damage() {
+    let encs = compiler::compile_str(EVENT_SCHEMA).unwrap();
+    let enc  = &encs[0];
+
+    // A damaged record body only loses that record, since the framing is intact.
+    let (mut buf, frames) = event_stream(enc, &[1, 2, 3], None);
+    let ok_at = frames[1] + 1 + 1;
+    assert_eq!(buf[ok_at], 0xFF);
+    buf[ok_at] = 7;
+
+    let events = read_events(enc, &buf[..]);
+    assert_eq!(events.len(), 3);
+    assert!(events[0].as_ref().ok() == Some(&1) && events[2].as_ref().ok() == Some(&3));
+    match events[1] {
+        Err(stream::Error::DecError(ref err)) => match *err.root() {
+            DecError::BadBool => (),
+            _                 => panic!("expected BadBool, got {:?}", err),
+        },
+        ref other => panic!("expected a decoding error, got {:?}", other),
+    }
+
+    // A damaged sync marker loses the stream's place, and it picks up again at the next marker.
+    let (mut buf, frames) = event_stream(enc, &[1, 2, 3, 4], Some(SYNC));
+    buf[frames[1] + 3] ^= 0xFF;
+
+    let events = read_events(enc, &buf[..]);
+    assert_eq!(events.len(), 4);
+    assert_eq!(events[0].as_ref().ok(), Some(&1));
+    match events[1] {
+        Err(stream::Error::BadSync) => (),
+        ref other                   => panic!("expected BadSync, got {:?}", other),
+    }
+    assert_eq!(events[2].as_ref().ok(), Some(&3));
+    assert_eq!(events[3].as_ref().ok(), Some(&4));
+
+    // So does a length which runs into the next record, though that record is lost too.
+    let (mut buf, frames) = event_stream(enc, &[1, 2, 3, 4], Some(SYNC));
+    buf[frames[1] + SYNC.len()] += 20;
+
+    let events: Vec<Option<u64>> = read_events(enc, &buf[..]).into_iter().map(|r| r.ok()).collect();
+    assert_eq!(events, vec![Some(1), None, None, Some(4)]);
+
+    // Without sync markers, there's no way to recover.
+    let (mut buf, frames) = event_stream(enc, &[1, 2, 3], None);
+    buf[frames[1]] = 0x7F;
+
+    let events = read_events(enc, &buf[..]);
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].as_ref().ok(), Some(&1));
+    assert!(events[1].is_err());
+}
+
+#[test]
+fn archives_look_up_entries() {
+    let encs = compiler::compile_str(EVENT_SCHEMA).unwrap();
+    let enc  = &encs[0];
+
+    let mut w = ArchiveWriter::new();
+
+    for name in ["zeta", "alpha", "mid"].iter() {
+        let ev = text::parse(enc, &format!("id: {}  ok: false  note: \"{}\"", name.len(), name)[..])
+            .unwrap();
+        w.add(name, enc, &ev).unwrap();
+    }
+
+    // Encodings go in the archive too, so entries needn't share one.
+    w.add("schema", &COMPLETE_ENC, enc).unwrap();
+
+    match w.add("mid", enc, &DynamicRecord::new(&enc.target)) {
+        Err(archive::Error::DuplicateName(ref name)) if name == "mid" => (),
+        other => panic!("expected DuplicateName, got {:?}", other),
+    }
+
+    let mut buf = vec![];
+    w.write_to(&mut buf).unwrap();
+
+    let mut r = ArchiveReader::open(io::Cursor::new(buf)).unwrap();
+
+    let names: Vec<&str> = r.entries().iter().map(|e| &e.name[..]).collect();
+    assert_eq!(names, vec!["alpha", "mid", "schema", "zeta"]);
+
+    assert_eq!(r.encoding("mid"), Some(enc));
+    assert_eq!(r.encoding("schema"), Some(&*COMPLETE_ENC));
+    assert!(r.entry("beta").is_none());
+
+    // Entries can be read in any order, any number of times.
+    for name in ["zeta", "alpha", "zeta", "mid"].iter() {
+        let ev   = r.read_dynamic(name).unwrap();
+        let note = ev.field_by_name("note").unwrap();
+        assert_eq!(note.values, vec![Value::Primitive(Primitive::String(name.to_string()))]);
+    }
+
+    let mut schema: Encoding = Default::default();
+    r.read("schema", &mut schema).unwrap();
+    assert_eq!(schema, *enc);
+
+    match r.read_dynamic("beta") {
+        Err(archive::Error::NotFound(ref name)) if name == "beta" => (),
+        other => panic!("expected NotFound, got {:?}", other),
+    }
+}
+
+// Build an archive by hand, with no data, a directory of (name, offset, length, schema) entries,
+// and a table of `schemas`.
+fn raw_archive(entries: &[(&str, u64, u64, u64)], schemas: &[CompleteEncoding]) -> Vec<u8> {
+    let mut dir = vec![];
+    write_uvarint(&mut dir, entries.len() as u64);
+
+    for &(name, offset, length, schema) in entries.iter() {
+        write_uvarint(&mut dir, name.len() as u64);
+        dir.extend(name.bytes());
+        write_uvarint(&mut dir, offset);
+        write_uvarint(&mut dir, length);
+        write_uvarint(&mut dir, schema);
+    }
+
+    let mut table = vec![];
+    write_uvarint(&mut table, schemas.len() as u64);
+
+    for enc in schemas.iter() {
+        assert!(encode_to(&COMPLETE_ENC, &mut table, enc).is_ok());
+    }
+
+    let mut buf = archive::MAGIC.to_vec();
+    buf.extend([archive::FORMAT_VERSION as u8, 0].iter().cloned());
+    write_uvarint(&mut buf, dir.len() as u64);
+    buf.extend(dir.into_iter());
+    write_uvarint(&mut buf, table.len() as u64);
+    buf.extend(table.into_iter());
+
+    buf
+}
+
+#[test]
+fn corrupt_archives_are_rejected() {
+    let open = |buf: Vec<u8>| ArchiveReader::open(io::Cursor::new(buf)).map(|_| ());
+
+    assert!(open(raw_archive(&[], &[])).is_ok());
+
+    let cases = [
+        // Entries out of order, or repeated.
+        raw_archive(&[("b", 0, 0, 0), ("a", 0, 0, 0)], &[]),
+        raw_archive(&[("a", 0, 0, 0), ("a", 0, 0, 0)], &[]),
+
+        // An entry whose schema isn't in the (empty) schema table.
+        raw_archive(&[("a", 0, 0, 0)], &[]),
+    ];
+
+    for buf in cases.iter() {
+        match open(buf.clone()) {
+            Err(archive::Error::Corrupt) => (),
+            other                        => panic!("expected Corrupt, got {:?}", other),
+        }
+    }
+
+    // A name which runs off the end of the directory.
+    let mut buf = raw_archive(&[("abc", 0, 0, 0)], &[]);
+    buf[archive::MAGIC.len() + 4] = 0x40;
+    match open(buf) {
+        Err(archive::Error::Corrupt) => (),
+        other                        => panic!("expected Corrupt, got {:?}", other),
+    }
+
+    // An offset so large that it overflows when added to the start of the data section.
+    let encs = compiler::compile_str("record Empty {}").unwrap();
+    let buf  = raw_archive(&[("a", u64::MAX, 1, 0)], &encs[..]);
+    let mut r = ArchiveReader::open(io::Cursor::new(buf)).unwrap();
+    match r.read_dynamic("a") {
+        Err(archive::Error::Corrupt) => (),
+        other => panic!("expected Corrupt, got {:?}", other.map(|_| ())),
+    }
+
+    let mut buf = raw_archive(&[], &[]);
+    buf[0] = b'Z';
+    match open(buf) {
+        Err(archive::Error::BadMagic) => (),
+        other                         => panic!("expected BadMagic, got {:?}", other),
+    }
+
+    let mut buf = raw_archive(&[], &[]);
+    buf[archive::MAGIC.len()] += 1;
+    match open(buf) {
+        Err(archive::Error::UnsupportedVersion(_)) => (),
+        other => panic!("expected UnsupportedVersion, got {:?}", other),
+    }
+
+    let mut buf = raw_archive(&[], &[]);
+    buf[archive::MAGIC.len() + 1] = 63;
+    match open(buf) {
+        Err(archive::Error::UnknownCodec(63)) => (),
+        other                                 => panic!("expected UnknownCodec, got {:?}", other),
+    }
+}
+
+#[cfg(feature = "lz")]
+#[test]
+fn lz_round_trips() {
+    use rex::codec::Lz;
+
+    let mut noisy = vec![];
+    let mut x     = 12345u32;
+    for _ in 0..5000 {
+        x = x.wrapping_mul(1103515245).wrapping_add(12345);
+        noisy.push((x >> 16) as u8);
+    }
+
+    let inputs: Vec<Vec<u8>> = vec![
+        vec![],
+        b"abc".to_vec(),
+        b"abcabcabcabcabcabcabcabcabcabc".to_vec(),
+        vec![7; 10000],
+        noisy.clone(),
+        noisy.iter().chain(noisy.iter()).cloned().collect(),
+    ];
+
+    for data in inputs.iter() {
+        let packed = Lz.compress(&data[..]);
+        assert_eq!(Lz.decompress(&packed[..], data.len() as u64).unwrap(), *data);
+    }
+
+    assert!(Lz.compress(&[7; 10000][..]).len() < 100);
+
+    // Compressed records can go in containers, streams and archives.
+    let encs = compiler::compile_str(EVENT_SCHEMA).unwrap();
+    let enc  = &encs[0];
+    let ev   = text::parse(enc, "id: 9  ok: true  note: \"again and again and again\"").unwrap();
+
+    let mut buf = vec![];
+    container::write_compressed(&mut buf, enc, &ev, &Lz).unwrap();
+    assert_eq!(container::read_dynamic(&mut &buf[..]).unwrap().1, ev);
+
+    let mut w = RecordWriter::with_codec(vec![], enc, Some(SYNC), Box::new(Lz)).unwrap();
+    w.write(&ev).unwrap();
+    w.write(&ev).unwrap();
+    let buf = w.into_inner();
+    assert_eq!(read_events(enc, &buf[..]).into_iter().map(|r| r.ok()).collect::<Vec<_>>(),
+               vec![Some(9), Some(9)]);
+
+    let mut w = ArchiveWriter::with_codec(Box::new(Lz));
+    w.add("ev", enc, &ev).unwrap();
+    let mut buf = vec![];
+    w.write_to(&mut buf).unwrap();
+    let mut r = ArchiveReader::open(io::Cursor::new(buf)).unwrap();
+    assert_eq!(r.read_dynamic("ev").unwrap(), ev);
+}
+
+#[cfg(feature = "lz")]
+#[test]
+fn lz_rejects_corrupt_input() {
+    use rex::codec::Lz;
+
+    let data: Vec<u8> = b"the quick brown fox jumps over the lazy dog; ".iter()
+        .cycle()
+        .take(400)
+        .cloned()
+        .collect();
+
+    let packed = Lz.compress(&data[..]);
+
+    // Every truncation is caught, since the uncompressed length comes first.
+    for cut in 0..packed.len() {
+        match Lz.decompress(&packed[..cut], u64::MAX) {
+            Err(_)  => (),
+            Ok(out) => panic!("cut at {} decompressed to {} bytes", cut, out.len()),
+        }
+    }
+
+    // Damage anywhere mustn't panic, although a changed literal can't be detected.
+    for i in 0..packed.len() {
+        for bit in 0..8 {
+            let mut bad = packed.clone();
+            bad[i] ^= 1 << bit;
+
+            if let Ok(out) = Lz.decompress(&bad[..], u64::MAX) {
+                assert_eq!(out.len(), data.len());
+            }
+        }
+    }
+
+    let cases: [&[u8]; 4] = [
+        // A literal run longer than the input.
+        &[4, 8, b'a'],
+        // A match before the start of the output.
+        &[8, 2, b'a', 1, 2],
+        // A match with distance 0.
+        &[8, 2, b'a', 1, 0],
+        // More output than the length promised.
+        &[1, 4, b'a', b'b'],
+    ];
+
+    for bad in cases.iter() {
+        match Lz.decompress(bad, u64::MAX) {
+            Err(DecError::BadCompression) => (),
+            other => panic!("expected BadCompression for {:?}, got {:?}", bad, other),
+        }
+    }
+
+    // A few bytes can promise a huge output, by repeating one literal byte, so the length is
+    // checked against the limit before anything is inflated.
+    let mut bomb = vec![];
+    write_uvarint(&mut bomb, 1 << 62);
+    bomb.extend([2, b'a'].iter().cloned());
+    write_uvarint(&mut bomb, ((1 << 62) - 1 - 4) << 1 | 1);
+    write_uvarint(&mut bomb, 1);
+
+    match Lz.decompress(&bomb[..], 1 << 20) {
+        Err(DecError::SizeLimitExceeded) => (),
+        other => panic!("expected SizeLimitExceeded, got {:?}", other.map(|out| out.len())),
+    }
+
+    match Lz.decompress(&packed[..], data.len() as u64 - 1) {
+        Err(DecError::SizeLimitExceeded) => (),
+        other => panic!("expected SizeLimitExceeded, got {:?}", other.map(|out| out.len())),
+    }
+
+    // A damaged record in a container is reported as such.
+    let encs = compiler::compile_str(EVENT_SCHEMA).unwrap();
+    let ev   = text::parse(&encs[0], "id: 1  ok: true  note: \"hello\"").unwrap();
+
+    let mut buf = vec![];
+    container::write_compressed(&mut buf, &encs[0], &ev, &Lz).unwrap();
+    let n = buf.len();
+    buf[n - 1] ^= 0xFF;
+    buf.push(0);
+
+    assert!(container::read_dynamic(&mut &buf[..]).is_err());
+}
+
+#[test]
+fn crc32c_matches_check_value() {
+    assert_eq!(crc::crc32c(b"123456789"), 0xE3069283);
+    assert_eq!(crc::crc32c(b""), 0);
+
+    let mut crc = crc::Crc32c::new();
+    crc.update(b"1234");
+    crc.update(b"");
+    crc.update(b"56789");
+    assert_eq!(crc.finish(), 0xE3069283);
+}
+
+// `Runs` is a run-length codec, whose compressed form is a sequence of (length, byte) pairs. Long
+// runs make a small payload decompress to a large one.
+struct Runs;
+
+impl Codec for Runs {
+    fn id(&self) -> u64 { 65 }
+
+    fn compress(&self, data: &[u8]) -> Vec<u8> {
+        let mut out = vec![];
+        let mut i   = 0;
+
+        while i < data.len() {
+            let n = data[i..].iter().take(255).take_while(|&&b| b == data[i]).count();
+            out.push(n as u8);
+            out.push(data[i]);
+            i += n;
+        }
+
+        out
+    }
+
+    fn decompress(&self, data: &[u8], max_len: u64) -> Result<Vec<u8>, DecError> {
+        if data.len() % 2 != 0 {
+            return Err(DecError::BadCompression);
+        }
+
+        let total = data.chunks(2).fold(0, |total, pair| total + pair[0] as u64);
+        if total > max_len {
+            return Err(DecError::SizeLimitExceeded);
+        }
+
+        let mut out = vec![];
+        for pair in data.chunks(2) {
+            out.extend(::std::iter::repeat(pair[1]).take(pair[0] as usize));
+        }
+
+        Ok(out)
+    }
+}
+
+#[test]
+fn readers_apply_decode_limits() {
+    let encs = compiler::compile_str(EVENT_SCHEMA).unwrap();
+    let enc  = &encs[0];
+    let ev   = text::parse(enc, "id: 7  ok: true  note: \"a longer note\"").unwrap();
+
+    let mut short: DecodeLimits = Default::default();
+    short.max_length = 4;
+
+    let mut tiny: DecodeLimits = Default::default();
+    tiny.max_bytes = 4;
+
+    let dec_error = |err: &DecError| -> String { format!("{:?}", err.root()) };
+
+    // Containers apply the limits to the schema and the payload, and to a compressed payload
+    // before it's read.
+    let mut buf = vec![];
+    container::write_self_describing(&mut buf, enc, &ev).unwrap();
+    assert!(container::read_dynamic(&mut &buf[..]).is_ok());
+
+    match container::read_dynamic_with_limits(&mut &buf[..], &short) {
+        Err(container::Error::DecError(ref err)) =>
+            assert!(dec_error(err).starts_with("LengthLimitExceeded")),
+        other => panic!("expected LengthLimitExceeded, got {:?}", other),
+    }
+
+    let mut buf = vec![];
+    container::write_compressed(&mut buf, enc, &ev, &Reverse).unwrap();
+
+    let r      = &mut &buf[..];
+    let header = container::read_header(r).unwrap();
+    let mut out = DynamicRecord::new(&enc.target);
+
+    match container::read_payload_with(r, &header, &Reverse, &mut out, &tiny) {
+        Err(container::Error::DecError(DecError::SizeLimitExceeded)) => (),
+        other => panic!("expected SizeLimitExceeded, got {:?}", other),
+    }
+
+    // Streams apply whichever limits were set last. A frame longer than `max_bytes` loses the
+    // stream's place, since its length can't be trusted.
+    let (buf, _) = event_stream(enc, &[1, 2, 3], None);
+    let mut r    = RecordReader::new(&buf[..], enc).unwrap();
+    let mut out  = DynamicRecord::new(&enc.target);
+
+    r.set_limits(short);
+    match r.read(&mut out) {
+        Err(stream::Error::DecError(ref err)) =>
+            assert!(dec_error(err).starts_with("LengthLimitExceeded")),
+        other => panic!("expected LengthLimitExceeded, got {:?}", other),
+    }
+
+    r.set_limits(Default::default());
+    assert!(r.read(&mut out).unwrap());
+
+    r.set_limits(tiny);
+    match r.read(&mut out) {
+        Err(stream::Error::DecError(DecError::SizeLimitExceeded)) => (),
+        other => panic!("expected SizeLimitExceeded, got {:?}", other),
+    }
+    assert!(!r.read(&mut out).unwrap());
+
+    // Archives apply the limits to the directory and schemas when they're opened, and to entries
+    // when they're read.
+    let mut w = ArchiveWriter::new();
+    w.add("ev", enc, &ev).unwrap();
+
+    let mut buf = vec![];
+    w.write_to(&mut buf).unwrap();
+
+    match ArchiveReader::open_with(io::Cursor::new(&buf[..]), None, tiny) {
+        Err(archive::Error::DecError(DecError::SizeLimitExceeded)) => (),
+        other => panic!("expected SizeLimitExceeded, got {:?}", other.map(|_| ())),
+    }
+
+    match ArchiveReader::open_with(io::Cursor::new(&buf[..]), None, short) {
+        Err(archive::Error::DecError(ref err)) =>
+            assert!(dec_error(err).starts_with("LengthLimitExceeded")),
+        other => panic!("expected LengthLimitExceeded, got {:?}", other.map(|_| ())),
+    }
+
+    let mut r = ArchiveReader::open_with(io::Cursor::new(&buf[..]), None, Default::default())
+        .unwrap();
+    assert_eq!(r.read_dynamic("ev").unwrap(), ev);
+
+    // A compressed record which is small enough to read but too large once decompressed is
+    // rejected by the codec, before it's inflated or decoded.
+    let long = format!("id: 8  ok: false  note: \"{}\"",
+                       ::std::iter::repeat("a").take(4000).collect::<String>());
+    let big  = text::parse(enc, &long[..]).unwrap();
+
+    let mut limits: DecodeLimits = Default::default();
+    limits.max_bytes = 1000;
+
+    let mut buf = vec![];
+    container::write_compressed(&mut buf, enc, &big, &Runs).unwrap();
+
+    let r       = &mut &buf[..];
+    let header  = container::read_header(r).unwrap();
+    let mut out = DynamicRecord::new(&enc.target);
+
+    match container::read_payload_with(r, &header, &Runs, &mut out, &limits) {
+        Err(container::Error::DecError(DecError::SizeLimitExceeded)) => (),
+        other => panic!("expected SizeLimitExceeded, got {:?}", other),
+    }
+
+    let mut w = RecordWriter::with_codec(vec![], enc, None, Box::new(Runs)).unwrap();
+    w.write(&big).unwrap();
+
+    let buf   = w.into_inner();
+    let mut r = RecordReader::with_codec(&buf[..], enc, Box::new(Runs)).unwrap();
+    r.set_limits(limits);
+
+    match r.read(&mut out) {
+        Err(stream::Error::DecError(DecError::SizeLimitExceeded)) => (),
+        other => panic!("expected SizeLimitExceeded, got {:?}", other),
+    }
+
+    let mut w = ArchiveWriter::with_codec(Box::new(Runs));
+    w.add("big", enc, &big).unwrap();
+
+    let mut buf = vec![];
+    w.write_to(&mut buf).unwrap();
+
+    let mut r = ArchiveReader::open_with(io::Cursor::new(&buf[..]), Some(Box::new(Runs)), limits)
+        .unwrap();
+
+    match r.read_dynamic("big") {
+        Err(archive::Error::DecError(DecError::SizeLimitExceeded)) => (),
+        other => panic!("expected SizeLimitExceeded, got {:?}", other),
+    }
+}
+
+#[test]
+fn checked_records_detect_corruption() {
+    let encs = compiler::compile_str(EVENT_SCHEMA).unwrap();
+    let enc  = &encs[0];
+    let ev   = text::parse(enc, "id: 5  ok: true  note: \"checked\"").unwrap();
+
+    let mut buf = vec![];
+    encode_to_checked(enc, &mut buf, &ev).unwrap();
+
+    let mut out = DynamicRecord::new(&enc.target);
+    decode_from_checked(enc, &mut &buf[..], &mut out).unwrap();
+    assert_eq!(out, ev);
+
+    // The length prefix is a single byte here. Damage to anything after it is caught by the
+    // checksum, before the record is decoded.
+    for i in 1..buf.len() {
+        let mut bad = buf.clone();
+        bad[i] ^= 0x10;
+
+        let mut out = DynamicRecord::new(&enc.target);
+        match decode_from_checked(enc, &mut &bad[..], &mut out) {
+            Err(DecError::ChecksumMismatch) => (),
+            other => panic!("flipped byte {}: expected ChecksumMismatch, got {:?}", i, other),
+        }
+    }
+
+    // Limits apply to checked records too, starting with the length prefix.
+    let mut limits: DecodeLimits = Default::default();
+    limits.max_bytes = buf[0] as u64 - 1;
+
+    let mut out = DynamicRecord::new(&enc.target);
+    match decode_from_checked_with_limits(enc, &mut &buf[..], &mut out, &limits) {
+        Err(DecError::SizeLimitExceeded) => (),
+        other => panic!("expected SizeLimitExceeded, got {:?}", other),
+    }
+
+    let mut limits: DecodeLimits = Default::default();
+    limits.max_length = 3;
+
+    let mut out = DynamicRecord::new(&enc.target);
+    let err = decode_from_checked_with_limits(enc, &mut &buf[..], &mut out, &limits).err().unwrap();
+    match *err.root() { DecError::LengthLimitExceeded(7) => (), _ => panic!("{:?}", err) }
+
+    // A huge length prefix is rejected without trying to read that much.
+    let mut huge = vec![];
+    write_uvarint(&mut huge, 1 << 40);
+
+    let mut out = DynamicRecord::new(&enc.target);
+    match decode_from_checked(enc, &mut &huge[..], &mut out) {
+        Err(DecError::SizeLimitExceeded) => (),
+        other => panic!("expected SizeLimitExceeded, got {:?}", other),
+    }
+}
+
+// Write `x` as a varint, as the library does for length prefixes.
+fn write_uvarint(out: &mut Vec<u8>, mut x: u64) {
+    while x >= 0x80 {
+        out.push((x as u8) | 0x80);
+        x >>= 7;
+    }
+    out.push(x as u8);
+}